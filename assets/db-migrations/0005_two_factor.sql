@@ -0,0 +1,28 @@
+CREATE TABLE two_factor (
+  account_id VARCHAR(255) NOT NULL,
+  secret VARCHAR(100) NOT NULL,
+  -- Last TOTP time step that was accepted. Used to prevent codes from being replayed
+  last_used_step BIGINT,
+  enabled_at TIMESTAMP,
+  recorded_at TIMESTAMP NOT NULL,
+  PRIMARY KEY(account_id),
+  CONSTRAINT fk_account_id FOREIGN KEY(account_id) REFERENCES account(id)
+);
+
+CREATE TABLE two_factor_recovery_code (
+  code_hash VARCHAR(100) NOT NULL,
+  account_id VARCHAR(255) NOT NULL,
+  used_at TIMESTAMP,
+  recorded_at TIMESTAMP NOT NULL,
+  PRIMARY KEY(code_hash, account_id),
+  CONSTRAINT fk_account_id FOREIGN KEY(account_id) REFERENCES account(id)
+);
+
+CREATE TABLE two_factor_challenge (
+  token VARCHAR(100) NOT NULL,
+  account_id VARCHAR(255) NOT NULL,
+  attempts INTEGER NOT NULL DEFAULT 0,
+  expires_at TIMESTAMP NOT NULL,
+  PRIMARY KEY(token),
+  CONSTRAINT fk_account_id FOREIGN KEY(account_id) REFERENCES account(id)
+);
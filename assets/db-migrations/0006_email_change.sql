@@ -0,0 +1,33 @@
+-- The account id is derived from the e-mail address, so changing the e-mail
+-- changes the id. Updates need to cascade to all the tables referencing it
+ALTER TABLE fluid_meter
+  DROP CONSTRAINT fk_owner_id,
+  ADD CONSTRAINT fk_owner_id FOREIGN KEY(owner_id) REFERENCES account(id) ON UPDATE CASCADE;
+ALTER TABLE session_token
+  DROP CONSTRAINT fk_account_id,
+  ADD CONSTRAINT fk_account_id FOREIGN KEY(account_id) REFERENCES account(id) ON UPDATE CASCADE;
+ALTER TABLE email_verification
+  DROP CONSTRAINT fk_account_id,
+  ADD CONSTRAINT fk_account_id FOREIGN KEY(account_id) REFERENCES account(id) ON UPDATE CASCADE;
+ALTER TABLE password_recovery
+  DROP CONSTRAINT fk_account_id,
+  ADD CONSTRAINT fk_account_id FOREIGN KEY(account_id) REFERENCES account(id) ON UPDATE CASCADE;
+ALTER TABLE two_factor
+  DROP CONSTRAINT fk_account_id,
+  ADD CONSTRAINT fk_account_id FOREIGN KEY(account_id) REFERENCES account(id) ON UPDATE CASCADE;
+ALTER TABLE two_factor_recovery_code
+  DROP CONSTRAINT fk_account_id,
+  ADD CONSTRAINT fk_account_id FOREIGN KEY(account_id) REFERENCES account(id) ON UPDATE CASCADE;
+ALTER TABLE two_factor_challenge
+  DROP CONSTRAINT fk_account_id,
+  ADD CONSTRAINT fk_account_id FOREIGN KEY(account_id) REFERENCES account(id) ON UPDATE CASCADE;
+
+CREATE TABLE email_change (
+  token VARCHAR(100) NOT NULL,
+  account_id VARCHAR(255) NOT NULL,
+  email VARCHAR(255) NOT NULL,
+  expires_at TIMESTAMP NOT NULL,
+  recorded_at TIMESTAMP NOT NULL,
+  PRIMARY KEY(token),
+  CONSTRAINT fk_account_id FOREIGN KEY(account_id) REFERENCES account(id) ON UPDATE CASCADE
+);
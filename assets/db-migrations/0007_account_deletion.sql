@@ -0,0 +1,33 @@
+-- Accounts are deleted after a grace period. Until then, logging in cancels
+-- the deletion
+ALTER TABLE account ADD COLUMN deletion_scheduled_at TIMESTAMP;
+CREATE INDEX idx_account_deletion_scheduled_at ON account(deletion_scheduled_at);
+
+-- Deleting an account deletes everything that belongs to it
+ALTER TABLE fluid_meter
+  DROP CONSTRAINT fk_owner_id,
+  ADD CONSTRAINT fk_owner_id FOREIGN KEY(owner_id) REFERENCES account(id) ON UPDATE CASCADE ON DELETE CASCADE;
+ALTER TABLE measurement
+  DROP CONSTRAINT fk_device_id,
+  ADD CONSTRAINT fk_device_id FOREIGN KEY(device_id) REFERENCES fluid_meter(id) ON DELETE CASCADE;
+ALTER TABLE session_token
+  DROP CONSTRAINT fk_account_id,
+  ADD CONSTRAINT fk_account_id FOREIGN KEY(account_id) REFERENCES account(id) ON UPDATE CASCADE ON DELETE CASCADE;
+ALTER TABLE email_verification
+  DROP CONSTRAINT fk_account_id,
+  ADD CONSTRAINT fk_account_id FOREIGN KEY(account_id) REFERENCES account(id) ON UPDATE CASCADE ON DELETE CASCADE;
+ALTER TABLE password_recovery
+  DROP CONSTRAINT fk_account_id,
+  ADD CONSTRAINT fk_account_id FOREIGN KEY(account_id) REFERENCES account(id) ON UPDATE CASCADE ON DELETE CASCADE;
+ALTER TABLE two_factor
+  DROP CONSTRAINT fk_account_id,
+  ADD CONSTRAINT fk_account_id FOREIGN KEY(account_id) REFERENCES account(id) ON UPDATE CASCADE ON DELETE CASCADE;
+ALTER TABLE two_factor_recovery_code
+  DROP CONSTRAINT fk_account_id,
+  ADD CONSTRAINT fk_account_id FOREIGN KEY(account_id) REFERENCES account(id) ON UPDATE CASCADE ON DELETE CASCADE;
+ALTER TABLE two_factor_challenge
+  DROP CONSTRAINT fk_account_id,
+  ADD CONSTRAINT fk_account_id FOREIGN KEY(account_id) REFERENCES account(id) ON UPDATE CASCADE ON DELETE CASCADE;
+ALTER TABLE email_change
+  DROP CONSTRAINT fk_account_id,
+  ADD CONSTRAINT fk_account_id FOREIGN KEY(account_id) REFERENCES account(id) ON UPDATE CASCADE ON DELETE CASCADE;
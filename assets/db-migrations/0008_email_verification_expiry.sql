@@ -0,0 +1,3 @@
+ALTER TABLE email_verification ADD COLUMN expires_at TIMESTAMP;
+UPDATE email_verification SET expires_at = recorded_at + INTERVAL '24 hours';
+ALTER TABLE email_verification ALTER COLUMN expires_at SET NOT NULL;
@@ -0,0 +1,28 @@
+-- Accounts other than the owner that have access to a fluid meter
+CREATE TABLE fluid_meter_member (
+  fluid_meter_id VARCHAR(255) NOT NULL,
+  account_id VARCHAR(255) NOT NULL,
+  role VARCHAR(30) NOT NULL,
+  -- If the member wants to receive the alerts for the meter
+  alerts BOOLEAN NOT NULL DEFAULT FALSE,
+  recorded_at TIMESTAMP NOT NULL,
+  PRIMARY KEY(fluid_meter_id, account_id),
+  CONSTRAINT fk_fluid_meter_id FOREIGN KEY(fluid_meter_id) REFERENCES fluid_meter(id) ON DELETE CASCADE,
+  CONSTRAINT fk_account_id FOREIGN KEY(account_id) REFERENCES account(id) ON UPDATE CASCADE ON DELETE CASCADE
+);
+CREATE INDEX idx_fluid_meter_member_account_id ON fluid_meter_member(account_id);
+
+-- Invites are sent by e-mail, so the account might not exist yet
+CREATE TABLE fluid_meter_invite (
+  token VARCHAR(100) NOT NULL,
+  fluid_meter_id VARCHAR(255) NOT NULL,
+  email VARCHAR(255) NOT NULL,
+  role VARCHAR(30) NOT NULL,
+  invited_by VARCHAR(255) NOT NULL,
+  expires_at TIMESTAMP NOT NULL,
+  recorded_at TIMESTAMP NOT NULL,
+  PRIMARY KEY(token),
+  CONSTRAINT fk_fluid_meter_id FOREIGN KEY(fluid_meter_id) REFERENCES fluid_meter(id) ON DELETE CASCADE,
+  CONSTRAINT fk_invited_by FOREIGN KEY(invited_by) REFERENCES account(id) ON UPDATE CASCADE ON DELETE CASCADE
+);
+CREATE INDEX idx_fluid_meter_invite_fluid_meter_id ON fluid_meter_invite(fluid_meter_id);
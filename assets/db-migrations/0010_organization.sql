@@ -0,0 +1,25 @@
+CREATE TABLE organization (
+  id VARCHAR(255) NOT NULL,
+  name VARCHAR(255) NOT NULL,
+  recorded_at TIMESTAMP NOT NULL,
+  PRIMARY KEY(id)
+);
+
+CREATE TABLE organization_member (
+  organization_id VARCHAR(255) NOT NULL,
+  account_id VARCHAR(255) NOT NULL,
+  role VARCHAR(30) NOT NULL,
+  recorded_at TIMESTAMP NOT NULL,
+  PRIMARY KEY(organization_id, account_id),
+  CONSTRAINT fk_organization_id FOREIGN KEY(organization_id) REFERENCES organization(id) ON DELETE CASCADE,
+  CONSTRAINT fk_account_id FOREIGN KEY(account_id) REFERENCES account(id) ON UPDATE CASCADE ON DELETE CASCADE
+);
+CREATE INDEX idx_organization_member_account_id ON organization_member(account_id);
+
+-- Meters are owned by either an account or an organization
+ALTER TABLE fluid_meter ALTER COLUMN owner_id DROP NOT NULL;
+ALTER TABLE fluid_meter ADD COLUMN organization_id VARCHAR(255);
+ALTER TABLE fluid_meter
+  ADD CONSTRAINT fk_organization_id FOREIGN KEY(organization_id) REFERENCES organization(id) ON DELETE CASCADE,
+  ADD CONSTRAINT chk_single_owner CHECK ((owner_id IS NULL) != (organization_id IS NULL));
+CREATE INDEX idx_fluid_meter_organization_id ON fluid_meter(organization_id);
@@ -0,0 +1,24 @@
+CREATE TABLE site (
+  id VARCHAR(255) NOT NULL,
+  owner_id VARCHAR(255),
+  organization_id VARCHAR(255),
+  name VARCHAR(255) NOT NULL,
+  address VARCHAR(1024) NOT NULL,
+  latitude DOUBLE PRECISION,
+  longitude DOUBLE PRECISION,
+  tags TEXT[] NOT NULL DEFAULT '{}',
+  recorded_at TIMESTAMP NOT NULL,
+  PRIMARY KEY(id),
+  CONSTRAINT fk_owner_id FOREIGN KEY(owner_id) REFERENCES account(id) ON UPDATE CASCADE ON DELETE CASCADE,
+  CONSTRAINT fk_organization_id FOREIGN KEY(organization_id) REFERENCES organization(id) ON DELETE CASCADE,
+  -- Sites are owned by either an account or an organization
+  CONSTRAINT chk_single_owner CHECK ((owner_id IS NULL) != (organization_id IS NULL))
+);
+CREATE INDEX idx_site_owner_id ON site(owner_id);
+CREATE INDEX idx_site_organization_id ON site(organization_id);
+CREATE INDEX idx_site_tags ON site USING GIN(tags);
+
+ALTER TABLE fluid_meter ADD COLUMN site_id VARCHAR(255);
+ALTER TABLE fluid_meter
+  ADD CONSTRAINT fk_site_id FOREIGN KEY(site_id) REFERENCES site(id) ON DELETE SET NULL;
+CREATE INDEX idx_fluid_meter_site_id ON fluid_meter(site_id);
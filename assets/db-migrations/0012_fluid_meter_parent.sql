@@ -0,0 +1,5 @@
+-- Sub-meters (e.g. apartments) point to the main meter of the building
+ALTER TABLE fluid_meter ADD COLUMN parent_id VARCHAR(255);
+ALTER TABLE fluid_meter
+  ADD CONSTRAINT fk_parent_id FOREIGN KEY(parent_id) REFERENCES fluid_meter(id) ON DELETE SET NULL;
+CREATE INDEX idx_fluid_meter_parent_id ON fluid_meter(parent_id);
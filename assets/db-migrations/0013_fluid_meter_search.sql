@@ -0,0 +1,4 @@
+-- Used to find the last measurement of a meter when filtering and sorting
+CREATE INDEX idx_measurement_device_id_recorded_at ON measurement(device_id, recorded_at);
+
+CREATE INDEX idx_fluid_meter_recorded_at ON fluid_meter(recorded_at);
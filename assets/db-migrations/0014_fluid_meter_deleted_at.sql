@@ -0,0 +1,5 @@
+-- Deleted meters can be restored during a grace period. After that, they are
+-- purged with all their measurements
+ALTER TABLE fluid_meter ADD COLUMN deleted_at TIMESTAMP;
+UPDATE fluid_meter SET deleted_at = updated_at WHERE status = 'deleted';
+CREATE INDEX idx_fluid_meter_deleted_at ON fluid_meter(deleted_at);
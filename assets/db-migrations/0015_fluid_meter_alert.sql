@@ -0,0 +1,9 @@
+-- Alerts that were found the last time the alerts job checked the meter. They
+-- are removed once the job doesn't find them anymore
+CREATE TABLE fluid_meter_alert (
+  fluid_meter_id VARCHAR(255) NOT NULL,
+  alert_type VARCHAR(50) NOT NULL,
+  recorded_at TIMESTAMP NOT NULL,
+  PRIMARY KEY(fluid_meter_id, alert_type),
+  CONSTRAINT fk_fluid_meter_id FOREIGN KEY(fluid_meter_id) REFERENCES fluid_meter(id) ON DELETE CASCADE
+);
@@ -0,0 +1,12 @@
+-- Devices register themselves when they are manufactured. Users bind them to
+-- their account with the claim code, which creates the fluid meter the device
+-- reports to
+CREATE TABLE device (
+  serial VARCHAR(100) PRIMARY KEY,
+  secret_hash VARCHAR(64) NOT NULL,
+  claim_code VARCHAR(20) UNIQUE,
+  fluid_meter_id VARCHAR(255) UNIQUE,
+  recorded_at TIMESTAMP NOT NULL,
+  claimed_at TIMESTAMP,
+  CONSTRAINT fk_fluid_meter_id FOREIGN KEY(fluid_meter_id) REFERENCES fluid_meter(id) ON DELETE CASCADE
+);
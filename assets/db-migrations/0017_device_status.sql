@@ -0,0 +1,10 @@
+-- Latest telemetry sent by the device of each meter
+CREATE TABLE device_status (
+  fluid_meter_id VARCHAR(255) PRIMARY KEY,
+  battery_voltage DOUBLE PRECISION,
+  rssi INTEGER,
+  firmware_version VARCHAR(50),
+  uptime_secs BIGINT,
+  updated_at TIMESTAMP NOT NULL,
+  CONSTRAINT fk_fluid_meter_id FOREIGN KEY(fluid_meter_id) REFERENCES fluid_meter(id) ON DELETE CASCADE
+);
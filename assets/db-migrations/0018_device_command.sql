@@ -0,0 +1,18 @@
+-- Commands queued for the device of a meter. Devices receive them until they
+-- acknowledge them
+CREATE TABLE device_command (
+  id VARCHAR(255) PRIMARY KEY,
+  fluid_meter_id VARCHAR(255) NOT NULL,
+  command_type VARCHAR(50) NOT NULL,
+  value BIGINT,
+  status VARCHAR(20) NOT NULL,
+  -- Empty for commands created by the system
+  created_by VARCHAR(255),
+  result VARCHAR(255),
+  recorded_at TIMESTAMP NOT NULL,
+  sent_at TIMESTAMP,
+  acknowledged_at TIMESTAMP,
+  CONSTRAINT fk_fluid_meter_id FOREIGN KEY(fluid_meter_id) REFERENCES fluid_meter(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_device_command_fluid_meter_id_status ON device_command(fluid_meter_id, status);
@@ -0,0 +1,30 @@
+-- Motorized valves installed next to a meter. They are controlled with the
+-- commands queue of the meter's device
+CREATE TABLE valve (
+  fluid_meter_id VARCHAR(255) PRIMARY KEY,
+  -- Last state confirmed by the device
+  state VARCHAR(20) NOT NULL,
+  -- State of the last command sent to the valve
+  requested_state VARCHAR(20) NOT NULL,
+  -- Close the valve when constant flow is detected
+  auto_shut_off BOOLEAN NOT NULL,
+  recorded_at TIMESTAMP NOT NULL,
+  updated_at TIMESTAMP NOT NULL,
+  CONSTRAINT fk_fluid_meter_id FOREIGN KEY(fluid_meter_id) REFERENCES fluid_meter(id) ON DELETE CASCADE
+);
+
+-- Every time a valve was asked to open or close
+CREATE TABLE valve_action (
+  id VARCHAR(255) PRIMARY KEY,
+  fluid_meter_id VARCHAR(255) NOT NULL,
+  target_state VARCHAR(20) NOT NULL,
+  origin VARCHAR(20) NOT NULL,
+  -- Empty for automatic actions
+  account_id VARCHAR(255),
+  command_id VARCHAR(255) NOT NULL,
+  recorded_at TIMESTAMP NOT NULL,
+  CONSTRAINT fk_fluid_meter_id FOREIGN KEY(fluid_meter_id) REFERENCES valve(fluid_meter_id) ON DELETE CASCADE,
+  CONSTRAINT fk_command_id FOREIGN KEY(command_id) REFERENCES device_command(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_valve_action_fluid_meter_id_recorded_at ON valve_action(fluid_meter_id, recorded_at);
@@ -0,0 +1,30 @@
+-- Firmware that can be installed over the air. The binary is kept in the file
+-- store, so a release can't be offered until it's uploaded
+CREATE TABLE firmware_release (
+  id VARCHAR(255) PRIMARY KEY,
+  hardware_model VARCHAR(100) NOT NULL,
+  version VARCHAR(50) NOT NULL,
+  -- Devices running one of these versions get the release. Empty for all
+  target_versions TEXT[] NOT NULL DEFAULT '{}',
+  rollout_percentage SMALLINT NOT NULL,
+  size_bytes BIGINT,
+  sha256 VARCHAR(64),
+  created_by VARCHAR(255) NOT NULL,
+  recorded_at TIMESTAMP NOT NULL,
+  uploaded_at TIMESTAMP,
+  UNIQUE(hardware_model, version)
+);
+
+-- Result of installing a release, reported by the device. Devices don't get a
+-- release again after it failed
+CREATE TABLE firmware_update (
+  release_id VARCHAR(255) NOT NULL,
+  serial VARCHAR(100) NOT NULL,
+  from_version VARCHAR(50) NOT NULL,
+  status VARCHAR(20) NOT NULL,
+  result VARCHAR(255),
+  recorded_at TIMESTAMP NOT NULL,
+  PRIMARY KEY(release_id, serial),
+  CONSTRAINT fk_release_id FOREIGN KEY(release_id) REFERENCES firmware_release(id) ON DELETE CASCADE,
+  CONSTRAINT fk_serial FOREIGN KEY(serial) REFERENCES device(serial) ON DELETE CASCADE
+);
@@ -0,0 +1,10 @@
+-- Minimum seconds between measurements of the meter. NULL uses the default
+ALTER TABLE fluid_meter ADD COLUMN reporting_interval_secs BIGINT;
+
+-- Token bucket used to rate limit the measurements of each meter
+CREATE TABLE reporting_bucket (
+  fluid_meter_id VARCHAR(255) PRIMARY KEY,
+  tokens DOUBLE PRECISION NOT NULL,
+  updated_at TIMESTAMP NOT NULL,
+  CONSTRAINT fk_fluid_meter_id FOREIGN KEY(fluid_meter_id) REFERENCES fluid_meter(id) ON DELETE CASCADE
+);
@@ -0,0 +1,7 @@
+-- Token buckets used by the rate limiting middleware when they are shared
+-- between instances
+CREATE TABLE rate_limit_bucket (
+  key VARCHAR(512) PRIMARY KEY,
+  tokens DOUBLE PRECISION NOT NULL,
+  updated_at TIMESTAMP NOT NULL
+);
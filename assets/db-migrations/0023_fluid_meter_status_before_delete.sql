@@ -0,0 +1,4 @@
+-- Status the meter had before it was deleted, so restoring it brings that status
+-- back. Meters deleted before this was tracked are restored as active
+ALTER TABLE fluid_meter ADD COLUMN status_before_delete VARCHAR(255);
+UPDATE fluid_meter SET status_before_delete = 'active' WHERE status = 'deleted';
@@ -0,0 +1,6 @@
+-- Commands keep who created them when the account changes its e-mail (which
+-- changes its id), and are kept without it when the account is deleted
+UPDATE device_command SET created_by = NULL
+WHERE created_by IS NOT NULL AND NOT EXISTS (SELECT 1 FROM account WHERE id = created_by);
+ALTER TABLE device_command
+  ADD CONSTRAINT fk_created_by FOREIGN KEY(created_by) REFERENCES account(id) ON UPDATE CASCADE ON DELETE SET NULL;
@@ -0,0 +1,6 @@
+-- Actions keep who requested them when the account changes its e-mail (which
+-- changes its id), and are kept without it when the account is deleted
+UPDATE valve_action SET account_id = NULL
+WHERE account_id IS NOT NULL AND NOT EXISTS (SELECT 1 FROM account WHERE id = account_id);
+ALTER TABLE valve_action
+  ADD CONSTRAINT fk_account_id FOREIGN KEY(account_id) REFERENCES account(id) ON UPDATE CASCADE ON DELETE SET NULL;
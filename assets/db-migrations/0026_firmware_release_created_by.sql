@@ -0,0 +1,7 @@
+-- Releases keep who created them when the account changes its e-mail (which
+-- changes its id), and are kept without it when the account is deleted
+ALTER TABLE firmware_release ALTER COLUMN created_by DROP NOT NULL;
+UPDATE firmware_release SET created_by = NULL
+WHERE created_by IS NOT NULL AND NOT EXISTS (SELECT 1 FROM account WHERE id = created_by);
+ALTER TABLE firmware_release
+  ADD CONSTRAINT fk_created_by FOREIGN KEY(created_by) REFERENCES account(id) ON UPDATE CASCADE ON DELETE SET NULL;
@@ -0,0 +1,5 @@
+-- Devices are provisioned at the factory, so they are kept when their meter is
+-- deleted and can be claimed again
+ALTER TABLE device
+  DROP CONSTRAINT fk_fluid_meter_id,
+  ADD CONSTRAINT fk_fluid_meter_id FOREIGN KEY(fluid_meter_id) REFERENCES fluid_meter(id) ON DELETE SET NULL;
@@ -0,0 +1,5 @@
+-- Failed codes are counted per account, so asking for new challenges doesn't
+-- give more guesses. Too many failures lock two factor log-in for a while
+ALTER TABLE two_factor
+  ADD COLUMN failed_attempts INTEGER NOT NULL DEFAULT 0,
+  ADD COLUMN locked_until TIMESTAMP;
@@ -0,0 +1,5 @@
+-- Start of the grace period before a meter is expected to report. Kept apart
+-- from updated_at, so editing a meter doesn't silence its alerts
+ALTER TABLE fluid_meter ADD COLUMN activated_at TIMESTAMP;
+UPDATE fluid_meter SET activated_at = updated_at;
+ALTER TABLE fluid_meter ALTER COLUMN activated_at SET NOT NULL;
@@ -1,8 +1,16 @@
 pub mod alert;
 pub mod common;
+pub mod device;
 pub mod email_verification;
+pub mod firmware;
 pub mod fluid_meter;
+pub mod fluid_meter_member;
 pub mod health;
 pub mod measurement;
 pub mod metadata;
+pub mod organization;
+pub mod purge;
+pub mod site;
+pub mod two_factor;
 pub mod user;
+pub mod valve;
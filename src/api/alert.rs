@@ -4,7 +4,13 @@ use std::fmt;
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub enum AlertType {
     ConstantFlow,
+    // The battery of the device is about to run out
+    LowBattery,
     NotReporting,
+    // The parent meter measured a lot more than its sub-meters
+    UnaccountedConsumption,
+    // The device might start losing measurements
+    WeakSignal,
 }
 
 impl fmt::Display for AlertType {
@@ -14,7 +20,10 @@ impl fmt::Display for AlertType {
             "{}",
             match self {
                 AlertType::ConstantFlow => "ConstantFlow",
+                AlertType::LowBattery => "LowBattery",
                 AlertType::NotReporting => "NotReporting",
+                AlertType::UnaccountedConsumption => "UnaccountedConsumption",
+                AlertType::WeakSignal => "WeakSignal",
             }
         )
     }
@@ -1,5 +1,6 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use chrono::NaiveDateTime;
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
 pub const DEFAULT_PAGE_SIZE: &'static u8 = &25;
 pub const MAX_PAGE_SIZE: &'static u8 = &100;
@@ -10,18 +11,83 @@ pub enum SortDirection {
     Desc,
 }
 
+// Position of an item in a sorted list. Clients get it as an opaque string and
+// send it back to get the next (or previous) page
+#[derive(Clone, Debug, PartialEq)]
+pub struct PageCursor {
+    // Name of the sort the cursor was created for
+    pub sort: String,
+    // Value the list is sorted by, for the item the cursor points to
+    pub key: String,
+    // Used to break ties when several items have the same key
+    pub id: String,
+    // If true, the items before the cursor are requested instead of the ones
+    // after it
+    pub backward: bool,
+}
+
+impl PageCursor {
+    pub fn encode(&self) -> String {
+        let raw = serde_json::json!([self.sort, self.key, self.id, self.backward]);
+        return URL_SAFE_NO_PAD.encode(raw.to_string());
+    }
+
+    pub fn decode(encoded: &str) -> Option<PageCursor> {
+        let raw = URL_SAFE_NO_PAD.decode(encoded).ok()?;
+        let (sort, key, id, backward) = serde_json::from_slice(&raw).ok()?;
+        return Some(PageCursor {
+            sort,
+            key,
+            id,
+            backward,
+        });
+    }
+}
+
+impl Serialize for PageCursor {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.encode())
+    }
+}
+
+impl<'de> Deserialize<'de> for PageCursor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        PageCursor::decode(&encoded).ok_or(de::Error::custom("invalid page cursor"))
+    }
+}
+
+// Type of the value a list is sorted by. Cursor keys need to be valid values
+// of it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SortKeyType {
+    Text,
+    Timestamp,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct PaginatedRequest {
     // Max number of itmes to return
     pub page_size: u8,
     // Only return items after this one (This one is not included)
-    pub page_cursor: Option<String>,
+    pub page_cursor: Option<PageCursor>,
+}
+
+// Query string of list endpoints that don't take any other options
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PageInput {
+    pub page_size: Option<u8>,
+    pub page_cursor: Option<PageCursor>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Pagination {
     pub has_more: bool,
     pub has_less: bool,
+    // Set when has_more is true
+    pub next_cursor: Option<PageCursor>,
+    // Set when has_less is true
+    pub previous_cursor: Option<PageCursor>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
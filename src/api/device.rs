@@ -0,0 +1,146 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::api::fluid_meter::CreateFluidMeterInput;
+
+// Commands are listed by creation time, newest first. Page cursors are tied to
+// this sort
+pub const DEVICE_COMMANDS_SORT: &'static str = "RecordedAt";
+
+#[derive(Clone, Debug, Deserialize, Serialize, sqlx::FromRow)]
+pub struct Device {
+    // Hardware serial, set at the factory
+    pub serial: String,
+    pub secret_hash: String,
+    // Cleared once the device is claimed
+    pub claim_code: Option<String>,
+    // The meter the device reports to. Only set after the device is claimed
+    pub fluid_meter_id: Option<String>,
+    pub recorded_at: NaiveDateTime,
+    pub claimed_at: Option<NaiveDateTime>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct RegisterDeviceInput {
+    pub serial: String,
+}
+
+/// The secret is only returned once. The device needs it to check-in and the
+/// claim code is printed on the device so the user can claim it
+#[derive(Clone, Deserialize, Serialize)]
+pub struct RegisterDeviceResponse {
+    pub serial: String,
+    pub secret: String,
+    pub claim_code: String,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct DeviceCheckInInput {
+    pub serial: String,
+    pub secret: String,
+}
+
+/// device_id is the id the device uses to send measurements. It's empty until
+/// a user claims the device
+#[derive(Clone, Deserialize, Serialize)]
+pub struct DeviceCheckInResponse {
+    pub claimed: bool,
+    pub device_id: Option<String>,
+    // Commands the device hasn't acknowledged yet
+    pub commands: Vec<DeviceCommand>,
+    // Minimum seconds between measurements
+    pub reporting_interval_secs: i64,
+}
+
+/// Claims a device and creates a meter for it with the given information
+#[derive(Clone, Deserialize, Serialize)]
+pub struct ClaimFluidMeterInput {
+    pub claim_code: String,
+    #[serde(flatten)]
+    pub meter: CreateFluidMeterInput,
+}
+
+/// Optional information about the device sent together with measurements
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct DeviceTelemetry {
+    pub battery_voltage: Option<f64>,
+    // Signal strength in dBm
+    pub rssi: Option<i32>,
+    pub firmware_version: Option<String>,
+    pub uptime_secs: Option<i64>,
+}
+
+/// The latest telemetry of the device reporting to a meter. Values the device
+/// didn't send keep the last known value
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, sqlx::FromRow)]
+pub struct DeviceStatus {
+    pub fluid_meter_id: String,
+    pub battery_voltage: Option<f64>,
+    pub rssi: Option<i32>,
+    pub firmware_version: Option<String>,
+    pub uptime_secs: Option<i64>,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "VARCHAR")] // Store as a string in the DB
+pub enum DeviceCommandType {
+    #[sqlx(rename = "close_valve")]
+    CloseValve,
+    #[sqlx(rename = "open_valve")]
+    OpenValve,
+    #[sqlx(rename = "reboot")]
+    Reboot,
+    // The value of the command is the number of seconds between reports
+    #[sqlx(rename = "set_reporting_interval")]
+    SetReportingInterval,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "VARCHAR")] // Store as a string in the DB
+pub enum DeviceCommandStatus {
+    // The device hasn't received the command yet
+    #[sqlx(rename = "pending")]
+    Pending,
+    // The device received the command, but hasn't acknowledged it. It will
+    // receive it again until it does
+    #[sqlx(rename = "sent")]
+    Sent,
+    #[sqlx(rename = "acknowledged")]
+    Acknowledged,
+    // The device received the command, but couldn't execute it
+    #[sqlx(rename = "failed")]
+    Failed,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, sqlx::FromRow)]
+pub struct DeviceCommand {
+    pub id: String,
+    pub fluid_meter_id: String,
+    pub command_type: DeviceCommandType,
+    pub value: Option<i64>,
+    pub status: DeviceCommandStatus,
+    // Empty for commands created by the system
+    pub created_by: Option<String>,
+    // Sent by the device when it acknowledges the command
+    pub result: Option<String>,
+    pub recorded_at: NaiveDateTime,
+    pub sent_at: Option<NaiveDateTime>,
+    pub acknowledged_at: Option<NaiveDateTime>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct CreateDeviceCommandInput {
+    pub command_type: DeviceCommandType,
+    pub value: Option<i64>,
+}
+
+/// Sent by the device after executing a command
+#[derive(Clone, Deserialize, Serialize)]
+pub struct AcknowledgeDeviceCommandInput {
+    pub serial: String,
+    pub secret: String,
+    pub command_id: String,
+    pub success: bool,
+    pub result: Option<String>,
+}
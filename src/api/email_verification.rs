@@ -5,6 +5,7 @@ use serde::{Deserialize, Serialize};
 pub struct EmailVerification {
     pub token: String,
     pub account_id: String,
+    pub expires_at: NaiveDateTime,
     pub recorded_at: NaiveDateTime,
 }
 
@@ -12,3 +13,11 @@ pub struct EmailVerification {
 pub struct EmailVerificationInput {
     pub token: String,
 }
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct ResendEmailVerificationInput {
+    pub email: String,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct ResendEmailVerificationResponse {}
@@ -0,0 +1,93 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+// Releases and update reports are listed by creation time, newest first. Page
+// cursors are tied to this sort
+pub const FIRMWARE_RELEASES_SORT: &'static str = "RecordedAt";
+pub const FIRMWARE_UPDATES_SORT: &'static str = "RecordedAt";
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, sqlx::FromRow)]
+pub struct FirmwareRelease {
+    pub id: String,
+    pub hardware_model: String,
+    pub version: String,
+    // Devices running one of these versions get the release. Empty for all
+    pub target_versions: Vec<String>,
+    // Percentage of the targeted devices that get the release
+    pub rollout_percentage: i16,
+    // Set once the binary is uploaded
+    pub size_bytes: Option<i64>,
+    pub sha256: Option<String>,
+    // Empty if the account that created it was deleted
+    pub created_by: Option<String>,
+    pub recorded_at: NaiveDateTime,
+    pub uploaded_at: Option<NaiveDateTime>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct CreateFirmwareReleaseInput {
+    pub hardware_model: String,
+    pub version: String,
+    pub target_versions: Option<Vec<String>>,
+    pub rollout_percentage: i16,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct SetFirmwareRolloutInput {
+    pub rollout_percentage: i16,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct FirmwareCheckInput {
+    pub serial: String,
+    pub secret: String,
+    pub hardware_model: String,
+    pub firmware_version: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FirmwareCheckResponse {
+    // Empty if the device is up to date
+    pub release: Option<FirmwareRelease>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct FirmwareDownloadInput {
+    pub serial: String,
+    pub secret: String,
+    // Same as in the check. Only releases offered to the device can be
+    // downloaded
+    pub hardware_model: String,
+    pub firmware_version: String,
+    pub release_id: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "VARCHAR")] // Store as a string in the DB
+pub enum FirmwareUpdateStatus {
+    #[sqlx(rename = "succeeded")]
+    Succeeded,
+    #[sqlx(rename = "failed")]
+    Failed,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, sqlx::FromRow)]
+pub struct FirmwareUpdate {
+    pub release_id: String,
+    pub serial: String,
+    pub from_version: String,
+    pub status: FirmwareUpdateStatus,
+    // Sent by the device. Usually the reason of a failure
+    pub result: Option<String>,
+    pub recorded_at: NaiveDateTime,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct FirmwareUpdateReportInput {
+    pub serial: String,
+    pub secret: String,
+    pub release_id: String,
+    pub from_version: String,
+    pub success: bool,
+    pub result: Option<String>,
+}
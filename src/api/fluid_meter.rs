@@ -1,9 +1,17 @@
-use crate::api::{alert::Alert, common::SortDirection};
+use crate::api::{
+    alert::Alert,
+    common::{PageCursor, SeriesGranularity, SortDirection, SortKeyType},
+    device::DeviceStatus,
+};
 
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
+// Deleted meters are listed by deletion time, newest first. Page cursors are
+// tied to this sort
+pub const DELETED_FLUID_METERS_SORT: &'static str = "DeletedAt";
+
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, sqlx::Type)]
 #[sqlx(type_name = "VARCHAR")] // Store as a string in the DB
 pub enum FluidMeterStatus {
@@ -31,16 +39,77 @@ impl fmt::Display for FluidMeterStatus {
 pub enum FluidMetersSort {
     Id,
     Name,
+    RecordedAt,
+    // Meters that never reported are sorted as the oldest
+    LastMeasurement,
+}
+
+impl fmt::Display for FluidMetersSort {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FluidMetersSort::Id => write!(f, "Id"),
+            FluidMetersSort::Name => write!(f, "Name"),
+            FluidMetersSort::RecordedAt => write!(f, "RecordedAt"),
+            FluidMetersSort::LastMeasurement => write!(f, "LastMeasurement"),
+        }
+    }
+}
+
+impl FluidMetersSort {
+    pub fn key_type(&self) -> SortKeyType {
+        match self {
+            FluidMetersSort::Id | FluidMetersSort::Name => SortKeyType::Text,
+            FluidMetersSort::RecordedAt | FluidMetersSort::LastMeasurement => {
+                SortKeyType::Timestamp
+            }
+        }
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, sqlx::FromRow)]
 pub struct FluidMeter {
     pub id: String,
-    pub owner_id: String,
+    // Meters are owned by either an account or an organization
+    pub owner_id: Option<String>,
+    pub organization_id: Option<String>,
+    pub site_id: Option<String>,
+    // The meter this one is a sub-meter of
+    pub parent_id: Option<String>,
     pub name: String,
     pub status: FluidMeterStatus,
     pub recorded_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
+    // Deleted meters can be restored for a while, before they are purged
+    pub deleted_at: Option<NaiveDateTime>,
+    // Minimum seconds between measurements. Empty to use the default
+    pub reporting_interval_secs: Option<i64>,
+    // When the meter was created, activated or restored. Meters aren't
+    // expected to report until a while after it
+    pub activated_at: NaiveDateTime,
+}
+
+// A meter with information about how its device is doing. Computed when the
+// meter is read
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, sqlx::FromRow)]
+pub struct FluidMeterSummary {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub meter: FluidMeter,
+    pub last_measurement_at: Option<NaiveDateTime>,
+    pub last_value: Option<String>,
+    // Number of measurements received in the last 24 hours
+    pub readings_last_24h: i64,
+    // Alerts found the last time the meter was checked. Only active meters
+    // have open alerts
+    pub open_alerts: i64,
+}
+
+/// The summary of a meter together with the latest telemetry of its device
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct FluidMeterDetail {
+    #[serde(flatten)]
+    pub summary: FluidMeterSummary,
+    pub device_status: Option<DeviceStatus>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -48,14 +117,86 @@ pub struct FluidMetersInput {
     pub sort: Option<FluidMetersSort>,
     pub sort_direction: Option<SortDirection>,
     pub status: Option<FluidMeterStatus>,
-    // Will retrieve only items after this one
-    pub page_cursor: Option<String>,
+    // Only return meters owned by this organization
+    pub organization_id: Option<String>,
+    // Only return meters assigned to this site
+    pub site_id: Option<String>,
+    // Only return meters assigned to a site with this tag
+    pub tag: Option<String>,
+    // Only return meters whose name contains this text (case-insensitive)
+    pub q: Option<String>,
+    // Only return meters created in this range
+    pub created_from: Option<NaiveDateTime>,
+    pub created_to: Option<NaiveDateTime>,
+    // Only return meters whose last measurement is in this range
+    pub reported_from: Option<NaiveDateTime>,
+    pub reported_to: Option<NaiveDateTime>,
+    // Only valid for the sort it was created for
+    pub page_cursor: Option<PageCursor>,
     pub page_size: Option<u8>,
 }
 
+impl FluidMetersInput {
+    /// Name of the sort page cursors are created for. Includes the direction,
+    /// so a cursor can't be used to read the list in the opposite one
+    pub fn cursor_sort(&self) -> String {
+        let sort = self.sort.clone().unwrap_or(FluidMetersSort::Id);
+        match self.sort_direction {
+            Some(SortDirection::Desc) => return format!("{}:Desc", sort),
+            _ => return format!("{}:Asc", sort),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct CreateFluidMeterInput {
     pub name: String,
+    // If set, the meter is owned by the organization instead of the user
+    pub organization_id: Option<String>,
+    pub site_id: Option<String>,
+}
+
+// Fields that aren't set are left as they are. Meters are removed from their
+// site and moved under a parent with their own endpoints
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UpdateFluidMeterInput {
+    pub name: Option<String>,
+    // The user needs to be an admin of the site
+    pub site_id: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SetReportingIntervalInput {
+    // None goes back to the default interval
+    pub reporting_interval_secs: Option<i64>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SetFluidMeterParentInput {
+    // None makes the meter a top-level meter again
+    pub parent_id: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct ReconciliationItem {
+    pub period_start: NaiveDateTime,
+    // Consumption measured by the parent meter
+    pub parent: String,
+    // Consumption measured by all the sub-meters together
+    pub children: String,
+    // Parent minus children. Usually common-area use or leaks. A negative
+    // value means the meters don't agree with each other
+    pub unaccounted: String,
+    pub exceeds_threshold: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Reconciliation {
+    pub granularity: SeriesGranularity,
+    // Fraction of the parent consumption that can be unaccounted before it's
+    // considered a problem
+    pub threshold: f64,
+    pub items: Vec<ReconciliationItem>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
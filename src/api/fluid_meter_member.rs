@@ -0,0 +1,81 @@
+use crate::api::common::PaginatedResponse;
+
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// Members are listed oldest first. Page cursors are tied to this sort
+pub const FLUID_METER_MEMBERS_SORT: &'static str = "RecordedAt";
+
+// Roles are sorted from less to more permissions, so they can be compared
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, PartialOrd, sqlx::Type)]
+#[sqlx(type_name = "VARCHAR")] // Store as a string in the DB
+pub enum FluidMeterRole {
+    // Can see the meter, its measurements and alerts
+    #[sqlx(rename = "viewer")]
+    Viewer,
+    // Can also change the status of the meter and manage its members
+    #[sqlx(rename = "manager")]
+    Manager,
+    // Not stored in the members table. The owner of the meter can do anything
+    #[sqlx(rename = "owner")]
+    Owner,
+}
+
+impl fmt::Display for FluidMeterRole {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FluidMeterRole::Viewer => write!(f, "viewer"),
+            FluidMeterRole::Manager => write!(f, "manager"),
+            FluidMeterRole::Owner => write!(f, "owner"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, sqlx::FromRow)]
+pub struct FluidMeterMember {
+    pub fluid_meter_id: String,
+    pub account_id: String,
+    pub role: FluidMeterRole,
+    pub alerts: bool,
+    pub recorded_at: NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, sqlx::FromRow)]
+pub struct FluidMeterInvite {
+    // Only sent by e-mail to the invited person
+    #[serde(skip)]
+    pub token: String,
+    pub fluid_meter_id: String,
+    pub email: String,
+    pub role: FluidMeterRole,
+    pub invited_by: String,
+    pub expires_at: NaiveDateTime,
+    pub recorded_at: NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FluidMeterMembers {
+    pub members: PaginatedResponse<FluidMeterMember>,
+    // Invites that haven't been accepted yet. Not paginated, since they
+    // expire after a few days
+    pub invites: Vec<FluidMeterInvite>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct InviteFluidMeterMemberInput {
+    pub email: String,
+    pub role: FluidMeterRole,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AcceptFluidMeterInviteInput {
+    pub token: String,
+}
+
+// Only the fields that are set will be updated
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UpdateFluidMeterMemberInput {
+    pub role: Option<FluidMeterRole>,
+    pub alerts: Option<bool>,
+}
@@ -1,4 +1,4 @@
-use crate::api::common::SeriesGranularity;
+use crate::api::{common::SeriesGranularity, device::DeviceTelemetry};
 
 use chrono::{NaiveDate, NaiveDateTime};
 use serde::{Deserialize, Serialize};
@@ -7,6 +7,20 @@ use serde::{Deserialize, Serialize};
 pub struct SaveMeasurementInput {
     pub device_id: String,
     pub measurement: String,
+    pub telemetry: Option<DeviceTelemetry>,
+    // The secret the device got when it was registered. Required for meters
+    // with a claimed device
+    pub secret: Option<String>,
+}
+
+/// Payload of the messages devices publish to the MQTT broker. The device id is
+/// part of the topic, so devices authenticate with the secret they got when
+/// they were registered
+#[derive(Clone, Deserialize, Serialize)]
+pub struct MqttMeasurementInput {
+    pub secret: String,
+    pub measurement: String,
+    pub telemetry: Option<DeviceTelemetry>,
 }
 
 #[derive(Clone, Deserialize, Serialize)]
@@ -24,3 +38,14 @@ pub struct Measurement {
     pub measurement: String,
     pub recorded_at: NaiveDateTime,
 }
+
+/// The saved measurement. Commands aren't included, because meters without a
+/// claimed device take measurements without a secret. Devices get them on
+/// check-in
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct SaveMeasurementResponse {
+    #[serde(flatten)]
+    pub measurement: Measurement,
+    // Minimum seconds until the next measurement
+    pub reporting_interval_secs: i64,
+}
@@ -0,0 +1,73 @@
+use crate::api::fluid_meter_member::FluidMeterRole;
+
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// Organizations are listed by name and their members oldest first. Page cursors
+// are tied to these sorts
+pub const ORGANIZATIONS_SORT: &'static str = "Name";
+pub const ORGANIZATION_MEMBERS_SORT: &'static str = "RecordedAt";
+
+// Roles are sorted from less to more permissions, so they can be compared
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, PartialOrd, sqlx::Type)]
+#[sqlx(type_name = "VARCHAR")] // Store as a string in the DB
+pub enum OrganizationRole {
+    // Can see the meters of the organization
+    #[sqlx(rename = "member")]
+    Member,
+    // Can also create and manage meters and members
+    #[sqlx(rename = "admin")]
+    Admin,
+    // Can also manage other owners
+    #[sqlx(rename = "owner")]
+    Owner,
+}
+
+impl OrganizationRole {
+    /// The role members of the organization have on its meters
+    pub fn fluid_meter_role(&self) -> FluidMeterRole {
+        match self {
+            OrganizationRole::Member => FluidMeterRole::Viewer,
+            OrganizationRole::Admin => FluidMeterRole::Manager,
+            OrganizationRole::Owner => FluidMeterRole::Owner,
+        }
+    }
+}
+
+impl fmt::Display for OrganizationRole {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OrganizationRole::Member => write!(f, "member"),
+            OrganizationRole::Admin => write!(f, "admin"),
+            OrganizationRole::Owner => write!(f, "owner"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, sqlx::FromRow)]
+pub struct Organization {
+    pub id: String,
+    pub name: String,
+    pub recorded_at: NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, sqlx::FromRow)]
+pub struct OrganizationMember {
+    pub organization_id: String,
+    pub account_id: String,
+    pub role: OrganizationRole,
+    pub recorded_at: NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CreateOrganizationInput {
+    pub name: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AddOrganizationMemberInput {
+    // The account needs to exist already
+    pub email: String,
+    pub role: OrganizationRole,
+}
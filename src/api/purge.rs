@@ -0,0 +1,11 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PurgeResponse {
+    // Number of accounts whose grace period ended and were deleted
+    pub accounts: u64,
+    // Number of deleted meters whose restore window ended and were purged
+    pub fluid_meters: u64,
+    // Number of measurements of the purged meters
+    pub measurements: u64,
+}
@@ -0,0 +1,39 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+// Sites are listed by name. Page cursors are tied to it
+pub const SITES_SORT: &'static str = "Name";
+
+// A place where meters are installed, e.g. a house or a garden plot
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, sqlx::FromRow)]
+pub struct Site {
+    pub id: String,
+    // Sites are owned by either an account or an organization
+    pub owner_id: Option<String>,
+    pub organization_id: Option<String>,
+    pub name: String,
+    pub address: String,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    // Free-form labels that can be used to filter meters
+    pub tags: Vec<String>,
+    pub recorded_at: NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CreateSiteInput {
+    pub name: String,
+    pub address: String,
+    // Coordinates are optional, but if one is set, the other one is required
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub tags: Option<Vec<String>>,
+    // If set, the site is owned by the organization instead of the user
+    pub organization_id: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SetFluidMeterSiteInput {
+    // None removes the meter from its site
+    pub site_id: Option<String>,
+}
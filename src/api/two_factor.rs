@@ -0,0 +1,69 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, Serialize, sqlx::FromRow)]
+pub struct TwoFactor {
+    pub account_id: String,
+    // Base32 encoded TOTP secret
+    pub secret: String,
+    pub last_used_step: Option<i64>,
+    // Two factor authentication is only enforced once the user confirms they
+    // can generate valid codes
+    pub enabled_at: Option<NaiveDateTime>,
+    pub recorded_at: NaiveDateTime,
+    // Codes that failed since the last successful log-in, across challenges
+    pub failed_attempts: i32,
+    // Challenges aren't created until this time after too many failures
+    pub locked_until: Option<NaiveDateTime>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, sqlx::FromRow)]
+pub struct TwoFactorChallenge {
+    pub token: String,
+    pub account_id: String,
+    pub attempts: i32,
+    pub expires_at: NaiveDateTime,
+}
+
+/// Returned by log-in when the user has two factor authentication enabled.
+/// The challenge needs to be sent together with a code to get a session token
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TwoFactorRequired {
+    pub challenge: String,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct TwoFactorEnrollment {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct ConfirmTwoFactorInput {
+    pub code: String,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct TwoFactorRecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
+// Either a code or a recovery code is required
+#[derive(Clone, Deserialize, Serialize)]
+pub struct TwoFactorLogInInput {
+    pub challenge: String,
+    pub code: Option<String>,
+    pub recovery_code: Option<String>,
+}
+
+// Either a code or a recovery code is required
+#[derive(Clone, Deserialize, Serialize)]
+pub struct DisableTwoFactorInput {
+    pub password: String,
+    pub code: Option<String>,
+    pub recovery_code: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct DisableTwoFactorResponse {}
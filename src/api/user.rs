@@ -1,3 +1,8 @@
+use crate::api::{
+    fluid_meter::FluidMeter, fluid_meter_member::FluidMeterMember, measurement::Measurement,
+    organization::OrganizationMember, two_factor::TwoFactorRequired,
+};
+
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 use std::fmt;
@@ -30,6 +35,35 @@ pub struct NewPasswordInput {
 #[derive(Clone, Deserialize, Serialize)]
 pub struct NewPasswordResponse {}
 
+// Only the fields that are set will be updated
+#[derive(Clone, Deserialize, Serialize)]
+pub struct UpdateUserInput {
+    pub name: Option<String>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct ChangePasswordInput {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct ChangePasswordResponse {}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct ChangeEmailInput {
+    pub email: String,
+    pub password: String,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct ChangeEmailResponse {}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct ConfirmEmailChangeInput {
+    pub token: String,
+}
+
 #[derive(Clone, Deserialize, Serialize)]
 pub struct LogOutUserResponse {}
 
@@ -70,6 +104,14 @@ pub struct SessionToken {
     pub expires_at: NaiveDateTime,
 }
 
+// Users with two factor authentication get a challenge instead of a session
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum LogInUserResponse {
+    Session(SessionToken),
+    TwoFactorRequired(TwoFactorRequired),
+}
+
 #[derive(Clone, Deserialize, Serialize, sqlx::FromRow)]
 pub struct PasswordRecovery {
     pub token: String,
@@ -77,3 +119,43 @@ pub struct PasswordRecovery {
     pub expires_at: NaiveDateTime,
     pub recorded_at: NaiveDateTime,
 }
+
+// A request to change the e-mail of an account. The change only happens after
+// the new address is verified
+#[derive(Clone, Deserialize, Serialize, sqlx::FromRow)]
+pub struct EmailChange {
+    pub token: String,
+    pub account_id: String,
+    pub email: String,
+    pub expires_at: NaiveDateTime,
+    pub recorded_at: NaiveDateTime,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct DeleteAccountInput {
+    pub password: String,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct DeleteAccountResponse {
+    // The account and all its data will be deleted after this date, unless the
+    // user logs in again
+    pub delete_at: NaiveDateTime,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct FluidMeterExport {
+    pub fluid_meter: FluidMeter,
+    pub measurements: Vec<Measurement>,
+}
+
+// Everything we store about a user
+#[derive(Clone, Deserialize, Serialize)]
+pub struct UserDataExport {
+    pub user: User,
+    pub fluid_meters: Vec<FluidMeterExport>,
+    // Meters and organizations the user was given access to
+    pub fluid_meter_memberships: Vec<FluidMeterMember>,
+    pub organization_memberships: Vec<OrganizationMember>,
+    pub exported_at: NaiveDateTime,
+}
@@ -0,0 +1,61 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::api::device::DeviceCommandStatus;
+
+// Valve actions are listed by creation time, newest first. Page cursors are
+// tied to this sort
+pub const VALVE_ACTIONS_SORT: &'static str = "RecordedAt";
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "VARCHAR")] // Store as a string in the DB
+pub enum ValveState {
+    #[sqlx(rename = "open")]
+    Open,
+    #[sqlx(rename = "closed")]
+    Closed,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "VARCHAR")] // Store as a string in the DB
+pub enum ValveActionOrigin {
+    #[sqlx(rename = "user")]
+    User,
+    // Closed because the meter had constant flow
+    #[sqlx(rename = "auto_shut_off")]
+    AutoShutOff,
+}
+
+/// A valve installed next to a meter. It's controlled through the device of
+/// the meter
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, sqlx::FromRow)]
+pub struct Valve {
+    pub fluid_meter_id: String,
+    // Last state confirmed by the device
+    pub state: ValveState,
+    // State of the last command sent to the valve. Differs from state until
+    // the device confirms it
+    pub requested_state: ValveState,
+    pub auto_shut_off: bool,
+    pub recorded_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct SetValveInput {
+    pub auto_shut_off: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, sqlx::FromRow)]
+pub struct ValveAction {
+    pub id: String,
+    pub fluid_meter_id: String,
+    pub target_state: ValveState,
+    pub origin: ValveActionOrigin,
+    // Empty for automatic actions
+    pub account_id: Option<String>,
+    pub command_id: String,
+    // Status of the command sent to the device. Tells if the action succeeded
+    pub command_status: DeviceCommandStatus,
+    pub recorded_at: NaiveDateTime,
+}
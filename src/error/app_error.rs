@@ -1,18 +1,24 @@
 use crate::json::extractor::Extractor;
-use crate::storage::error::Error;
+use crate::middleware::request_id::current_request_id;
+use crate::storage::error::{Error, ErrorCode};
 
 use axum::{
     extract::rejection::JsonRejection,
-    http::StatusCode,
+    http::{header::RETRY_AFTER, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
 };
 use serde::{Deserialize, Serialize};
 use std::fmt;
+use tracing::error;
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub enum AppErrorCode {
+    Conflict,
+    Forbidden,
     InternalError,
     InvalidInput,
+    NotFound,
+    TooManyRequests,
     Unauthorized,
     ValidationError,
 }
@@ -20,8 +26,12 @@ pub enum AppErrorCode {
 impl fmt::Display for AppErrorCode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            AppErrorCode::Conflict => write!(f, "Conflict"),
+            AppErrorCode::Forbidden => write!(f, "Forbidden"),
             AppErrorCode::InvalidInput => write!(f, "InvalidInput"),
             AppErrorCode::InternalError => write!(f, "InternalError"),
+            AppErrorCode::NotFound => write!(f, "NotFound"),
+            AppErrorCode::TooManyRequests => write!(f, "TooManyRequests"),
             AppErrorCode::ValidationError => write!(f, "ValidationError"),
             AppErrorCode::Unauthorized => write!(f, "Unauthorized"),
         }
@@ -41,6 +51,15 @@ pub enum AppError {
     ValidationError(Vec<FailedValidation>),
     // Permission error
     Unauthorized,
+    // The user is logged in, but isn't allowed to do this
+    Forbidden,
+    // The resource doesn't exist, or the user can't see it
+    NotFound,
+    // The resource already exists
+    Conflict,
+    // The client sent too many requests. Contains the seconds until it can try
+    // again
+    TooManyRequests(u64),
 }
 
 impl From<JsonRejection> for AppError {
@@ -64,9 +83,12 @@ impl From<Vec<FailedValidation>> for AppError {
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub enum ValidationIssue {
     Invalid,
+    // The device was registered, but no user has claimed it yet
+    NotClaimed,
+    // The e-mail address of the account hasn't been verified yet
+    NotVerified,
     Required,
     TooLarge,
-    TooFrequent,
     TooWeak,
 }
 
@@ -89,11 +111,18 @@ pub struct ErrorResponse {
     pub code: AppErrorCode,
     pub message: String,
     pub data: ErrorData,
+    // Id of the request that failed, so it can be found in the logs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
 }
 
 // Tell axum how `AppError` should be converted into a response.
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        let retry_after = match self {
+            AppError::TooManyRequests(secs) => Some(secs),
+            _ => None,
+        };
         let (response_status, error_code, error_message, error_data) = match self {
             // Generic backend error
             AppError::ServerError => (
@@ -109,12 +138,29 @@ impl IntoResponse for AppError {
                 "Invalid JSON for this endpoint".to_string(),
                 ErrorData::Empty,
             ),
-            AppError::DatabaseError(_e) => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                AppErrorCode::InternalError,
-                "We made a mistake. Sorry".to_string(),
-                ErrorData::Empty,
-            ),
+            AppError::DatabaseError(e) => match e.code {
+                ErrorCode::DuplicateError => (
+                    StatusCode::CONFLICT,
+                    AppErrorCode::Conflict,
+                    "The resource already exists".to_string(),
+                    ErrorData::Empty,
+                ),
+                ErrorCode::NotFoundError => (
+                    StatusCode::NOT_FOUND,
+                    AppErrorCode::NotFound,
+                    "The resource doesn't exist".to_string(),
+                    ErrorData::Empty,
+                ),
+                _ => {
+                    error!("Database error: {}", e);
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        AppErrorCode::InternalError,
+                        "We made a mistake. Sorry".to_string(),
+                        ErrorData::Empty,
+                    )
+                }
+            },
             AppError::ValidationError(data) => (
                 StatusCode::BAD_REQUEST,
                 AppErrorCode::ValidationError,
@@ -127,17 +173,48 @@ impl IntoResponse for AppError {
                 "You don't have access to this resource".to_string(),
                 ErrorData::Empty,
             ),
+            AppError::Forbidden => (
+                StatusCode::FORBIDDEN,
+                AppErrorCode::Forbidden,
+                "You don't have permission to do this".to_string(),
+                ErrorData::Empty,
+            ),
+            AppError::NotFound => (
+                StatusCode::NOT_FOUND,
+                AppErrorCode::NotFound,
+                "The resource doesn't exist".to_string(),
+                ErrorData::Empty,
+            ),
+            AppError::Conflict => (
+                StatusCode::CONFLICT,
+                AppErrorCode::Conflict,
+                "The resource already exists".to_string(),
+                ErrorData::Empty,
+            ),
+            AppError::TooManyRequests(_) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                AppErrorCode::TooManyRequests,
+                "Too many requests. Try again later".to_string(),
+                ErrorData::Empty,
+            ),
         };
 
-        (
+        let mut response = (
             response_status,
             Extractor(ErrorResponse {
                 code: error_code,
                 message: error_message,
                 data: error_data,
+                request_id: current_request_id(),
             }),
         )
-            .into_response()
+            .into_response();
+        if let Some(secs) = retry_after {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(secs));
+        }
+        return response;
     }
 }
 
@@ -149,11 +226,22 @@ pub fn validation_error<T>(validation_errors: Vec<FailedValidation>) -> Result<T
     return Err(AppError::ValidationError(validation_errors));
 }
 
-// Generic bad request response
-pub fn bad_request<T>() -> Result<T, AppError> {
-    return Err(AppError::ValidationError(vec![]));
-}
-
 pub fn unauthorized<T>() -> Result<T, AppError> {
     return Err(AppError::Unauthorized);
 }
+
+pub fn forbidden<T>() -> Result<T, AppError> {
+    return Err(AppError::Forbidden);
+}
+
+pub fn not_found<T>() -> Result<T, AppError> {
+    return Err(AppError::NotFound);
+}
+
+pub fn conflict<T>() -> Result<T, AppError> {
+    return Err(AppError::Conflict);
+}
+
+pub fn too_many_requests<T>(retry_after_secs: u64) -> Result<T, AppError> {
+    return Err(AppError::TooManyRequests(retry_after_secs));
+}
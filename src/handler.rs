@@ -1,5 +1,14 @@
 pub mod alert;
+pub mod device;
+pub mod firmware;
 pub mod fluid_meter;
+pub mod fluid_meter_member;
 pub mod health;
 pub mod measurement;
+pub mod metrics;
+pub mod organization;
+pub mod purge;
+pub mod site;
+pub mod two_factor;
 pub mod user;
+pub mod valve;
@@ -1,6 +1,12 @@
 use crate::{
-    api::{common::PaginatedRequest, fluid_meter::FluidMeterAlerts, health::Health},
-    error::app_error::{bad_request, internal_error, AppError},
+    api::{
+        alert::AlertType::ConstantFlow,
+        common::PaginatedRequest,
+        fluid_meter::{FluidMeter, FluidMeterAlerts},
+        health::Health,
+    },
+    error::app_error::{internal_error, too_many_requests, AppError},
+    helper::{metrics::ALERT_RUN_DURATION, valve::auto_shut_off},
     json::extractor::Extractor,
     AppState,
 };
@@ -15,6 +21,32 @@ pub const DT_FORMAT: &'static str = "%Y-%m-%d %H:%M:%S%.f";
 pub const LAST_ALERTS_RUN_KEY: &'static str = "last-alerts-run";
 pub const METERS_PAGE_SIZE: &'static u8 = &100;
 
+// The valve is already closing, so failing to tell someone doesn't stop the
+// alerts of other meters
+async fn notify_valve_closed(state: &AppState, recipients: &Vec<String>, meter: &FluidMeter) {
+    for r in recipients {
+        let account = match state.storage.user_by_id(r).await {
+            Ok(Some(ac)) => ac,
+            Ok(None) => {
+                error!("Alert recipient not found. Account: {}", r);
+                continue;
+            }
+            Err(e) => {
+                error!("Error getting alert recipient {}: {}", r, e);
+                continue;
+            }
+        };
+
+        if !state
+            .mail_helper
+            .valve_closed(&state.settings, &account, meter)
+            .await
+        {
+            error!("Error sending valve closed e-mail for user: {}", r);
+        }
+    }
+}
+
 pub async fn trigger_alerts(State(state): State<AppState>) -> Result<Extractor<Health>, AppError> {
     let last_run = match state.storage.get_metadata(&LAST_ALERTS_RUN_KEY).await {
         Ok(d) => d,
@@ -24,17 +56,18 @@ pub async fn trigger_alerts(State(state): State<AppState>) -> Result<Extractor<H
         }
     };
 
-    if last_run.is_some()
-        && NaiveDateTime::parse_from_str(&last_run.clone().unwrap().value, DT_FORMAT).unwrap()
-            > (Utc::now().naive_utc() - Duration::minutes(*ALERTS_MAX_FREQUENCY_MINS))
-    {
-        error!(
-            "Rate limiting trigger alerts. Last run: {}",
-            last_run.unwrap().value
-        );
-        return bad_request();
+    if let Some(last_run) = last_run {
+        let next_run = NaiveDateTime::parse_from_str(&last_run.value, DT_FORMAT).unwrap()
+            + Duration::minutes(*ALERTS_MAX_FREQUENCY_MINS);
+        let now = Utc::now().naive_utc();
+        if next_run > now {
+            error!("Rate limiting trigger alerts. Last run: {}", last_run.value);
+            return too_many_requests((next_run - now).num_seconds().max(1) as u64);
+        }
     }
 
+    // Observed when dropped, so failed runs are measured too
+    let _timer = ALERT_RUN_DURATION.start_timer();
     match state
         .storage
         .save_metadata(LAST_ALERTS_RUN_KEY, &Utc::now().naive_utc().to_string())
@@ -47,12 +80,12 @@ pub async fn trigger_alerts(State(state): State<AppState>) -> Result<Extractor<H
         }
     };
 
+    let mut options = PaginatedRequest {
+        page_cursor: None,
+        page_size: *METERS_PAGE_SIZE,
+    };
     let mut has_more = true;
     while has_more {
-        let mut options = PaginatedRequest {
-            page_cursor: None,
-            page_size: *METERS_PAGE_SIZE,
-        };
         let meters = match state.storage.get_active_fluid_meters(&options).await {
             Ok(r) => r,
             Err(e) => {
@@ -65,11 +98,11 @@ pub async fn trigger_alerts(State(state): State<AppState>) -> Result<Extractor<H
             break;
         }
 
-        // Key is the id of the owner
+        // Key is the id of the account that will receive the alerts
         let mut alerts: HashMap<String, Vec<FluidMeterAlerts>> = HashMap::new();
 
         has_more = meters.pagination.has_more;
-        options.page_cursor = Some(meters.items.last().unwrap().id.clone());
+        options.page_cursor = meters.pagination.next_cursor.clone();
         for m in meters.items {
             match state
                 .alert_helper
@@ -77,11 +110,36 @@ pub async fn trigger_alerts(State(state): State<AppState>) -> Result<Extractor<H
                 .await
             {
                 Ok(a) => {
+                    if let Err(e) = state.storage.set_fluid_meter_alerts(&m.id, &a.alerts).await {
+                        error!("Error saving alerts for meter {}: {}", &m.id, e);
+                        return internal_error();
+                    }
                     if a.alerts.len() > 0 {
-                        if alerts.contains_key(&m.owner_id) {
-                            alerts.get_mut(&m.owner_id).unwrap().push(a);
-                        } else {
-                            alerts.insert(m.owner_id, vec![a]);
+                        let recipients =
+                            match state.storage.fluid_meter_alert_recipients(&m.id).await {
+                                Ok(r) => r,
+                                Err(e) => {
+                                    error!("Error triggering alerts: {}", e);
+                                    return internal_error();
+                                }
+                            };
+                        // Water that never stops flowing is probably a leak, so
+                        // we close the valve if the owner allowed us to
+                        if a.alerts.iter().any(|al| al.alert_type == ConstantFlow) {
+                            match auto_shut_off(state.storage.clone(), &m.id).await {
+                                Ok(Some(_)) => notify_valve_closed(&state, &recipients, &m).await,
+                                Ok(None) => {}
+                                Err(e) => {
+                                    error!("Error closing valve of meter {}: {:?}", &m.id, e)
+                                }
+                            }
+                        }
+                        for r in recipients {
+                            if alerts.contains_key(&r) {
+                                alerts.get_mut(&r).unwrap().push(a.clone());
+                            } else {
+                                alerts.insert(r, vec![a.clone()]);
+                            }
                         }
                     }
                 }
@@ -95,10 +153,7 @@ pub async fn trigger_alerts(State(state): State<AppState>) -> Result<Extractor<H
             let account = match state.storage.user_by_id(&a.0).await {
                 Ok(ac) => {
                     if ac.is_none() {
-                        error!(
-                            "Meter assigned to account, but account not found. Account: {}",
-                            &a.0
-                        );
+                        error!("Alert recipient not found. Account: {}", &a.0);
                         return internal_error();
                     }
                     ac.unwrap()
@@ -0,0 +1,279 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::HeaderMap,
+    Extension,
+};
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::{
+    api::{
+        common::{PageInput, PaginatedRequest, PaginatedResponse, SortKeyType::Timestamp},
+        device::{
+            AcknowledgeDeviceCommandInput, CreateDeviceCommandInput, Device, DeviceCheckInInput,
+            DeviceCheckInResponse, DeviceCommand,
+            DeviceCommandStatus::{Acknowledged, Failed, Pending},
+            DeviceCommandType::{CloseValve, OpenValve, SetReportingInterval},
+            RegisterDeviceInput, RegisterDeviceResponse, DEVICE_COMMANDS_SORT,
+        },
+        fluid_meter_member::FluidMeterRole::{Owner, Viewer},
+        user::User,
+        valve::ValveState::{Closed, Open},
+    },
+    error::app_error::{
+        conflict, internal_error, not_found, unauthorized, validation_error, AppError,
+        FailedValidation,
+        ValidationIssue::{Invalid, Required, TooLarge},
+    },
+    helper::{
+        device::{
+            device_secret_matches, generate_claim_code, hash_device_secret,
+            validate_device_command, DEVICE_SECRET_LEN, MAX_COMMAND_RESULT_LEN, MAX_SERIAL_LEN,
+        },
+        measurement::reporting_interval,
+        pagination::validate_page,
+        token::{alphanumeric, has_bearer_token},
+        user::require_fluid_meter_role,
+    },
+    json::extractor::Extractor,
+    storage::error::ErrorCode::{DuplicateError, NotFoundError},
+    AppState,
+};
+
+fn invalid_serial<T>() -> Result<T, AppError> {
+    return validation_error(vec![FailedValidation {
+        field: "serial".to_string(),
+        issue: Invalid,
+    }]);
+}
+
+// Returns the device with the given serial if the secret is correct
+pub(crate) async fn authenticate_device(
+    state: &AppState,
+    serial: &str,
+    secret: &str,
+) -> Result<Device, AppError> {
+    let device = match state.storage.device_by_serial(serial.trim()).await? {
+        Some(d) => d,
+        None => return invalid_serial(),
+    };
+
+    if !device_secret_matches(&device, secret) {
+        return invalid_serial();
+    }
+
+    return Ok(device);
+}
+
+/// Registers a device when it's manufactured. Only the factory can do it, with
+/// the provisioning token. Returns the secret the device uses to check-in and
+/// the code users need to claim it
+pub async fn register_device(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Extractor(input): Extractor<RegisterDeviceInput>,
+) -> Result<Extractor<RegisterDeviceResponse>, AppError> {
+    let token = match &state.settings.device {
+        Some(d) if d.provisioning_token.len() > 0 => &d.provisioning_token,
+        _ => return not_found(),
+    };
+    if !has_bearer_token(&headers, token) {
+        return unauthorized();
+    }
+
+    let serial = input.serial.trim();
+    if serial.len() == 0 {
+        return validation_error(vec![FailedValidation {
+            field: "serial".to_string(),
+            issue: Required,
+        }]);
+    } else if serial.len() > *MAX_SERIAL_LEN {
+        return validation_error(vec![FailedValidation {
+            field: "serial".to_string(),
+            issue: TooLarge,
+        }]);
+    }
+
+    let secret = alphanumeric(DEVICE_SECRET_LEN);
+    let claim_code = generate_claim_code();
+    let device = Device {
+        serial: serial.to_string(),
+        secret_hash: hash_device_secret(&secret),
+        claim_code: Some(claim_code.clone()),
+        fluid_meter_id: None,
+        recorded_at: Utc::now().naive_utc(),
+        claimed_at: None,
+    };
+    match state.storage.insert_device(&device).await {
+        Ok(_) => {}
+        Err(e) => {
+            if e.code == DuplicateError {
+                return conflict();
+            }
+            return internal_error();
+        }
+    };
+
+    return Ok(Extractor(RegisterDeviceResponse {
+        serial: device.serial,
+        secret,
+        claim_code,
+    }));
+}
+
+/// Devices check-in periodically. Once they are claimed, they receive the id
+/// they need to send measurements and the commands they haven't acknowledged
+pub async fn device_check_in(
+    State(state): State<AppState>,
+    Extractor(input): Extractor<DeviceCheckInInput>,
+) -> Result<Extractor<DeviceCheckInResponse>, AppError> {
+    let device = authenticate_device(&state, &input.serial, &input.secret).await?;
+
+    let mut commands = vec![];
+    let mut reporting_interval_secs = state.settings.fluid_meter.default_reporting_interval_secs;
+    if let Some(id) = &device.fluid_meter_id {
+        commands = state.storage.take_device_commands(id).await?;
+        if let Some(m) = state.storage.get_fluid_meter_by_id(id).await? {
+            reporting_interval_secs = reporting_interval(&state.settings, &m);
+        }
+    }
+
+    return Ok(Extractor(DeviceCheckInResponse {
+        claimed: device.fluid_meter_id.is_some(),
+        device_id: device.fluid_meter_id,
+        commands,
+        reporting_interval_secs,
+    }));
+}
+
+/// Sent by the device after it executes, or fails to execute, a command
+pub async fn acknowledge_device_command(
+    State(state): State<AppState>,
+    Extractor(input): Extractor<AcknowledgeDeviceCommandInput>,
+) -> Result<Extractor<DeviceCommand>, AppError> {
+    let device = authenticate_device(&state, &input.serial, &input.secret).await?;
+    let fluid_meter_id = match device.fluid_meter_id {
+        Some(id) => id,
+        None => return invalid_serial(),
+    };
+
+    if let Some(r) = &input.result {
+        if r.len() > *MAX_COMMAND_RESULT_LEN {
+            return validation_error(vec![FailedValidation {
+                field: "result".to_string(),
+                issue: TooLarge,
+            }]);
+        }
+    }
+
+    let status = if input.success { Acknowledged } else { Failed };
+    match state
+        .storage
+        .acknowledge_device_command(&input.command_id, &fluid_meter_id, status, &input.result)
+        .await
+    {
+        Ok(c) => {
+            let target_state = match c.command_type {
+                CloseValve => Some(Closed),
+                OpenValve => Some(Open),
+                _ => None,
+            };
+            if let Some(target_state) = target_state {
+                state
+                    .storage
+                    .acknowledge_valve_command(&fluid_meter_id, &target_state, input.success)
+                    .await?;
+            }
+            return Ok(Extractor(c));
+        }
+        Err(e) => {
+            if e.code == NotFoundError {
+                return not_found();
+            }
+            return internal_error();
+        }
+    }
+}
+
+/// Queues a command for the device of the meter. Only owners can send commands
+pub async fn create_device_command(
+    State(state): State<AppState>,
+    Path(meter_id): Path<String>,
+    user: Extension<User>,
+    Extractor(input): Extractor<CreateDeviceCommandInput>,
+) -> Result<Extractor<DeviceCommand>, AppError> {
+    require_fluid_meter_role(
+        &state.user_helper,
+        state.storage.clone(),
+        &user.id,
+        &meter_id,
+        Owner,
+    )
+    .await?;
+
+    let validation_errors = validate_device_command(&input);
+    if !validation_errors.is_empty() {
+        return validation_error(validation_errors);
+    }
+
+    // Measurements are rate limited with the interval of the meter, so it needs
+    // to match the one of the device
+    if input.command_type == SetReportingInterval {
+        state
+            .storage
+            .set_fluid_meter_reporting_interval(&meter_id, input.value)
+            .await?;
+    }
+
+    let command = DeviceCommand {
+        id: Uuid::new_v4().to_string(),
+        fluid_meter_id: meter_id,
+        command_type: input.command_type,
+        value: input.value,
+        status: Pending,
+        created_by: Some(user.id.clone()),
+        result: None,
+        recorded_at: Utc::now().naive_utc(),
+        sent_at: None,
+        acknowledged_at: None,
+    };
+    let command = state.storage.insert_device_command(&command).await?;
+
+    return Ok(Extractor(command));
+}
+
+/// Lists the commands sent to the device of the meter and their status, newest
+/// first
+pub async fn device_commands(
+    State(state): State<AppState>,
+    Path(meter_id): Path<String>,
+    Query(input): Query<PageInput>,
+    user: Extension<User>,
+) -> Result<Extractor<PaginatedResponse<DeviceCommand>>, AppError> {
+    require_fluid_meter_role(
+        &state.user_helper,
+        state.storage.clone(),
+        &user.id,
+        &meter_id,
+        Viewer,
+    )
+    .await?;
+
+    let (page_size, validation_errors) = validate_page(
+        input.page_size,
+        &input.page_cursor,
+        DEVICE_COMMANDS_SORT,
+        Timestamp,
+    );
+    if !validation_errors.is_empty() {
+        return validation_error(validation_errors);
+    }
+
+    let options = PaginatedRequest {
+        page_size,
+        page_cursor: input.page_cursor,
+    };
+    let commands = state.storage.device_commands(&meter_id, &options).await?;
+
+    return Ok(Extractor(commands));
+}
@@ -0,0 +1,333 @@
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::header::CONTENT_TYPE,
+    response::IntoResponse,
+    Extension,
+};
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::{
+    api::{
+        common::{PageInput, PaginatedRequest, PaginatedResponse, SortKeyType::Timestamp},
+        device::DeviceTelemetry,
+        firmware::{
+            CreateFirmwareReleaseInput, FirmwareCheckInput, FirmwareCheckResponse,
+            FirmwareDownloadInput, FirmwareRelease, FirmwareUpdate, FirmwareUpdateReportInput,
+            FirmwareUpdateStatus::{Failed, Succeeded},
+            SetFirmwareRolloutInput, FIRMWARE_RELEASES_SORT, FIRMWARE_UPDATES_SORT,
+        },
+        user::User,
+    },
+    error::app_error::{
+        conflict, forbidden, internal_error, not_found, validation_error, AppError,
+        FailedValidation,
+        ValidationIssue::{Invalid, Required, TooLarge},
+    },
+    handler::device::authenticate_device,
+    helper::{
+        device::{MAX_COMMAND_RESULT_LEN, MAX_FIRMWARE_VERSION_LEN},
+        firmware::{
+            hash_firmware, in_rollout, is_firmware_admin, read_firmware_binary,
+            save_firmware_binary, valid_rollout_percentage, validate_firmware_release,
+        },
+        pagination::validate_page,
+    },
+    json::extractor::Extractor,
+    storage::error::ErrorCode::{DuplicateError, NotFoundError},
+    AppState,
+};
+
+/// Lists all firmware releases, newest first. Only for firmware admins
+pub async fn firmware_releases(
+    State(state): State<AppState>,
+    Query(input): Query<PageInput>,
+    user: Extension<User>,
+) -> Result<Extractor<PaginatedResponse<FirmwareRelease>>, AppError> {
+    if !is_firmware_admin(&state.settings, &user.id) {
+        return forbidden();
+    }
+
+    let (page_size, validation_errors) = validate_page(
+        input.page_size,
+        &input.page_cursor,
+        FIRMWARE_RELEASES_SORT,
+        Timestamp,
+    );
+    if !validation_errors.is_empty() {
+        return validation_error(validation_errors);
+    }
+
+    let options = PaginatedRequest {
+        page_size,
+        page_cursor: input.page_cursor,
+    };
+    let releases = state.storage.firmware_releases(&options).await?;
+
+    return Ok(Extractor(releases));
+}
+
+/// Creates a firmware release for a hardware model. Devices don't get it until
+/// the binary is uploaded
+pub async fn create_firmware_release(
+    State(state): State<AppState>,
+    user: Extension<User>,
+    Extractor(input): Extractor<CreateFirmwareReleaseInput>,
+) -> Result<Extractor<FirmwareRelease>, AppError> {
+    if !is_firmware_admin(&state.settings, &user.id) {
+        return forbidden();
+    }
+
+    let validation_errors = validate_firmware_release(&input);
+    if !validation_errors.is_empty() {
+        return validation_error(validation_errors);
+    }
+
+    let mut target_versions: Vec<String> = input
+        .target_versions
+        .unwrap_or_default()
+        .iter()
+        .map(|v| v.trim().to_string())
+        .filter(|v| v.len() > 0)
+        .collect();
+    target_versions.sort();
+    target_versions.dedup();
+    let release = FirmwareRelease {
+        id: Uuid::new_v4().to_string(),
+        hardware_model: input.hardware_model.trim().to_string(),
+        version: input.version.trim().to_string(),
+        target_versions,
+        rollout_percentage: input.rollout_percentage,
+        size_bytes: None,
+        sha256: None,
+        created_by: Some(user.id.clone()),
+        recorded_at: Utc::now().naive_utc(),
+        uploaded_at: None,
+    };
+    match state.storage.insert_firmware_release(&release).await {
+        Ok(r) => return Ok(Extractor(r)),
+        Err(e) => {
+            if e.code == DuplicateError {
+                return conflict();
+            }
+            return internal_error();
+        }
+    }
+}
+
+/// Uploads the binary of a release. The body of the request is the binary.
+/// Uploading again replaces it
+pub async fn upload_firmware_binary(
+    State(state): State<AppState>,
+    Path(release_id): Path<String>,
+    user: Extension<User>,
+    binary: Bytes,
+) -> Result<Extractor<FirmwareRelease>, AppError> {
+    if !is_firmware_admin(&state.settings, &user.id) {
+        return forbidden();
+    }
+
+    if binary.len() == 0 {
+        return validation_error(vec![FailedValidation {
+            field: "binary".to_string(),
+            issue: Required,
+        }]);
+    }
+
+    if state
+        .storage
+        .firmware_release_by_id(&release_id)
+        .await?
+        .is_none()
+    {
+        return not_found();
+    }
+
+    save_firmware_binary(&state.settings, &release_id, &binary).await?;
+    let release = state
+        .storage
+        .set_firmware_binary(&release_id, binary.len() as i64, &hash_firmware(&binary))
+        .await?;
+
+    return Ok(Extractor(release));
+}
+
+/// Changes the percentage of targeted devices that get the release. Setting
+/// it to 0 stops the rollout
+pub async fn set_firmware_rollout(
+    State(state): State<AppState>,
+    Path(release_id): Path<String>,
+    user: Extension<User>,
+    Extractor(input): Extractor<SetFirmwareRolloutInput>,
+) -> Result<Extractor<FirmwareRelease>, AppError> {
+    if !is_firmware_admin(&state.settings, &user.id) {
+        return forbidden();
+    }
+
+    if !valid_rollout_percentage(input.rollout_percentage) {
+        return validation_error(vec![FailedValidation {
+            field: "rollout_percentage".to_string(),
+            issue: Invalid,
+        }]);
+    }
+
+    match state
+        .storage
+        .set_firmware_rollout(&release_id, input.rollout_percentage)
+        .await
+    {
+        Ok(r) => return Ok(Extractor(r)),
+        Err(e) => {
+            if e.code == NotFoundError {
+                return not_found();
+            }
+            return internal_error();
+        }
+    }
+}
+
+/// Lists the results devices reported for a release, newest first
+pub async fn firmware_updates(
+    State(state): State<AppState>,
+    Path(release_id): Path<String>,
+    Query(input): Query<PageInput>,
+    user: Extension<User>,
+) -> Result<Extractor<PaginatedResponse<FirmwareUpdate>>, AppError> {
+    if !is_firmware_admin(&state.settings, &user.id) {
+        return forbidden();
+    }
+
+    let (page_size, validation_errors) = validate_page(
+        input.page_size,
+        &input.page_cursor,
+        FIRMWARE_UPDATES_SORT,
+        Timestamp,
+    );
+    if !validation_errors.is_empty() {
+        return validation_error(validation_errors);
+    }
+
+    let options = PaginatedRequest {
+        page_size,
+        page_cursor: input.page_cursor,
+    };
+    let updates = state
+        .storage
+        .firmware_updates(&release_id, &options)
+        .await?;
+
+    return Ok(Extractor(updates));
+}
+
+/// Called by devices to know if they should install new firmware. Returns the
+/// newest release targeting the device
+pub async fn check_firmware(
+    State(state): State<AppState>,
+    Extractor(input): Extractor<FirmwareCheckInput>,
+) -> Result<Extractor<FirmwareCheckResponse>, AppError> {
+    let device = authenticate_device(&state, &input.serial, &input.secret).await?;
+
+    let releases = state
+        .storage
+        .firmware_releases_for_device(
+            &device.serial,
+            input.hardware_model.trim(),
+            input.firmware_version.trim(),
+        )
+        .await?;
+    let release = releases.into_iter().find(|r| in_rollout(r, &device.serial));
+
+    return Ok(Extractor(FirmwareCheckResponse { release }));
+}
+
+/// Returns the binary of a release to a device. Only releases the device would
+/// be offered by check_firmware can be downloaded
+pub async fn download_firmware(
+    State(state): State<AppState>,
+    Extractor(input): Extractor<FirmwareDownloadInput>,
+) -> Result<impl IntoResponse, AppError> {
+    let device = authenticate_device(&state, &input.serial, &input.secret).await?;
+
+    let releases = state
+        .storage
+        .firmware_releases_for_device(
+            &device.serial,
+            input.hardware_model.trim(),
+            input.firmware_version.trim(),
+        )
+        .await?;
+    if !releases
+        .iter()
+        .any(|r| r.id == input.release_id && in_rollout(r, &device.serial))
+    {
+        return not_found();
+    }
+
+    let binary = read_firmware_binary(&state.settings, &input.release_id).await?;
+    return Ok(([(CONTENT_TYPE, "application/octet-stream")], binary));
+}
+
+/// Called by devices after trying to install a release. Devices don't get a
+/// release again after reporting it failed
+pub async fn report_firmware_update(
+    State(state): State<AppState>,
+    Extractor(input): Extractor<FirmwareUpdateReportInput>,
+) -> Result<Extractor<FirmwareUpdate>, AppError> {
+    let device = authenticate_device(&state, &input.serial, &input.secret).await?;
+
+    let release = match state
+        .storage
+        .firmware_release_by_id(&input.release_id)
+        .await?
+    {
+        Some(r) => r,
+        None => return not_found(),
+    };
+
+    let mut validation_errors = vec![];
+    if input.from_version.trim().len() > *MAX_FIRMWARE_VERSION_LEN {
+        validation_errors.push(FailedValidation {
+            field: "from_version".to_string(),
+            issue: TooLarge,
+        });
+    }
+    if input
+        .result
+        .as_ref()
+        .is_some_and(|r| r.len() > *MAX_COMMAND_RESULT_LEN)
+    {
+        validation_errors.push(FailedValidation {
+            field: "result".to_string(),
+            issue: TooLarge,
+        });
+    }
+    if !validation_errors.is_empty() {
+        return validation_error(validation_errors);
+    }
+
+    let update = FirmwareUpdate {
+        release_id: release.id.clone(),
+        serial: device.serial.clone(),
+        from_version: input.from_version.trim().to_string(),
+        status: if input.success { Succeeded } else { Failed },
+        result: input.result,
+        recorded_at: Utc::now().naive_utc(),
+    };
+    let update = state.storage.save_firmware_update(&update).await?;
+
+    // The meter shows the new version right away, without waiting for the
+    // next telemetry
+    if let (true, Some(fluid_meter_id)) = (input.success, &device.fluid_meter_id) {
+        let telemetry = DeviceTelemetry {
+            firmware_version: Some(release.version.clone()),
+            ..Default::default()
+        };
+        state
+            .storage
+            .save_device_status(fluid_meter_id, &telemetry)
+            .await?;
+    }
+
+    return Ok(Extractor(update));
+}
@@ -2,40 +2,84 @@ use axum::{
     extract::{Path, Query, State},
     Extension,
 };
-use chrono::Utc;
+use chrono::{Duration, Utc};
 use tracing::error;
 use uuid::Uuid;
 
 use crate::{
     api::{
-        common::{PaginatedResponse, Pagination, DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE},
+        common::{PageInput, PaginatedRequest, PaginatedResponse, SortKeyType::Timestamp},
+        device::{
+            ClaimFluidMeterInput, DeviceCommand, DeviceCommandStatus::Pending,
+            DeviceCommandType::SetReportingInterval,
+        },
         fluid_meter::{
-            CreateFluidMeterInput, FluidMeter, FluidMeterAlerts, FluidMeterStatus, FluidMetersInput,
+            CreateFluidMeterInput, FluidMeter, FluidMeterAlerts, FluidMeterDetail,
+            FluidMeterStatus, FluidMeterSummary, FluidMetersInput, FluidMetersSort,
+            SetFluidMeterParentInput, SetReportingIntervalInput, UpdateFluidMeterInput,
+            DELETED_FLUID_METERS_SORT,
         },
+        fluid_meter_member::FluidMeterRole::{Manager, Owner, Viewer},
+        organization::OrganizationRole::{self, Admin},
+        site::SetFluidMeterSiteInput,
         user::User,
     },
     error::app_error::{
-        internal_error, AppError, FailedValidation,
+        internal_error, not_found, validation_error, AppError, FailedValidation,
         ValidationIssue::{Invalid, Required, TooLarge},
     },
+    helper::{
+        device::{normalize_claim_code, MAX_REPORTING_INTERVAL_SECS, MIN_REPORTING_INTERVAL_SECS},
+        pagination::validate_page,
+        user::require_fluid_meter_role,
+    },
     json::extractor::Extractor,
+    storage::error::ErrorCode::NotFoundError,
     AppState,
 };
 
-/// Lists all fluid meters for the logged in user
+pub const MAX_NAME_LEN: &'static usize = &255;
+
+/// Lists all fluid meters the logged in user has access to. Can be filtered by
+/// organization, site, name, creation time and last report time
 pub async fn fluid_meters(
     State(state): State<AppState>,
     Query(input): Query<FluidMetersInput>,
     user: Extension<User>,
-) -> Result<Extractor<PaginatedResponse<FluidMeter>>, AppError> {
-    let mut validation_errors = vec![];
+) -> Result<Extractor<PaginatedResponse<FluidMeterSummary>>, AppError> {
+    let sort = input.sort.clone().unwrap_or(FluidMetersSort::Id);
+    let (page_size, mut validation_errors) = validate_page(
+        input.page_size,
+        &input.page_cursor,
+        &input.cursor_sort(),
+        sort.key_type(),
+    );
 
-    let page_size = input.page_size.unwrap_or(*DEFAULT_PAGE_SIZE);
-    if page_size > *MAX_PAGE_SIZE {
-        validation_errors.push(FailedValidation {
-            field: "page_size".to_string(),
-            issue: TooLarge,
-        });
+    if let Some(q) = &input.q {
+        if q.len() > *MAX_NAME_LEN {
+            validation_errors.push(FailedValidation {
+                field: "q".to_string(),
+                issue: TooLarge,
+            });
+        }
+    }
+
+    if let (Some(from), Some(to)) = (input.created_from, input.created_to) {
+        if from > to {
+            validation_errors.push(FailedValidation {
+                field: "created_to".to_string(),
+                issue: Invalid,
+            });
+        }
+    }
+
+    if let (Some(from), Some(to)) = (input.reported_from, input.reported_to) {
+        if from > to {
+            validation_errors.push(FailedValidation {
+                field: "reported_to".to_string(),
+                issue: Invalid,
+            });
+        }
     }
 
     if !validation_errors.is_empty() {
@@ -43,34 +87,29 @@ pub async fn fluid_meters(
     }
 
     let mut options = input.clone();
-    options.page_size = Some(page_size + 1);
+    options.page_size = Some(page_size);
+    // Searching for blanks would match everything
+    options.q = input
+        .q
+        .as_ref()
+        .map(|q| q.trim().to_string())
+        .filter(|q| q.len() > 0);
 
-    let mut meters = match state.storage.get_fluid_meters(&user.id, &options).await {
+    let meters = match state.storage.get_fluid_meters(&user.id, &options).await {
         Ok(m) => m,
         Err(_) => return internal_error(),
     };
-    let has_more = (meters.len() as u8) > page_size;
-    if has_more {
-        meters.pop();
-    }
 
-    let resp = PaginatedResponse {
-        items: meters,
-        pagination: Pagination {
-            has_more,
-            has_less: input.page_cursor.is_some(),
-        },
-    };
-
-    return Ok(Extractor(resp));
+    return Ok(Extractor(meters));
 }
 
-/// Creates a new fluid meter
-pub async fn create_fluid_meter(
-    State(state): State<AppState>,
-    user: Extension<User>,
-    Extractor(input): Extractor<CreateFluidMeterInput>,
-) -> Result<Extractor<FluidMeter>, AppError> {
+// Checks the name and that the user can add meters to the given organization and
+// site
+async fn validate_new_fluid_meter(
+    state: &AppState,
+    user_id: &str,
+    input: &CreateFluidMeterInput,
+) -> Result<Vec<FailedValidation>, AppError> {
     let mut validation_errors = vec![];
 
     let name = input.name.trim();
@@ -79,21 +118,77 @@ pub async fn create_fluid_meter(
             field: "name".to_string(),
             issue: Required,
         });
+    } else if name.len() > *MAX_NAME_LEN {
+        validation_errors.push(FailedValidation {
+            field: "name".to_string(),
+            issue: TooLarge,
+        });
     }
 
-    if !validation_errors.is_empty() {
-        return Err(AppError::ValidationError(validation_errors));
+    // Only admins of an organization can add meters to it
+    if let Some(organization_id) = &input.organization_id {
+        if !state
+            .user_helper
+            .has_organization_role(state.storage.clone(), user_id, organization_id, Admin)
+            .await?
+        {
+            validation_errors.push(FailedValidation {
+                field: "organization_id".to_string(),
+                issue: Invalid,
+            });
+        }
+    }
+
+    if let Some(site_id) = &input.site_id {
+        if !state
+            .user_helper
+            .has_site_role(state.storage.clone(), user_id, site_id, Admin)
+            .await?
+        {
+            validation_errors.push(FailedValidation {
+                field: "site_id".to_string(),
+                issue: Invalid,
+            });
+        }
     }
 
-    let meter = FluidMeter {
+    return Ok(validation_errors);
+}
+
+fn new_fluid_meter(user_id: &str, input: &CreateFluidMeterInput) -> FluidMeter {
+    let owner_id = match input.organization_id {
+        Some(_) => None,
+        None => Some(user_id.to_string()),
+    };
+    let now = Utc::now().naive_utc();
+    return FluidMeter {
         id: Uuid::new_v4().to_string(),
-        name: name.to_string(),
+        name: input.name.trim().to_string(),
         status: FluidMeterStatus::Active,
-        owner_id: user.id.clone(),
-        recorded_at: Utc::now().naive_utc(),
-        updated_at: Utc::now().naive_utc(),
+        owner_id,
+        organization_id: input.organization_id.clone(),
+        site_id: input.site_id.clone(),
+        parent_id: None,
+        recorded_at: now,
+        updated_at: now,
+        deleted_at: None,
+        reporting_interval_secs: None,
+        activated_at: now,
     };
+}
 
+/// Creates a new fluid meter
+pub async fn create_fluid_meter(
+    State(state): State<AppState>,
+    user: Extension<User>,
+    Extractor(input): Extractor<CreateFluidMeterInput>,
+) -> Result<Extractor<FluidMeter>, AppError> {
+    let validation_errors = validate_new_fluid_meter(&state, &user.id, &input).await?;
+    if !validation_errors.is_empty() {
+        return Err(AppError::ValidationError(validation_errors));
+    }
+
+    let meter = new_fluid_meter(&user.id, &input);
     let meter = match state.storage.insert_fluid_meter(&meter).await {
         Ok(m) => m,
         Err(_) => return internal_error(),
@@ -102,22 +197,122 @@ pub async fn create_fluid_meter(
     return Ok(Extractor(meter));
 }
 
+/// Binds a registered device to the logged in user using the code that came
+/// with it. A meter is created for the device, which gets its id the next
+/// time it checks in
+pub async fn claim_fluid_meter(
+    State(state): State<AppState>,
+    user: Extension<User>,
+    Extractor(input): Extractor<ClaimFluidMeterInput>,
+) -> Result<Extractor<FluidMeter>, AppError> {
+    let mut validation_errors = validate_new_fluid_meter(&state, &user.id, &input.meter).await?;
+
+    let claim_code = normalize_claim_code(&input.claim_code);
+    if claim_code.len() == 0 {
+        validation_errors.push(FailedValidation {
+            field: "claim_code".to_string(),
+            issue: Required,
+        });
+    }
+
+    if !validation_errors.is_empty() {
+        return Err(AppError::ValidationError(validation_errors));
+    }
+
+    let meter = new_fluid_meter(&user.id, &input.meter);
+    match state.storage.claim_device(&claim_code, &meter).await {
+        Ok(m) => return Ok(Extractor(m)),
+        Err(e) => {
+            // Unknown and already claimed codes look the same to the user
+            if e.code == NotFoundError {
+                return Err(AppError::ValidationError(vec![FailedValidation {
+                    field: "claim_code".to_string(),
+                    issue: Invalid,
+                }]));
+            }
+            return internal_error();
+        }
+    }
+}
+
+/// Updates the given fields of a meter
+pub async fn update_fluid_meter(
+    State(state): State<AppState>,
+    Path(meter_id): Path<String>,
+    user: Extension<User>,
+    Extractor(input): Extractor<UpdateFluidMeterInput>,
+) -> Result<Extractor<FluidMeter>, AppError> {
+    require_fluid_meter_role(
+        &state.user_helper,
+        state.storage.clone(),
+        &user.id,
+        &meter_id,
+        Manager,
+    )
+    .await?;
+
+    let mut meter = match state.storage.get_fluid_meter_by_id(&meter_id).await? {
+        Some(m) => m,
+        None => {
+            error!("User has role for non existing meter: {}", meter_id);
+            return internal_error();
+        }
+    };
+
+    let mut validation_errors = vec![];
+    if let Some(name) = &input.name {
+        let name = name.trim();
+        if name.len() == 0 {
+            validation_errors.push(FailedValidation {
+                field: "name".to_string(),
+                issue: Required,
+            });
+        } else if name.len() > *MAX_NAME_LEN {
+            validation_errors.push(FailedValidation {
+                field: "name".to_string(),
+                issue: TooLarge,
+            });
+        }
+        meter.name = name.to_string();
+    }
+
+    if let Some(site_id) = &input.site_id {
+        if !state
+            .user_helper
+            .has_site_role(state.storage.clone(), &user.id, site_id, Admin)
+            .await?
+        {
+            validation_errors.push(FailedValidation {
+                field: "site_id".to_string(),
+                issue: Invalid,
+            });
+        }
+        meter.site_id = Some(site_id.clone());
+    }
+
+    if !validation_errors.is_empty() {
+        return Err(AppError::ValidationError(validation_errors));
+    }
+
+    let meter = state.storage.update_fluid_meter(&meter).await?;
+
+    return Ok(Extractor(meter));
+}
+
 /// Deletes a meter
 pub async fn delete_fluid_meter(
     State(state): State<AppState>,
     Path(meter_id): Path<String>,
     user: Extension<User>,
 ) -> Result<Extractor<()>, AppError> {
-    if !state
-        .user_helper
-        .owns_fluid_meter(state.storage.clone(), &user.id, &meter_id)
-        .await?
-    {
-        return Err(AppError::ValidationError(vec![FailedValidation {
-            field: "meter_id".to_string(),
-            issue: Invalid,
-        }]));
-    }
+    require_fluid_meter_role(
+        &state.user_helper,
+        state.storage.clone(),
+        &user.id,
+        &meter_id,
+        Owner,
+    )
+    .await?;
 
     match state.storage.delete_fluid_meter(&meter_id).await {
         Ok(_) => {
@@ -130,56 +325,278 @@ pub async fn delete_fluid_meter(
     }
 }
 
-/// Gets information about a specific fluid meter
-pub async fn get_fluid_meter(
+/// Lists the meters the logged in user deleted and can still restore
+pub async fn deleted_fluid_meters(
+    State(state): State<AppState>,
+    Query(input): Query<PageInput>,
+    user: Extension<User>,
+) -> Result<Extractor<PaginatedResponse<FluidMeter>>, AppError> {
+    let (page_size, validation_errors) = validate_page(
+        input.page_size,
+        &input.page_cursor,
+        DELETED_FLUID_METERS_SORT,
+        Timestamp,
+    );
+    if !validation_errors.is_empty() {
+        return Err(AppError::ValidationError(validation_errors));
+    }
+
+    let options = PaginatedRequest {
+        page_size,
+        page_cursor: input.page_cursor,
+    };
+    let since =
+        Utc::now().naive_utc() - Duration::days(state.settings.fluid_meter.restore_window_days);
+    let meters = state
+        .storage
+        .deleted_fluid_meters(&user.id, since, &options)
+        .await?;
+
+    return Ok(Extractor(meters));
+}
+
+/// Restores a deleted meter. Only possible before its restore window ends.
+/// Nobody has a role for deleted meters, so this checks the user owns it
+/// directly or as an owner of its organization, like the deleted meters list
+pub async fn restore_fluid_meter(
     State(state): State<AppState>,
     Path(meter_id): Path<String>,
     user: Extension<User>,
 ) -> Result<Extractor<FluidMeter>, AppError> {
-    if !state
-        .user_helper
-        .owns_fluid_meter(state.storage.clone(), &user.id, &meter_id)
-        .await?
-    {
-        return Err(AppError::ValidationError(vec![FailedValidation {
+    let meter = match state.storage.get_fluid_meter_by_id(&meter_id).await? {
+        Some(m) => m,
+        None => return not_found(),
+    };
+    let owns_meter = match (&meter.owner_id, &meter.organization_id) {
+        (Some(owner_id), _) if owner_id == &user.id => true,
+        (_, Some(organization_id)) => {
+            state
+                .user_helper
+                .has_organization_role(
+                    state.storage.clone(),
+                    &user.id,
+                    organization_id,
+                    OrganizationRole::Owner,
+                )
+                .await?
+        }
+        _ => false,
+    };
+    if !owns_meter {
+        return not_found();
+    }
+    let since =
+        Utc::now().naive_utc() - Duration::days(state.settings.fluid_meter.restore_window_days);
+    if meter.status != FluidMeterStatus::Deleted || meter.deleted_at.is_none_or(|d| d < since) {
+        return validation_error(vec![FailedValidation {
             field: "meter_id".to_string(),
             issue: Invalid,
-        }]));
+        }]);
     }
 
-    match state.storage.get_fluid_meter_by_id(&meter_id).await {
+    let meter = state.storage.restore_fluid_meter(&meter_id).await?;
+
+    return Ok(Extractor(meter));
+}
+
+/// Gets information about a specific fluid meter, including when it last
+/// reported, its open alerts and the telemetry of its device
+pub async fn get_fluid_meter(
+    State(state): State<AppState>,
+    Path(meter_id): Path<String>,
+    user: Extension<User>,
+) -> Result<Extractor<FluidMeterDetail>, AppError> {
+    require_fluid_meter_role(
+        &state.user_helper,
+        state.storage.clone(),
+        &user.id,
+        &meter_id,
+        Viewer,
+    )
+    .await?;
+
+    let summary = match state.storage.get_fluid_meter_summary(&meter_id).await {
         Ok(m) => {
             if m.is_none() {
                 error!("User owns none existing meter: {}", meter_id);
                 return internal_error();
             }
 
-            return Ok(Extractor(m.unwrap()));
+            m.unwrap()
         }
         Err(e) => {
             error!("Error getting fluid meter: {}. Error: {}", meter_id, e);
             return internal_error();
         }
+    };
+
+    let device_status = state
+        .storage
+        .device_status_by_fluid_meter(&meter_id)
+        .await?;
+
+    return Ok(Extractor(FluidMeterDetail {
+        summary,
+        device_status,
+    }));
+}
+
+/// Assigns the meter to a site, or removes it from its site. The user needs to
+/// be able to manage both
+pub async fn set_fluid_meter_site(
+    State(state): State<AppState>,
+    Path(meter_id): Path<String>,
+    user: Extension<User>,
+    Extractor(input): Extractor<SetFluidMeterSiteInput>,
+) -> Result<Extractor<()>, AppError> {
+    require_fluid_meter_role(
+        &state.user_helper,
+        state.storage.clone(),
+        &user.id,
+        &meter_id,
+        Manager,
+    )
+    .await?;
+
+    if let Some(site_id) = &input.site_id {
+        if !state
+            .user_helper
+            .has_site_role(state.storage.clone(), &user.id, site_id, Admin)
+            .await?
+        {
+            return Err(AppError::ValidationError(vec![FailedValidation {
+                field: "site_id".to_string(),
+                issue: Invalid,
+            }]));
+        }
     }
+
+    state
+        .storage
+        .set_fluid_meter_site(&meter_id, &input.site_id)
+        .await?;
+
+    return Ok(Extractor(()));
 }
 
-/// Activate given meter
-pub async fn activate_fluid_meter(
+/// Sets how often the device of the meter can send measurements. The device
+/// is sent a command with the new interval
+pub async fn set_fluid_meter_reporting_interval(
     State(state): State<AppState>,
     Path(meter_id): Path<String>,
     user: Extension<User>,
+    Extractor(input): Extractor<SetReportingIntervalInput>,
+) -> Result<Extractor<DeviceCommand>, AppError> {
+    require_fluid_meter_role(
+        &state.user_helper,
+        state.storage.clone(),
+        &user.id,
+        &meter_id,
+        Owner,
+    )
+    .await?;
+
+    if let Some(i) = input.reporting_interval_secs {
+        if !(*MIN_REPORTING_INTERVAL_SECS..=*MAX_REPORTING_INTERVAL_SECS).contains(&i) {
+            return Err(AppError::ValidationError(vec![FailedValidation {
+                field: "reporting_interval_secs".to_string(),
+                issue: Invalid,
+            }]));
+        }
+    }
+
+    state
+        .storage
+        .set_fluid_meter_reporting_interval(&meter_id, input.reporting_interval_secs)
+        .await?;
+
+    let command = DeviceCommand {
+        id: Uuid::new_v4().to_string(),
+        fluid_meter_id: meter_id,
+        command_type: SetReportingInterval,
+        value: Some(
+            input
+                .reporting_interval_secs
+                .unwrap_or(state.settings.fluid_meter.default_reporting_interval_secs),
+        ),
+        status: Pending,
+        created_by: Some(user.id.clone()),
+        result: None,
+        recorded_at: Utc::now().naive_utc(),
+        sent_at: None,
+        acknowledged_at: None,
+    };
+    let command = state.storage.insert_device_command(&command).await?;
+
+    return Ok(Extractor(command));
+}
+
+/// Makes the meter a sub-meter of another one, or a top-level meter again. The
+/// user needs to be able to manage both meters
+pub async fn set_fluid_meter_parent(
+    State(state): State<AppState>,
+    Path(meter_id): Path<String>,
+    user: Extension<User>,
+    Extractor(input): Extractor<SetFluidMeterParentInput>,
 ) -> Result<Extractor<()>, AppError> {
-    if !state
-        .user_helper
-        .owns_fluid_meter(state.storage.clone(), &user.id, &meter_id)
-        .await?
-    {
-        return Err(AppError::ValidationError(vec![FailedValidation {
-            field: "meter_id".to_string(),
+    require_fluid_meter_role(
+        &state.user_helper,
+        state.storage.clone(),
+        &user.id,
+        &meter_id,
+        Manager,
+    )
+    .await?;
+
+    if let Some(parent_id) = &input.parent_id {
+        let invalid_parent = Err(AppError::ValidationError(vec![FailedValidation {
+            field: "parent_id".to_string(),
             issue: Invalid,
         }]));
+        if !state
+            .user_helper
+            .has_fluid_meter_role(state.storage.clone(), &user.id, parent_id, Manager)
+            .await?
+        {
+            return invalid_parent;
+        }
+
+        // Walk up from the new parent to make sure we don't create a cycle
+        let mut ancestor = Some(parent_id.clone());
+        while let Some(a) = ancestor {
+            if a == meter_id {
+                return invalid_parent;
+            }
+            ancestor = match state.storage.get_fluid_meter_by_id(&a).await? {
+                Some(m) => m.parent_id,
+                None => None,
+            };
+        }
     }
 
+    state
+        .storage
+        .set_fluid_meter_parent(&meter_id, &input.parent_id)
+        .await?;
+
+    return Ok(Extractor(()));
+}
+
+/// Activate given meter
+pub async fn activate_fluid_meter(
+    State(state): State<AppState>,
+    Path(meter_id): Path<String>,
+    user: Extension<User>,
+) -> Result<Extractor<()>, AppError> {
+    require_fluid_meter_role(
+        &state.user_helper,
+        state.storage.clone(),
+        &user.id,
+        &meter_id,
+        Manager,
+    )
+    .await?;
+
     match state.storage.activate_fluid_meter(&meter_id).await {
         Ok(_) => {
             return Ok(Extractor(()));
@@ -197,16 +614,14 @@ pub async fn deactivate_fluid_meter(
     Path(meter_id): Path<String>,
     user: Extension<User>,
 ) -> Result<Extractor<()>, AppError> {
-    if !state
-        .user_helper
-        .owns_fluid_meter(state.storage.clone(), &user.id, &meter_id)
-        .await?
-    {
-        return Err(AppError::ValidationError(vec![FailedValidation {
-            field: "meter_id".to_string(),
-            issue: Invalid,
-        }]));
-    }
+    require_fluid_meter_role(
+        &state.user_helper,
+        state.storage.clone(),
+        &user.id,
+        &meter_id,
+        Manager,
+    )
+    .await?;
 
     match state.storage.deactivate_fluid_meter(&meter_id).await {
         Ok(_) => {
@@ -225,16 +640,14 @@ pub async fn get_fluid_meter_alerts(
     Path(meter_id): Path<String>,
     user: Extension<User>,
 ) -> Result<Extractor<FluidMeterAlerts>, AppError> {
-    if !state
-        .user_helper
-        .owns_fluid_meter(state.storage.clone(), &user.id, &meter_id)
-        .await?
-    {
-        return Err(AppError::ValidationError(vec![FailedValidation {
-            field: "meter_id".to_string(),
-            issue: Invalid,
-        }]));
-    }
+    require_fluid_meter_role(
+        &state.user_helper,
+        state.storage.clone(),
+        &user.id,
+        &meter_id,
+        Viewer,
+    )
+    .await?;
 
     let fm = match state.storage.get_fluid_meter_by_id(&meter_id).await {
         Ok(m) => m.unwrap(),
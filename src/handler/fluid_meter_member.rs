@@ -0,0 +1,258 @@
+use axum::{
+    extract::{Path, Query, State},
+    Extension,
+};
+use email_address::EmailAddress;
+use tracing::error;
+
+use crate::{
+    api::{
+        common::{PageInput, PaginatedRequest, SortKeyType::Timestamp},
+        fluid_meter_member::{
+            AcceptFluidMeterInviteInput, FluidMeterInvite, FluidMeterMember, FluidMeterMembers,
+            FluidMeterRole::{Manager, Owner, Viewer},
+            InviteFluidMeterMemberInput, UpdateFluidMeterMemberInput, FLUID_METER_MEMBERS_SORT,
+        },
+        user::User,
+    },
+    error::app_error::{
+        forbidden, internal_error, not_found, validation_error, AppError, FailedValidation,
+        ValidationIssue::Invalid,
+    },
+    helper::{pagination::validate_page, user::require_fluid_meter_role},
+    json::extractor::Extractor,
+    AppState,
+};
+
+/// Lists the members of a meter, a page at a time, and the invites that haven't
+/// been accepted
+pub async fn fluid_meter_members(
+    State(state): State<AppState>,
+    Path(meter_id): Path<String>,
+    Query(input): Query<PageInput>,
+    user: Extension<User>,
+) -> Result<Extractor<FluidMeterMembers>, AppError> {
+    require_fluid_meter_role(
+        &state.user_helper,
+        state.storage.clone(),
+        &user.id,
+        &meter_id,
+        Viewer,
+    )
+    .await?;
+
+    let (page_size, validation_errors) = validate_page(
+        input.page_size,
+        &input.page_cursor,
+        FLUID_METER_MEMBERS_SORT,
+        Timestamp,
+    );
+    if !validation_errors.is_empty() {
+        return validation_error(validation_errors);
+    }
+
+    let options = PaginatedRequest {
+        page_size,
+        page_cursor: input.page_cursor,
+    };
+    let members = state
+        .storage
+        .fluid_meter_members(&meter_id, &options)
+        .await?;
+    let invites = state.storage.fluid_meter_invites(&meter_id).await?;
+
+    return Ok(Extractor(FluidMeterMembers { members, invites }));
+}
+
+/// Invites someone to a meter by e-mail. They become a member once they accept
+pub async fn invite_fluid_meter_member(
+    State(state): State<AppState>,
+    Path(meter_id): Path<String>,
+    user: Extension<User>,
+    Extractor(input): Extractor<InviteFluidMeterMemberInput>,
+) -> Result<Extractor<FluidMeterInvite>, AppError> {
+    require_fluid_meter_role(
+        &state.user_helper,
+        state.storage.clone(),
+        &user.id,
+        &meter_id,
+        Manager,
+    )
+    .await?;
+
+    let mut validation_errors = vec![];
+    let clean_mail = input.email.trim().to_lowercase();
+    if !EmailAddress::is_valid(&clean_mail) || clean_mail == user.email {
+        validation_errors.push(FailedValidation {
+            field: "email".to_string(),
+            issue: Invalid,
+        });
+    }
+
+    // There can only be one owner
+    if input.role == Owner {
+        validation_errors.push(FailedValidation {
+            field: "role".to_string(),
+            issue: Invalid,
+        });
+    }
+
+    if !validation_errors.is_empty() {
+        return validation_error(validation_errors);
+    }
+
+    let meter = match state.storage.get_fluid_meter_by_id(&meter_id).await? {
+        Some(m) => m,
+        None => {
+            error!("User has role for non existing meter: {}", meter_id);
+            return internal_error();
+        }
+    };
+
+    let invite = state
+        .storage
+        .create_fluid_meter_invite(
+            &meter,
+            &clean_mail,
+            &input.role,
+            &user,
+            state.settings.clone(),
+            state.mail_helper.clone(),
+        )
+        .await?;
+
+    return Ok(Extractor(invite));
+}
+
+/// Accepts an invite to a meter. The invite needs to have been sent to the
+/// e-mail of the logged in user
+pub async fn accept_fluid_meter_invite(
+    State(state): State<AppState>,
+    user: Extension<User>,
+    Extractor(input): Extractor<AcceptFluidMeterInviteInput>,
+) -> Result<Extractor<FluidMeterMember>, AppError> {
+    let invalid_token = vec![FailedValidation {
+        field: "token".to_string(),
+        issue: Invalid,
+    }];
+
+    let invite = match state
+        .storage
+        .fluid_meter_invite_by_token(&input.token)
+        .await?
+    {
+        Some(i) => i,
+        None => return validation_error(invalid_token),
+    };
+    if invite.email != user.email {
+        return validation_error(invalid_token);
+    }
+
+    // The owner can't become a member of their own meter
+    let role = state
+        .storage
+        .fluid_meter_role(&invite.fluid_meter_id, &user.id)
+        .await?;
+    if role == Some(Owner) {
+        return validation_error(invalid_token);
+    }
+
+    let member = state
+        .storage
+        .accept_fluid_meter_invite(&invite, &user.id)
+        .await?;
+
+    return Ok(Extractor(member));
+}
+
+/// Updates a member of a meter. Managers can change the role of other members.
+/// Members can only choose if they want to receive alerts for themselves
+pub async fn update_fluid_meter_member(
+    State(state): State<AppState>,
+    Path((meter_id, account_id)): Path<(String, String)>,
+    user: Extension<User>,
+    Extractor(input): Extractor<UpdateFluidMeterMemberInput>,
+) -> Result<Extractor<FluidMeterMember>, AppError> {
+    require_fluid_meter_role(
+        &state.user_helper,
+        state.storage.clone(),
+        &user.id,
+        &meter_id,
+        Viewer,
+    )
+    .await?;
+
+    let mut member = match state
+        .storage
+        .fluid_meter_member(&meter_id, &account_id)
+        .await?
+    {
+        Some(m) => m,
+        None => return not_found(),
+    };
+
+    if let Some(role) = input.role {
+        require_fluid_meter_role(
+            &state.user_helper,
+            state.storage.clone(),
+            &user.id,
+            &meter_id,
+            Manager,
+        )
+        .await?;
+
+        if role == Owner {
+            return validation_error(vec![FailedValidation {
+                field: "role".to_string(),
+                issue: Invalid,
+            }]);
+        }
+
+        member.role = role;
+    }
+
+    if let Some(alerts) = input.alerts {
+        if account_id != user.id {
+            return forbidden();
+        }
+
+        member.alerts = alerts;
+    }
+
+    let member = state.storage.update_fluid_meter_member(&member).await?;
+    return Ok(Extractor(member));
+}
+
+/// Removes a member from a meter. Members can remove themselves
+pub async fn delete_fluid_meter_member(
+    State(state): State<AppState>,
+    Path((meter_id, account_id)): Path<(String, String)>,
+    user: Extension<User>,
+) -> Result<Extractor<()>, AppError> {
+    if account_id != user.id {
+        require_fluid_meter_role(
+            &state.user_helper,
+            state.storage.clone(),
+            &user.id,
+            &meter_id,
+            Manager,
+        )
+        .await?;
+    }
+
+    if state
+        .storage
+        .fluid_meter_member(&meter_id, &account_id)
+        .await?
+        .is_none()
+    {
+        return not_found();
+    }
+
+    state
+        .storage
+        .delete_fluid_meter_member(&meter_id, &account_id)
+        .await?;
+
+    return Ok(Extractor(()));
+}
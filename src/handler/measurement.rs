@@ -2,67 +2,60 @@ use axum::{
     extract::{Path, Query, State},
     Extension,
 };
-use chrono::{Duration, NaiveTime, Utc};
-use uuid::Uuid;
 
 use crate::{
     api::{
-        common::{Series, SeriesGranularity},
-        fluid_meter::FluidMeterStatus::Active,
-        measurement::{GetMeasurementsInput, Measurement, SaveMeasurementInput},
+        common::Series,
+        fluid_meter::Reconciliation,
+        fluid_meter_member::FluidMeterRole::Viewer,
+        measurement::{GetMeasurementsInput, SaveMeasurementInput, SaveMeasurementResponse},
         user::User,
     },
-    error::app_error::{
-        internal_error, validation_error, AppError, FailedValidation,
-        ValidationIssue::{Invalid, Required, TooFrequent},
+    error::app_error::{unauthorized, AppError},
+    helper::{
+        alert::{RECONCILIATION_MAX_MEASUREMENTS, UNACCOUNTED_THRESHOLD},
+        device::device_secret_matches,
+        measurement::{create_series, reconcile, record_measurement, series_range},
+        user::require_fluid_meter_role,
     },
-    helper::measurement::create_series,
     json::extractor::Extractor,
-    storage::error::ErrorCode::RateLimitError,
     AppState,
 };
 
+/// Saves a measurement sent by a device over HTTP. The response includes how
+/// often it can report. Meters with a claimed device only take measurements
+/// sent with its secret, since they can close its valve. Telemetry is only
+/// taken from them
 pub async fn save_measurement(
     State(state): State<AppState>,
     Extractor(input): Extractor<SaveMeasurementInput>,
-) -> Result<Extractor<Measurement>, AppError> {
-    let meter = state
+) -> Result<Extractor<SaveMeasurementResponse>, AppError> {
+    let device = state
         .storage
-        .get_fluid_meter_by_id(&input.device_id)
+        .device_by_fluid_meter(&input.device_id)
         .await?;
-    match meter {
-        Some(m) => {
-            if m.status == Active {
-                let measurement = Measurement {
-                    id: Uuid::new_v4().to_string(),
-                    device_id: input.device_id,
-                    measurement: input.measurement,
-                    recorded_at: Utc::now().naive_utc(),
-                };
-                match state.storage.save_measurement(&measurement).await {
-                    Ok(_) => {}
-                    Err(e) => {
-                        if e.code == RateLimitError {
-                            let validation_errors = vec![FailedValidation {
-                                field: "request".to_string(),
-                                issue: TooFrequent,
-                            }];
-                            return validation_error(validation_errors);
-                        }
-                        return internal_error();
-                    }
-                }
-
-                return Ok(Extractor(measurement));
-            }
+    if let Some(d) = &device {
+        let authenticated = input
+            .secret
+            .as_ref()
+            .is_some_and(|s| device_secret_matches(d, s));
+        if !authenticated {
+            return unauthorized();
         }
-        None => {}
     }
 
-    return validation_error(vec![FailedValidation {
-        field: "device_id".to_string(),
-        issue: Invalid,
-    }]);
+    let (measurement, reporting_interval_secs) = record_measurement(
+        state.storage.clone(),
+        &state.settings,
+        input,
+        device.is_some(),
+    )
+    .await?;
+
+    return Ok(Extractor(SaveMeasurementResponse {
+        measurement,
+        reporting_interval_secs,
+    }));
 }
 
 /// Gets the measurements for the given meter.
@@ -74,37 +67,16 @@ pub async fn get_measurements_for_meter(
     user: Extension<User>,
     Query(input): Query<GetMeasurementsInput>,
 ) -> Result<Extractor<Series>, AppError> {
-    if !state
-        .user_helper
-        .owns_fluid_meter(state.storage.clone(), &user.id, &meter_id)
-        .await?
-    {
-        return Err(AppError::ValidationError(vec![FailedValidation {
-            field: "meter_id".to_string(),
-            issue: Invalid,
-        }]));
-    }
+    require_fluid_meter_role(
+        &state.user_helper,
+        state.storage.clone(),
+        &user.id,
+        &meter_id,
+        Viewer,
+    )
+    .await?;
 
-    let from;
-    let to;
-    let granularity = input.granularity.unwrap_or(SeriesGranularity::Day);
-    match granularity {
-        SeriesGranularity::Hour => {
-            if input.day.is_none() {
-                return Err(AppError::ValidationError(vec![FailedValidation {
-                    field: "day".to_string(),
-                    issue: Required,
-                }]));
-            }
-
-            from = input.day.unwrap().and_time(NaiveTime::MIN);
-            to = from + Duration::hours(24);
-        }
-        _ => {
-            to = Utc::now().naive_utc();
-            from = to - Duration::days(30);
-        }
-    }
+    let (from, to, granularity) = series_range(&input)?;
 
     let measurements = state
         .storage
@@ -115,3 +87,43 @@ pub async fn get_measurements_for_meter(
 
     Ok(Extractor(ret))
 }
+
+/// Compares the consumption of a meter against the one of all its sub-meters
+/// together, period by period. Takes the same options as get_measurements_for_meter
+pub async fn get_reconciliation_for_meter(
+    State(state): State<AppState>,
+    Path(meter_id): Path<String>,
+    user: Extension<User>,
+    Query(input): Query<GetMeasurementsInput>,
+) -> Result<Extractor<Reconciliation>, AppError> {
+    require_fluid_meter_role(
+        &state.user_helper,
+        state.storage.clone(),
+        &user.id,
+        &meter_id,
+        Viewer,
+    )
+    .await?;
+
+    let (from, to, granularity) = series_range(&input)?;
+    let parent = state
+        .storage
+        .get_measurements(meter_id.clone(), from, to, *RECONCILIATION_MAX_MEASUREMENTS)
+        .await?;
+    let children = state
+        .storage
+        .get_children_measurements(&meter_id, from, to, *RECONCILIATION_MAX_MEASUREMENTS)
+        .await?;
+
+    let items = reconcile(
+        &create_series(&parent, granularity.clone()),
+        &create_series(&children, granularity.clone()),
+        *UNACCOUNTED_THRESHOLD,
+    );
+
+    return Ok(Extractor(Reconciliation {
+        granularity,
+        threshold: *UNACCOUNTED_THRESHOLD,
+        items,
+    }));
+}
@@ -0,0 +1,32 @@
+use crate::{
+    error::app_error::{not_found, unauthorized, AppError},
+    helper::{metrics::render, token::has_bearer_token},
+    AppState,
+};
+
+use axum::{
+    extract::State,
+    http::{header::CONTENT_TYPE, HeaderMap},
+    response::IntoResponse,
+};
+
+/// Metrics in the Prometheus text format. Only served if a token is configured,
+/// and only to clients that send it
+pub async fn metrics(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let token = match &state.settings.metrics {
+        Some(m) if m.token.len() > 0 => &m.token,
+        _ => return not_found(),
+    };
+
+    if !has_bearer_token(&headers, token) {
+        return unauthorized();
+    }
+
+    return Ok((
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        render(&state.storage),
+    ));
+}
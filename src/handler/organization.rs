@@ -0,0 +1,274 @@
+use axum::{
+    extract::{Path, Query, State},
+    Extension,
+};
+use chrono::Utc;
+use email_address::EmailAddress;
+use uuid::Uuid;
+
+use crate::{
+    api::{
+        common::{
+            PageInput, PaginatedRequest, PaginatedResponse, Series,
+            SortKeyType::{Text, Timestamp},
+        },
+        measurement::GetMeasurementsInput,
+        organization::{
+            AddOrganizationMemberInput, CreateOrganizationInput, Organization, OrganizationMember,
+            OrganizationRole::{Admin, Member, Owner},
+            ORGANIZATIONS_SORT, ORGANIZATION_MEMBERS_SORT,
+        },
+        user::{User, UserAuthProvider::Password},
+    },
+    error::app_error::{
+        forbidden, not_found, validation_error, AppError, FailedValidation,
+        ValidationIssue::{Invalid, Required},
+    },
+    helper::{
+        measurement::{create_series, series_range},
+        pagination::validate_page,
+        user::require_organization_role,
+    },
+    json::extractor::Extractor,
+    AppState,
+};
+
+// Organizations can have many meters, so we allow more measurements than for a
+// single meter
+pub const MAX_ORGANIZATION_MEASUREMENTS: &'static u32 = &25000;
+
+fn invalid_account<T>() -> Result<T, AppError> {
+    return validation_error(vec![FailedValidation {
+        field: "account_id".to_string(),
+        issue: Invalid,
+    }]);
+}
+
+/// Lists the organizations the logged in user is a member of
+pub async fn organizations(
+    State(state): State<AppState>,
+    Query(input): Query<PageInput>,
+    user: Extension<User>,
+) -> Result<Extractor<PaginatedResponse<Organization>>, AppError> {
+    let (page_size, validation_errors) = validate_page(
+        input.page_size,
+        &input.page_cursor,
+        ORGANIZATIONS_SORT,
+        Text,
+    );
+    if !validation_errors.is_empty() {
+        return validation_error(validation_errors);
+    }
+
+    let options = PaginatedRequest {
+        page_size,
+        page_cursor: input.page_cursor,
+    };
+    let organizations = state
+        .storage
+        .organizations_by_user(&user.id, &options)
+        .await?;
+    return Ok(Extractor(organizations));
+}
+
+/// Creates an organization. The logged in user becomes its owner
+pub async fn create_organization(
+    State(state): State<AppState>,
+    user: Extension<User>,
+    Extractor(input): Extractor<CreateOrganizationInput>,
+) -> Result<Extractor<Organization>, AppError> {
+    let name = input.name.trim();
+    if name.len() == 0 {
+        return validation_error(vec![FailedValidation {
+            field: "name".to_string(),
+            issue: Required,
+        }]);
+    }
+
+    let organization = Organization {
+        id: Uuid::new_v4().to_string(),
+        name: name.to_string(),
+        recorded_at: Utc::now().naive_utc(),
+    };
+    let organization = state
+        .storage
+        .create_organization(&organization, &user.id)
+        .await?;
+
+    return Ok(Extractor(organization));
+}
+
+/// Lists the members of an organization
+pub async fn organization_members(
+    State(state): State<AppState>,
+    Path(organization_id): Path<String>,
+    Query(input): Query<PageInput>,
+    user: Extension<User>,
+) -> Result<Extractor<PaginatedResponse<OrganizationMember>>, AppError> {
+    require_organization_role(
+        &state.user_helper,
+        state.storage.clone(),
+        &user.id,
+        &organization_id,
+        Member,
+    )
+    .await?;
+
+    let (page_size, validation_errors) = validate_page(
+        input.page_size,
+        &input.page_cursor,
+        ORGANIZATION_MEMBERS_SORT,
+        Timestamp,
+    );
+    if !validation_errors.is_empty() {
+        return validation_error(validation_errors);
+    }
+
+    let options = PaginatedRequest {
+        page_size,
+        page_cursor: input.page_cursor,
+    };
+    let members = state
+        .storage
+        .organization_members(&organization_id, &options)
+        .await?;
+    return Ok(Extractor(members));
+}
+
+/// Adds an existing account to the organization, or changes its role if it's
+/// already a member. Only owners can grant or take away the owner role
+pub async fn add_organization_member(
+    State(state): State<AppState>,
+    Path(organization_id): Path<String>,
+    user: Extension<User>,
+    Extractor(input): Extractor<AddOrganizationMemberInput>,
+) -> Result<Extractor<OrganizationMember>, AppError> {
+    require_organization_role(
+        &state.user_helper,
+        state.storage.clone(),
+        &user.id,
+        &organization_id,
+        Admin,
+    )
+    .await?;
+
+    let clean_mail = input.email.trim().to_lowercase();
+    let account_id = format!("{}+{}", clean_mail, Password);
+    let invalid_email = vec![FailedValidation {
+        field: "email".to_string(),
+        issue: Invalid,
+    }];
+    if !EmailAddress::is_valid(&clean_mail) || account_id == user.id {
+        return validation_error(invalid_email);
+    }
+    if state.storage.user_by_id(&account_id).await?.is_none() {
+        return validation_error(invalid_email);
+    }
+
+    let current = state
+        .storage
+        .organization_member(&organization_id, &account_id)
+        .await?;
+    let is_owner = current.as_ref().map(|m| m.role == Owner).unwrap_or(false);
+    if (input.role == Owner || is_owner)
+        && !state
+            .user_helper
+            .has_organization_role(state.storage.clone(), &user.id, &organization_id, Owner)
+            .await?
+    {
+        return forbidden();
+    }
+
+    let member = OrganizationMember {
+        organization_id,
+        account_id,
+        role: input.role,
+        recorded_at: current
+            .map(|m| m.recorded_at)
+            .unwrap_or(Utc::now().naive_utc()),
+    };
+    let member = state.storage.add_organization_member(&member).await?;
+
+    return Ok(Extractor(member));
+}
+
+/// Removes a member from the organization. Members can remove themselves,
+/// admins can remove members and admins, and owners can remove anyone. The
+/// last owner can't be removed
+pub async fn delete_organization_member(
+    State(state): State<AppState>,
+    Path((organization_id, account_id)): Path<(String, String)>,
+    user: Extension<User>,
+) -> Result<Extractor<()>, AppError> {
+    require_organization_role(
+        &state.user_helper,
+        state.storage.clone(),
+        &user.id,
+        &organization_id,
+        Member,
+    )
+    .await?;
+
+    let member = match state
+        .storage
+        .organization_member(&organization_id, &account_id)
+        .await?
+    {
+        Some(m) => m,
+        None => return not_found(),
+    };
+
+    if account_id != user.id {
+        let required = if member.role == Owner { Owner } else { Admin };
+        if !state
+            .user_helper
+            .has_organization_role(state.storage.clone(), &user.id, &organization_id, required)
+            .await?
+        {
+            return forbidden();
+        }
+    }
+
+    if member.role == Owner {
+        let owners = state
+            .storage
+            .count_organization_owners(&organization_id)
+            .await?;
+        if owners <= 1 {
+            return invalid_account();
+        }
+    }
+
+    state
+        .storage
+        .delete_organization_member(&organization_id, &account_id)
+        .await?;
+
+    return Ok(Extractor(()));
+}
+
+/// Gets the consumption of all the meters of the organization added together.
+/// Takes the same options as the measurements of a single meter
+pub async fn get_organization_consumption(
+    State(state): State<AppState>,
+    Path(organization_id): Path<String>,
+    user: Extension<User>,
+    Query(input): Query<GetMeasurementsInput>,
+) -> Result<Extractor<Series>, AppError> {
+    require_organization_role(
+        &state.user_helper,
+        state.storage.clone(),
+        &user.id,
+        &organization_id,
+        Member,
+    )
+    .await?;
+
+    let (from, to, granularity) = series_range(&input)?;
+    let measurements = state
+        .storage
+        .get_organization_measurements(&organization_id, from, to, *MAX_ORGANIZATION_MEASUREMENTS)
+        .await?;
+
+    return Ok(Extractor(create_series(&measurements, granularity)));
+}
@@ -0,0 +1,74 @@
+use crate::{
+    api::purge::PurgeResponse,
+    error::app_error::{not_found, too_many_requests, unauthorized, AppError},
+    handler::alert::DT_FORMAT,
+    helper::token::has_bearer_token,
+    json::extractor::Extractor,
+    AppState,
+};
+
+use axum::{extract::State, http::HeaderMap};
+use chrono::{Duration, NaiveDateTime, Utc};
+use tracing::{error, info};
+
+pub const LAST_PURGE_RUN_KEY: &'static str = "last-purge-run";
+pub const PURGE_MAX_FREQUENCY_MINS: &'static i64 = &10;
+
+/// Permanently deletes data that was scheduled for deletion and whose grace
+/// period already ended. Meant to be called periodically by a job that has the
+/// jobs token
+pub async fn purge(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Extractor<PurgeResponse>, AppError> {
+    let token = match &state.settings.jobs {
+        Some(j) if j.token.len() > 0 => &j.token,
+        _ => return not_found(),
+    };
+    if !has_bearer_token(&headers, token) {
+        return unauthorized();
+    }
+
+    if let Some(last_run) = state.storage.get_metadata(LAST_PURGE_RUN_KEY).await? {
+        let next_run = NaiveDateTime::parse_from_str(&last_run.value, DT_FORMAT).unwrap()
+            + Duration::minutes(*PURGE_MAX_FREQUENCY_MINS);
+        let now = Utc::now().naive_utc();
+        if next_run > now {
+            error!("Rate limiting purge. Last run: {}", last_run.value);
+            return too_many_requests((next_run - now).num_seconds().max(1) as u64);
+        }
+    }
+    state
+        .storage
+        .save_metadata(LAST_PURGE_RUN_KEY, &Utc::now().naive_utc().to_string())
+        .await?;
+
+    let accounts = state
+        .storage
+        .delete_accounts_scheduled_before(Utc::now().naive_utc())
+        .await?;
+    info!("Purged {} accounts", accounts);
+
+    let deleted_before =
+        Utc::now().naive_utc() - Duration::days(state.settings.fluid_meter.restore_window_days);
+    let (fluid_meters, measurements) = state
+        .storage
+        .purge_deleted_fluid_meters(deleted_before)
+        .await?;
+    info!(
+        "Purged {} fluid meters and {} measurements",
+        fluid_meters, measurements
+    );
+
+    let buckets = state
+        .storage
+        .delete_rate_limit_buckets(Utc::now().naive_utc() - Duration::minutes(1))
+        .await?;
+    info!("Purged {} rate limit buckets", buckets);
+
+    return Ok(Extractor(PurgeResponse {
+        accounts,
+        fluid_meters,
+        measurements,
+    }));
+}
@@ -0,0 +1,221 @@
+use axum::{
+    extract::{Path, Query, State},
+    Extension,
+};
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::{
+    api::{
+        common::{PageInput, PaginatedRequest, PaginatedResponse, Series, SortKeyType::Text},
+        measurement::GetMeasurementsInput,
+        organization::OrganizationRole::{Admin, Member},
+        site::{CreateSiteInput, Site, SITES_SORT},
+        user::User,
+    },
+    error::app_error::{
+        internal_error, validation_error, AppError, FailedValidation,
+        ValidationIssue::{Invalid, Required, TooLarge},
+    },
+    helper::{
+        measurement::{create_series, series_range},
+        pagination::validate_page,
+        user::require_site_role,
+    },
+    json::extractor::Extractor,
+    AppState,
+};
+
+pub const MAX_SITE_TAGS: &'static usize = &20;
+pub const MAX_SITE_TAG_LEN: &'static usize = &50;
+// Sites can have many meters, so we allow more measurements than for a single
+// meter
+pub const MAX_SITE_MEASUREMENTS: &'static u32 = &25000;
+
+/// Lists the sites of the logged in user and the ones of their organizations
+pub async fn sites(
+    State(state): State<AppState>,
+    Query(input): Query<PageInput>,
+    user: Extension<User>,
+) -> Result<Extractor<PaginatedResponse<Site>>, AppError> {
+    let (page_size, validation_errors) =
+        validate_page(input.page_size, &input.page_cursor, SITES_SORT, Text);
+    if !validation_errors.is_empty() {
+        return validation_error(validation_errors);
+    }
+
+    let options = PaginatedRequest {
+        page_size,
+        page_cursor: input.page_cursor,
+    };
+    let sites = state.storage.sites_by_user(&user.id, &options).await?;
+    return Ok(Extractor(sites));
+}
+
+/// Creates a site meters can be assigned to
+pub async fn create_site(
+    State(state): State<AppState>,
+    user: Extension<User>,
+    Extractor(input): Extractor<CreateSiteInput>,
+) -> Result<Extractor<Site>, AppError> {
+    let mut validation_errors = vec![];
+
+    let name = input.name.trim();
+    if name.len() == 0 {
+        validation_errors.push(FailedValidation {
+            field: "name".to_string(),
+            issue: Required,
+        });
+    }
+
+    match (input.latitude, input.longitude) {
+        (Some(lat), Some(lon)) => {
+            if !(-90.0..=90.0).contains(&lat) {
+                validation_errors.push(FailedValidation {
+                    field: "latitude".to_string(),
+                    issue: Invalid,
+                });
+            }
+            if !(-180.0..=180.0).contains(&lon) {
+                validation_errors.push(FailedValidation {
+                    field: "longitude".to_string(),
+                    issue: Invalid,
+                });
+            }
+        }
+        (Some(_), None) => {
+            validation_errors.push(FailedValidation {
+                field: "longitude".to_string(),
+                issue: Required,
+            });
+        }
+        (None, Some(_)) => {
+            validation_errors.push(FailedValidation {
+                field: "latitude".to_string(),
+                issue: Required,
+            });
+        }
+        (None, None) => {}
+    }
+
+    // Tags are compared case-insensitively, so we store them in lowercase
+    let mut tags: Vec<String> = input
+        .tags
+        .unwrap_or_default()
+        .iter()
+        .map(|t| t.trim().to_lowercase())
+        .filter(|t| t.len() > 0)
+        .collect();
+    tags.sort();
+    tags.dedup();
+    if tags.len() > *MAX_SITE_TAGS || tags.iter().any(|t| t.len() > *MAX_SITE_TAG_LEN) {
+        validation_errors.push(FailedValidation {
+            field: "tags".to_string(),
+            issue: TooLarge,
+        });
+    }
+
+    if let Some(organization_id) = &input.organization_id {
+        if !state
+            .user_helper
+            .has_organization_role(state.storage.clone(), &user.id, organization_id, Admin)
+            .await?
+        {
+            validation_errors.push(FailedValidation {
+                field: "organization_id".to_string(),
+                issue: Invalid,
+            });
+        }
+    }
+
+    if !validation_errors.is_empty() {
+        return validation_error(validation_errors);
+    }
+
+    let owner_id = match input.organization_id {
+        Some(_) => None,
+        None => Some(user.id.clone()),
+    };
+    let site = Site {
+        id: Uuid::new_v4().to_string(),
+        owner_id,
+        organization_id: input.organization_id.clone(),
+        name: name.to_string(),
+        address: input.address.trim().to_string(),
+        latitude: input.latitude,
+        longitude: input.longitude,
+        tags,
+        recorded_at: Utc::now().naive_utc(),
+    };
+    let site = state.storage.insert_site(&site).await?;
+
+    return Ok(Extractor(site));
+}
+
+/// Gets information about a specific site
+pub async fn get_site(
+    State(state): State<AppState>,
+    Path(site_id): Path<String>,
+    user: Extension<User>,
+) -> Result<Extractor<Site>, AppError> {
+    require_site_role(
+        &state.user_helper,
+        state.storage.clone(),
+        &user.id,
+        &site_id,
+        Member,
+    )
+    .await?;
+
+    match state.storage.site_by_id(&site_id).await? {
+        Some(s) => return Ok(Extractor(s)),
+        None => return internal_error(),
+    }
+}
+
+/// Deletes a site. Its meters are kept, but they aren't assigned to a site
+/// anymore
+pub async fn delete_site(
+    State(state): State<AppState>,
+    Path(site_id): Path<String>,
+    user: Extension<User>,
+) -> Result<Extractor<()>, AppError> {
+    require_site_role(
+        &state.user_helper,
+        state.storage.clone(),
+        &user.id,
+        &site_id,
+        Admin,
+    )
+    .await?;
+
+    state.storage.delete_site(&site_id).await?;
+
+    return Ok(Extractor(()));
+}
+
+/// Gets the consumption of all the meters assigned to the site added together.
+/// Takes the same options as the measurements of a single meter
+pub async fn get_site_consumption(
+    State(state): State<AppState>,
+    Path(site_id): Path<String>,
+    user: Extension<User>,
+    Query(input): Query<GetMeasurementsInput>,
+) -> Result<Extractor<Series>, AppError> {
+    require_site_role(
+        &state.user_helper,
+        state.storage.clone(),
+        &user.id,
+        &site_id,
+        Member,
+    )
+    .await?;
+
+    let (from, to, granularity) = series_range(&input)?;
+    let measurements = state
+        .storage
+        .get_site_measurements(&site_id, from, to, *MAX_SITE_MEASUREMENTS)
+        .await?;
+
+    return Ok(Extractor(create_series(&measurements, granularity)));
+}
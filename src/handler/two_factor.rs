@@ -0,0 +1,221 @@
+use axum::{extract::State, Extension};
+use chrono::Utc;
+use tracing::error;
+
+use crate::{
+    api::{
+        two_factor::{
+            ConfirmTwoFactorInput, DisableTwoFactorInput, DisableTwoFactorResponse,
+            TwoFactorEnrollment, TwoFactorLogInInput, TwoFactorRecoveryCodes,
+        },
+        user::{SessionToken, User},
+    },
+    error::app_error::{
+        internal_error, too_many_requests, validation_error, AppError, FailedValidation,
+        ValidationIssue::{Invalid, Required},
+    },
+    helper::two_factor::{
+        generate_recovery_codes, generate_secret, hash_recovery_code, lockout_secs, matching_step,
+        otpauth_uri, verify_second_factor,
+    },
+    json::extractor::Extractor,
+    AppState,
+};
+
+fn second_factor_required(
+    code: &Option<String>,
+    recovery_code: &Option<String>,
+) -> Result<(), AppError> {
+    if code.is_none() && recovery_code.is_none() {
+        return validation_error(vec![FailedValidation {
+            field: "code".to_string(),
+            issue: Required,
+        }]);
+    }
+
+    return Ok(());
+}
+
+/// Starts two factor enrollment for the logged in user. The returned secret
+/// needs to be confirmed with a valid code before it's enforced
+pub async fn enroll_two_factor(
+    State(state): State<AppState>,
+    user: Extension<User>,
+) -> Result<Extractor<TwoFactorEnrollment>, AppError> {
+    let existing = state.storage.two_factor_by_user(&user.id).await?;
+    if existing.is_some() && existing.unwrap().enabled_at.is_some() {
+        return validation_error(vec![FailedValidation {
+            field: "two_factor".to_string(),
+            issue: Invalid,
+        }]);
+    }
+
+    let secret = generate_secret();
+    let otpauth_uri = match otpauth_uri(&secret, &user.email) {
+        Some(u) => u,
+        None => return internal_error(),
+    };
+    state
+        .storage
+        .save_two_factor_secret(&user.id, &secret)
+        .await?;
+
+    return Ok(Extractor(TwoFactorEnrollment {
+        secret,
+        otpauth_uri,
+    }));
+}
+
+/// Confirms the user can generate valid codes and enables two factor
+/// authentication. Returns recovery codes. This is the only time they are shown
+pub async fn confirm_two_factor(
+    State(state): State<AppState>,
+    user: Extension<User>,
+    Extractor(input): Extractor<ConfirmTwoFactorInput>,
+) -> Result<Extractor<TwoFactorRecoveryCodes>, AppError> {
+    let invalid = vec![FailedValidation {
+        field: "code".to_string(),
+        issue: Invalid,
+    }];
+
+    let two_factor = match state.storage.two_factor_by_user(&user.id).await? {
+        Some(t) => t,
+        None => return validation_error(invalid),
+    };
+    if two_factor.enabled_at.is_some() {
+        return validation_error(invalid);
+    }
+
+    let now = Utc::now().timestamp() as u64;
+    let step = match matching_step(&two_factor.secret, &input.code, now) {
+        Some(s) => s,
+        None => return validation_error(invalid),
+    };
+
+    let recovery_codes = generate_recovery_codes();
+    let hashes = recovery_codes
+        .iter()
+        .map(|c| hash_recovery_code(c))
+        .collect();
+    state
+        .storage
+        .enable_two_factor(&user.id, step, &hashes)
+        .await?;
+
+    return Ok(Extractor(TwoFactorRecoveryCodes { recovery_codes }));
+}
+
+/// Disables two factor authentication. The user needs to provide their
+/// password and a code (or recovery code)
+pub async fn disable_two_factor(
+    State(state): State<AppState>,
+    user: Extension<User>,
+    Extractor(input): Extractor<DisableTwoFactorInput>,
+) -> Result<Extractor<DisableTwoFactorResponse>, AppError> {
+    second_factor_required(&input.code, &input.recovery_code)?;
+
+    // The user in the request doesn't include the password hash
+    let account = match state.storage.user_by_id(&user.id).await? {
+        Some(a) => a,
+        None => {
+            error!("Logged in user not found: {}", &user.id);
+            return internal_error();
+        }
+    };
+    let valid = match &account.password {
+        Some(p) => state.user_helper.verify_hash(&input.password, p)?,
+        None => false,
+    };
+    if !valid {
+        return validation_error(vec![FailedValidation {
+            field: "password".to_string(),
+            issue: Invalid,
+        }]);
+    }
+
+    let two_factor = match state.storage.two_factor_by_user(&user.id).await? {
+        Some(t) => t,
+        None => return Ok(Extractor(DisableTwoFactorResponse {})),
+    };
+    if two_factor.enabled_at.is_some()
+        && !verify_second_factor(
+            state.storage.clone(),
+            &two_factor,
+            &input.code,
+            &input.recovery_code,
+        )
+        .await?
+    {
+        return validation_error(vec![FailedValidation {
+            field: "code".to_string(),
+            issue: Invalid,
+        }]);
+    }
+
+    state.storage.disable_two_factor(&user.id).await?;
+
+    return Ok(Extractor(DisableTwoFactorResponse {}));
+}
+
+/// Second step of log-in for users with two factor authentication. Exchanges
+/// the challenge returned by log-in and a code for a session token
+pub async fn log_in_two_factor(
+    State(state): State<AppState>,
+    Extractor(input): Extractor<TwoFactorLogInInput>,
+) -> Result<Extractor<SessionToken>, AppError> {
+    second_factor_required(&input.code, &input.recovery_code)?;
+
+    let invalid_challenge = vec![FailedValidation {
+        field: "challenge".to_string(),
+        issue: Invalid,
+    }];
+    let challenge = match state
+        .storage
+        .two_factor_challenge_by_token(&input.challenge)
+        .await?
+    {
+        Some(c) => c,
+        None => return validation_error(invalid_challenge),
+    };
+    let two_factor = match state
+        .storage
+        .two_factor_by_user(&challenge.account_id)
+        .await?
+    {
+        Some(t) => t,
+        None => return validation_error(invalid_challenge),
+    };
+    if let Some(secs) = lockout_secs(&two_factor, Utc::now().naive_utc()) {
+        return too_many_requests(secs);
+    }
+
+    if !verify_second_factor(
+        state.storage.clone(),
+        &two_factor,
+        &input.code,
+        &input.recovery_code,
+    )
+    .await?
+    {
+        state
+            .storage
+            .fail_two_factor_challenge(&challenge.token)
+            .await?;
+        return validation_error(vec![FailedValidation {
+            field: "code".to_string(),
+            issue: Invalid,
+        }]);
+    }
+
+    state
+        .storage
+        .delete_two_factor_challenge(&challenge.token)
+        .await?;
+    state
+        .storage
+        .clear_two_factor_failures(&challenge.account_id)
+        .await?;
+    let session_token = state.storage.log_in(&challenge.account_id).await?;
+
+    return Ok(Extractor(session_token));
+}
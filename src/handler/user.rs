@@ -1,29 +1,64 @@
 use crate::{
     api::{
-        email_verification::EmailVerificationInput,
+        email_verification::{
+            EmailVerificationInput, ResendEmailVerificationInput, ResendEmailVerificationResponse,
+        },
+        two_factor::TwoFactorRequired,
         user::{
-            LogInUserInput, LogOutUserResponse, NewPasswordInput, NewPasswordResponse,
-            RecoverPasswordInput, RecoverPasswordResponse, SessionToken, SignUpUserInput, User,
+            ChangeEmailInput, ChangeEmailResponse, ChangePasswordInput, ChangePasswordResponse,
+            ConfirmEmailChangeInput, DeleteAccountInput, DeleteAccountResponse, LogInUserInput,
+            LogInUserResponse, LogOutUserResponse, NewPasswordInput, NewPasswordResponse,
+            RecoverPasswordInput, RecoverPasswordResponse, SignUpUserInput, UpdateUserInput, User,
             UserAuthProvider::Password,
         },
     },
     error::app_error::{
-        internal_error, validation_error, AppError, FailedValidation,
-        ValidationIssue::{Invalid, Required, TooFrequent, TooWeak},
+        conflict, internal_error, too_many_requests, validation_error, AppError, FailedValidation,
+        ValidationIssue::{Invalid, NotVerified, Required, TooWeak},
+    },
+    helper::{
+        two_factor::lockout_secs,
+        user::{EMAIL_VERIFICATION_RESEND_MINS, PASSWORD_RECOVERY_TTL_MINS},
     },
     json::extractor::Extractor,
-    storage::error::ErrorCode::{NotFoundError, RateLimitError},
+    storage::error::ErrorCode::{DuplicateError, NotFoundError, RateLimitError},
     AppState,
 };
 
 use axum::{
     body::Body,
     extract::{Query, State},
-    http::{header::AUTHORIZATION, Request},
+    http::{
+        header::{AUTHORIZATION, CONTENT_DISPOSITION},
+        HeaderMap, Request,
+    },
+    response::IntoResponse,
     Extension,
 };
-use chrono::Utc;
+use chrono::{Duration, Utc};
 use email_address::EmailAddress;
+use tracing::error;
+
+/// The user in the request doesn't include the password hash, so it needs to be
+/// fetched before it can be verified
+async fn password_matches(
+    state: &AppState,
+    user_id: &str,
+    password: &str,
+) -> Result<bool, AppError> {
+    let account = match state.storage.user_by_id(user_id).await? {
+        Some(a) => a,
+        None => {
+            error!("Logged in user not found: {}", user_id);
+            return internal_error();
+        }
+    };
+
+    return match &account.password {
+        Some(p) => state.user_helper.verify_hash(password, p),
+        None => Ok(false),
+    };
+}
 
 /// Creates a new user in the system.
 pub async fn sign_up_user(
@@ -83,14 +118,23 @@ pub async fn sign_up_user(
         email_verified_at: None,
         recorded_at: Utc::now().naive_utc(),
     };
-    let inserted = state
+    let inserted = match state
         .storage
         .sign_up_user(
             user.clone(),
             state.settings.clone(),
             state.mail_helper.clone(),
         )
-        .await?;
+        .await
+    {
+        Ok(u) => u,
+        Err(e) => {
+            if e.code == DuplicateError {
+                return conflict();
+            }
+            return internal_error();
+        }
+    };
 
     Ok(Extractor(User {
         id: inserted.id,
@@ -141,11 +185,52 @@ pub async fn email_verification(
     }
 }
 
-/// Logs in a user
+/// Sends a new verification e-mail. The previous token stops working. The
+/// response is the same if the account doesn't exist or is already verified
+pub async fn resend_email_verification(
+    State(state): State<AppState>,
+    Extractor(input): Extractor<ResendEmailVerificationInput>,
+) -> Result<Extractor<ResendEmailVerificationResponse>, AppError> {
+    let clean_mail = input.email.trim().to_lowercase();
+    if !EmailAddress::is_valid(&clean_mail) {
+        return validation_error(vec![FailedValidation {
+            field: "email".to_string(),
+            issue: Invalid,
+        }]);
+    }
+
+    let id = format!("{}+{}", clean_mail, Password);
+    let user = match state.storage.user_by_id(&id).await? {
+        Some(u) => u,
+        None => return Ok(Extractor(ResendEmailVerificationResponse {})),
+    };
+    if user.email_verified_at.is_some() {
+        return Ok(Extractor(ResendEmailVerificationResponse {}));
+    }
+
+    match state
+        .storage
+        .resend_email_verification(&user, state.settings.clone(), state.mail_helper.clone())
+        .await
+    {
+        Ok(_) => {}
+        Err(e) => {
+            if e.code == RateLimitError {
+                return too_many_requests((*EMAIL_VERIFICATION_RESEND_MINS * 60) as u64);
+            }
+            return internal_error();
+        }
+    }
+
+    return Ok(Extractor(ResendEmailVerificationResponse {}));
+}
+
+/// Logs in a user. If the user has two factor authentication enabled, a
+/// challenge is returned instead of a session token
 pub async fn log_in_user(
     State(state): State<AppState>,
     Extractor(input): Extractor<LogInUserInput>,
-) -> Result<Extractor<SessionToken>, AppError> {
+) -> Result<Extractor<LogInUserResponse>, AppError> {
     let validation_errors = vec![FailedValidation {
         field: "email".to_string(),
         issue: Invalid,
@@ -170,10 +255,6 @@ pub async fn log_in_user(
         }
     };
 
-    if user.email_verified_at.is_none() {
-        return Err(AppError::ValidationError(validation_errors));
-    }
-
     let valid = match state
         .user_helper
         .verify_hash(&input.password, &user.password.unwrap())
@@ -186,6 +267,29 @@ pub async fn log_in_user(
         return Err(AppError::ValidationError(validation_errors));
     }
 
+    // Only checked after the password, so it doesn't tell anyone else whether
+    // the account exists
+    if user.email_verified_at.is_none() {
+        return validation_error(vec![FailedValidation {
+            field: "email".to_string(),
+            issue: NotVerified,
+        }]);
+    }
+
+    let two_factor = state.storage.two_factor_by_user(&id).await?;
+    if let Some(t) = two_factor.filter(|t| t.enabled_at.is_some()) {
+        if let Some(secs) = lockout_secs(&t, Utc::now().naive_utc()) {
+            return too_many_requests(secs);
+        }
+        let challenge = state.storage.create_two_factor_challenge(&id).await?;
+        return Ok(Extractor(LogInUserResponse::TwoFactorRequired(
+            TwoFactorRequired {
+                challenge: challenge.token,
+                expires_at: challenge.expires_at,
+            },
+        )));
+    }
+
     let session_token = match state.storage.log_in(&id).await {
         Ok(t) => t,
         Err(_) => {
@@ -193,7 +297,7 @@ pub async fn log_in_user(
         }
     };
 
-    return Ok(Extractor(session_token));
+    return Ok(Extractor(LogInUserResponse::Session(session_token)));
 }
 
 /// Returns currently logged in user
@@ -201,6 +305,187 @@ pub async fn me(user: Extension<User>) -> Result<Extractor<User>, AppError> {
     Ok(Extractor((*user).clone()))
 }
 
+/// Updates the profile of the currently logged in user
+pub async fn update_me(
+    State(state): State<AppState>,
+    user: Extension<User>,
+    Extractor(input): Extractor<UpdateUserInput>,
+) -> Result<Extractor<User>, AppError> {
+    let mut updated = (*user).clone();
+    if let Some(name) = &input.name {
+        if name.trim().len() == 0 {
+            return validation_error(vec![FailedValidation {
+                field: "name".to_string(),
+                issue: Required,
+            }]);
+        }
+
+        updated.name = name.clone();
+    }
+
+    let updated = state.storage.update_user(&updated).await?;
+    return Ok(Extractor(updated));
+}
+
+/// Changes the password of the logged in user. All other sessions of the user
+/// are ended
+pub async fn change_password(
+    State(state): State<AppState>,
+    user: Extension<User>,
+    headers: HeaderMap,
+    Extractor(input): Extractor<ChangePasswordInput>,
+) -> Result<Extractor<ChangePasswordResponse>, AppError> {
+    // The auth middleware already verified the header has the form "Bearer <token>"
+    let token = match headers.get(AUTHORIZATION) {
+        Some(h) => h.to_str().unwrap().trim_start_matches("Bearer").trim(),
+        None => return internal_error(),
+    };
+
+    if !password_matches(&state, &user.id, &input.current_password).await? {
+        return validation_error(vec![FailedValidation {
+            field: "current_password".to_string(),
+            issue: Invalid,
+        }]);
+    }
+
+    if state.user_helper.password_is_weak(&input.new_password) {
+        return validation_error(vec![FailedValidation {
+            field: "new_password".to_string(),
+            issue: TooWeak,
+        }]);
+    }
+
+    let password_hash = state.user_helper.hash(&input.new_password)?;
+    state
+        .storage
+        .change_password(&user.id, &password_hash, token)
+        .await?;
+
+    return Ok(Extractor(ChangePasswordResponse {}));
+}
+
+/// Starts an e-mail change for the logged in user. The account keeps its
+/// current e-mail until the new one is verified
+pub async fn change_email(
+    State(state): State<AppState>,
+    user: Extension<User>,
+    Extractor(input): Extractor<ChangeEmailInput>,
+) -> Result<Extractor<ChangeEmailResponse>, AppError> {
+    let invalid_email = vec![FailedValidation {
+        field: "email".to_string(),
+        issue: Invalid,
+    }];
+
+    let clean_mail = input.email.trim().to_lowercase();
+    if !EmailAddress::is_valid(&clean_mail) || clean_mail == user.email {
+        return validation_error(invalid_email);
+    }
+
+    if !password_matches(&state, &user.id, &input.password).await? {
+        return validation_error(vec![FailedValidation {
+            field: "password".to_string(),
+            issue: Invalid,
+        }]);
+    }
+
+    let id = format!("{}+{}", clean_mail, user.provider);
+    if state.storage.user_by_id(&id).await?.is_some() {
+        return validation_error(invalid_email);
+    }
+
+    state
+        .storage
+        .email_change(
+            &user,
+            &clean_mail,
+            state.settings.clone(),
+            state.mail_helper.clone(),
+        )
+        .await?;
+
+    return Ok(Extractor(ChangeEmailResponse {}));
+}
+
+/// Verifies the new e-mail address and switches the account to it
+pub async fn confirm_email_change(
+    State(state): State<AppState>,
+    Query(input): Query<ConfirmEmailChangeInput>,
+) -> Result<Extractor<User>, AppError> {
+    if input.token.trim().len() == 0 {
+        return validation_error(vec![FailedValidation {
+            field: "token".to_string(),
+            issue: Required,
+        }]);
+    }
+
+    match state.storage.confirm_email_change(&input.token).await {
+        Ok(u) => {
+            return Ok(Extractor(User {
+                password: None,
+                ..u
+            }));
+        }
+        Err(e) => {
+            if e.code == NotFoundError {
+                return validation_error(vec![FailedValidation {
+                    field: "token".to_string(),
+                    issue: Invalid,
+                }]);
+            }
+            if e.code == DuplicateError {
+                return conflict();
+            }
+            return internal_error();
+        }
+    }
+}
+
+/// Schedules the logged in user's account for deletion. All sessions are ended.
+/// The account can still be recovered by logging in during the grace period
+pub async fn delete_me(
+    State(state): State<AppState>,
+    user: Extension<User>,
+    Extractor(input): Extractor<DeleteAccountInput>,
+) -> Result<Extractor<DeleteAccountResponse>, AppError> {
+    if !password_matches(&state, &user.id, &input.password).await? {
+        return validation_error(vec![FailedValidation {
+            field: "password".to_string(),
+            issue: Invalid,
+        }]);
+    }
+
+    let delete_at =
+        Utc::now().naive_utc() + Duration::days(state.settings.account.deletion_grace_days);
+    state
+        .storage
+        .schedule_account_deletion(&user.id, delete_at)
+        .await?;
+
+    return Ok(Extractor(DeleteAccountResponse { delete_at }));
+}
+
+/// Returns all the data we have about the logged in user as a JSON file
+pub async fn export_me(
+    State(state): State<AppState>,
+    user: Extension<User>,
+) -> Result<impl IntoResponse, AppError> {
+    let export = match state.storage.user_data_export(&user.id).await? {
+        Some(e) => e,
+        None => {
+            error!("Logged in user not found: {}", &user.id);
+            return internal_error();
+        }
+    };
+
+    return Ok((
+        [(
+            CONTENT_DISPOSITION,
+            "attachment; filename=\"mekadomus-export.json\"",
+        )],
+        Extractor(export),
+    ));
+}
+
 /// Deletes the user session in the request
 pub async fn log_out_user(
     State(state): State<AppState>,
@@ -250,11 +535,7 @@ pub async fn recover_password(
         Ok(_) => {}
         Err(e) => {
             if e.code == RateLimitError {
-                let validation_errors = vec![FailedValidation {
-                    field: "request".to_string(),
-                    issue: TooFrequent,
-                }];
-                return validation_error(validation_errors);
+                return too_many_requests((*PASSWORD_RECOVERY_TTL_MINS * 60) as u64);
             }
             return internal_error();
         }
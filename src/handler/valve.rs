@@ -0,0 +1,155 @@
+use axum::{
+    extract::{Path, Query, State},
+    Extension,
+};
+use chrono::Utc;
+
+use crate::{
+    api::{
+        common::{PageInput, PaginatedRequest, PaginatedResponse, SortKeyType::Timestamp},
+        fluid_meter_member::FluidMeterRole::{self, Manager, Owner, Viewer},
+        user::User,
+        valve::{
+            SetValveInput, Valve, ValveAction,
+            ValveActionOrigin::User as UserOrigin,
+            ValveState::{self, Closed, Open},
+            VALVE_ACTIONS_SORT,
+        },
+    },
+    error::app_error::{not_found, validation_error, AppError},
+    helper::{
+        pagination::validate_page, user::require_fluid_meter_role, valve::request_valve_state,
+    },
+    json::extractor::Extractor,
+    AppState,
+};
+
+// Returns the valve of the meter if the user has the given role
+async fn valve_for_role(
+    state: &AppState,
+    user_id: &str,
+    meter_id: &str,
+    role: FluidMeterRole,
+) -> Result<Valve, AppError> {
+    require_fluid_meter_role(
+        &state.user_helper,
+        state.storage.clone(),
+        user_id,
+        meter_id,
+        role,
+    )
+    .await?;
+
+    match state.storage.valve_by_fluid_meter(meter_id).await? {
+        Some(v) => return Ok(v),
+        None => return not_found(),
+    }
+}
+
+async fn move_valve(
+    state: &AppState,
+    user_id: &str,
+    meter_id: &str,
+    target_state: ValveState,
+) -> Result<ValveAction, AppError> {
+    let valve = valve_for_role(state, user_id, meter_id, Manager).await?;
+    return request_valve_state(
+        state.storage.clone(),
+        &valve,
+        target_state,
+        UserOrigin,
+        Some(user_id.to_string()),
+    )
+    .await;
+}
+
+/// Gets the valve installed next to the meter
+pub async fn get_valve(
+    State(state): State<AppState>,
+    Path(meter_id): Path<String>,
+    user: Extension<User>,
+) -> Result<Extractor<Valve>, AppError> {
+    let valve = valve_for_role(&state, &user.id, &meter_id, Viewer).await?;
+    return Ok(Extractor(valve));
+}
+
+/// Adds a valve to the meter, or changes its settings. New valves are assumed
+/// to be open
+pub async fn set_valve(
+    State(state): State<AppState>,
+    Path(meter_id): Path<String>,
+    user: Extension<User>,
+    Extractor(input): Extractor<SetValveInput>,
+) -> Result<Extractor<Valve>, AppError> {
+    require_fluid_meter_role(
+        &state.user_helper,
+        state.storage.clone(),
+        &user.id,
+        &meter_id,
+        Owner,
+    )
+    .await?;
+
+    let now = Utc::now().naive_utc();
+    let valve = Valve {
+        fluid_meter_id: meter_id,
+        state: Open,
+        requested_state: Open,
+        auto_shut_off: input.auto_shut_off,
+        recorded_at: now,
+        updated_at: now,
+    };
+    let valve = state.storage.save_valve(&valve).await?;
+
+    return Ok(Extractor(valve));
+}
+
+/// Sends the valve a command to open. The state of the valve changes once the
+/// device confirms it
+pub async fn open_valve(
+    State(state): State<AppState>,
+    Path(meter_id): Path<String>,
+    user: Extension<User>,
+) -> Result<Extractor<ValveAction>, AppError> {
+    let action = move_valve(&state, &user.id, &meter_id, Open).await?;
+    return Ok(Extractor(action));
+}
+
+/// Sends the valve a command to close. The state of the valve changes once the
+/// device confirms it
+pub async fn close_valve(
+    State(state): State<AppState>,
+    Path(meter_id): Path<String>,
+    user: Extension<User>,
+) -> Result<Extractor<ValveAction>, AppError> {
+    let action = move_valve(&state, &user.id, &meter_id, Closed).await?;
+    return Ok(Extractor(action));
+}
+
+/// Lists every time the valve was asked to open or close, newest first
+pub async fn valve_actions(
+    State(state): State<AppState>,
+    Path(meter_id): Path<String>,
+    Query(input): Query<PageInput>,
+    user: Extension<User>,
+) -> Result<Extractor<PaginatedResponse<ValveAction>>, AppError> {
+    let _ = valve_for_role(&state, &user.id, &meter_id, Viewer).await?;
+
+    let (page_size, validation_errors) = validate_page(
+        input.page_size,
+        &input.page_cursor,
+        VALVE_ACTIONS_SORT,
+        Timestamp,
+    );
+    if !validation_errors.is_empty() {
+        return validation_error(validation_errors);
+    }
+
+    let options = PaginatedRequest {
+        page_size,
+        page_cursor: input.page_cursor,
+    };
+    let actions = state.storage.valve_actions(&meter_id, &options).await?;
+
+    return Ok(Extractor(actions));
+}
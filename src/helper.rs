@@ -1,5 +1,12 @@
 pub mod alert;
+pub mod device;
+pub mod firmware;
 pub mod mail;
 pub mod measurement;
+pub mod metrics;
+pub mod pagination;
+pub mod rate_limit;
 pub mod token;
+pub mod two_factor;
 pub mod user;
+pub mod valve;
@@ -1,10 +1,12 @@
 use crate::{
     api::{
         alert::{Alert, AlertType},
+        device::DeviceStatus,
         fluid_meter::{FluidMeter, FluidMeterAlerts, FluidMeterStatus::Active},
         measurement::Measurement,
     },
     error::app_error::{internal_error, AppError},
+    helper::measurement::{exceeds_threshold, total},
     storage::Storage,
 };
 
@@ -25,13 +27,34 @@ pub trait AlertHelper: Send + Sync {
     ) -> Result<FluidMeterAlerts, AppError>;
     /// Returns true if the meter has had non-stop flow for a threshold
     fn has_constant_flow(&self, measurements: &Vec<Measurement>) -> bool;
+    /// Returns true if the last battery voltage reported by the device is below
+    /// a threshold
+    fn has_low_battery(&self, status: &DeviceStatus) -> bool;
+    /// Returns true if the last signal strength reported by the device is below
+    /// a threshold
+    fn has_weak_signal(&self, status: &DeviceStatus) -> bool;
     /// Returns true if the meter hasn't reported measuments for a threshold
     fn isnt_reporting(&self, fluid_meter: &FluidMeter, measurements: &Vec<Measurement>) -> bool;
+    /// Returns true if the consumption of a meter and the one of its sub-meters
+    /// differ by more than a threshold
+    fn has_unaccounted_consumption(
+        &self,
+        parent: &Vec<Measurement>,
+        children: &Vec<Measurement>,
+    ) -> bool;
 }
 
 pub const CONSTANT_FLOW_THRESHOLD: &'static usize = &5;
+pub const LOW_BATTERY_VOLTAGE: &'static f64 = &3.3;
 pub const MEASUREMENTS_PAGE_SIZE: &'static u8 = &10;
 pub const NO_REPORTS_THRESHOLD: &'static Duration = &Duration::days(1);
+pub const RECONCILIATION_MAX_MEASUREMENTS: &'static u32 = &25000;
+// Fraction of the consumption of a parent meter that can be unaccounted for by
+// its sub-meters before alerting
+pub const UNACCOUNTED_THRESHOLD: &'static f64 = &0.2;
+pub const UNACCOUNTED_WINDOW: &'static Duration = &Duration::days(1);
+// In dBm
+pub const WEAK_SIGNAL_RSSI: &'static i32 = &-100;
 
 pub struct DefaultAlertHelper;
 
@@ -80,6 +103,59 @@ impl AlertHelper for DefaultAlertHelper {
             });
         }
 
+        if let Some(status) = storage
+            .device_status_by_fluid_meter(&fluid_meter.id)
+            .await?
+        {
+            if self.has_low_battery(&status) {
+                result.alerts.push(Alert {
+                    alert_type: AlertType::LowBattery,
+                });
+            }
+
+            if self.has_weak_signal(&status) {
+                result.alerts.push(Alert {
+                    alert_type: AlertType::WeakSignal,
+                });
+            }
+        }
+
+        let children = match storage.get_fluid_meter_children(&fluid_meter.id).await {
+            Ok(c) => c,
+            Err(e) => {
+                error!(
+                    "Failed to get children for meter {}. Error: {}",
+                    &fluid_meter.id, e
+                );
+                return internal_error();
+            }
+        };
+        if children.len() > 0 {
+            let since = now - *UNACCOUNTED_WINDOW;
+            let parent_measurements = storage
+                .get_measurements(
+                    fluid_meter.id.clone(),
+                    since,
+                    now,
+                    *RECONCILIATION_MAX_MEASUREMENTS,
+                )
+                .await?;
+            let children_measurements = storage
+                .get_children_measurements(
+                    &fluid_meter.id,
+                    since,
+                    now,
+                    *RECONCILIATION_MAX_MEASUREMENTS,
+                )
+                .await?;
+
+            if self.has_unaccounted_consumption(&parent_measurements, &children_measurements) {
+                result.alerts.push(Alert {
+                    alert_type: AlertType::UnaccountedConsumption,
+                });
+            }
+        }
+
         return Ok(result);
     }
 
@@ -97,9 +173,23 @@ impl AlertHelper for DefaultAlertHelper {
         return true;
     }
 
+    fn has_low_battery(&self, status: &DeviceStatus) -> bool {
+        match status.battery_voltage {
+            Some(v) => return v < *LOW_BATTERY_VOLTAGE,
+            None => return false,
+        }
+    }
+
+    fn has_weak_signal(&self, status: &DeviceStatus) -> bool {
+        match status.rssi {
+            Some(r) => return r < *WEAK_SIGNAL_RSSI,
+            None => return false,
+        }
+    }
+
     fn isnt_reporting(&self, fluid_meter: &FluidMeter, measurements: &Vec<Measurement>) -> bool {
         let now = Utc::now().naive_utc();
-        if fluid_meter.status != Active || now - fluid_meter.updated_at < *NO_REPORTS_THRESHOLD {
+        if fluid_meter.status != Active || now - fluid_meter.activated_at < *NO_REPORTS_THRESHOLD {
             return false;
         }
 
@@ -109,6 +199,14 @@ impl AlertHelper for DefaultAlertHelper {
 
         return false;
     }
+
+    fn has_unaccounted_consumption(
+        &self,
+        parent: &Vec<Measurement>,
+        children: &Vec<Measurement>,
+    ) -> bool {
+        return exceeds_threshold(total(parent), total(children), *UNACCOUNTED_THRESHOLD);
+    }
 }
 
 #[cfg(test)]
@@ -116,6 +214,7 @@ mod tests {
     use crate::{
         api::{
             alert::{Alert, AlertType},
+            device::DeviceStatus,
             fluid_meter::{
                 FluidMeter, FluidMeterAlerts,
                 FluidMeterStatus::{Active, Inactive},
@@ -133,11 +232,17 @@ mod tests {
     fn isnt_reporting_not_active() {
         let fm = FluidMeter {
             id: "some_id".to_string(),
-            owner_id: "some_owner_id".to_string(),
+            owner_id: Some("some_owner_id".to_string()),
+            organization_id: None,
+            site_id: None,
+            parent_id: None,
             name: "name".to_string(),
             status: Inactive,
             recorded_at: Utc::now().naive_utc(),
             updated_at: Utc::now().naive_utc(),
+            deleted_at: None,
+            reporting_interval_secs: None,
+            activated_at: Utc::now().naive_utc(),
         };
 
         let helper = DefaultAlertHelper {};
@@ -146,14 +251,20 @@ mod tests {
     }
 
     #[test]
-    fn isnt_reporting_updated_at() {
+    fn isnt_reporting_activated_at() {
         let fm = FluidMeter {
             id: "some_id".to_string(),
-            owner_id: "some_owner_id".to_string(),
+            owner_id: Some("some_owner_id".to_string()),
+            organization_id: None,
+            site_id: None,
+            parent_id: None,
             name: "name".to_string(),
             status: Active,
-            recorded_at: Utc::now().naive_utc(),
-            updated_at: Utc::now().naive_utc(),
+            recorded_at: Utc::now().naive_utc() - Duration::hours(25),
+            updated_at: Utc::now().naive_utc() - Duration::hours(25),
+            deleted_at: None,
+            reporting_interval_secs: None,
+            activated_at: Utc::now().naive_utc(),
         };
 
         let helper = DefaultAlertHelper {};
@@ -165,11 +276,17 @@ mod tests {
     fn isnt_reporting_last_measurement() {
         let fm = FluidMeter {
             id: "some_id".to_string(),
-            owner_id: "some_owner_id".to_string(),
+            owner_id: Some("some_owner_id".to_string()),
+            organization_id: None,
+            site_id: None,
+            parent_id: None,
             name: "name".to_string(),
             status: Active,
             recorded_at: Utc::now().naive_utc(),
             updated_at: Utc::now().naive_utc() - Duration::hours(25),
+            deleted_at: None,
+            reporting_interval_secs: None,
+            activated_at: Utc::now().naive_utc() - Duration::hours(25),
         };
 
         let helper = DefaultAlertHelper {};
@@ -186,11 +303,17 @@ mod tests {
     fn isnt_reporting_alerting() {
         let fm = FluidMeter {
             id: "some_id".to_string(),
-            owner_id: "some_owner_id".to_string(),
+            owner_id: Some("some_owner_id".to_string()),
+            organization_id: None,
+            site_id: None,
+            parent_id: None,
             name: "name".to_string(),
             status: Active,
             recorded_at: Utc::now().naive_utc(),
             updated_at: Utc::now().naive_utc() - Duration::hours(25),
+            deleted_at: None,
+            reporting_interval_secs: None,
+            activated_at: Utc::now().naive_utc() - Duration::hours(25),
         };
 
         let helper = DefaultAlertHelper {};
@@ -291,11 +414,17 @@ mod tests {
         let device_id = "dev_id";
         let fm = FluidMeter {
             id: device_id.to_string(),
-            owner_id: "some_owner_id".to_string(),
+            owner_id: Some("some_owner_id".to_string()),
+            organization_id: None,
+            site_id: None,
+            parent_id: None,
             name: "name".to_string(),
             status: Active,
             recorded_at: Utc::now().naive_utc(),
             updated_at: Utc::now().naive_utc() - Duration::hours(25),
+            deleted_at: None,
+            reporting_interval_secs: None,
+            activated_at: Utc::now().naive_utc() - Duration::hours(25),
         };
 
         let measurements = vec![Measurement {
@@ -309,12 +438,32 @@ mod tests {
             .expect_get_measurements()
             .with(eq(device_id.to_string()), always(), always(), eq(10))
             .return_const(Ok(measurements));
+        storage
+            .expect_get_fluid_meter_children()
+            .with(eq(device_id))
+            .return_const(Ok(vec![]));
+        storage
+            .expect_device_status_by_fluid_meter()
+            .with(eq(device_id))
+            .return_const(Ok(Some(DeviceStatus {
+                fluid_meter_id: device_id.to_string(),
+                battery_voltage: Some(3.0),
+                rssi: Some(-70),
+                firmware_version: None,
+                uptime_secs: None,
+                updated_at: Utc::now().naive_utc(),
+            })));
 
         let expected = FluidMeterAlerts {
             meter: fm.clone(),
-            alerts: vec![Alert {
-                alert_type: AlertType::NotReporting,
-            }],
+            alerts: vec![
+                Alert {
+                    alert_type: AlertType::NotReporting,
+                },
+                Alert {
+                    alert_type: AlertType::LowBattery,
+                },
+            ],
         };
         let helper = DefaultAlertHelper {};
         assert_eq!(
@@ -322,4 +471,39 @@ mod tests {
             helper.get_alerts(Arc::new(storage), &fm).await.unwrap()
         );
     }
+
+    #[test]
+    fn has_unaccounted_consumption_alerting() {
+        let helper = DefaultAlertHelper {};
+        let measurement = |value: &str| Measurement {
+            id: "id".to_string(),
+            measurement: value.to_string(),
+            device_id: "some_id".to_string(),
+            recorded_at: Utc::now().naive_utc(),
+        };
+        let parent = vec![measurement("6.0"), measurement("4.0")];
+
+        assert!(!helper.has_unaccounted_consumption(&vec![], &vec![]));
+        assert!(!helper.has_unaccounted_consumption(&parent, &vec![measurement("9.0")]));
+        assert!(helper.has_unaccounted_consumption(&parent, &vec![measurement("7.0")]));
+        assert!(helper.has_unaccounted_consumption(&vec![], &vec![measurement("1.0")]));
+    }
+
+    #[test]
+    fn has_weak_signal_alerting() {
+        let helper = DefaultAlertHelper {};
+        let status = |rssi: Option<i32>| DeviceStatus {
+            fluid_meter_id: "some_id".to_string(),
+            battery_voltage: None,
+            rssi,
+            firmware_version: None,
+            uptime_secs: None,
+            updated_at: Utc::now().naive_utc(),
+        };
+
+        assert!(!helper.has_weak_signal(&status(None)));
+        assert!(!helper.has_weak_signal(&status(Some(-60))));
+        assert!(helper.has_weak_signal(&status(Some(-110))));
+        assert!(!helper.has_low_battery(&status(None)));
+    }
 }
@@ -0,0 +1,200 @@
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    api::device::{CreateDeviceCommandInput, Device, DeviceCommandType, DeviceTelemetry},
+    error::app_error::{
+        FailedValidation,
+        ValidationIssue::{Invalid, Required, TooLarge},
+    },
+    helper::token::secrets_match,
+};
+
+// Letters and digits that can't be confused with each other when read from a
+// label (No 0/O or 1/I)
+pub const CLAIM_CODE_ALPHABET: &'static [u8] = b"23456789ABCDEFGHJKLMNPQRSTUVWXYZ";
+pub const CLAIM_CODE_LEN: &'static usize = &8;
+pub const DEVICE_SECRET_LEN: &'static usize = &64;
+pub const MAX_COMMAND_RESULT_LEN: &'static usize = &255;
+pub const MAX_FIRMWARE_VERSION_LEN: &'static usize = &50;
+pub const MAX_REPORTING_INTERVAL_SECS: &'static i64 = &86400;
+pub const MAX_SERIAL_LEN: &'static usize = &100;
+pub const MIN_REPORTING_INTERVAL_SECS: &'static i64 = &60;
+
+/// Generates a short code users type to claim a device
+pub fn generate_claim_code() -> String {
+    let mut rng = rand::thread_rng();
+    (0..*CLAIM_CODE_LEN)
+        .map(|_| CLAIM_CODE_ALPHABET[rng.gen_range(0..CLAIM_CODE_ALPHABET.len())] as char)
+        .collect()
+}
+
+/// Users type claim codes by hand, so we ignore case, spaces and dashes
+pub fn normalize_claim_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .collect::<String>()
+        .to_uppercase()
+}
+
+/// Device secrets are random, so a fast hash is enough to avoid storing them in
+/// plain text
+pub fn hash_device_secret(secret: &str) -> String {
+    format!("{:x}", Sha256::digest(secret.as_bytes()))
+}
+
+/// Whether the secret is the one the device got when it was registered
+pub fn device_secret_matches(device: &Device, secret: &str) -> bool {
+    return secrets_match(&device.secret_hash, &hash_device_secret(secret));
+}
+
+/// Checks the values sent by the device are possible
+pub fn validate_telemetry(telemetry: &DeviceTelemetry) -> Vec<FailedValidation> {
+    let mut validation_errors = vec![];
+
+    if let Some(v) = telemetry.battery_voltage {
+        if !v.is_finite() || v < 0.0 {
+            validation_errors.push(FailedValidation {
+                field: "battery_voltage".to_string(),
+                issue: Invalid,
+            });
+        }
+    }
+
+    // Signal strength is reported in dBm, so it's never positive
+    if let Some(r) = telemetry.rssi {
+        if r > 0 {
+            validation_errors.push(FailedValidation {
+                field: "rssi".to_string(),
+                issue: Invalid,
+            });
+        }
+    }
+
+    if let Some(f) = &telemetry.firmware_version {
+        if f.len() > *MAX_FIRMWARE_VERSION_LEN {
+            validation_errors.push(FailedValidation {
+                field: "firmware_version".to_string(),
+                issue: TooLarge,
+            });
+        }
+    }
+
+    if let Some(u) = telemetry.uptime_secs {
+        if u < 0 {
+            validation_errors.push(FailedValidation {
+                field: "uptime_secs".to_string(),
+                issue: Invalid,
+            });
+        }
+    }
+
+    return validation_errors;
+}
+
+/// Only commands that need a value can have one. Valve commands have their
+/// own endpoints, so every time a valve moves is audited
+pub fn validate_device_command(input: &CreateDeviceCommandInput) -> Vec<FailedValidation> {
+    let mut validation_errors = vec![];
+
+    if matches!(
+        input.command_type,
+        DeviceCommandType::CloseValve | DeviceCommandType::OpenValve
+    ) {
+        validation_errors.push(FailedValidation {
+            field: "command_type".to_string(),
+            issue: Invalid,
+        });
+        return validation_errors;
+    }
+
+    match (&input.command_type, input.value) {
+        (DeviceCommandType::SetReportingInterval, Some(v)) => {
+            if !(*MIN_REPORTING_INTERVAL_SECS..=*MAX_REPORTING_INTERVAL_SECS).contains(&v) {
+                validation_errors.push(FailedValidation {
+                    field: "value".to_string(),
+                    issue: Invalid,
+                });
+            }
+        }
+        (DeviceCommandType::SetReportingInterval, None) => {
+            validation_errors.push(FailedValidation {
+                field: "value".to_string(),
+                issue: Required,
+            });
+        }
+        (_, Some(_)) => {
+            validation_errors.push(FailedValidation {
+                field: "value".to_string(),
+                issue: Invalid,
+            });
+        }
+        (_, None) => {}
+    }
+
+    return validation_errors;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        generate_claim_code, normalize_claim_code, validate_device_command, validate_telemetry,
+        CLAIM_CODE_ALPHABET, CLAIM_CODE_LEN,
+    };
+    use crate::api::device::{
+        CreateDeviceCommandInput,
+        DeviceCommandType::{CloseValve, Reboot, SetReportingInterval},
+        DeviceTelemetry,
+    };
+
+    #[test]
+    fn claim_codes_use_the_alphabet() {
+        let code = generate_claim_code();
+        assert_eq!(code.len(), *CLAIM_CODE_LEN);
+        assert!(code.bytes().all(|c| CLAIM_CODE_ALPHABET.contains(&c)));
+        assert_eq!(normalize_claim_code(&code), code);
+    }
+
+    #[test]
+    fn claim_codes_are_normalized() {
+        assert_eq!(normalize_claim_code(" abcd-ef23 "), "ABCDEF23");
+    }
+
+    #[test]
+    fn telemetry_is_validated() {
+        assert!(validate_telemetry(&DeviceTelemetry::default()).is_empty());
+
+        let telemetry = DeviceTelemetry {
+            battery_voltage: Some(-1.0),
+            rssi: Some(10),
+            firmware_version: Some("1.0.0".to_string()),
+            uptime_secs: Some(-5),
+        };
+        let fields: Vec<String> = validate_telemetry(&telemetry)
+            .into_iter()
+            .map(|v| v.field)
+            .collect();
+        assert_eq!(fields, vec!["battery_voltage", "rssi", "uptime_secs"]);
+    }
+
+    #[test]
+    fn device_command_values_are_validated() {
+        let command = |command_type, value| CreateDeviceCommandInput {
+            command_type,
+            value,
+        };
+
+        assert!(validate_device_command(&command(Reboot, None)).is_empty());
+        assert!(validate_device_command(&command(SetReportingInterval, Some(300))).is_empty());
+        assert_eq!(validate_device_command(&command(Reboot, Some(1))).len(), 1);
+        assert_eq!(
+            validate_device_command(&command(SetReportingInterval, None)).len(),
+            1
+        );
+        assert_eq!(
+            validate_device_command(&command(SetReportingInterval, Some(1))).len(),
+            1
+        );
+        assert_eq!(validate_device_command(&command(CloseValve, None)).len(), 1);
+    }
+}
@@ -0,0 +1,187 @@
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use tracing::error;
+
+use crate::{
+    api::firmware::{CreateFirmwareReleaseInput, FirmwareRelease},
+    error::app_error::{
+        internal_error, AppError, FailedValidation,
+        ValidationIssue::{Invalid, Required, TooLarge},
+    },
+    helper::device::MAX_FIRMWARE_VERSION_LEN,
+    settings::settings::Settings,
+};
+
+pub const MAX_FIRMWARE_SIZE_BYTES: &'static usize = &(16 * 1024 * 1024);
+pub const MAX_HARDWARE_MODEL_LEN: &'static usize = &100;
+pub const MAX_TARGET_VERSIONS: &'static usize = &50;
+
+pub fn is_firmware_admin(settings: &Settings, user_id: &str) -> bool {
+    return settings
+        .firmware
+        .admins
+        .split(',')
+        .any(|a| a.trim() == user_id);
+}
+
+pub fn valid_rollout_percentage(rollout_percentage: i16) -> bool {
+    return (0..=100).contains(&rollout_percentage);
+}
+
+pub fn validate_firmware_release(input: &CreateFirmwareReleaseInput) -> Vec<FailedValidation> {
+    let mut validation_errors = vec![];
+
+    let hardware_model = input.hardware_model.trim();
+    if hardware_model.len() == 0 {
+        validation_errors.push(FailedValidation {
+            field: "hardware_model".to_string(),
+            issue: Required,
+        });
+    } else if hardware_model.len() > *MAX_HARDWARE_MODEL_LEN {
+        validation_errors.push(FailedValidation {
+            field: "hardware_model".to_string(),
+            issue: TooLarge,
+        });
+    }
+
+    let version = input.version.trim();
+    if version.len() == 0 {
+        validation_errors.push(FailedValidation {
+            field: "version".to_string(),
+            issue: Required,
+        });
+    } else if version.len() > *MAX_FIRMWARE_VERSION_LEN {
+        validation_errors.push(FailedValidation {
+            field: "version".to_string(),
+            issue: TooLarge,
+        });
+    }
+
+    if let Some(t) = &input.target_versions {
+        if t.len() > *MAX_TARGET_VERSIONS
+            || t.iter().any(|v| v.trim().len() > *MAX_FIRMWARE_VERSION_LEN)
+        {
+            validation_errors.push(FailedValidation {
+                field: "target_versions".to_string(),
+                issue: TooLarge,
+            });
+        }
+    }
+
+    if !valid_rollout_percentage(input.rollout_percentage) {
+        validation_errors.push(FailedValidation {
+            field: "rollout_percentage".to_string(),
+            issue: Invalid,
+        });
+    }
+
+    return validation_errors;
+}
+
+/// Tells if the device is part of the staged rollout of the release. Each
+/// device gets a stable bucket per release, so raising the percentage only adds
+/// devices
+pub fn in_rollout(release: &FirmwareRelease, serial: &str) -> bool {
+    let hash = Sha256::digest(format!("{}:{}", release.id, serial).as_bytes());
+    let bucket = u16::from_be_bytes([hash[0], hash[1]]) % 100;
+    return (bucket as i16) < release.rollout_percentage;
+}
+
+/// Returns the sha256 of the binary, in hex
+pub fn hash_firmware(binary: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(binary))
+}
+
+fn firmware_path(settings: &Settings, release_id: &str) -> PathBuf {
+    return PathBuf::from(&settings.firmware.storage_path).join(format!("{}.bin", release_id));
+}
+
+/// Saves the binary of the release in the file store
+pub async fn save_firmware_binary(
+    settings: &Settings,
+    release_id: &str,
+    binary: &[u8],
+) -> Result<(), AppError> {
+    if let Err(e) = tokio::fs::create_dir_all(&settings.firmware.storage_path).await {
+        error!("Error creating firmware store: {}", e);
+        return internal_error();
+    }
+
+    match tokio::fs::write(firmware_path(settings, release_id), binary).await {
+        Ok(_) => return Ok(()),
+        Err(e) => {
+            error!("Error saving firmware {}: {}", release_id, e);
+            return internal_error();
+        }
+    }
+}
+
+/// Reads the binary of the release from the file store
+pub async fn read_firmware_binary(
+    settings: &Settings,
+    release_id: &str,
+) -> Result<Vec<u8>, AppError> {
+    match tokio::fs::read(firmware_path(settings, release_id)).await {
+        Ok(b) => return Ok(b),
+        Err(e) => {
+            error!("Error reading firmware {}: {}", release_id, e);
+            return internal_error();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::{in_rollout, validate_firmware_release};
+    use crate::api::firmware::{CreateFirmwareReleaseInput, FirmwareRelease};
+
+    #[test]
+    fn rollout_is_stable() {
+        let mut release = FirmwareRelease {
+            id: "release".to_string(),
+            hardware_model: "wm-1".to_string(),
+            version: "1.1.0".to_string(),
+            target_versions: vec![],
+            rollout_percentage: 0,
+            size_bytes: None,
+            sha256: None,
+            created_by: Some("admin".to_string()),
+            recorded_at: Utc::now().naive_utc(),
+            uploaded_at: None,
+        };
+        let serials: Vec<String> = (0..200).map(|i| format!("SN-{}", i)).collect();
+
+        assert!(serials.iter().all(|s| !in_rollout(&release, s)));
+
+        release.rollout_percentage = 30;
+        let first: Vec<&String> = serials.iter().filter(|s| in_rollout(&release, s)).collect();
+        assert!(first.len() > 0 && first.len() < serials.len());
+
+        // Devices in a rollout stay in it when it grows
+        release.rollout_percentage = 60;
+        assert!(first.iter().all(|s| in_rollout(&release, s)));
+
+        release.rollout_percentage = 100;
+        assert!(serials.iter().all(|s| in_rollout(&release, s)));
+    }
+
+    #[test]
+    fn firmware_release_is_validated() {
+        let input = CreateFirmwareReleaseInput {
+            hardware_model: " ".to_string(),
+            version: "1.0.0".to_string(),
+            target_versions: Some(vec!["x".repeat(51)]),
+            rollout_percentage: 101,
+        };
+        let fields: Vec<String> = validate_firmware_release(&input)
+            .into_iter()
+            .map(|v| v.field)
+            .collect();
+        assert_eq!(
+            fields,
+            vec!["hardware_model", "target_versions", "rollout_percentage"]
+        );
+    }
+}
@@ -1,12 +1,16 @@
 use crate::{
-    api::{fluid_meter::FluidMeterAlerts, user::User},
+    api::{
+        fluid_meter::{FluidMeter, FluidMeterAlerts},
+        user::User,
+    },
+    helper::metrics::EMAIL_SEND_FAILURES,
     settings::settings::Settings,
 };
 
 use async_trait::async_trait;
 use mockall::automock;
 use reqwest::Client;
-use serde_json::Value;
+use serde_json::{json, Value};
 use std::fmt::Write;
 use tracing::error;
 
@@ -21,12 +25,56 @@ pub trait MailHelper: Send + Sync {
         user: &User,
         alerts: &Vec<FluidMeterAlerts>,
     ) -> bool;
+    async fn email_change_verification(
+        &self,
+        settings: &Settings,
+        user: &User,
+        email: &str,
+        token: &str,
+    ) -> bool;
+    async fn fluid_meter_invite(
+        &self,
+        settings: &Settings,
+        inviter: &User,
+        meter: &FluidMeter,
+        email: &str,
+        token: &str,
+    ) -> bool;
     async fn password_recovery(&self, settings: &Settings, user: &User, token: &str) -> bool;
     async fn sign_up_verification(&self, settings: &Settings, user: &User, token: &str) -> bool;
+    async fn valve_closed(&self, settings: &Settings, user: &User, meter: &FluidMeter) -> bool;
 }
 
 pub struct DefaultMailHelper;
 
+/// Sends an e-mail using brevo's API
+async fn send(settings: &Settings, body: &Value) -> bool {
+    let client = Client::new();
+    match client
+        .post("https://api.brevo.com/v3/smtp/email")
+        .header("accept", "application/json")
+        .header("content-type", "application/json")
+        .header("api-key", &settings.mail.api_key)
+        .json(body)
+        .send()
+        .await
+    {
+        Ok(res) => {
+            if !res.status().is_success() {
+                error!("Response from brevo was {}", res.status());
+                EMAIL_SEND_FAILURES.inc();
+                return false;
+            }
+            return true;
+        }
+        Err(e) => {
+            error!("Error sending email. {}", e);
+            EMAIL_SEND_FAILURES.inc();
+            return false;
+        }
+    }
+}
+
 fn alerts_mail_body(
     mailer_name: &str,
     mailer_address: &str,
@@ -103,28 +151,102 @@ impl MailHelper for DefaultMailHelper {
             }
         };
 
-        let client = Client::new();
-        match client
-            .post("https://api.brevo.com/v3/smtp/email")
-            .header("accept", "application/json")
-            .header("content-type", "application/json")
-            .header("api-key", &settings.mail.api_key)
-            .json(&body)
-            .send()
-            .await
-        {
-            Ok(res) => {
-                if !res.status().is_success() {
-                    error!("Response from brevo was {}", res.status());
-                    return false;
-                }
-                return true;
-            }
+        return send(settings, &body).await;
+    }
+
+    /// Sends an e-mail to the new address of a user, so they can verify they own it
+    async fn email_change_verification(
+        &self,
+        settings: &Settings,
+        user: &User,
+        email: &str,
+        token: &str,
+    ) -> bool {
+        let body_str = format!(
+            r#"{{
+            "sender": {{
+                "name": "{}",
+                "email": "{}"
+            }},
+            "to": [
+                {{
+                    "name": "{}",
+                    "email": "{}"
+                }}
+            ],
+            "subject": "Confirm your new Mekadomus e-mail",
+            "htmlContent": "<html><body>Hello {},<br />To start using this address for your account, click the following link.<br /><br /><a href=\"https://console.mekadomus.com/email-change/{}\">Confirm e-mail</a><br><br></body></html>"
+        }}"#,
+            settings.mail.mailer_name,
+            settings.mail.mailer_address,
+            user.name,
+            email,
+            user.name,
+            token
+        );
+        let body: serde_json::Value = match serde_json::from_str(&body_str) {
+            Ok(b) => b,
             Err(e) => {
-                error!("Error sending email. {}", e);
+                error!("Couldn't parse json. {}", e);
                 return false;
             }
-        }
+        };
+
+        return send(settings, &body).await;
+    }
+
+    /// Invites someone to have access to a fluid meter. The e-mail might not
+    /// belong to an account yet
+    async fn fluid_meter_invite(
+        &self,
+        settings: &Settings,
+        inviter: &User,
+        meter: &FluidMeter,
+        email: &str,
+        token: &str,
+    ) -> bool {
+        let body = json!({
+            "sender": {
+                "name": settings.mail.mailer_name,
+                "email": settings.mail.mailer_address
+            },
+            "to": [
+                {
+                    "email": email
+                }
+            ],
+            "subject": format!("{} shared a Mekadomus meter with you", inviter.name),
+            "htmlContent": format!(
+                "<html><body>Hello,<br />{} invited you to see their meter {}. To accept, click the following link.<br /><br /><a href=\"https://console.mekadomus.com/fluid-meter-invite/{}\">Accept invite</a><br><br></body></html>",
+                inviter.name, meter.name, token
+            )
+        });
+
+        return send(settings, &body).await;
+    }
+
+    /// Lets user know the valve of a meter was closed because water kept
+    /// flowing
+    async fn valve_closed(&self, settings: &Settings, user: &User, meter: &FluidMeter) -> bool {
+        let body = json!({
+            "sender": {
+                "name": settings.mail.mailer_name,
+                "email": settings.mail.mailer_address
+            },
+            "to": [
+                {
+                    "name": user.name,
+                    "email": user.email
+                }
+            ],
+            "subject": format!("The valve of {} was closed", meter.name),
+            "htmlContent": format!(
+                "<html><body>Hello {},<br />Water kept flowing through your meter {}, so we closed its valve to prevent a leak. You can open it again from the console.<br /><br /><a href=\"https://console.mekadomus.com/meter/{}\">See meter</a><br><br></body></html>",
+                user.name, meter.name, meter.id
+            )
+        });
+
+        return send(settings, &body).await;
     }
 
     /// Sends user an e-mail they can use to reset their password
@@ -159,28 +281,7 @@ impl MailHelper for DefaultMailHelper {
             }
         };
 
-        let client = Client::new();
-        match client
-            .post("https://api.brevo.com/v3/smtp/email")
-            .header("accept", "application/json")
-            .header("content-type", "application/json")
-            .header("api-key", &settings.mail.api_key)
-            .json(&body)
-            .send()
-            .await
-        {
-            Ok(res) => {
-                if !res.status().is_success() {
-                    error!("Response from brevo was {}", res.status());
-                    return false;
-                }
-                return true;
-            }
-            Err(e) => {
-                error!("Error sending email. {}", e);
-                return false;
-            }
-        }
+        return send(settings, &body).await;
     }
 
     async fn alerts(
@@ -202,28 +303,7 @@ impl MailHelper for DefaultMailHelper {
             }
         };
 
-        let client = Client::new();
-        match client
-            .post("https://api.brevo.com/v3/smtp/email")
-            .header("accept", "application/json")
-            .header("content-type", "application/json")
-            .header("api-key", &settings.mail.api_key)
-            .json(&body)
-            .send()
-            .await
-        {
-            Ok(res) => {
-                if !res.status().is_success() {
-                    error!("Response from brevo was {}", res.status());
-                    return false;
-                }
-                return true;
-            }
-            Err(e) => {
-                error!("Error sending email. {}", e);
-                return false;
-            }
-        }
+        return send(settings, &body).await;
     }
 }
 
@@ -255,11 +335,17 @@ mod tests {
             FluidMeterAlerts {
                 meter: FluidMeter {
                     id: "b".to_string(),
-                    owner_id: "a".to_string(),
+                    owner_id: Some("a".to_string()),
+                    organization_id: None,
+                    site_id: None,
+                    parent_id: None,
                     name: "meter".to_string(),
                     status: Active,
                     recorded_at: Utc::now().naive_utc(),
                     updated_at: Utc::now().naive_utc(),
+                    deleted_at: None,
+                    reporting_interval_secs: None,
+                    activated_at: Utc::now().naive_utc(),
                 },
                 alerts: vec![
                     Alert {
@@ -273,11 +359,17 @@ mod tests {
             FluidMeterAlerts {
                 meter: FluidMeter {
                     id: "c".to_string(),
-                    owner_id: "a".to_string(),
+                    owner_id: Some("a".to_string()),
+                    organization_id: None,
+                    site_id: None,
+                    parent_id: None,
                     name: "meter_2".to_string(),
                     status: Active,
                     recorded_at: Utc::now().naive_utc(),
                     updated_at: Utc::now().naive_utc(),
+                    deleted_at: None,
+                    reporting_interval_secs: None,
+                    activated_at: Utc::now().naive_utc(),
                 },
                 alerts: vec![Alert {
                     alert_type: ConstantFlow,
@@ -1,9 +1,82 @@
-use crate::api::{
-    common::{Series, SeriesGranularity, SeriesItem},
-    measurement::Measurement,
+use crate::{
+    api::{
+        common::{Series, SeriesGranularity, SeriesItem},
+        fluid_meter::{FluidMeter, FluidMeterStatus::Active, ReconciliationItem},
+        measurement::{GetMeasurementsInput, Measurement, SaveMeasurementInput},
+    },
+    error::app_error::{
+        internal_error, too_many_requests, unauthorized, validation_error, AppError,
+        FailedValidation,
+        ValidationIssue::{Invalid, NotClaimed, Required},
+    },
+    helper::{device::validate_telemetry, metrics::MEASUREMENTS_INGESTED},
+    settings::settings::Settings,
+    storage::{error::ErrorCode::RateLimitError, Storage},
 };
 
-use chrono::{Duration, NaiveTime};
+use chrono::{Duration, NaiveDateTime, NaiveTime, Utc};
+use std::{collections::BTreeMap, sync::Arc};
+use uuid::Uuid;
+
+// Fraction of the reporting interval a device can report early. Device clocks
+// drift, so measurements don't arrive exactly one interval apart
+pub const REPORTING_JITTER: &'static f64 = &0.1;
+
+/// The minimum seconds between measurements of the meter
+pub fn reporting_interval(settings: &Settings, meter: &FluidMeter) -> i64 {
+    return meter
+        .reporting_interval_secs
+        .unwrap_or(settings.fluid_meter.default_reporting_interval_secs);
+}
+
+/// Token bucket that allows one measurement per reporting interval. The bucket
+/// holds at most one token, so devices can't save up for a burst. Takes a
+/// token and returns the tokens left, or None if the measurement came too early.
+/// Devices can go a little into debt to tolerate jitter, but they pay it back
+/// with the next measurement
+pub fn take_reporting_token(
+    tokens: f64,
+    updated_at: NaiveDateTime,
+    now: NaiveDateTime,
+    interval_secs: i64,
+) -> Option<f64> {
+    let elapsed = (now - updated_at).num_milliseconds().max(0) as f64 / 1000.0;
+    let tokens = (tokens + elapsed / interval_secs as f64).min(1.0);
+    if tokens < 1.0 - *REPORTING_JITTER {
+        return None;
+    }
+
+    return Some(tokens - 1.0);
+}
+
+/// Returns the time range (from, to) and granularity a series request covers.
+/// By default (If the input doesn't specify otherwise), it covers one month
+/// grouped by day
+pub fn series_range(
+    input: &GetMeasurementsInput,
+) -> Result<(NaiveDateTime, NaiveDateTime, SeriesGranularity), AppError> {
+    let granularity = input.granularity.clone().unwrap_or(SeriesGranularity::Day);
+    match granularity {
+        SeriesGranularity::Hour => {
+            let day = match input.day {
+                Some(d) => d,
+                None => {
+                    return Err(AppError::ValidationError(vec![FailedValidation {
+                        field: "day".to_string(),
+                        issue: Required,
+                    }]));
+                }
+            };
+
+            let from = day.and_time(NaiveTime::MIN);
+            return Ok((from, from + Duration::hours(24), granularity));
+        }
+        _ => {
+            let to = Utc::now().naive_utc();
+            return Ok((to - Duration::days(30), to, granularity));
+        }
+    }
+}
 
 /// Given a list of measurements, it aggregates them at the specified granularity
 /// measurement - The measurements to aggregate. Sorted by recorded_at, descending
@@ -51,9 +124,117 @@ pub fn create_series(measurements: &Vec<Measurement>, granularity: SeriesGranula
     return Series { granularity, items };
 }
 
+/// Adds up all the given measurements
+pub fn total(measurements: &Vec<Measurement>) -> f64 {
+    measurements
+        .iter()
+        .map(|m| m.measurement.parse::<f64>().unwrap_or(0.0))
+        .sum()
+}
+
+/// Returns true if the difference between the parent and children consumption
+/// is bigger than the given fraction of the parent consumption
+pub fn exceeds_threshold(parent: f64, children: f64, threshold: f64) -> bool {
+    return (parent - children).abs() > parent * threshold;
+}
+
+/// Compares the series of a parent meter against the series of all its
+/// sub-meters together, period by period. Both series need to have the same
+/// granularity. Items are sorted starting with the most recent period
+pub fn reconcile(parent: &Series, children: &Series, threshold: f64) -> Vec<ReconciliationItem> {
+    let mut periods: BTreeMap<NaiveDateTime, (f64, f64)> = BTreeMap::new();
+    for i in &parent.items {
+        periods.entry(i.period_start).or_insert((0.0, 0.0)).0 += i.value.parse::<f64>().unwrap();
+    }
+    for i in &children.items {
+        periods.entry(i.period_start).or_insert((0.0, 0.0)).1 += i.value.parse::<f64>().unwrap();
+    }
+
+    return periods
+        .into_iter()
+        .rev()
+        .map(|(period_start, (p, c))| ReconciliationItem {
+            period_start,
+            parent: p.to_string(),
+            children: c.to_string(),
+            unaccounted: (p - c).to_string(),
+            exceeds_threshold: exceeds_threshold(p, c, threshold),
+        })
+        .collect();
+}
+
+/// Validates and saves a measurement sent by a device. Used by every way
+/// devices have to send measurements. Returns the measurement and the
+/// reporting interval of the meter, so devices can adapt to it
+/// authenticated - If the device sent its secret. Telemetry is only taken from
+///                 authenticated devices, since it creates alerts
+pub async fn record_measurement(
+    storage: Arc<dyn Storage>,
+    settings: &Settings,
+    input: SaveMeasurementInput,
+    authenticated: bool,
+) -> Result<(Measurement, i64), AppError> {
+    if let Some(t) = &input.telemetry {
+        if !authenticated {
+            return unauthorized();
+        }
+
+        let validation_errors = validate_telemetry(t);
+        if !validation_errors.is_empty() {
+            return validation_error(validation_errors);
+        }
+    }
+
+    let meter = storage.get_fluid_meter_by_id(&input.device_id).await?;
+    match meter {
+        Some(m) => {
+            if m.status == Active {
+                let measurement = Measurement {
+                    id: Uuid::new_v4().to_string(),
+                    device_id: input.device_id.clone(),
+                    measurement: input.measurement,
+                    recorded_at: Utc::now().naive_utc(),
+                };
+                let interval = reporting_interval(settings, &m);
+                match storage.save_measurement(&measurement, interval).await {
+                    Ok(_) => MEASUREMENTS_INGESTED.inc(),
+                    Err(e) => {
+                        if e.code == RateLimitError {
+                            return too_many_requests(interval as u64);
+                        }
+                        return internal_error();
+                    }
+                }
+
+                if let Some(t) = &input.telemetry {
+                    storage.save_device_status(&m.id, t).await?;
+                }
+
+                return Ok((measurement, interval));
+            }
+        }
+        None => {
+            // Devices send their serial until they learn their id on check-in
+            if let Some(d) = storage.device_by_serial(&input.device_id).await? {
+                if d.fluid_meter_id.is_none() {
+                    return validation_error(vec![FailedValidation {
+                        field: "device_id".to_string(),
+                        issue: NotClaimed,
+                    }]);
+                }
+            }
+        }
+    }
+
+    return validation_error(vec![FailedValidation {
+        field: "device_id".to_string(),
+        issue: Invalid,
+    }]);
+}
+
 #[cfg(test)]
 mod tests {
-    use super::create_series;
+    use super::{create_series, reconcile, take_reporting_token};
     use crate::api::{
         common::{Series, SeriesGranularity, SeriesItem},
         measurement::Measurement,
@@ -144,4 +325,76 @@ mod tests {
         };
         assert_eq!(expected, res);
     }
+
+    #[test]
+    fn reconcile_reports_unaccounted() {
+        let day =
+            NaiveDateTime::parse_from_str("2025-03-27 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let parent = Series {
+            granularity: SeriesGranularity::Day,
+            items: vec![
+                SeriesItem {
+                    period_start: day,
+                    value: "10".to_string(),
+                },
+                SeriesItem {
+                    period_start: day - Duration::days(1),
+                    value: "20".to_string(),
+                },
+            ],
+        };
+        let children = Series {
+            granularity: SeriesGranularity::Day,
+            items: vec![
+                SeriesItem {
+                    period_start: day,
+                    value: "9.5".to_string(),
+                },
+                SeriesItem {
+                    period_start: day - Duration::days(2),
+                    value: "1".to_string(),
+                },
+            ],
+        };
+
+        let res = reconcile(&parent, &children, 0.1);
+        assert_eq!(res.len(), 3);
+        assert_eq!(res[0].period_start, day);
+        assert_eq!(res[0].unaccounted, "0.5");
+        assert!(!res[0].exceeds_threshold);
+        assert_eq!(res[1].unaccounted, "20");
+        assert!(res[1].exceeds_threshold);
+        // Sub-meters measuring more than the parent is also a problem
+        assert_eq!(res[2].parent, "0");
+        assert_eq!(res[2].unaccounted, "-1");
+        assert!(res[2].exceeds_threshold);
+    }
+
+    #[test]
+    fn reporting_token_tolerates_jitter() {
+        let start =
+            NaiveDateTime::parse_from_str("2024-01-01 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let at = |secs| start + Duration::seconds(secs);
+
+        // A full bucket allows one measurement right away
+        let tokens = take_reporting_token(1.0, start, at(0), 100).unwrap();
+        assert_eq!(tokens, 0.0);
+
+        // Too early
+        assert_eq!(take_reporting_token(tokens, at(0), at(50), 100), None);
+
+        // A little early is fine, but the next one needs to make up for it
+        let tokens = take_reporting_token(tokens, at(0), at(95), 100).unwrap();
+        assert!(tokens < 0.0);
+        assert_eq!(take_reporting_token(tokens, at(95), at(180), 100), None);
+        assert!(take_reporting_token(tokens, at(95), at(195), 100).is_some());
+
+        // Long pauses don't allow bursts
+        let tokens = take_reporting_token(0.0, at(0), at(10000), 100).unwrap();
+        assert_eq!(tokens, 0.0);
+        assert_eq!(
+            take_reporting_token(tokens, at(10000), at(10001), 100),
+            None
+        );
+    }
 }
@@ -0,0 +1,107 @@
+use crate::storage::Storage;
+
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    register_int_gauge, Encoder, Histogram, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    TextEncoder,
+};
+use std::sync::Arc;
+use tracing::error;
+
+pub static HTTP_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "http_requests_total",
+        "Requests handled, by route and status",
+        &["method", "route", "status"]
+    )
+    .unwrap()
+});
+
+pub static HTTP_REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "http_request_duration_seconds",
+        "Time taken to handle requests, by route and status",
+        &["method", "route", "status"]
+    )
+    .unwrap()
+});
+
+pub static DB_POOL_CONNECTIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "db_pool_connections",
+        "Connections open in the database pool"
+    )
+    .unwrap()
+});
+
+pub static DB_POOL_IDLE_CONNECTIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "db_pool_idle_connections",
+        "Connections in the database pool that aren't in use"
+    )
+    .unwrap()
+});
+
+pub static DB_POOL_MAX_CONNECTIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "db_pool_max_connections",
+        "Connections the database pool can open"
+    )
+    .unwrap()
+});
+
+pub static MEASUREMENTS_INGESTED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "measurements_ingested_total",
+        "Measurements saved, from any source"
+    )
+    .unwrap()
+});
+
+pub static RATE_LIMIT_REJECTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "rate_limit_rejections_total",
+        "Requests rejected by the rate limiter, by the bucket that ran out",
+        &["bucket"]
+    )
+    .unwrap()
+});
+
+pub static ALERT_RUN_DURATION: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "alert_run_duration_seconds",
+        "Time taken to check the meters and send alerts",
+        vec![0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0]
+    )
+    .unwrap()
+});
+
+pub static EMAIL_SEND_FAILURES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!("email_send_failures_total", "E-mails that couldn't be sent").unwrap()
+});
+
+/// Renders all the metrics in the Prometheus text format. Gauges that are read
+/// from other components are updated first
+pub fn render(storage: &Arc<dyn Storage>) -> String {
+    if let Some(pool) = storage.connection_pool() {
+        DB_POOL_CONNECTIONS.set(pool.connections as i64);
+        DB_POOL_IDLE_CONNECTIONS.set(pool.idle as i64);
+        DB_POOL_MAX_CONNECTIONS.set(pool.max_connections as i64);
+    }
+
+    // Metrics that weren't used yet are only registered when they are first
+    // used, so they are touched to always show up
+    Lazy::force(&HTTP_REQUESTS);
+    Lazy::force(&HTTP_REQUEST_DURATION);
+    Lazy::force(&MEASUREMENTS_INGESTED);
+    Lazy::force(&RATE_LIMIT_REJECTIONS);
+    Lazy::force(&ALERT_RUN_DURATION);
+    Lazy::force(&EMAIL_SEND_FAILURES);
+
+    let mut buffer = vec![];
+    if let Err(e) = TextEncoder::new().encode(&prometheus::gather(), &mut buffer) {
+        error!("Error encoding metrics. {}", e);
+    }
+    return String::from_utf8(buffer).unwrap_or_default();
+}
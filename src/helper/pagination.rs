@@ -0,0 +1,130 @@
+use crate::{
+    api::common::{PageCursor, SortKeyType, DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE},
+    error::app_error::{
+        FailedValidation,
+        ValidationIssue::{Invalid, TooLarge},
+    },
+};
+
+use chrono::{Datelike, NaiveDateTime};
+
+// Latest year PostgreSQL can store in a TIMESTAMP
+const MAX_TIMESTAMP_YEAR: &'static i32 = &294276;
+
+// Cursor keys are bound to the query and cast to the type of the sort, so a
+// key that doesn't parse would make the query fail. Keys are created from the
+// TEXT representation of the value, so that's the only format accepted
+fn is_valid_key(key: &str, key_type: SortKeyType) -> bool {
+    if key.contains('\0') {
+        return false;
+    }
+
+    match key_type {
+        SortKeyType::Text => return true,
+        SortKeyType::Timestamp => {
+            if key == "-infinity" || key == "infinity" {
+                return true;
+            }
+            if !key.starts_with(|c: char| c.is_ascii_digit()) {
+                return false;
+            }
+            match NaiveDateTime::parse_from_str(key, "%Y-%m-%d %H:%M:%S%.f") {
+                Ok(dt) => return dt.year() >= 1 && dt.year() <= *MAX_TIMESTAMP_YEAR,
+                Err(_) => return false,
+            }
+        }
+    }
+}
+
+/// Validates the pagination options of a list request. Returns the page size
+/// to use and the failed validations, if any
+/// page_size - Requested page size. The default is used if not set
+/// cursor - Requested cursor
+/// sort - Sort of the list, including its direction if it can change. Cursors
+///        can only be used with the sort they were created for
+/// key_type - Type of the value the list is sorted by
+pub fn validate_page(
+    page_size: Option<u8>,
+    cursor: &Option<PageCursor>,
+    sort: &str,
+    key_type: SortKeyType,
+) -> (u8, Vec<FailedValidation>) {
+    let mut validation_errors = vec![];
+
+    let page_size = page_size.unwrap_or(*DEFAULT_PAGE_SIZE);
+    if page_size > *MAX_PAGE_SIZE {
+        validation_errors.push(FailedValidation {
+            field: "page_size".to_string(),
+            issue: TooLarge,
+        });
+    }
+
+    if let Some(c) = cursor {
+        if c.sort != sort || !is_valid_key(&c.key, key_type) || c.id.contains('\0') {
+            validation_errors.push(FailedValidation {
+                field: "page_cursor".to_string(),
+                issue: Invalid,
+            });
+        }
+    }
+
+    return (page_size, validation_errors);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::validate_page;
+    use crate::api::common::{PageCursor, SortKeyType};
+
+    #[test]
+    fn validate_page_cursor_sort() {
+        let cursor = PageCursor {
+            sort: "Name".to_string(),
+            key: "kitchen".to_string(),
+            id: "1".to_string(),
+            backward: true,
+        };
+        assert_eq!(PageCursor::decode(&cursor.encode()), Some(cursor.clone()));
+        assert_eq!(PageCursor::decode("not a cursor"), None);
+
+        let (page_size, errors) =
+            validate_page(None, &Some(cursor.clone()), "Name", SortKeyType::Text);
+        assert_eq!(page_size, 25);
+        assert!(errors.is_empty());
+
+        let (_, errors) = validate_page(Some(101), &Some(cursor), "Id", SortKeyType::Text);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].field, "page_size");
+        assert_eq!(errors[1].field, "page_cursor");
+    }
+
+    #[test]
+    fn validate_page_cursor_key() {
+        let cursor = |key: &str| {
+            Some(PageCursor {
+                sort: "RecordedAt".to_string(),
+                key: key.to_string(),
+                id: "1".to_string(),
+                backward: false,
+            })
+        };
+        let valid = |key: &str| {
+            let (_, errors) =
+                validate_page(None, &cursor(key), "RecordedAt", SortKeyType::Timestamp);
+            errors.is_empty()
+        };
+
+        assert!(valid("2024-03-01 10:20:30"));
+        assert!(valid("2024-03-01 10:20:30.123456"));
+        assert!(valid("-infinity"));
+        assert!(!valid("kitchen"));
+        assert!(!valid("2024-03-01"));
+        assert!(!valid("-2024-03-01 10:20:30"));
+        assert!(!valid("0000-03-01 10:20:30"));
+        assert!(!valid("2024-03-01 10:20:30\0"));
+
+        let (_, errors) =
+            validate_page(None, &cursor("kitchen\0"), "RecordedAt", SortKeyType::Text);
+        assert_eq!(errors.len(), 1);
+    }
+}
@@ -0,0 +1,260 @@
+use crate::{
+    settings::settings::Settings,
+    storage::{error::Error, Storage},
+};
+
+use chrono::{Duration, NaiveDateTime};
+use http::Method;
+use std::{collections::HashMap, str::FromStr, sync::Arc, sync::Mutex};
+use tracing::error;
+
+// Number of in memory buckets after which they are pruned
+const MAX_BUCKETS: &'static usize = &10000;
+// Number of buckets left after pruning. The gap with MAX_BUCKETS makes sure
+// pruning only happens once every few thousand new keys
+const PRUNED_BUCKETS: &'static usize = &7500;
+
+type Buckets = HashMap<String, (f64, NaiveDateTime)>;
+
+/// Token bucket that allows requests_per_minute requests in a burst and refills
+/// at the same rate. Takes a token and returns the tokens left, or the seconds
+/// until a token is available if the bucket is empty
+pub fn take_token(
+    tokens: f64,
+    updated_at: NaiveDateTime,
+    now: NaiveDateTime,
+    requests_per_minute: u32,
+) -> Result<f64, u64> {
+    let capacity = requests_per_minute as f64;
+    let refill_per_sec = capacity / 60.0;
+    let elapsed = (now - updated_at).num_milliseconds().max(0) as f64 / 1000.0;
+    let tokens = (tokens + elapsed * refill_per_sec).min(capacity);
+    if tokens < 1.0 {
+        return Err(((1.0 - tokens) / refill_per_sec).ceil() as u64);
+    }
+
+    return Ok(tokens - 1.0);
+}
+
+/// Drops the buckets that are full, since they behave the same as a new one. If
+/// there are still too many, the least recently used are dropped until there
+/// are PRUNED_BUCKETS left
+fn prune_buckets(buckets: &mut Buckets, now: NaiveDateTime) {
+    // Buckets refill completely in a minute, so older ones are full
+    buckets.retain(|_, (_, updated_at)| now - *updated_at < Duration::minutes(1));
+    if buckets.len() <= *PRUNED_BUCKETS {
+        return;
+    }
+
+    let mut updated: Vec<NaiveDateTime> = buckets.values().map(|(_, u)| *u).collect();
+    let excess = buckets.len() - *PRUNED_BUCKETS;
+    let (_, cutoff, _) = updated.select_nth_unstable(excess - 1);
+    let cutoff = *cutoff;
+    let mut left = excess;
+    buckets.retain(|_, (_, updated_at)| {
+        if left > 0 && *updated_at <= cutoff {
+            left -= 1;
+            return false;
+        }
+        return true;
+    });
+}
+
+/// Parses a comma separated list of `METHOD /path=requests_per_minute`. Invalid
+/// entries are ignored
+pub fn parse_route_limits(routes: &str) -> HashMap<(Method, String), u32> {
+    let mut limits = HashMap::new();
+    for route in routes.split(",") {
+        let route = route.trim();
+        if route.len() == 0 {
+            continue;
+        }
+
+        let parsed = route.split_once("=").and_then(|(endpoint, limit)| {
+            let (method, path) = endpoint.trim().split_once(" ")?;
+            let method = Method::from_str(&method.to_uppercase()).ok()?;
+            let limit = limit.trim().parse::<u32>().ok()?;
+            return Some(((method, path.trim().to_string()), limit));
+        });
+        match parsed {
+            Some((endpoint, limit)) => {
+                limits.insert(endpoint, limit);
+            }
+            None => {
+                error!("Invalid rate limit for route: {}", route);
+            }
+        }
+    }
+
+    return limits;
+}
+
+/// Keeps the token buckets used to rate limit requests. Buckets live in memory
+/// unless they are shared, in which case they live in the storage
+pub struct RateLimiter {
+    buckets: Mutex<Buckets>,
+    routes: HashMap<(Method, String), u32>,
+    shared: bool,
+}
+
+impl RateLimiter {
+    pub fn new(settings: &Settings) -> Self {
+        let (routes, shared) = match &settings.rate_limit {
+            Some(r) => (parse_route_limits(&r.routes), r.shared),
+            None => (HashMap::new(), false),
+        };
+        return RateLimiter {
+            buckets: Mutex::new(HashMap::new()),
+            routes,
+            shared,
+        };
+    }
+
+    /// The requests per minute allowed for the route, if it has its own limit
+    pub fn route_limit(&self, method: &Method, path: &str) -> Option<u32> {
+        return self
+            .routes
+            .get(&(method.clone(), path.to_string()))
+            .copied();
+    }
+
+    /// Takes a token from each of the buckets, given as key and requests per
+    /// minute. If any of them is empty no token is taken, and its key is
+    /// returned with the seconds until a token is available
+    pub async fn take(
+        &self,
+        storage: &Arc<dyn Storage>,
+        buckets: &Vec<(String, u32)>,
+        now: NaiveDateTime,
+    ) -> Result<Option<(String, u64)>, Error> {
+        if self.shared {
+            return storage.take_rate_limit_tokens(buckets, now).await;
+        }
+
+        let mut stored = self.buckets.lock().unwrap();
+        if stored.len() >= *MAX_BUCKETS {
+            prune_buckets(&mut stored, now);
+        }
+
+        let mut taken = vec![];
+        for (key, requests_per_minute) in buckets {
+            let (tokens, updated_at) = stored
+                .get(key)
+                .copied()
+                .unwrap_or((*requests_per_minute as f64, now));
+            match take_token(tokens, updated_at, now, *requests_per_minute) {
+                Ok(t) => taken.push((key.clone(), t)),
+                Err(retry_after) => return Ok(Some((key.clone(), retry_after))),
+            }
+        }
+        for (key, tokens) in taken {
+            stored.insert(key, (tokens, now));
+        }
+        return Ok(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        parse_route_limits, prune_buckets, take_token, Buckets, RateLimiter, PRUNED_BUCKETS,
+    };
+    use crate::storage::{mock::MockStorage, Storage};
+
+    use chrono::{Duration, NaiveDateTime};
+    use http::Method;
+    use std::{collections::HashMap, sync::Arc, sync::Mutex};
+
+    #[test]
+    fn take_token_success() {
+        let now =
+            NaiveDateTime::parse_from_str("2025-03-27 14:42:32", "%Y-%m-%d %H:%M:%S").unwrap();
+
+        // A full bucket allows bursts up to its capacity
+        assert_eq!(take_token(2.0, now, now, 2), Ok(1.0));
+        assert_eq!(take_token(1.0, now, now, 2), Ok(0.0));
+        assert_eq!(take_token(0.0, now, now, 2), Err(30));
+
+        // Refills at requests_per_minute
+        assert_eq!(
+            take_token(0.0, now, now + Duration::seconds(15), 2),
+            Err(15)
+        );
+        assert_eq!(
+            take_token(0.0, now, now + Duration::seconds(30), 2),
+            Ok(0.0)
+        );
+
+        // Never holds more than its capacity
+        assert_eq!(take_token(0.0, now, now + Duration::hours(1), 2), Ok(1.0));
+    }
+
+    #[test]
+    fn parse_route_limits_success() {
+        let limits = parse_route_limits(
+            "POST /v1/log-in=10, get /v1/recover-password = 5,invalid,POST /v1/sign-up=x,",
+        );
+        assert_eq!(limits.len(), 2);
+        assert_eq!(
+            limits.get(&(Method::POST, "/v1/log-in".to_string())),
+            Some(&10)
+        );
+        assert_eq!(
+            limits.get(&(Method::GET, "/v1/recover-password".to_string())),
+            Some(&5)
+        );
+    }
+
+    #[test]
+    fn prune_buckets_success() {
+        let now =
+            NaiveDateTime::parse_from_str("2025-03-27 14:42:32", "%Y-%m-%d %H:%M:%S").unwrap();
+
+        // Full buckets are dropped
+        let mut buckets = Buckets::new();
+        buckets.insert("full".to_string(), (10.0, now - Duration::minutes(2)));
+        buckets.insert("recent".to_string(), (0.0, now));
+        prune_buckets(&mut buckets, now);
+        assert_eq!(buckets.len(), 1);
+        assert!(buckets.contains_key("recent"));
+
+        // If all of them were used recently, the least recently used are dropped
+        let mut buckets = Buckets::new();
+        for i in 0..10000 {
+            let updated_at = now - Duration::milliseconds(10000 - i);
+            buckets.insert(i.to_string(), (0.0, updated_at));
+        }
+        prune_buckets(&mut buckets, now);
+        assert_eq!(buckets.len(), *PRUNED_BUCKETS);
+        assert!(!buckets.contains_key("2499"));
+        assert!(buckets.contains_key("2500"));
+        assert!(buckets.contains_key("9999"));
+    }
+
+    #[tokio::test]
+    async fn take_all_or_nothing() {
+        let now =
+            NaiveDateTime::parse_from_str("2025-03-27 14:42:32", "%Y-%m-%d %H:%M:%S").unwrap();
+        let limiter = RateLimiter {
+            buckets: Mutex::new(HashMap::new()),
+            routes: HashMap::new(),
+            shared: false,
+        };
+        let storage: Arc<dyn Storage> = Arc::new(MockStorage::new());
+        let both = vec![("a".to_string(), 2), ("b".to_string(), 1)];
+        let only_a = vec![("a".to_string(), 2)];
+
+        assert_eq!(limiter.take(&storage, &both, now).await.unwrap(), None);
+        assert_eq!(
+            limiter.take(&storage, &both, now).await.unwrap(),
+            Some(("b".to_string(), 60))
+        );
+
+        // The rejected request didn't take a token from the other bucket
+        assert_eq!(limiter.take(&storage, &only_a, now).await.unwrap(), None);
+        assert_eq!(
+            limiter.take(&storage, &only_a, now).await.unwrap(),
+            Some(("a".to_string(), 30))
+        );
+    }
+}
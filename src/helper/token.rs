@@ -1,7 +1,56 @@
+use axum::http::{header::AUTHORIZATION, HeaderMap};
 use rand::distributions::{Alphanumeric, DistString};
+use sha2::{Digest, Sha256};
 
 pub const AUTH_TOKEN_LEN: &'static usize = &100;
 
 pub fn alphanumeric(len: &usize) -> String {
     Alphanumeric.sample_string(&mut rand::thread_rng(), *len)
 }
+
+/// Compares two secrets in constant time. Both are hashed first, so their
+/// lengths don't leak either
+pub fn secrets_match(a: &str, b: &str) -> bool {
+    let a = Sha256::digest(a.as_bytes());
+    let b = Sha256::digest(b.as_bytes());
+    return a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0;
+}
+
+/// Whether the request has the given token as bearer token. An empty token never
+/// matches, so unset tokens don't let anyone in
+pub fn has_bearer_token(headers: &HeaderMap, token: &str) -> bool {
+    if token.len() == 0 {
+        return false;
+    }
+
+    return match headers
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+    {
+        Some(t) => secrets_match(t, token),
+        None => false,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{has_bearer_token, secrets_match};
+
+    use axum::http::{header::AUTHORIZATION, HeaderMap, HeaderValue};
+
+    #[test]
+    fn has_bearer_token_success() {
+        assert!(secrets_match("secret", "secret"));
+        assert!(!secrets_match("secret", "secreT"));
+
+        let mut headers = HeaderMap::new();
+        assert!(!has_bearer_token(&headers, "secret"));
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer secret"));
+        assert!(has_bearer_token(&headers, "secret"));
+        assert!(!has_bearer_token(&headers, "other"));
+        assert!(!has_bearer_token(&headers, ""));
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer "));
+        assert!(!has_bearer_token(&headers, ""));
+    }
+}
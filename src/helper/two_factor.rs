@@ -0,0 +1,190 @@
+use crate::{
+    api::two_factor::TwoFactor, error::app_error::AppError, helper::token::alphanumeric,
+    storage::Storage,
+};
+
+use chrono::{NaiveDateTime, Utc};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use totp_rs::{Algorithm, Secret, TOTP};
+use tracing::error;
+
+// Failed codes allowed for an account, across challenges, before it's locked
+pub const ACCOUNT_MAX_FAILED_ATTEMPTS: &'static i32 = &10;
+pub const CHALLENGE_MAX_ATTEMPTS: &'static i32 = &5;
+pub const CHALLENGE_TTL_MINS: &'static i64 = &5;
+pub const ISSUER: &'static str = "Mekadomus";
+pub const LOCKOUT_MINS: &'static i64 = &15;
+pub const RECOVERY_CODE_LEN: &'static usize = &12;
+pub const RECOVERY_CODES: &'static usize = &10;
+pub const SECRET_BYTES: &'static usize = &20;
+// Number of steps before and after the current one that are accepted, so small
+// differences between the server and the user's clock don't matter
+pub const STEP_SKEW: &'static u64 = &1;
+pub const STEP_SECS: &'static u64 = &30;
+
+fn totp(secret: &str, account: &str) -> Option<TOTP> {
+    let bytes = match Secret::Encoded(secret.to_string()).to_bytes() {
+        Ok(b) => b,
+        Err(e) => {
+            error!("Invalid TOTP secret. {:?}", e);
+            return None;
+        }
+    };
+
+    match TOTP::new(
+        Algorithm::SHA1,
+        6,
+        0,
+        *STEP_SECS,
+        bytes,
+        Some(ISSUER.to_string()),
+        account.to_string(),
+    ) {
+        Ok(t) => Some(t),
+        Err(e) => {
+            error!("Couldn't create TOTP. {}", e);
+            None
+        }
+    }
+}
+
+/// Generates a random secret for a new TOTP enrollment. Encoded in base32
+pub fn generate_secret() -> String {
+    let mut bytes = vec![0u8; *SECRET_BYTES];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    Secret::Raw(bytes).to_encoded().to_string()
+}
+
+/// Returns the otpauth:// URI authenticator apps use to add the account
+pub fn otpauth_uri(secret: &str, account: &str) -> Option<String> {
+    totp(secret, account).map(|t| t.get_url())
+}
+
+/// Returns the code for the given secret at the given unix timestamp
+pub fn code_at(secret: &str, timestamp: u64) -> Option<String> {
+    totp(secret, "").map(|t| t.generate(timestamp))
+}
+
+/// Checks the code against the steps around the given unix timestamp and
+/// returns the step it matched, if any
+pub fn matching_step(secret: &str, code: &str, timestamp: u64) -> Option<i64> {
+    let t = totp(secret, "")?;
+    let current = timestamp / *STEP_SECS;
+    for step in current.saturating_sub(*STEP_SKEW)..=(current + *STEP_SKEW) {
+        if t.generate(step * *STEP_SECS) == code.trim() {
+            return Some(step as i64);
+        }
+    }
+
+    return None;
+}
+
+/// Generates a new set of single-use recovery codes
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..*RECOVERY_CODES)
+        .map(|_| alphanumeric(RECOVERY_CODE_LEN).to_lowercase())
+        .collect()
+}
+
+/// Recovery codes are random, so a fast hash is enough to avoid storing them in
+/// plain text
+pub fn hash_recovery_code(code: &str) -> String {
+    format!(
+        "{:x}",
+        Sha256::digest(code.trim().to_lowercase().as_bytes())
+    )
+}
+
+/// Seconds until the user can try two factor log-in again, if too many codes
+/// failed
+pub fn lockout_secs(two_factor: &TwoFactor, now: NaiveDateTime) -> Option<u64> {
+    match two_factor.locked_until {
+        Some(l) if l > now => return Some((l - now).num_seconds().max(1) as u64),
+        _ => return None,
+    }
+}
+
+/// Verifies the second factor provided by the user. Either a TOTP code or a
+/// recovery code. Codes are single-use, so they are marked as used if valid
+pub async fn verify_second_factor(
+    storage: Arc<dyn Storage>,
+    two_factor: &TwoFactor,
+    code: &Option<String>,
+    recovery_code: &Option<String>,
+) -> Result<bool, AppError> {
+    if let Some(c) = code {
+        let now = Utc::now().timestamp() as u64;
+        let step = match matching_step(&two_factor.secret, c, now) {
+            Some(s) => s,
+            None => return Ok(false),
+        };
+
+        return Ok(storage
+            .use_two_factor_step(&two_factor.account_id, step)
+            .await?);
+    }
+
+    if let Some(c) = recovery_code {
+        return Ok(storage
+            .use_recovery_code(&two_factor.account_id, &hash_recovery_code(c))
+            .await?);
+    }
+
+    return Ok(false);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        code_at, generate_recovery_codes, generate_secret, hash_recovery_code, matching_step,
+        otpauth_uri, RECOVERY_CODES,
+    };
+
+    // Secret "12345678901234567890" from RFC 6238 test vectors
+    const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn code_at_matches_rfc_vectors() {
+        assert_eq!(code_at(RFC_SECRET, 59).unwrap(), "287082");
+        assert_eq!(code_at(RFC_SECRET, 1111111109).unwrap(), "081804");
+        assert_eq!(code_at(RFC_SECRET, 1234567890).unwrap(), "005924");
+    }
+
+    #[test]
+    fn matching_step_allows_skew() {
+        let now = 1234567890;
+        let code = code_at(RFC_SECRET, now).unwrap();
+        let step = (now / 30) as i64;
+
+        assert_eq!(matching_step(RFC_SECRET, &code, now), Some(step));
+        assert_eq!(matching_step(RFC_SECRET, &code, now + 30), Some(step));
+        assert_eq!(matching_step(RFC_SECRET, &code, now - 30), Some(step));
+        assert_eq!(matching_step(RFC_SECRET, &code, now + 90), None);
+        assert_eq!(matching_step(RFC_SECRET, "000000", now), None);
+    }
+
+    #[test]
+    fn generated_secret_is_usable() {
+        let secret = generate_secret();
+        assert!(code_at(&secret, 59).is_some());
+
+        let uri = otpauth_uri(&secret, "a@b.c").unwrap();
+        assert!(uri.starts_with("otpauth://totp/Mekadomus:a%40b.c?"));
+        assert!(uri.contains(&format!("secret={}", secret)));
+    }
+
+    #[test]
+    fn recovery_codes_are_unique() {
+        let mut codes = generate_recovery_codes();
+        assert_eq!(codes.len(), *RECOVERY_CODES);
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), *RECOVERY_CODES);
+        assert_eq!(
+            hash_recovery_code(&codes[0]),
+            hash_recovery_code(&format!(" {} ", codes[0].to_uppercase()))
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use crate::{
-    error::app_error::AppError,
+    api::{fluid_meter_member::FluidMeterRole, organization::OrganizationRole},
+    error::app_error::{forbidden, not_found, AppError},
     http_client::cloudflare::{check_captcha, CheckCaptchaRequest},
     storage::Storage,
 };
@@ -11,6 +12,12 @@ use tracing::error;
 use uuid::Uuid;
 use zxcvbn::{zxcvbn, Score::Three};
 
+// Minimum minutes between verification e-mails sent to the same account
+pub const EMAIL_VERIFICATION_RESEND_MINS: &'static i64 = &5;
+// Password recovery tokens expire after this many minutes. A new one can't be
+// requested while one is still valid
+pub const PASSWORD_RECOVERY_TTL_MINS: &'static i64 = &30;
+
 #[automock]
 #[async_trait]
 pub trait UserHelper: Send + Sync {
@@ -18,11 +25,26 @@ pub trait UserHelper: Send + Sync {
     fn password_is_weak(&self, pass: &str) -> bool;
     fn hash(&self, input: &str) -> Result<String, AppError>;
     fn verify_hash(&self, input: &str, hash: &str) -> Result<bool, AppError>;
-    async fn owns_fluid_meter(
+    async fn has_fluid_meter_role(
         &self,
         storage: Arc<dyn Storage>,
         user_id: &str,
         meter_id: &str,
+        role: FluidMeterRole,
+    ) -> Result<bool, AppError>;
+    async fn has_organization_role(
+        &self,
+        storage: Arc<dyn Storage>,
+        user_id: &str,
+        organization_id: &str,
+        role: OrganizationRole,
+    ) -> Result<bool, AppError>;
+    async fn has_site_role(
+        &self,
+        storage: Arc<dyn Storage>,
+        user_id: &str,
+        site_id: &str,
+        role: OrganizationRole,
     ) -> Result<bool, AppError>;
 }
 
@@ -79,15 +101,152 @@ impl UserHelper for DefaultUserHelper {
         };
     }
 
-    async fn owns_fluid_meter(
+    /// Returns true if the user has at least the given role for the meter. The
+    /// owner of the meter has all the roles
+    async fn has_fluid_meter_role(
         &self,
         storage: Arc<dyn Storage>,
         user_id: &str,
         meter_id: &str,
+        role: FluidMeterRole,
+    ) -> Result<bool, AppError> {
+        if Uuid::try_parse(meter_id).is_err() {
+            return Ok(false);
+        }
+
+        return match storage.fluid_meter_role(meter_id, user_id).await? {
+            Some(r) => Ok(r >= role),
+            None => Ok(false),
+        };
+    }
+
+    /// Returns true if the user is a member of the organization with at least
+    /// the given role
+    async fn has_organization_role(
+        &self,
+        storage: Arc<dyn Storage>,
+        user_id: &str,
+        organization_id: &str,
+        role: OrganizationRole,
+    ) -> Result<bool, AppError> {
+        if Uuid::try_parse(organization_id).is_err() {
+            return Ok(false);
+        }
+
+        return match storage
+            .organization_member(organization_id, user_id)
+            .await?
+        {
+            Some(m) => Ok(m.role >= role),
+            None => Ok(false),
+        };
+    }
+
+    /// Returns true if the user has at least the given role for the site. The
+    /// owner of a personal site has all the roles. For sites of an organization,
+    /// the role in the organization is used
+    async fn has_site_role(
+        &self,
+        storage: Arc<dyn Storage>,
+        user_id: &str,
+        site_id: &str,
+        role: OrganizationRole,
     ) -> Result<bool, AppError> {
-        return Ok(!Uuid::try_parse(&meter_id).is_err()
-            && storage.is_fluid_meter_owner(&meter_id, &user_id).await?);
+        if Uuid::try_parse(site_id).is_err() {
+            return Ok(false);
+        }
+
+        let site = match storage.site_by_id(site_id).await? {
+            Some(s) => s,
+            None => return Ok(false),
+        };
+        if site.owner_id.as_deref() == Some(user_id) {
+            return Ok(true);
+        }
+
+        return match site.organization_id {
+            Some(o) => self.has_organization_role(storage, user_id, &o, role).await,
+            None => Ok(false),
+        };
+    }
+}
+
+/// Fails with NotFound if the user can't see the meter at all, or with Forbidden
+/// if they can see it but don't have the given role
+pub async fn require_fluid_meter_role(
+    user_helper: &Arc<dyn UserHelper>,
+    storage: Arc<dyn Storage>,
+    user_id: &str,
+    meter_id: &str,
+    role: FluidMeterRole,
+) -> Result<(), AppError> {
+    if user_helper
+        .has_fluid_meter_role(storage.clone(), user_id, meter_id, role.clone())
+        .await?
+    {
+        return Ok(());
+    }
+
+    if role > FluidMeterRole::Viewer
+        && user_helper
+            .has_fluid_meter_role(storage, user_id, meter_id, FluidMeterRole::Viewer)
+            .await?
+    {
+        return forbidden();
+    }
+    return not_found();
+}
+
+/// Fails with NotFound if the user isn't a member of the organization, or with
+/// Forbidden if they are but don't have the given role
+pub async fn require_organization_role(
+    user_helper: &Arc<dyn UserHelper>,
+    storage: Arc<dyn Storage>,
+    user_id: &str,
+    organization_id: &str,
+    role: OrganizationRole,
+) -> Result<(), AppError> {
+    if user_helper
+        .has_organization_role(storage.clone(), user_id, organization_id, role.clone())
+        .await?
+    {
+        return Ok(());
+    }
+
+    if role > OrganizationRole::Member
+        && user_helper
+            .has_organization_role(storage, user_id, organization_id, OrganizationRole::Member)
+            .await?
+    {
+        return forbidden();
+    }
+    return not_found();
+}
+
+/// Fails with NotFound if the user can't see the site at all, or with Forbidden
+/// if they can see it but don't have the given role
+pub async fn require_site_role(
+    user_helper: &Arc<dyn UserHelper>,
+    storage: Arc<dyn Storage>,
+    user_id: &str,
+    site_id: &str,
+    role: OrganizationRole,
+) -> Result<(), AppError> {
+    if user_helper
+        .has_site_role(storage.clone(), user_id, site_id, role.clone())
+        .await?
+    {
+        return Ok(());
+    }
+
+    if role > OrganizationRole::Member
+        && user_helper
+            .has_site_role(storage, user_id, site_id, OrganizationRole::Member)
+            .await?
+    {
+        return forbidden();
     }
+    return not_found();
 }
 
 #[cfg(test)]
@@ -0,0 +1,80 @@
+use chrono::Utc;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    api::{
+        device::{
+            DeviceCommand,
+            DeviceCommandStatus::Pending,
+            DeviceCommandType::{CloseValve, OpenValve},
+        },
+        valve::{
+            Valve, ValveAction,
+            ValveActionOrigin::{self, AutoShutOff},
+            ValveState::{self, Closed, Open},
+        },
+    },
+    error::app_error::AppError,
+    storage::Storage,
+};
+
+/// Queues the command that moves the valve to the given state and records who
+/// asked for it
+/// account_id - The user that asked for it. Empty for automatic actions
+pub async fn request_valve_state(
+    storage: Arc<dyn Storage>,
+    valve: &Valve,
+    target_state: ValveState,
+    origin: ValveActionOrigin,
+    account_id: Option<String>,
+) -> Result<ValveAction, AppError> {
+    let now = Utc::now().naive_utc();
+    let command = DeviceCommand {
+        id: Uuid::new_v4().to_string(),
+        fluid_meter_id: valve.fluid_meter_id.clone(),
+        command_type: match target_state {
+            Open => OpenValve,
+            Closed => CloseValve,
+        },
+        value: None,
+        status: Pending,
+        created_by: account_id.clone(),
+        result: None,
+        recorded_at: now,
+        sent_at: None,
+        acknowledged_at: None,
+    };
+    let action = ValveAction {
+        id: Uuid::new_v4().to_string(),
+        fluid_meter_id: valve.fluid_meter_id.clone(),
+        target_state,
+        origin,
+        account_id,
+        command_id: command.id.clone(),
+        command_status: command.status.clone(),
+        recorded_at: now,
+    };
+
+    return Ok(storage.insert_valve_action(&action, &command).await?);
+}
+
+/// Closes the valve of the meter if it has automatic shut-off enabled. Returns
+/// the action if the valve was asked to close. Nothing happens if the valve
+/// is already closed or closing
+pub async fn auto_shut_off(
+    storage: Arc<dyn Storage>,
+    fluid_meter_id: &str,
+) -> Result<Option<ValveAction>, AppError> {
+    let valve = match storage.valve_by_fluid_meter(fluid_meter_id).await? {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+
+    if !valve.auto_shut_off || valve.requested_state == Closed {
+        return Ok(None);
+    }
+
+    let action = request_valve_state(storage, &valve, Closed, AutoShutOff, None).await?;
+    return Ok(Some(action));
+}
@@ -7,14 +7,19 @@ pub mod helper;
 pub mod http_client;
 pub mod logging;
 pub mod middleware;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
 pub mod settings;
 pub mod storage;
 
 use axum::{
-    extract::FromRef,
-    http::{header::HeaderValue, Method},
+    extract::{DefaultBodyLimit, FromRef},
+    http::{
+        header::{HeaderValue, RETRY_AFTER},
+        Method,
+    },
     middleware::{from_fn, from_fn_with_state},
-    routing::{delete, get, post},
+    routing::{delete, get, patch, post, put},
     Router,
 };
 use std::sync::Arc;
@@ -27,21 +32,53 @@ use tracing::{error, info, Level};
 use crate::{
     handler::{
         alert::trigger_alerts,
+        device::{
+            acknowledge_device_command, create_device_command, device_check_in, device_commands,
+            register_device,
+        },
+        firmware::{
+            check_firmware, create_firmware_release, download_firmware, firmware_releases,
+            firmware_updates, report_firmware_update, set_firmware_rollout, upload_firmware_binary,
+        },
         fluid_meter::{
-            activate_fluid_meter, create_fluid_meter, deactivate_fluid_meter, delete_fluid_meter,
-            fluid_meters, get_fluid_meter, get_fluid_meter_alerts,
+            activate_fluid_meter, claim_fluid_meter, create_fluid_meter, deactivate_fluid_meter,
+            delete_fluid_meter, deleted_fluid_meters, fluid_meters, get_fluid_meter,
+            get_fluid_meter_alerts, restore_fluid_meter, set_fluid_meter_parent,
+            set_fluid_meter_reporting_interval, set_fluid_meter_site, update_fluid_meter,
+        },
+        fluid_meter_member::{
+            accept_fluid_meter_invite, delete_fluid_meter_member, fluid_meter_members,
+            invite_fluid_meter_member, update_fluid_meter_member,
         },
         health::health_check,
-        measurement::{get_measurements_for_meter, save_measurement},
+        measurement::{get_measurements_for_meter, get_reconciliation_for_meter, save_measurement},
+        metrics::metrics,
+        organization::{
+            add_organization_member, create_organization, delete_organization_member,
+            get_organization_consumption, organization_members, organizations,
+        },
+        purge::purge,
+        site::{create_site, delete_site, get_site, get_site_consumption, sites},
+        two_factor::{
+            confirm_two_factor, disable_two_factor, enroll_two_factor, log_in_two_factor,
+        },
         user::{
-            email_verification, log_in_user, log_out_user, me, new_password, recover_password,
-            sign_up_user,
+            change_email, change_password, confirm_email_change, delete_me, email_verification,
+            export_me, log_in_user, log_out_user, me, new_password, recover_password,
+            resend_email_verification, sign_up_user, update_me,
         },
+        valve::{close_valve, get_valve, open_valve, set_valve, valve_actions},
+    },
+    helper::{
+        alert::AlertHelper, firmware::MAX_FIRMWARE_SIZE_BYTES, mail::MailHelper,
+        rate_limit::RateLimiter, user::UserHelper,
     },
-    helper::{alert::AlertHelper, mail::MailHelper, user::UserHelper},
     middleware::{
         auth::{auth, Authorizer},
         debug_request::debug_request,
+        metrics::track_metrics,
+        rate_limit::{rate_limit_by_ip, rate_limit_by_user},
+        request_id::{make_request_span, request_id, REQUEST_ID_HEADER},
     },
     settings::settings::{LoggingFormat::Json, Settings},
     storage::Storage,
@@ -52,6 +89,7 @@ pub struct AppState {
     alert_helper: Arc<dyn AlertHelper>,
     authorizer: Arc<dyn Authorizer>,
     mail_helper: Arc<dyn MailHelper>,
+    rate_limiter: Arc<RateLimiter>,
     settings: Arc<Settings>,
     storage: Arc<dyn Storage>,
     user_helper: Arc<dyn UserHelper>,
@@ -74,11 +112,19 @@ fn create_cors_layer(settings: Arc<Settings>) -> CorsLayer {
     }
 
     return CorsLayer::new()
-        .allow_methods([Method::DELETE, Method::GET, Method::POST, Method::PUT])
+        .allow_methods([
+            Method::DELETE,
+            Method::GET,
+            Method::PATCH,
+            Method::POST,
+            Method::PUT,
+        ])
         .allow_headers([
             "Content-Type".parse().unwrap(),
             "Authorization".parse().unwrap(),
+            REQUEST_ID_HEADER.clone(),
         ])
+        .expose_headers([RETRY_AFTER, REQUEST_ID_HEADER.clone()])
         .allow_origin(AllowOrigin::list(cors_domains));
 }
 
@@ -105,6 +151,7 @@ pub async fn app(
         alert_helper,
         authorizer,
         mail_helper,
+        rate_limiter: Arc::new(RateLimiter::new(&settings)),
         settings: settings.clone(),
         storage,
         user_helper,
@@ -113,19 +160,37 @@ pub async fn app(
     let cors = create_cors_layer(settings);
     Router::new()
         .route("/health", get(health_check))
+        .route("/metrics", get(metrics))
         // Users and sessions
+        .route("/v1/email-change", get(confirm_email_change))
         .route("/v1/email-verification", get(email_verification))
+        .route(
+            "/v1/email-verification/resend",
+            post(resend_email_verification),
+        )
         .route("/v1/log-in", post(log_in_user))
+        .route("/v1/log-in/two-factor", post(log_in_two_factor))
         .route("/v1/log-out", post(log_out_user))
         .route("/v1/me", get(me))
+        .route("/v1/me", patch(update_me))
+        .route("/v1/me", delete(delete_me))
+        .route("/v1/me/email", post(change_email))
+        .route("/v1/me/export", get(export_me))
+        .route("/v1/me/password", post(change_password))
         .route("/v1/new-password", post(new_password))
         .route("/v1/recover-password", get(recover_password))
         .route("/v1/sign-up", post(sign_up_user))
+        .route("/v1/two-factor", post(enroll_two_factor))
+        .route("/v1/two-factor/confirm", post(confirm_two_factor))
+        .route("/v1/two-factor/disable", post(disable_two_factor))
         // Fluid meters
         .route("/v1/fluid-meter", get(fluid_meters))
         .route("/v1/fluid-meter", post(create_fluid_meter))
+        .route("/v1/fluid-meter/claim", post(claim_fluid_meter))
+        .route("/v1/fluid-meter/deleted", get(deleted_fluid_meters))
         .route("/v1/fluid-meter/{meter_id}", delete(delete_fluid_meter))
         .route("/v1/fluid-meter/{meter_id}", get(get_fluid_meter))
+        .route("/v1/fluid-meter/{meter_id}", patch(update_fluid_meter))
         .route(
             "/v1/fluid-meter/{meter_id}/activate",
             post(activate_fluid_meter),
@@ -134,6 +199,11 @@ pub async fn app(
             "/v1/fluid-meter/{meter_id}/alert",
             get(get_fluid_meter_alerts),
         )
+        .route("/v1/fluid-meter/{meter_id}/command", get(device_commands))
+        .route(
+            "/v1/fluid-meter/{meter_id}/command",
+            post(create_device_command),
+        )
         .route(
             "/v1/fluid-meter/{meter_id}/deactivate",
             post(deactivate_fluid_meter),
@@ -142,17 +212,115 @@ pub async fn app(
             "/v1/fluid-meter/{meter_id}/measurement",
             get(get_measurements_for_meter),
         )
+        .route(
+            "/v1/fluid-meter/{meter_id}/member",
+            get(fluid_meter_members),
+        )
+        .route(
+            "/v1/fluid-meter/{meter_id}/member",
+            post(invite_fluid_meter_member),
+        )
+        .route(
+            "/v1/fluid-meter/{meter_id}/member/{account_id}",
+            delete(delete_fluid_meter_member),
+        )
+        .route(
+            "/v1/fluid-meter/{meter_id}/member/{account_id}",
+            patch(update_fluid_meter_member),
+        )
+        .route(
+            "/v1/fluid-meter/{meter_id}/parent",
+            put(set_fluid_meter_parent),
+        )
+        .route(
+            "/v1/fluid-meter/{meter_id}/reconciliation",
+            get(get_reconciliation_for_meter),
+        )
+        .route(
+            "/v1/fluid-meter/{meter_id}/reporting-interval",
+            put(set_fluid_meter_reporting_interval),
+        )
+        .route(
+            "/v1/fluid-meter/{meter_id}/restore",
+            post(restore_fluid_meter),
+        )
+        .route("/v1/fluid-meter/{meter_id}/site", put(set_fluid_meter_site))
+        .route("/v1/fluid-meter/{meter_id}/valve", get(get_valve))
+        .route("/v1/fluid-meter/{meter_id}/valve", put(set_valve))
+        .route(
+            "/v1/fluid-meter/{meter_id}/valve/action",
+            get(valve_actions),
+        )
+        .route("/v1/fluid-meter/{meter_id}/valve/close", post(close_valve))
+        .route("/v1/fluid-meter/{meter_id}/valve/open", post(open_valve))
+        .route(
+            "/v1/fluid-meter-invite/accept",
+            post(accept_fluid_meter_invite),
+        )
+        // Organizations
+        .route("/v1/organization", get(organizations))
+        .route("/v1/organization", post(create_organization))
+        .route(
+            "/v1/organization/{organization_id}/consumption",
+            get(get_organization_consumption),
+        )
+        .route(
+            "/v1/organization/{organization_id}/member",
+            get(organization_members),
+        )
+        .route(
+            "/v1/organization/{organization_id}/member",
+            post(add_organization_member),
+        )
+        .route(
+            "/v1/organization/{organization_id}/member/{account_id}",
+            delete(delete_organization_member),
+        )
+        // Sites
+        .route("/v1/site", get(sites))
+        .route("/v1/site", post(create_site))
+        .route("/v1/site/{site_id}", delete(delete_site))
+        .route("/v1/site/{site_id}", get(get_site))
+        .route("/v1/site/{site_id}/consumption", get(get_site_consumption))
+        // Devices
+        .route("/v1/device", post(register_device))
+        .route("/v1/device/check-in", post(device_check_in))
+        .route("/v1/device/command/ack", post(acknowledge_device_command))
+        .route("/v1/device/firmware", post(check_firmware))
+        .route("/v1/device/firmware/download", post(download_firmware))
+        .route("/v1/device/firmware/report", post(report_firmware_update))
+        // Firmware
+        .route("/v1/firmware", get(firmware_releases))
+        .route("/v1/firmware", post(create_firmware_release))
+        .route(
+            "/v1/firmware/{release_id}/binary",
+            put(upload_firmware_binary).layer(DefaultBodyLimit::max(*MAX_FIRMWARE_SIZE_BYTES)),
+        )
+        .route(
+            "/v1/firmware/{release_id}/rollout",
+            put(set_firmware_rollout),
+        )
+        .route("/v1/firmware/{release_id}/update", get(firmware_updates))
         // Measurements
         .route("/v1/measurement", post(save_measurement))
         // Alerts
         .route("/v1/alert", post(trigger_alerts))
+        // Jobs
+        .route("/v1/purge", post(purge))
         .with_state(state.clone())
-        .layer(from_fn_with_state(state, auth))
+        // Runs after auth, so it can limit by user
+        .layer(from_fn_with_state(state.clone(), rate_limit_by_user))
+        .layer(from_fn_with_state(state.clone(), auth))
+        // Runs before auth, so requests that fail to authenticate are limited too
+        .layer(from_fn_with_state(state, rate_limit_by_ip))
         .layer(from_fn(debug_request))
+        // Outside auth and rate limiting, so rejected requests are counted too
+        .layer(from_fn(track_metrics))
         .layer(cors)
+        .layer(from_fn(request_id))
         .layer(
             TraceLayer::new_for_http()
-                .make_span_with(trace::DefaultMakeSpan::new().level(Level::INFO))
+                .make_span_with(make_request_span)
                 .on_response(trace::DefaultOnResponse::new().level(Level::INFO)),
         )
 }
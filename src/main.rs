@@ -5,7 +5,7 @@ use mekadomus_api::{
     storage::{postgres::PostgresStorage, Storage},
 };
 
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc};
 use tokio::net::TcpListener;
 use tracing::info;
 
@@ -20,6 +20,9 @@ async fn main() {
     let storage: Arc<dyn Storage> =
         Arc::new(PostgresStorage::new(&settings.database.postgres.connection_string).await);
 
+    #[cfg(feature = "mqtt")]
+    tokio::spawn(mekadomus_api::mqtt::run(settings.clone(), storage.clone()));
+
     let app = mekadomus_api::app(
         alert_helper,
         authorizer,
@@ -34,5 +37,10 @@ async fn main() {
         .await
         .unwrap();
     info!("listening on {}", listener.local_addr().unwrap());
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
@@ -1,4 +1,5 @@
 use crate::{
+    api::user::User,
     error::app_error::{unauthorized, AppError},
     helper::token::AUTH_TOKEN_LEN,
     AppState,
@@ -16,16 +17,36 @@ use http::method::Method;
 use mockall::automock;
 use once_cell::sync::Lazy;
 use std::collections::{HashMap, HashSet};
-use tracing::error;
+use tracing::{error, Span};
 
 static PUBLIC_PATHS: Lazy<HashMap<&str, HashSet<Method>>> = Lazy::new(|| {
     let mut m = HashMap::new();
     m.insert("/health", HashSet::from([Method::GET, Method::HEAD]));
+    // Checks its own token
+    m.insert("/metrics", HashSet::from([Method::GET]));
     m.insert("/v1/alert", HashSet::from([Method::POST]));
+    // Checks its own token
+    m.insert("/v1/device", HashSet::from([Method::POST]));
+    m.insert("/v1/device/check-in", HashSet::from([Method::POST]));
+    m.insert("/v1/device/command/ack", HashSet::from([Method::POST]));
+    m.insert("/v1/device/firmware", HashSet::from([Method::POST]));
+    m.insert(
+        "/v1/device/firmware/download",
+        HashSet::from([Method::POST]),
+    );
+    m.insert("/v1/device/firmware/report", HashSet::from([Method::POST]));
+    m.insert("/v1/email-change", HashSet::from([Method::GET]));
     m.insert("/v1/email-verification", HashSet::from([Method::GET]));
+    m.insert(
+        "/v1/email-verification/resend",
+        HashSet::from([Method::POST]),
+    );
     m.insert("/v1/log-in", HashSet::from([Method::POST]));
+    m.insert("/v1/log-in/two-factor", HashSet::from([Method::POST]));
     m.insert("/v1/measurement", HashSet::from([Method::POST]));
     m.insert("/v1/new-password", HashSet::from([Method::POST]));
+    // Checks its own token
+    m.insert("/v1/purge", HashSet::from([Method::POST]));
     m.insert("/v1/recover-password", HashSet::from([Method::GET]));
     m.insert("/v1/sign-up", HashSet::from([Method::POST]));
     return m;
@@ -97,6 +118,9 @@ pub async fn auth(
         .authorize(state, &mut request)
         .await
     {
+        if let Some(user) = request.extensions().get::<User>() {
+            Span::current().record("user_id", user.id.as_str());
+        }
         return Ok(next.run(request).await);
     } else {
         return unauthorized();
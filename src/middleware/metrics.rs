@@ -0,0 +1,25 @@
+use crate::helper::metrics::{HTTP_REQUESTS, HTTP_REQUEST_DURATION};
+
+use axum::{body::Body, extract::MatchedPath, http::Request, middleware::Next, response::Response};
+use std::time::Instant;
+
+/// Counts requests and records how long they took, by route and status. Routes
+/// are labeled with their pattern, so ids in the path don't create new series
+pub async fn track_metrics(request: Request<Body>, next: Next) -> Response {
+    let start = Instant::now();
+    let method = request.method().to_string();
+    let route = match request.extensions().get::<MatchedPath>() {
+        Some(p) => p.as_str().to_string(),
+        None => "unmatched".to_string(),
+    };
+
+    let response = next.run(request).await;
+
+    let status = response.status().as_u16().to_string();
+    let labels = [method.as_str(), route.as_str(), status.as_str()];
+    HTTP_REQUESTS.with_label_values(&labels).inc();
+    HTTP_REQUEST_DURATION
+        .with_label_values(&labels)
+        .observe(start.elapsed().as_secs_f64());
+    return response;
+}
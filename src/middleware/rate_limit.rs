@@ -0,0 +1,127 @@
+use crate::{
+    api::user::User,
+    error::app_error::{too_many_requests, AppError},
+    helper::metrics::RATE_LIMIT_REJECTIONS,
+    AppState,
+};
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, MatchedPath, State},
+    http::Request,
+    middleware::Next,
+    response::Response,
+};
+use chrono::Utc;
+use std::net::SocketAddr;
+use tracing::{error, info};
+
+/// The address of the client that sent the request
+fn client_ip(request: &Request<Body>, trust_forwarded_for: bool) -> String {
+    if trust_forwarded_for {
+        let forwarded = request
+            .headers()
+            .get("X-Forwarded-For")
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.split(",").next())
+            .map(|ip| ip.trim().to_string());
+        if let Some(ip) = forwarded {
+            if ip.len() > 0 {
+                return ip;
+            }
+        }
+    }
+
+    return match request.extensions().get::<ConnectInfo<SocketAddr>>() {
+        Some(ConnectInfo(addr)) => addr.ip().to_string(),
+        None => "unknown".to_string(),
+    };
+}
+
+/// Takes a token from each of the buckets with a limit. Rejects the request
+/// without taking any if one of them is empty
+async fn take_tokens(state: &AppState, buckets: Vec<(String, u32)>) -> Result<(), AppError> {
+    // A limit of 0 disables the bucket
+    let buckets: Vec<(String, u32)> = buckets.into_iter().filter(|(_, l)| *l > 0).collect();
+    if buckets.len() == 0 {
+        return Ok(());
+    }
+
+    match state
+        .rate_limiter
+        .take(&state.storage, &buckets, Utc::now().naive_utc())
+        .await
+    {
+        Ok(None) => return Ok(()),
+        Ok(Some((key, retry_after))) => {
+            info!("Rate limiting {}. Retry after {}s", key, retry_after);
+            let bucket = key.split(":").next().unwrap_or_default();
+            RATE_LIMIT_REJECTIONS.with_label_values(&[bucket]).inc();
+            return too_many_requests(retry_after);
+        }
+        // Better to let requests through than to fail them all
+        Err(e) => {
+            error!("Failed to rate limit {:?}. {}", buckets, e);
+            return Ok(());
+        }
+    }
+}
+
+/// Rejects the request if the client IP ran out of tokens. Runs before auth, so
+/// requests that fail to authenticate are limited too
+pub async fn rate_limit_by_ip(
+    State(state): State<AppState>,
+    request: Request<Body>,
+    next: Next,
+) -> Result<Response, AppError> {
+    let settings = match &state.settings.rate_limit {
+        Some(s) => s,
+        None => return Ok(next.run(request).await),
+    };
+
+    let ip = client_ip(&request, settings.trust_forwarded_for);
+    take_tokens(
+        &state,
+        vec![(format!("ip:{}", ip), settings.ip_requests_per_minute)],
+    )
+    .await?;
+
+    return Ok(next.run(request).await);
+}
+
+/// Rejects the request if the logged in user or the client on the route ran
+/// out of tokens. Runs after auth, so it knows the user
+pub async fn rate_limit_by_user(
+    State(state): State<AppState>,
+    request: Request<Body>,
+    next: Next,
+) -> Result<Response, AppError> {
+    let settings = match &state.settings.rate_limit {
+        Some(s) => s,
+        None => return Ok(next.run(request).await),
+    };
+
+    let user_id = request.extensions().get::<User>().map(|u| u.id.clone());
+    let path = match request.extensions().get::<MatchedPath>() {
+        Some(p) => p.as_str().to_string(),
+        None => request.uri().path().to_string(),
+    };
+    let client = match &user_id {
+        Some(id) => format!("user:{}", id),
+        None => format!("ip:{}", client_ip(&request, settings.trust_forwarded_for)),
+    };
+
+    let mut buckets = vec![];
+    if let Some(limit) = state.rate_limiter.route_limit(request.method(), &path) {
+        buckets.push((
+            format!("route:{} {}:{}", request.method(), path, client),
+            limit,
+        ));
+    }
+    if let Some(id) = &user_id {
+        buckets.push((format!("user:{}", id), settings.user_requests_per_minute));
+    }
+    take_tokens(&state, buckets).await?;
+
+    return Ok(next.run(request).await);
+}
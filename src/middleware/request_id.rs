@@ -0,0 +1,84 @@
+use axum::{
+    body::Body,
+    http::{HeaderName, HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use tracing::{field::Empty, info_span, Span};
+use uuid::Uuid;
+
+pub static REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+// Longest request id accepted from clients
+const MAX_REQUEST_ID_LEN: &'static usize = &128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// The id of the request being handled, if any
+pub fn current_request_id() -> Option<String> {
+    return REQUEST_ID.try_with(|id| id.clone()).ok();
+}
+
+/// Span for a request. The request and user ids are recorded later by the
+/// request_id and auth middleware
+pub fn make_request_span(request: &Request<Body>) -> Span {
+    return info_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        version = ?request.version(),
+        request_id = Empty,
+        user_id = Empty,
+    );
+}
+
+/// Ids sent by clients end up in logs, so only simple ones are accepted
+fn valid_request_id(id: &str) -> bool {
+    return id.len() > 0
+        && id.len() <= *MAX_REQUEST_ID_LEN
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+}
+
+/// Uses the request id sent by the client, or generates one. The id is added to
+/// the request span and returned in the response
+pub async fn request_id(mut request: Request<Body>, next: Next) -> Response {
+    let id = match request
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|h| h.to_str().ok())
+    {
+        Some(id) if valid_request_id(id) => id.to_string(),
+        _ => Uuid::new_v4().to_string(),
+    };
+
+    Span::current().record("request_id", id.as_str());
+    let header = HeaderValue::from_str(&id).unwrap();
+    request
+        .headers_mut()
+        .insert(REQUEST_ID_HEADER.clone(), header.clone());
+
+    let mut response = REQUEST_ID.scope(id, next.run(request)).await;
+    response
+        .headers_mut()
+        .insert(REQUEST_ID_HEADER.clone(), header);
+    return response;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::valid_request_id;
+
+    #[test]
+    fn valid_request_id_success() {
+        assert!(valid_request_id("4b3c1f0e-9a2d-4e55-8f1b-0c6a7d2e9f10"));
+        assert!(valid_request_id("web_client.42"));
+        assert!(!valid_request_id(""));
+        assert!(!valid_request_id("id with spaces"));
+        assert!(!valid_request_id("id\nforged log line"));
+        assert!(!valid_request_id(&"a".repeat(129)));
+    }
+}
@@ -0,0 +1,139 @@
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+use std::{sync::Arc, time::Duration};
+use tracing::{error, info};
+
+use crate::{
+    api::measurement::{Measurement, MqttMeasurementInput, SaveMeasurementInput},
+    error::app_error::{validation_error, AppError, FailedValidation, ValidationIssue::Invalid},
+    helper::{device::device_secret_matches, measurement::record_measurement},
+    settings::settings::Settings,
+    storage::Storage,
+};
+
+// Devices publish to meters/{device_id}/measurement
+pub const MEASUREMENT_TOPIC: &'static str = "meters/+/measurement";
+pub const KEEP_ALIVE: &'static Duration = &Duration::from_secs(30);
+// Time to wait before reconnecting after losing the connection to the broker
+pub const RECONNECT_DELAY: &'static Duration = &Duration::from_secs(5);
+
+/// Returns the device id of a measurement topic
+pub fn device_id_from_topic(topic: &str) -> Option<&str> {
+    let parts: Vec<&str> = topic.split('/').collect();
+    match parts.as_slice() {
+        ["meters", device_id, "measurement"] if device_id.len() > 0 => Some(device_id),
+        _ => None,
+    }
+}
+
+fn invalid_device<T>() -> Result<T, AppError> {
+    return validation_error(vec![FailedValidation {
+        field: "device_id".to_string(),
+        issue: Invalid,
+    }]);
+}
+
+/// Authenticates the device that published the message and saves the
+/// measurement the same way the HTTP endpoint does
+pub async fn handle_message(
+    storage: Arc<dyn Storage>,
+    settings: &Settings,
+    topic: &str,
+    payload: &[u8],
+) -> Result<Measurement, AppError> {
+    let device_id = match device_id_from_topic(topic) {
+        Some(d) => d,
+        None => return invalid_device(),
+    };
+
+    let input: MqttMeasurementInput = match serde_json::from_slice(payload) {
+        Ok(i) => i,
+        Err(_) => {
+            return validation_error(vec![FailedValidation {
+                field: "payload".to_string(),
+                issue: Invalid,
+            }]);
+        }
+    };
+
+    // Only claimed devices have credentials
+    let device = match storage.device_by_fluid_meter(device_id).await? {
+        Some(d) => d,
+        None => return invalid_device(),
+    };
+    if !device_secret_matches(&device, &input.secret) {
+        return invalid_device();
+    }
+
+    let (measurement, _) = record_measurement(
+        storage,
+        settings,
+        SaveMeasurementInput {
+            device_id: device_id.to_string(),
+            measurement: input.measurement,
+            telemetry: input.telemetry,
+            secret: Some(input.secret),
+        },
+        true,
+    )
+    .await?;
+    return Ok(measurement);
+}
+
+/// Consumes measurements from the configured broker until the process ends.
+/// Does nothing if no broker is configured
+pub async fn run(settings: Arc<Settings>, storage: Arc<dyn Storage>) {
+    let mqtt = match &settings.mqtt {
+        Some(m) => m,
+        None => return,
+    };
+
+    let mut options = MqttOptions::new(&mqtt.client_id, &mqtt.host, mqtt.port);
+    options.set_keep_alive(*KEEP_ALIVE);
+    if let (Some(username), Some(password)) = (&mqtt.username, &mqtt.password) {
+        options.set_credentials(username, password);
+    }
+
+    let (client, mut event_loop) = AsyncClient::new(options, 100);
+    loop {
+        match event_loop.poll().await {
+            // Subscriptions are lost when the session ends, so we subscribe
+            // every time we connect
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                info!("Connected to MQTT broker {}:{}", mqtt.host, mqtt.port);
+                if let Err(e) = client.subscribe(MEASUREMENT_TOPIC, QoS::AtLeastOnce).await {
+                    error!("Couldn't subscribe to {}. Error: {}", MEASUREMENT_TOPIC, e);
+                }
+            }
+            Ok(Event::Incoming(Packet::Publish(p))) => {
+                let storage = storage.clone();
+                let settings = settings.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_message(storage, &settings, &p.topic, &p.payload).await {
+                        error!("Rejected MQTT measurement on {}. Error: {:?}", p.topic, e);
+                    }
+                });
+            }
+            Ok(_) => {}
+            Err(e) => {
+                error!("MQTT connection error: {}", e);
+                tokio::time::sleep(*RECONNECT_DELAY).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::device_id_from_topic;
+
+    #[test]
+    fn device_id_from_topic_success() {
+        assert_eq!(
+            device_id_from_topic("meters/some-id/measurement"),
+            Some("some-id")
+        );
+        assert_eq!(device_id_from_topic("meters//measurement"), None);
+        assert_eq!(device_id_from_topic("meters/some-id/status"), None);
+        assert_eq!(device_id_from_topic("meters/a/b/measurement"), None);
+    }
+}
@@ -26,6 +26,12 @@ pub struct Database {
     pub postgres: Postgres,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct Account {
+    // Number of days deleted accounts can be recovered before they are purged
+    pub deletion_grace_days: i64,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Captcha {
     pub secret: String,
@@ -38,6 +44,66 @@ pub struct Mail {
     pub mailer_address: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct Device {
+    // The factory needs to send it as a bearer token to register devices
+    pub provisioning_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Firmware {
+    // Comma separated list of accounts that can manage firmware releases
+    pub admins: String,
+    // Directory where firmware binaries are stored
+    pub storage_path: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FluidMeter {
+    // Minimum seconds between measurements, for meters that don't set their own
+    pub default_reporting_interval_secs: i64,
+    // Number of days deleted meters can be restored before they are purged
+    pub restore_window_days: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Jobs {
+    // Clients need to send it as a bearer token to run jobs like /v1/purge
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Metrics {
+    // Clients need to send it as a bearer token to read the metrics
+    pub token: String,
+}
+
+#[cfg(feature = "mqtt")]
+#[derive(Debug, Deserialize)]
+pub struct Mqtt {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RateLimit {
+    // Requests per minute allowed from each IP address. 0 disables this limit
+    pub ip_requests_per_minute: u32,
+    // Requests per minute allowed from each logged in user. 0 disables this limit
+    pub user_requests_per_minute: u32,
+    // Comma separated list of per route limits for each client, with the format
+    // `METHOD /path=requests_per_minute`. e.g. `POST /v1/log-in=10`
+    pub routes: String,
+    // Share the buckets between instances by keeping them in the database
+    pub shared: bool,
+    // Use the first address in X-Forwarded-For as the client IP. Only enable this
+    // behind a proxy that sets the header
+    pub trust_forwarded_for: bool,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Logging {
     pub level: LevelFilterWrapper,
@@ -46,9 +112,23 @@ pub struct Logging {
 
 #[derive(Debug, Deserialize)]
 pub struct Settings {
+    pub account: Account,
     pub captcha: Captcha,
     pub database: Database,
+    // Devices can only be registered if this is set
+    pub device: Option<Device>,
+    pub firmware: Firmware,
+    pub fluid_meter: FluidMeter,
+    // Jobs that need a token are only run if this is set
+    pub jobs: Option<Jobs>,
     pub mail: Mail,
+    // /metrics is only served if this is set
+    pub metrics: Option<Metrics>,
+    // Measurements are only consumed from a broker if this is set
+    #[cfg(feature = "mqtt")]
+    pub mqtt: Option<Mqtt>,
+    // Requests are only rate limited if this is set
+    pub rate_limit: Option<RateLimit>,
     pub service: Service,
     pub logging: Logging,
 }
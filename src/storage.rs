@@ -4,12 +4,22 @@ pub mod postgres;
 
 use crate::{
     api::{
+        alert::Alert,
         common::{PaginatedRequest, PaginatedResponse},
+        device::{Device, DeviceCommand, DeviceCommandStatus, DeviceStatus, DeviceTelemetry},
         email_verification::EmailVerification,
-        fluid_meter::{FluidMeter, FluidMetersInput},
+        firmware::{FirmwareRelease, FirmwareUpdate},
+        fluid_meter::{FluidMeter, FluidMeterSummary, FluidMetersInput},
+        fluid_meter_member::{FluidMeterInvite, FluidMeterMember, FluidMeterRole},
         measurement::Measurement,
         metadata::Metadata,
-        user::{NewPasswordInput, PasswordRecovery, SessionToken, User},
+        organization::{Organization, OrganizationMember},
+        site::Site,
+        two_factor::{TwoFactor, TwoFactorChallenge},
+        user::{
+            EmailChange, NewPasswordInput, PasswordRecovery, SessionToken, User, UserDataExport,
+        },
+        valve::{Valve, ValveAction, ValveState},
     },
     helper::mail::MailHelper,
     settings::settings::Settings,
@@ -20,6 +30,109 @@ use async_trait::async_trait;
 use chrono::NaiveDateTime;
 use std::sync::Arc;
 
+pub struct ConnectionPoolUsage {
+    // Open connections, idle or not
+    pub connections: u32,
+    pub idle: usize,
+    pub max_connections: u32,
+}
+
+#[async_trait]
+pub trait DeviceStorage {
+    /// Sets the final status of a command sent to the device of the meter.
+    /// Returns NotFoundError if the command doesn't exist or was already
+    /// acknowledged
+    async fn acknowledge_device_command(
+        &self,
+        id: &str,
+        fluid_meter_id: &str,
+        status: DeviceCommandStatus,
+        result: &Option<String>,
+    ) -> Result<DeviceCommand, Error>;
+    /// Binds the unclaimed device with the given claim code to a new meter.
+    /// Returns NotFoundError if there is no unclaimed device with that code
+    async fn claim_device(
+        &self,
+        claim_code: &str,
+        fluid_meter: &FluidMeter,
+    ) -> Result<FluidMeter, Error>;
+    /// Returns the device reporting to the given meter, if it was claimed
+    async fn device_by_fluid_meter(&self, fluid_meter_id: &str) -> Result<Option<Device>, Error>;
+    async fn device_by_serial(&self, serial: &str) -> Result<Option<Device>, Error>;
+    /// Returns the commands of the meter, newest first
+    async fn device_commands(
+        &self,
+        fluid_meter_id: &str,
+        options: &PaginatedRequest,
+    ) -> Result<PaginatedResponse<DeviceCommand>, Error>;
+    /// Returns the latest telemetry of the device reporting to the meter
+    async fn device_status_by_fluid_meter(
+        &self,
+        fluid_meter_id: &str,
+    ) -> Result<Option<DeviceStatus>, Error>;
+    /// Returns DuplicateError if a device with the same serial is registered
+    async fn insert_device(&self, device: &Device) -> Result<Device, Error>;
+    async fn insert_device_command(&self, command: &DeviceCommand) -> Result<DeviceCommand, Error>;
+    /// Saves the telemetry sent by the device reporting to the meter. Values that
+    /// weren't sent keep their last known value
+    async fn save_device_status(
+        &self,
+        fluid_meter_id: &str,
+        telemetry: &DeviceTelemetry,
+    ) -> Result<DeviceStatus, Error>;
+    /// Returns the commands of the meter the device hasn't acknowledged, oldest
+    /// first, and marks them as sent
+    async fn take_device_commands(&self, fluid_meter_id: &str)
+        -> Result<Vec<DeviceCommand>, Error>;
+}
+
+#[async_trait]
+pub trait FirmwareStorage {
+    async fn firmware_release_by_id(&self, id: &str) -> Result<Option<FirmwareRelease>, Error>;
+    /// Newest releases first
+    async fn firmware_releases(
+        &self,
+        options: &PaginatedRequest,
+    ) -> Result<PaginatedResponse<FirmwareRelease>, Error>;
+    /// Returns the uploaded releases the device could install, newest first.
+    /// Doesn't include releases the device already failed to install
+    async fn firmware_releases_for_device(
+        &self,
+        serial: &str,
+        hardware_model: &str,
+        firmware_version: &str,
+    ) -> Result<Vec<FirmwareRelease>, Error>;
+    /// Newest reports first
+    async fn firmware_updates(
+        &self,
+        release_id: &str,
+        options: &PaginatedRequest,
+    ) -> Result<PaginatedResponse<FirmwareUpdate>, Error>;
+    /// Returns DuplicateError if there is a release with the same version for
+    /// the hardware model
+    async fn insert_firmware_release(
+        &self,
+        release: &FirmwareRelease,
+    ) -> Result<FirmwareRelease, Error>;
+    /// Records the binary of the release was uploaded. Returns NotFoundError if
+    /// the release doesn't exist
+    async fn set_firmware_binary(
+        &self,
+        id: &str,
+        size_bytes: i64,
+        sha256: &str,
+    ) -> Result<FirmwareRelease, Error>;
+    /// Returns NotFoundError if the release doesn't exist
+    async fn set_firmware_rollout(
+        &self,
+        id: &str,
+        rollout_percentage: i16,
+    ) -> Result<FirmwareRelease, Error>;
+    /// Saves the result of installing a release. Replaces previous reports of
+    /// the device for the same release
+    async fn save_firmware_update(&self, update: &FirmwareUpdate) -> Result<FirmwareUpdate, Error>;
+}
+
 #[async_trait]
 pub trait FluidMeterStorage {
     async fn activate_fluid_meter(&self, meter_id: &str) -> Result<(), Error>;
@@ -29,24 +142,131 @@ pub trait FluidMeterStorage {
         &self,
         options: &PaginatedRequest,
     ) -> Result<PaginatedResponse<FluidMeter>, Error>;
+    /// Marks the meter as deleted. It can be restored until it's purged
     async fn delete_fluid_meter(&self, id: &str) -> Result<(), Error>;
+    /// Returns the meters owned by the user (directly or as an organization
+    /// owner) that were deleted after the given date. Newest deletions first
+    async fn deleted_fluid_meters(
+        &self,
+        user: &str,
+        since: NaiveDateTime,
+        options: &PaginatedRequest,
+    ) -> Result<PaginatedResponse<FluidMeter>, Error>;
     async fn get_fluid_meters(
         &self,
         user: &str,
         filters: &FluidMetersInput,
-    ) -> Result<Vec<FluidMeter>, Error>;
+    ) -> Result<PaginatedResponse<FluidMeterSummary>, Error>;
     async fn get_fluid_meter_by_id(&self, id: &str) -> Result<Option<FluidMeter>, Error>;
+    async fn get_fluid_meter_summary(&self, id: &str) -> Result<Option<FluidMeterSummary>, Error>;
+    /// Returns the sub-meters of the given meter, excluding deleted ones
+    async fn get_fluid_meter_children(&self, parent_id: &str) -> Result<Vec<FluidMeter>, Error>;
     async fn insert_fluid_meter(&self, fluid_meter: &FluidMeter) -> Result<FluidMeter, Error>;
-    async fn is_fluid_meter_owner(
+    /// Permanently deletes the meters deleted before the given date, with all
+    /// their measurements. Returns the number of deleted meters and measurements
+    async fn purge_deleted_fluid_meters(&self, date: NaiveDateTime) -> Result<(u64, u64), Error>;
+    /// Gives a deleted meter back the status it had before it was deleted. Fails
+    /// with NotFoundError if the meter isn't deleted
+    async fn restore_fluid_meter(&self, id: &str) -> Result<FluidMeter, Error>;
+    /// Replaces the open alerts of the meter with the given ones
+    async fn set_fluid_meter_alerts(
         &self,
-        fluid_meter_id: &str,
+        meter_id: &str,
+        alerts: &Vec<Alert>,
+    ) -> Result<(), Error>;
+    /// Assigns the meter to the given site. None removes it from its site
+    async fn set_fluid_meter_site(
+        &self,
+        meter_id: &str,
+        site_id: &Option<String>,
+    ) -> Result<(), Error>;
+    /// Sets the minimum seconds between measurements of the meter. None goes
+    /// back to the default
+    async fn set_fluid_meter_reporting_interval(
+        &self,
+        meter_id: &str,
+        reporting_interval_secs: Option<i64>,
+    ) -> Result<(), Error>;
+    /// Saves the name and site of the meter and bumps updated_at. Returns the
+    /// updated meter
+    async fn update_fluid_meter(&self, fluid_meter: &FluidMeter) -> Result<FluidMeter, Error>;
+    /// Makes the meter a sub-meter of the given one. None makes it a top-level
+    /// meter
+    async fn set_fluid_meter_parent(
+        &self,
+        meter_id: &str,
+        parent_id: &Option<String>,
+    ) -> Result<(), Error>;
+}
+
+#[async_trait]
+pub trait FluidMeterMemberStorage {
+    /// Accepts the invite for the given account. The invite is deleted. If the
+    /// account was already a member, its role is replaced
+    async fn accept_fluid_meter_invite(
+        &self,
+        invite: &FluidMeterInvite,
+        account_id: &str,
+    ) -> Result<FluidMeterMember, Error>;
+    /// Creates an invite for the given e-mail and sends it by e-mail. Replaces
+    /// previous invites for the same e-mail
+    async fn create_fluid_meter_invite(
+        &self,
+        meter: &FluidMeter,
+        email: &str,
+        role: &FluidMeterRole,
+        inviter: &User,
+        settings: Arc<Settings>,
+        mail_helper: Arc<dyn MailHelper>,
+    ) -> Result<FluidMeterInvite, Error>;
+    async fn delete_fluid_meter_member(
+        &self,
+        meter_id: &str,
+        account_id: &str,
+    ) -> Result<(), Error>;
+    /// Returns the ids of the owner (or the owners and admins of the owning
+    /// organization) and the members that opted in to receive the alerts of the meter
+    async fn fluid_meter_alert_recipients(&self, meter_id: &str) -> Result<Vec<String>, Error>;
+    /// Only returns invites that haven't expired
+    async fn fluid_meter_invite_by_token(
+        &self,
+        token: &str,
+    ) -> Result<Option<FluidMeterInvite>, Error>;
+    /// Only returns invites that haven't expired
+    async fn fluid_meter_invites(&self, meter_id: &str) -> Result<Vec<FluidMeterInvite>, Error>;
+    async fn fluid_meter_member(
+        &self,
+        meter_id: &str,
         account_id: &str,
-    ) -> Result<bool, Error>;
+    ) -> Result<Option<FluidMeterMember>, Error>;
+    async fn fluid_meter_members(
+        &self,
+        meter_id: &str,
+        options: &PaginatedRequest,
+    ) -> Result<PaginatedResponse<FluidMeterMember>, Error>;
+    /// Returns the role the account has for the meter. None if it doesn't have
+    /// access. Nobody has a role for deleted meters
+    async fn fluid_meter_role(
+        &self,
+        meter_id: &str,
+        account_id: &str,
+    ) -> Result<Option<FluidMeterRole>, Error>;
+    /// Updates the role and alerts preference of the member
+    async fn update_fluid_meter_member(
+        &self,
+        member: &FluidMeterMember,
+    ) -> Result<FluidMeterMember, Error>;
 }
 
 #[async_trait]
 pub trait MeasurementStorage {
-    async fn save_measurement(&self, measurement: &Measurement) -> Result<Measurement, Error>;
+    /// Returns RateLimitError if the meter sent a measurement less than the
+    /// given interval ago
+    async fn save_measurement(
+        &self,
+        measurement: &Measurement,
+        reporting_interval_secs: i64,
+    ) -> Result<Measurement, Error>;
     /// Returns list of measurements for a given device. Results are sorted by
     /// creation date, with the newest coming first
     /// from - Returns measurements with a creation date higher to this date
@@ -59,6 +279,15 @@ pub trait MeasurementStorage {
         to: NaiveDateTime,
         num_records: u32,
     ) -> Result<Vec<Measurement>, Error>;
+    /// Same as get_measurements, but returns the measurements of all the
+    /// sub-meters of the given meter (excluding deleted ones)
+    async fn get_children_measurements(
+        &self,
+        parent_id: &str,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+        num_records: u32,
+    ) -> Result<Vec<Measurement>, Error>;
 }
 
 #[async_trait]
@@ -67,10 +296,171 @@ pub trait MetadataStorage {
     async fn save_metadata(&self, key: &str, value: &str) -> Result<Metadata, Error>;
 }
 
+#[async_trait]
+pub trait OrganizationStorage {
+    /// Adds the account to the organization. If it was already a member, its
+    /// role is replaced
+    async fn add_organization_member(
+        &self,
+        member: &OrganizationMember,
+    ) -> Result<OrganizationMember, Error>;
+    /// Returns the number of members with the owner role
+    async fn count_organization_owners(&self, organization_id: &str) -> Result<i64, Error>;
+    /// Creates the organization with the given account as its owner
+    async fn create_organization(
+        &self,
+        organization: &Organization,
+        owner_id: &str,
+    ) -> Result<Organization, Error>;
+    async fn delete_organization_member(
+        &self,
+        organization_id: &str,
+        account_id: &str,
+    ) -> Result<(), Error>;
+    /// Returns the measurements of all the meters of the organization (excluding
+    /// deleted ones). Sorted by creation date, with the newest coming first
+    async fn get_organization_measurements(
+        &self,
+        organization_id: &str,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+        num_records: u32,
+    ) -> Result<Vec<Measurement>, Error>;
+    async fn organization_member(
+        &self,
+        organization_id: &str,
+        account_id: &str,
+    ) -> Result<Option<OrganizationMember>, Error>;
+    /// Returns the members of the organization, oldest first
+    async fn organization_members(
+        &self,
+        organization_id: &str,
+        options: &PaginatedRequest,
+    ) -> Result<PaginatedResponse<OrganizationMember>, Error>;
+    /// Returns the organizations the account is a member of, sorted by name
+    async fn organizations_by_user(
+        &self,
+        account_id: &str,
+        options: &PaginatedRequest,
+    ) -> Result<PaginatedResponse<Organization>, Error>;
+}
+
+#[async_trait]
+pub trait RateLimitStorage {
+    /// Deletes the rate limit buckets that weren't used since the given time.
+    /// Returns the number of deleted buckets
+    async fn delete_rate_limit_buckets(&self, before: NaiveDateTime) -> Result<u64, Error>;
+    /// Takes a token from each of the shared buckets, given as key and requests
+    /// per minute. If any of them is empty no token is taken, and its key is
+    /// returned with the seconds until a token is available
+    async fn take_rate_limit_tokens(
+        &self,
+        buckets: &Vec<(String, u32)>,
+        now: NaiveDateTime,
+    ) -> Result<Option<(String, u64)>, Error>;
+}
+
+#[async_trait]
+pub trait SiteStorage {
+    /// Meters assigned to the site are kept, but they aren't assigned to any
+    /// site anymore
+    async fn delete_site(&self, id: &str) -> Result<(), Error>;
+    /// Returns the measurements of all the meters assigned to the site
+    /// (excluding deleted ones). Sorted by creation date, with the newest
+    /// coming first
+    async fn get_site_measurements(
+        &self,
+        site_id: &str,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+        num_records: u32,
+    ) -> Result<Vec<Measurement>, Error>;
+    async fn insert_site(&self, site: &Site) -> Result<Site, Error>;
+    async fn site_by_id(&self, id: &str) -> Result<Option<Site>, Error>;
+    /// Returns the sites the account owns and the ones of its organizations,
+    /// sorted by name
+    async fn sites_by_user(
+        &self,
+        account_id: &str,
+        options: &PaginatedRequest,
+    ) -> Result<PaginatedResponse<Site>, Error>;
+}
+
+#[async_trait]
+pub trait TwoFactorStorage {
+    /// Saves a new secret for the user. Replaces any secret that wasn't confirmed
+    async fn save_two_factor_secret(
+        &self,
+        account_id: &str,
+        secret: &str,
+    ) -> Result<TwoFactor, Error>;
+    async fn two_factor_by_user(&self, account_id: &str) -> Result<Option<TwoFactor>, Error>;
+    /// Enables two factor authentication for the user and replaces their
+    /// recovery codes with the given ones
+    /// step - The step of the code used to confirm the secret
+    async fn enable_two_factor(
+        &self,
+        account_id: &str,
+        step: i64,
+        recovery_code_hashes: &Vec<String>,
+    ) -> Result<(), Error>;
+    /// Removes the secret, recovery codes and pending challenges for the user
+    async fn disable_two_factor(&self, account_id: &str) -> Result<(), Error>;
+    /// Returns false if a code for the given step (or a later one) was already used
+    async fn use_two_factor_step(&self, account_id: &str, step: i64) -> Result<bool, Error>;
+    /// Returns false if the recovery code doesn't exist or was already used
+    async fn use_recovery_code(&self, account_id: &str, code_hash: &str) -> Result<bool, Error>;
+    async fn create_two_factor_challenge(
+        &self,
+        account_id: &str,
+    ) -> Result<TwoFactorChallenge, Error>;
+    /// Only returns challenges that haven't expired
+    async fn two_factor_challenge_by_token(
+        &self,
+        token: &str,
+    ) -> Result<Option<TwoFactorChallenge>, Error>;
+    /// Counts a failed attempt for the challenge and its account. The challenge
+    /// is deleted after too many failures. The account is locked for a while
+    /// after too many failures across challenges, and all its challenges are
+    /// deleted
+    async fn fail_two_factor_challenge(&self, token: &str) -> Result<(), Error>;
+    /// Resets the failed attempts of the account after a successful log-in
+    async fn clear_two_factor_failures(&self, account_id: &str) -> Result<(), Error>;
+    async fn delete_two_factor_challenge(&self, token: &str) -> Result<(), Error>;
+}
+
 #[async_trait]
 pub trait UserStorage {
+    /// Sets a new password hash and ends all the sessions of the user, except
+    /// the given one
+    async fn change_password(
+        &self,
+        user_id: &str,
+        password: &str,
+        keep_token: &str,
+    ) -> Result<(), Error>;
+    /// Switches the account to the e-mail of the given change request. Since the
+    /// id is derived from the e-mail, the id of the user changes too.
+    /// Returns DuplicateError if there is already an account with that e-mail
+    async fn confirm_email_change(&self, token: &str) -> Result<User, Error>;
+    /// Permanently deletes the accounts whose grace period ended before the given
+    /// date, including everything that belongs to them. Returns the number of
+    /// deleted accounts
+    async fn delete_accounts_scheduled_before(&self, date: NaiveDateTime) -> Result<u64, Error>;
+    /// Creates an e-mail change request and sends an e-mail to the new address
+    /// so the user can verify they own it
+    async fn email_change(
+        &self,
+        user: &User,
+        email: &str,
+        settings: Arc<Settings>,
+        mail_helper: Arc<dyn MailHelper>,
+    ) -> Result<(), Error>;
+    async fn email_change_by_user(&self, user_id: &str) -> Result<Option<EmailChange>, Error>;
     async fn email_verification_by_id(&self, id: &str) -> Result<Option<EmailVerification>, Error>;
     async fn insert_user(&self, user: &User) -> Result<User, Error>;
+    /// Creates a session for the user. Logging in cancels any scheduled deletion
+    /// of the account
     async fn log_in(&self, id: &str) -> Result<SessionToken, Error>;
     async fn log_out(&self, token: &str) -> Result<(), Error>;
     async fn new_password(&self, input: &NewPasswordInput) -> Result<(), Error>;
@@ -84,19 +474,84 @@ pub trait UserStorage {
         &self,
         user_id: &str,
     ) -> Result<Option<PasswordRecovery>, Error>;
+    /// Replaces the e-mail verification token of the user with a new one and
+    /// sends it by e-mail. Returns RateLimitError if the last one was sent recently
+    async fn resend_email_verification(
+        &self,
+        user: &User,
+        settings: Arc<Settings>,
+        mail_helper: Arc<dyn MailHelper>,
+    ) -> Result<(), Error>;
+    /// Schedules the account for deletion and ends all its sessions
+    async fn schedule_account_deletion(
+        &self,
+        user_id: &str,
+        delete_at: NaiveDateTime,
+    ) -> Result<(), Error>;
+    /// Returns DuplicateError if there is already an account with that e-mail
     async fn sign_up_user(
         &self,
         user: User,
         settings: Arc<Settings>,
         mail_helper: Arc<dyn MailHelper>,
     ) -> Result<User, Error>;
+    /// Only the name can be updated for now
+    async fn update_user(&self, user: &User) -> Result<User, Error>;
     async fn user_by_id(&self, id: &str) -> Result<Option<User>, Error>;
     async fn user_by_token(&self, token: &str) -> Result<Option<User>, Error>;
+    /// Returns the profile of the user, their meters with all their measurements
+    /// and the meters and organizations they are a member of
+    async fn user_data_export(&self, user_id: &str) -> Result<Option<UserDataExport>, Error>;
+    /// Returns NotFoundError if the token doesn't exist or expired
     async fn verify_email(&self, token: &str) -> Result<User, Error>;
 }
 
+#[async_trait]
+pub trait ValveStorage {
+    /// Updates the valve after the device acknowledges a command to move it to
+    /// the given state. If the command failed, the valve keeps its state
+    async fn acknowledge_valve_command(
+        &self,
+        fluid_meter_id: &str,
+        target_state: &ValveState,
+        success: bool,
+    ) -> Result<(), Error>;
+    /// Queues the command that moves the valve and records the action
+    async fn insert_valve_action(
+        &self,
+        action: &ValveAction,
+        command: &DeviceCommand,
+    ) -> Result<ValveAction, Error>;
+    /// Creates the valve of the meter, or updates its settings if it exists
+    async fn save_valve(&self, valve: &Valve) -> Result<Valve, Error>;
+    /// Returns the actions of the valve, newest first
+    async fn valve_actions(
+        &self,
+        fluid_meter_id: &str,
+        options: &PaginatedRequest,
+    ) -> Result<PaginatedResponse<ValveAction>, Error>;
+    async fn valve_by_fluid_meter(&self, fluid_meter_id: &str) -> Result<Option<Valve>, Error>;
+}
+
 #[async_trait]
 pub trait Storage:
-    Send + Sync + FluidMeterStorage + MeasurementStorage + MetadataStorage + UserStorage
+    Send
+    + Sync
+    + DeviceStorage
+    + FirmwareStorage
+    + FluidMeterStorage
+    + FluidMeterMemberStorage
+    + MeasurementStorage
+    + MetadataStorage
+    + OrganizationStorage
+    + RateLimitStorage
+    + SiteStorage
+    + TwoFactorStorage
+    + UserStorage
+    + ValveStorage
 {
+    /// Usage of the connection pool, for storages that keep one
+    fn connection_pool(&self) -> Option<ConnectionPoolUsage> {
+        return None;
+    }
 }
@@ -1,40 +1,135 @@
-use std::fmt;
+use std::{fmt, sync::Arc};
+
+// Postgres error codes we handle differently
+pub const UNIQUE_VIOLATION: &str = "23505";
+pub const FOREIGN_KEY_VIOLATION: &str = "23503";
+pub const SERIALIZATION_FAILURE: &str = "40001";
+pub const DEADLOCK_DETECTED: &str = "40P01";
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum ErrorCode {
     DuplicateError,
+    // The row references a row that doesn't exist, or is referenced by others
+    ForeignKeyError,
     NotFoundError,
     RateLimitError,
+    // The transaction conflicted with another one. It can be retried
+    SerializationError,
     UndefinedError,
 }
 
 #[derive(Clone, Debug)]
 pub struct Error {
     pub code: ErrorCode,
+    // Storage operation that failed
+    pub operation: Option<&'static str>,
+    // Id of the entity the operation was working on
+    pub entity_id: Option<String>,
+    pub source: Option<Arc<sqlx::Error>>,
+}
+
+impl Error {
+    pub fn new(code: ErrorCode) -> Self {
+        return Error {
+            code,
+            operation: None,
+            entity_id: None,
+            source: None,
+        };
+    }
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self.code)
+        write!(f, "{:?}", self.code)?;
+        if let Some(operation) = self.operation {
+            write!(f, " in {}", operation)?;
+        }
+        if let Some(id) = &self.entity_id {
+            write!(f, " for {}", id)?;
+        }
+        if let Some(source) = &self.source {
+            write!(f, ": {}", source)?;
+        }
+        return Ok(());
     }
 }
 
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        return self
+            .source
+            .as_ref()
+            .map(|s| s.as_ref() as &(dyn std::error::Error + 'static));
+    }
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(error: sqlx::Error) -> Self {
+        let code = match &error {
+            sqlx::Error::RowNotFound => ErrorCode::NotFoundError,
+            sqlx::Error::Database(e) => match e.code().as_deref() {
+                Some(UNIQUE_VIOLATION) => ErrorCode::DuplicateError,
+                Some(FOREIGN_KEY_VIOLATION) => ErrorCode::ForeignKeyError,
+                Some(SERIALIZATION_FAILURE) | Some(DEADLOCK_DETECTED) => {
+                    ErrorCode::SerializationError
+                }
+                _ => ErrorCode::UndefinedError,
+            },
+            _ => ErrorCode::UndefinedError,
+        };
+        return Error {
+            source: Some(Arc::new(error)),
+            ..Error::new(code)
+        };
+    }
+}
 
 pub fn not_found<T>() -> Result<T, Error> {
-    Err(Error {
-        code: ErrorCode::NotFoundError,
-    })
+    Err(Error::new(ErrorCode::NotFoundError))
 }
 
 pub fn undefined<T>() -> Result<T, Error> {
-    Err(Error {
-        code: ErrorCode::UndefinedError,
-    })
+    Err(Error::new(ErrorCode::UndefinedError))
 }
 
 pub fn rate_limit<T>() -> Result<T, Error> {
-    Err(Error {
-        code: ErrorCode::RateLimitError,
-    })
+    Err(Error::new(ErrorCode::RateLimitError))
+}
+
+pub fn duplicate<T>() -> Result<T, Error> {
+    Err(Error::new(ErrorCode::DuplicateError))
+}
+
+/// Error for a failed query, with the operation that ran it and the id of the
+/// entity it was for
+pub fn query_error<T>(
+    source: sqlx::Error,
+    operation: &'static str,
+    entity_id: Option<&str>,
+) -> Result<T, Error> {
+    let mut error = Error::from(source);
+    error.operation = Some(operation);
+    error.entity_id = entity_id.map(|id| id.to_string());
+    return Err(error);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{query_error, ErrorCode};
+
+    #[test]
+    fn query_error_context() {
+        let error =
+            query_error::<()>(sqlx::Error::RowNotFound, "site_by_id", Some("s-1")).unwrap_err();
+        assert_eq!(error.code, ErrorCode::NotFoundError);
+        assert_eq!(
+            error.to_string(),
+            "NotFoundError in site_by_id for s-1: no rows returned by a query that expected to return at least one row"
+        );
+
+        let error = query_error::<()>(sqlx::Error::PoolTimedOut, "log_out", None).unwrap_err();
+        assert_eq!(error.code, ErrorCode::UndefinedError);
+        assert!(std::error::Error::source(&error).is_some());
+    }
 }
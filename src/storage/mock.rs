@@ -1,15 +1,29 @@
 use crate::{
     api::{
+        alert::Alert,
         common::{PaginatedRequest, PaginatedResponse},
+        device::{Device, DeviceCommand, DeviceCommandStatus, DeviceStatus, DeviceTelemetry},
         email_verification::EmailVerification,
-        fluid_meter::{FluidMeter, FluidMetersInput},
+        firmware::{FirmwareRelease, FirmwareUpdate},
+        fluid_meter::{FluidMeter, FluidMeterSummary, FluidMetersInput},
+        fluid_meter_member::{FluidMeterInvite, FluidMeterMember, FluidMeterRole},
         measurement::Measurement,
         metadata::Metadata,
-        user::{NewPasswordInput, PasswordRecovery, SessionToken, User},
+        organization::{Organization, OrganizationMember},
+        site::Site,
+        two_factor::{TwoFactor, TwoFactorChallenge},
+        user::{
+            EmailChange, NewPasswordInput, PasswordRecovery, SessionToken, User, UserDataExport,
+        },
+        valve::{Valve, ValveAction, ValveState},
     },
     helper::mail::MailHelper,
     settings::settings::Settings,
-    storage::{error::Error, FluidMeterStorage, MeasurementStorage, MetadataStorage, UserStorage},
+    storage::{
+        error::Error, DeviceStorage, FirmwareStorage, FluidMeterMemberStorage, FluidMeterStorage,
+        MeasurementStorage, MetadataStorage, OrganizationStorage, RateLimitStorage, SiteStorage,
+        TwoFactorStorage, UserStorage, ValveStorage,
+    },
 };
 
 use async_trait::async_trait;
@@ -23,11 +37,88 @@ mock! {
     #[async_trait]
     impl crate::storage::Storage for Storage {}
 
+    #[async_trait]
+    impl DeviceStorage for Storage {
+        async fn acknowledge_device_command(
+            &self,
+            id: &str,
+            fluid_meter_id: &str,
+            status: DeviceCommandStatus,
+            result: &Option<String>,
+        ) -> Result<DeviceCommand, Error>;
+        async fn claim_device(
+            &self,
+            claim_code: &str,
+            fluid_meter: &FluidMeter,
+        ) -> Result<FluidMeter, Error>;
+        async fn device_by_fluid_meter(&self, fluid_meter_id: &str) -> Result<Option<Device>, Error>;
+        async fn device_by_serial(&self, serial: &str) -> Result<Option<Device>, Error>;
+        async fn device_commands(
+            &self,
+            fluid_meter_id: &str,
+            options: &PaginatedRequest,
+        ) -> Result<PaginatedResponse<DeviceCommand>, Error>;
+        async fn device_status_by_fluid_meter(
+            &self,
+            fluid_meter_id: &str,
+        ) -> Result<Option<DeviceStatus>, Error>;
+        async fn insert_device(&self, device: &Device) -> Result<Device, Error>;
+        async fn insert_device_command(&self, command: &DeviceCommand) -> Result<DeviceCommand, Error>;
+        async fn save_device_status(
+            &self,
+            fluid_meter_id: &str,
+            telemetry: &DeviceTelemetry,
+        ) -> Result<DeviceStatus, Error>;
+        async fn take_device_commands(&self, fluid_meter_id: &str) -> Result<Vec<DeviceCommand>, Error>;
+    }
+
+    #[async_trait]
+    impl FirmwareStorage for Storage {
+        async fn firmware_release_by_id(&self, id: &str) -> Result<Option<FirmwareRelease>, Error>;
+        async fn firmware_releases(
+            &self,
+            options: &PaginatedRequest,
+        ) -> Result<PaginatedResponse<FirmwareRelease>, Error>;
+        async fn firmware_releases_for_device(
+            &self,
+            serial: &str,
+            hardware_model: &str,
+            firmware_version: &str,
+        ) -> Result<Vec<FirmwareRelease>, Error>;
+        async fn firmware_updates(
+            &self,
+            release_id: &str,
+            options: &PaginatedRequest,
+        ) -> Result<PaginatedResponse<FirmwareUpdate>, Error>;
+        async fn insert_firmware_release(
+            &self,
+            release: &FirmwareRelease,
+        ) -> Result<FirmwareRelease, Error>;
+        async fn set_firmware_binary(
+            &self,
+            id: &str,
+            size_bytes: i64,
+            sha256: &str,
+        ) -> Result<FirmwareRelease, Error>;
+        async fn set_firmware_rollout(
+            &self,
+            id: &str,
+            rollout_percentage: i16,
+        ) -> Result<FirmwareRelease, Error>;
+        async fn save_firmware_update(&self, update: &FirmwareUpdate) -> Result<FirmwareUpdate, Error>;
+    }
+
     #[async_trait]
     impl FluidMeterStorage for Storage {
         async fn activate_fluid_meter(&self, meter_id: &str) -> Result<(), Error>;
         async fn deactivate_fluid_meter(&self, meter_id: &str) -> Result<(), Error>;
         async fn delete_fluid_meter(&self, id: &str) -> Result<(), Error>;
+        async fn deleted_fluid_meters(
+            &self,
+            user: &str,
+            since: NaiveDateTime,
+            options: &PaginatedRequest,
+        ) -> Result<PaginatedResponse<FluidMeter>, Error>;
         async fn get_active_fluid_meters(
             &self,
             options: &PaginatedRequest,
@@ -36,19 +127,88 @@ mock! {
             &self,
             user: &str,
             filters: &FluidMetersInput,
-        ) -> Result<Vec<FluidMeter>, Error>;
+        ) -> Result<PaginatedResponse<FluidMeterSummary>, Error>;
         async fn get_fluid_meter_by_id(&self, id: &str) -> Result<Option<FluidMeter>, Error>;
+        async fn get_fluid_meter_summary(&self, id: &str) -> Result<Option<FluidMeterSummary>, Error>;
+        async fn get_fluid_meter_children(&self, parent_id: &str) -> Result<Vec<FluidMeter>, Error>;
         async fn insert_fluid_meter(&self, fluid_meter: &FluidMeter) -> Result<FluidMeter, Error>;
-        async fn is_fluid_meter_owner(
+        async fn purge_deleted_fluid_meters(&self, date: NaiveDateTime) -> Result<(u64, u64), Error>;
+        async fn restore_fluid_meter(&self, id: &str) -> Result<FluidMeter, Error>;
+        async fn set_fluid_meter_alerts(&self, meter_id: &str, alerts: &Vec<Alert>)
+            -> Result<(), Error>;
+        async fn set_fluid_meter_site(
             &self,
-            fluid_meter_id: &str,
+            meter_id: &str,
+            site_id: &Option<String>,
+        ) -> Result<(), Error>;
+        async fn set_fluid_meter_reporting_interval(
+            &self,
+            meter_id: &str,
+            reporting_interval_secs: Option<i64>,
+        ) -> Result<(), Error>;
+        async fn update_fluid_meter(&self, fluid_meter: &FluidMeter) -> Result<FluidMeter, Error>;
+        async fn set_fluid_meter_parent(
+            &self,
+            meter_id: &str,
+            parent_id: &Option<String>,
+        ) -> Result<(), Error>;
+    }
+
+    #[async_trait]
+    impl FluidMeterMemberStorage for Storage {
+        async fn accept_fluid_meter_invite(
+            &self,
+            invite: &FluidMeterInvite,
             account_id: &str,
-        ) -> Result<bool, Error>;
+        ) -> Result<FluidMeterMember, Error>;
+        async fn create_fluid_meter_invite(
+            &self,
+            meter: &FluidMeter,
+            email: &str,
+            role: &FluidMeterRole,
+            inviter: &User,
+            settings: Arc<Settings>,
+            mail_helper: Arc<dyn MailHelper>,
+        ) -> Result<FluidMeterInvite, Error>;
+        async fn delete_fluid_meter_member(
+            &self,
+            meter_id: &str,
+            account_id: &str,
+        ) -> Result<(), Error>;
+        async fn fluid_meter_alert_recipients(&self, meter_id: &str) -> Result<Vec<String>, Error>;
+        async fn fluid_meter_invite_by_token(
+            &self,
+            token: &str,
+        ) -> Result<Option<FluidMeterInvite>, Error>;
+        async fn fluid_meter_invites(&self, meter_id: &str) -> Result<Vec<FluidMeterInvite>, Error>;
+        async fn fluid_meter_member(
+            &self,
+            meter_id: &str,
+            account_id: &str,
+        ) -> Result<Option<FluidMeterMember>, Error>;
+        async fn fluid_meter_members(
+            &self,
+            meter_id: &str,
+            options: &PaginatedRequest,
+        ) -> Result<PaginatedResponse<FluidMeterMember>, Error>;
+        async fn fluid_meter_role(
+            &self,
+            meter_id: &str,
+            account_id: &str,
+        ) -> Result<Option<FluidMeterRole>, Error>;
+        async fn update_fluid_meter_member(
+            &self,
+            member: &FluidMeterMember,
+        ) -> Result<FluidMeterMember, Error>;
     }
 
     #[async_trait]
     impl MeasurementStorage for Storage {
-        async fn save_measurement(&self, measurement: &Measurement) -> Result<Measurement, Error>;
+        async fn save_measurement(
+            &self,
+            measurement: &Measurement,
+            reporting_interval_secs: i64,
+        ) -> Result<Measurement, Error>;
         async fn get_measurements(
             &self,
             device_id: String,
@@ -56,6 +216,13 @@ mock! {
             to: NaiveDateTime,
             num_records: u32,
         ) -> Result<Vec<Measurement>, Error>;
+        async fn get_children_measurements(
+            &self,
+            parent_id: &str,
+            from: NaiveDateTime,
+            to: NaiveDateTime,
+            num_records: u32,
+        ) -> Result<Vec<Measurement>, Error>;
     }
 
     #[async_trait]
@@ -64,8 +231,124 @@ mock! {
         async fn save_metadata(&self, key: &str, value: &str) -> Result<Metadata, Error>;
     }
 
+    #[async_trait]
+    impl OrganizationStorage for Storage {
+        async fn add_organization_member(
+            &self,
+            member: &OrganizationMember,
+        ) -> Result<OrganizationMember, Error>;
+        async fn count_organization_owners(&self, organization_id: &str) -> Result<i64, Error>;
+        async fn create_organization(
+            &self,
+            organization: &Organization,
+            owner_id: &str,
+        ) -> Result<Organization, Error>;
+        async fn delete_organization_member(
+            &self,
+            organization_id: &str,
+            account_id: &str,
+        ) -> Result<(), Error>;
+        async fn get_organization_measurements(
+            &self,
+            organization_id: &str,
+            from: NaiveDateTime,
+            to: NaiveDateTime,
+            num_records: u32,
+        ) -> Result<Vec<Measurement>, Error>;
+        async fn organization_member(
+            &self,
+            organization_id: &str,
+            account_id: &str,
+        ) -> Result<Option<OrganizationMember>, Error>;
+        async fn organization_members(
+            &self,
+            organization_id: &str,
+            options: &PaginatedRequest,
+        ) -> Result<PaginatedResponse<OrganizationMember>, Error>;
+        async fn organizations_by_user(
+            &self,
+            account_id: &str,
+            options: &PaginatedRequest,
+        ) -> Result<PaginatedResponse<Organization>, Error>;
+    }
+
+    #[async_trait]
+    impl RateLimitStorage for Storage {
+        async fn delete_rate_limit_buckets(&self, before: NaiveDateTime) -> Result<u64, Error>;
+        async fn take_rate_limit_tokens(
+            &self,
+            buckets: &Vec<(String, u32)>,
+            now: NaiveDateTime,
+        ) -> Result<Option<(String, u64)>, Error>;
+    }
+
+    #[async_trait]
+    impl SiteStorage for Storage {
+        async fn delete_site(&self, id: &str) -> Result<(), Error>;
+        async fn get_site_measurements(
+            &self,
+            site_id: &str,
+            from: NaiveDateTime,
+            to: NaiveDateTime,
+            num_records: u32,
+        ) -> Result<Vec<Measurement>, Error>;
+        async fn insert_site(&self, site: &Site) -> Result<Site, Error>;
+        async fn site_by_id(&self, id: &str) -> Result<Option<Site>, Error>;
+        async fn sites_by_user(
+            &self,
+            account_id: &str,
+            options: &PaginatedRequest,
+        ) -> Result<PaginatedResponse<Site>, Error>;
+    }
+
+    #[async_trait]
+    impl TwoFactorStorage for Storage {
+        async fn save_two_factor_secret(
+            &self,
+            account_id: &str,
+            secret: &str,
+        ) -> Result<TwoFactor, Error>;
+        async fn two_factor_by_user(&self, account_id: &str) -> Result<Option<TwoFactor>, Error>;
+        async fn enable_two_factor(
+            &self,
+            account_id: &str,
+            step: i64,
+            recovery_code_hashes: &Vec<String>,
+        ) -> Result<(), Error>;
+        async fn disable_two_factor(&self, account_id: &str) -> Result<(), Error>;
+        async fn use_two_factor_step(&self, account_id: &str, step: i64) -> Result<bool, Error>;
+        async fn use_recovery_code(&self, account_id: &str, code_hash: &str) -> Result<bool, Error>;
+        async fn create_two_factor_challenge(
+            &self,
+            account_id: &str,
+        ) -> Result<TwoFactorChallenge, Error>;
+        async fn two_factor_challenge_by_token(
+            &self,
+            token: &str,
+        ) -> Result<Option<TwoFactorChallenge>, Error>;
+        async fn fail_two_factor_challenge(&self, token: &str) -> Result<(), Error>;
+        async fn clear_two_factor_failures(&self, account_id: &str) -> Result<(), Error>;
+        async fn delete_two_factor_challenge(&self, token: &str) -> Result<(), Error>;
+    }
+
     #[async_trait]
     impl UserStorage for Storage {
+        async fn change_password(
+            &self,
+            user_id: &str,
+            password: &str,
+            keep_token: &str,
+        ) -> Result<(), Error>;
+        async fn confirm_email_change(&self, token: &str) -> Result<User, Error>;
+        async fn delete_accounts_scheduled_before(&self, date: NaiveDateTime) -> Result<u64, Error>;
+        async fn email_change(
+            &self,
+            user: &User,
+            email: &str,
+            settings: Arc<Settings>,
+            mail_helper: Arc<dyn MailHelper>,
+        ) -> Result<(), Error>;
+        async fn email_change_by_user(&self, user_id: &str) -> Result<Option<EmailChange>, Error>;
         async fn email_verification_by_id(&self, id: &str) -> Result<Option<EmailVerification>, Error>;
         async fn insert_user(&self, user: &User) -> Result<User, Error>;
         async fn log_in(&self, id: &str) -> Result<SessionToken, Error>;
@@ -81,14 +364,49 @@ mock! {
             &self,
             user_id: &str,
         ) -> Result<Option<PasswordRecovery>, Error>;
+        async fn resend_email_verification(
+            &self,
+            user: &User,
+            settings: Arc<Settings>,
+            mail_helper: Arc<dyn MailHelper>,
+        ) -> Result<(), Error>;
+        async fn schedule_account_deletion(
+            &self,
+            user_id: &str,
+            delete_at: NaiveDateTime,
+        ) -> Result<(), Error>;
         async fn sign_up_user(
             &self,
             user: User,
             settings: Arc<Settings>,
             mail_helper: Arc<dyn MailHelper>,
         ) -> Result<User, Error>;
+        async fn update_user(&self, user: &User) -> Result<User, Error>;
         async fn user_by_id(&self, id: &str) -> Result<Option<User>, Error>;
         async fn user_by_token(&self, token: &str) -> Result<Option<User>, Error>;
+        async fn user_data_export(&self, user_id: &str) -> Result<Option<UserDataExport>, Error>;
         async fn verify_email(&self, token: &str) -> Result<User, Error>;
     }
+
+    #[async_trait]
+    impl ValveStorage for Storage {
+        async fn acknowledge_valve_command(
+            &self,
+            fluid_meter_id: &str,
+            target_state: &ValveState,
+            success: bool,
+        ) -> Result<(), Error>;
+        async fn insert_valve_action(
+            &self,
+            action: &ValveAction,
+            command: &DeviceCommand,
+        ) -> Result<ValveAction, Error>;
+        async fn save_valve(&self, valve: &Valve) -> Result<Valve, Error>;
+        async fn valve_actions(
+            &self,
+            fluid_meter_id: &str,
+            options: &PaginatedRequest,
+        ) -> Result<PaginatedResponse<ValveAction>, Error>;
+        async fn valve_by_fluid_meter(&self, fluid_meter_id: &str) -> Result<Option<Valve>, Error>;
+    }
 }
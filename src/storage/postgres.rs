@@ -1,17 +1,29 @@
+pub mod device;
+pub mod firmware;
 pub mod fluid_meter;
+pub mod fluid_meter_member;
 pub mod measurement;
 pub mod metadata;
+pub mod organization;
+pub mod rate_limit;
+pub mod site;
+pub mod two_factor;
 pub mod user;
+pub mod valve;
 
 use sqlx::{
-    postgres::{PgPoolOptions, Postgres},
-    Pool,
+    postgres::{PgPoolOptions, PgRow, Postgres},
+    FromRow, Pool, Row,
 };
-use tracing::info;
+use tracing::{error, info};
 
-use crate::storage::Storage;
-
-pub const UNIQUE_VIOLATION: &str = "23505";
+use crate::{
+    api::common::{PageCursor, PaginatedResponse, Pagination},
+    storage::{
+        error::{undefined, Error},
+        ConnectionPoolUsage, Storage,
+    },
+};
 
 #[derive(Clone)]
 pub struct PostgresStorage {
@@ -41,4 +53,157 @@ impl PostgresStorage {
     }
 }
 
-impl Storage for PostgresStorage {}
+impl Storage for PostgresStorage {
+    fn connection_pool(&self) -> Option<ConnectionPoolUsage> {
+        return Some(ConnectionPoolUsage {
+            connections: self.pool.size(),
+            idle: self.pool.num_idle(),
+            max_connections: self.pool.options().get_max_connections(),
+        });
+    }
+}
+
+/// Keyset pagination for a list sorted by an expression, using the id to break
+/// ties. Queries select the extra columns returned by `columns`, filter with
+/// `page_filter`, sort with `order_by` and read `limit` rows. The cursor key
+/// and id need to be bound to the parameters given to those functions
+pub struct Keyset<'a> {
+    // Cursors are only valid for the sort they were created for
+    pub sort: String,
+    // SQL expression the list is sorted by
+    pub expr: &'a str,
+    // SQL type of the expression. Cursor keys are cast to it
+    pub key_type: &'a str,
+    // SQL expression of the id of the row
+    pub id: &'a str,
+    pub descending: bool,
+    pub cursor: &'a Option<PageCursor>,
+    pub page_size: u8,
+}
+
+impl<'a> Keyset<'a> {
+    fn backward(&self) -> bool {
+        return self.cursor.as_ref().is_some_and(|c| c.backward);
+    }
+
+    // Rows are read starting at the cursor, so backward pages are read in the
+    // opposite order
+    fn reads_descending(&self) -> bool {
+        return self.descending != self.backward();
+    }
+
+    /// The key and id of the cursor, to be bound to the query
+    pub fn binds(&self) -> (Option<String>, Option<String>) {
+        match self.cursor {
+            Some(c) => return (Some(c.key.clone()), Some(c.id.clone())),
+            None => return (None, None),
+        }
+    }
+
+    /// Extra columns needed to build the response. `from` and `filters` need
+    /// to be the same ones used by the query, so we can tell if there are rows
+    /// on the other side of the cursor
+    pub fn columns(&self, from: &str, filters: &str, key: u8, id: u8) -> String {
+        let op = if self.reads_descending() { ">=" } else { "<=" };
+        return format!(
+            r#"
+            ({0})::TEXT AS page_key,
+            ({1})::TEXT AS page_id,
+            EXISTS(
+                SELECT 1 FROM {2} WHERE {3}
+                AND ({0}, {1}) {4} (${5}::{6}, ${7}::VARCHAR)
+            ) AS has_other_side
+            "#,
+            self.expr, self.id, from, filters, op, key, self.key_type, id
+        );
+    }
+
+    /// Matches the rows of the requested page. Matches everything if there is
+    /// no cursor
+    pub fn page_filter(&self, key: u8, id: u8) -> String {
+        let op = if self.reads_descending() { "<" } else { ">" };
+        return format!(
+            "(${0}::TEXT IS NULL OR ({1}, {2}) {3} (${0}::{4}, ${5}::VARCHAR))",
+            key, self.expr, self.id, op, self.key_type, id
+        );
+    }
+
+    pub fn order_by(&self) -> String {
+        let direction = if self.reads_descending() {
+            "DESC"
+        } else {
+            "ASC"
+        };
+        return format!("ORDER BY {0} {2}, {1} {2}", self.expr, self.id, direction);
+    }
+
+    /// One more row than the page size is read, to know if there are more
+    pub fn limit(&self) -> i32 {
+        return self.page_size as i32 + 1;
+    }
+
+    pub fn paginate<T>(&self, rows: Vec<PgRow>) -> Result<PaginatedResponse<T>, Error>
+    where
+        T: for<'r> FromRow<'r, PgRow>,
+    {
+        let has_other_side = match rows.first() {
+            Some(r) => r.try_get("has_other_side").unwrap_or(false),
+            // Without rows we can't tell, but going past the last page is the
+            // common case
+            None => self.cursor.is_some(),
+        };
+        let has_next = rows.len() > self.page_size as usize;
+
+        let mut items = vec![];
+        let mut keys = vec![];
+        for row in rows.iter().take(self.page_size as usize) {
+            let item = T::from_row(row);
+            let key: Result<String, _> = row.try_get("page_key");
+            let id: Result<String, _> = row.try_get("page_id");
+            match (item, key, id) {
+                (Ok(item), Ok(key), Ok(id)) => {
+                    items.push(item);
+                    keys.push((key, id));
+                }
+                _ => {
+                    error!("Error reading page row");
+                    return undefined();
+                }
+            }
+        }
+        if self.backward() {
+            items.reverse();
+            keys.reverse();
+        }
+
+        let (has_more, has_less) = if self.backward() {
+            (has_other_side, has_next)
+        } else {
+            (has_next, self.cursor.is_some() && has_other_side)
+        };
+        // An empty page has no items to point to, so the other side is read
+        // from the cursor it was requested with
+        let cursor = |key: Option<&(String, String)>, backward: bool| match key {
+            Some((key, id)) => Some(PageCursor {
+                sort: self.sort.clone(),
+                key: key.clone(),
+                id: id.clone(),
+                backward,
+            }),
+            None => self.cursor.as_ref().map(|c| PageCursor {
+                backward,
+                ..c.clone()
+            }),
+        };
+
+        return Ok(PaginatedResponse {
+            items,
+            pagination: Pagination {
+                has_more,
+                has_less,
+                next_cursor: cursor(keys.last(), false).filter(|_| has_more),
+                previous_cursor: cursor(keys.first(), true).filter(|_| has_less),
+            },
+        });
+    }
+}
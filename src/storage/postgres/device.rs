@@ -0,0 +1,412 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use sqlx::{Postgres, Transaction};
+use tracing::error;
+
+use crate::{
+    api::{
+        common::{PaginatedRequest, PaginatedResponse},
+        device::{
+            Device, DeviceCommand, DeviceCommandStatus,
+            DeviceCommandStatus::{Pending, Sent},
+            DeviceStatus, DeviceTelemetry, DEVICE_COMMANDS_SORT,
+        },
+        fluid_meter::FluidMeter,
+    },
+    helper::device::generate_claim_code,
+    storage::{
+        error::{duplicate, not_found, query_error, Error},
+        postgres::{Keyset, PostgresStorage},
+        DeviceStorage,
+    },
+};
+
+/// Makes the devices of the given meters claimable again, with a new claim code.
+/// Needs to be called before the meters are deleted, so the hardware can be
+/// reused without provisioning it again
+pub(crate) async fn release_devices(
+    tx: &mut Transaction<'_, Postgres>,
+    meter_ids: &Vec<String>,
+) -> Result<(), sqlx::Error> {
+    let serials: Vec<String> =
+        sqlx::query_scalar("SELECT serial FROM device WHERE fluid_meter_id = ANY($1) FOR UPDATE")
+            .bind(meter_ids)
+            .fetch_all(&mut **tx)
+            .await?;
+    for serial in serials {
+        sqlx::query(
+            "UPDATE device SET fluid_meter_id = NULL, claim_code = $1, claimed_at = NULL WHERE serial = $2",
+        )
+        .bind(generate_claim_code())
+        .bind(&serial)
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    return Ok(());
+}
+
+#[async_trait]
+impl DeviceStorage for PostgresStorage {
+    async fn acknowledge_device_command(
+        &self,
+        id: &str,
+        fluid_meter_id: &str,
+        status: DeviceCommandStatus,
+        result: &Option<String>,
+    ) -> Result<DeviceCommand, Error> {
+        match sqlx::query_as(
+            r#"
+            UPDATE device_command
+            SET status = $1, result = $2, acknowledged_at = $3
+            WHERE id = $4 AND fluid_meter_id = $5 AND status IN ($6, $7)
+            RETURNING *
+            "#,
+        )
+        .bind(&status)
+        .bind(result)
+        .bind(Utc::now().naive_utc())
+        .bind(id)
+        .bind(fluid_meter_id)
+        .bind(&Pending)
+        .bind(&Sent)
+        .fetch_one(&self.pool)
+        .await
+        {
+            Ok(c) => return Ok(c),
+            Err(e) => {
+                match e {
+                    sqlx::Error::RowNotFound => {
+                        return not_found();
+                    }
+                    _ => {}
+                }
+
+                error!("Error acknowledging device command: {}", e);
+                return query_error(e, "acknowledge_device_command", Some(id));
+            }
+        };
+    }
+
+    async fn claim_device(
+        &self,
+        claim_code: &str,
+        fluid_meter: &FluidMeter,
+    ) -> Result<FluidMeter, Error> {
+        let mut tx = match self.pool.begin().await {
+            Ok(t) => t,
+            Err(e) => {
+                error!("Error creating transaction. {}", e);
+                return query_error(e, "claim_device", Some(&fluid_meter.id));
+            }
+        };
+
+        // Lock the device so the same code can't be claimed twice
+        let serial: String = match sqlx::query_scalar(
+            "SELECT serial FROM device WHERE claim_code = $1 AND fluid_meter_id IS NULL FOR UPDATE",
+        )
+        .bind(claim_code)
+        .fetch_one(&mut *tx)
+        .await
+        {
+            Ok(s) => s,
+            Err(e) => {
+                let _ = tx.rollback().await;
+                match e {
+                    sqlx::Error::RowNotFound => return not_found(),
+                    _ => {
+                        error!("Error getting device by claim code: {}", e);
+                        return query_error(e, "claim_device", Some(&fluid_meter.id));
+                    }
+                }
+            }
+        };
+
+        match sqlx::query(
+            "INSERT INTO fluid_meter(id, owner_id, organization_id, site_id, parent_id, name, status, recorded_at, updated_at, deleted_at, reporting_interval_secs, activated_at) VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)",
+        )
+        .bind(&fluid_meter.id)
+        .bind(&fluid_meter.owner_id)
+        .bind(&fluid_meter.organization_id)
+        .bind(&fluid_meter.site_id)
+        .bind(&fluid_meter.parent_id)
+        .bind(&fluid_meter.name)
+        .bind(&fluid_meter.status)
+        .bind(fluid_meter.recorded_at)
+        .bind(fluid_meter.updated_at)
+        .bind(fluid_meter.deleted_at)
+        .bind(fluid_meter.reporting_interval_secs)
+        .bind(fluid_meter.activated_at)
+        .execute(&mut *tx)
+        .await
+        {
+            Ok(_) => {}
+            Err(e) => {
+                let _ = tx.rollback().await;
+                error!("Error inserting fluid meter for device: {}", e);
+                return query_error(e, "claim_device", Some(&fluid_meter.id));
+            }
+        };
+
+        match sqlx::query(
+            "UPDATE device SET fluid_meter_id = $1, claim_code = NULL, claimed_at = $2 WHERE serial = $3",
+        )
+        .bind(&fluid_meter.id)
+        .bind(Utc::now().naive_utc())
+        .bind(&serial)
+        .execute(&mut *tx)
+        .await
+        {
+            Ok(_) => {}
+            Err(e) => {
+                let _ = tx.rollback().await;
+                error!("Error claiming device: {}", e);
+                return query_error(e, "claim_device", Some(&fluid_meter.id));
+            }
+        };
+
+        match tx.commit().await {
+            Ok(_) => return Ok(fluid_meter.clone()),
+            Err(e) => {
+                error!("Error committing transaction. {}", e);
+                return query_error(e, "claim_device", Some(&fluid_meter.id));
+            }
+        };
+    }
+
+    async fn device_by_fluid_meter(&self, fluid_meter_id: &str) -> Result<Option<Device>, Error> {
+        match sqlx::query_as("SELECT * FROM device WHERE fluid_meter_id = $1")
+            .bind(fluid_meter_id)
+            .fetch_one(&self.pool)
+            .await
+        {
+            Ok(d) => return Ok(Some(d)),
+            Err(e) => {
+                match e {
+                    sqlx::Error::RowNotFound => {
+                        return Ok(None);
+                    }
+                    _ => {}
+                }
+
+                error!("Error getting device by fluid meter: {}", e);
+                return query_error(e, "device_by_fluid_meter", Some(fluid_meter_id));
+            }
+        };
+    }
+
+    async fn device_by_serial(&self, serial: &str) -> Result<Option<Device>, Error> {
+        match sqlx::query_as("SELECT * FROM device WHERE serial = $1")
+            .bind(serial)
+            .fetch_one(&self.pool)
+            .await
+        {
+            Ok(d) => return Ok(Some(d)),
+            Err(e) => {
+                match e {
+                    sqlx::Error::RowNotFound => {
+                        return Ok(None);
+                    }
+                    _ => {}
+                }
+
+                error!("Error getting device by serial: {}", e);
+                return query_error(e, "device_by_serial", Some(serial));
+            }
+        };
+    }
+
+    async fn device_commands(
+        &self,
+        fluid_meter_id: &str,
+        options: &PaginatedRequest,
+    ) -> Result<PaginatedResponse<DeviceCommand>, Error> {
+        let keyset = Keyset {
+            sort: DEVICE_COMMANDS_SORT.to_string(),
+            expr: "recorded_at",
+            key_type: "TIMESTAMP",
+            id: "id",
+            descending: true,
+            cursor: &options.page_cursor,
+            page_size: options.page_size,
+        };
+        let filters = "fluid_meter_id = $1";
+        let query = format!(
+            r#"
+                SELECT *, {}
+                FROM device_command
+                WHERE {} AND {}
+                {}
+                LIMIT $4
+            "#,
+            keyset.columns("device_command", filters, 2, 3),
+            filters,
+            keyset.page_filter(2, 3),
+            keyset.order_by(),
+        );
+
+        let (key, id) = keyset.binds();
+        match sqlx::query(&query)
+            .bind(fluid_meter_id)
+            .bind(key)
+            .bind(id)
+            .bind(keyset.limit())
+            .fetch_all(&self.pool)
+            .await
+        {
+            Ok(rows) => return keyset.paginate(rows),
+            Err(e) => {
+                error!("Error getting device commands: {}", e);
+                return query_error(e, "device_commands", Some(fluid_meter_id));
+            }
+        };
+    }
+
+    async fn device_status_by_fluid_meter(
+        &self,
+        fluid_meter_id: &str,
+    ) -> Result<Option<DeviceStatus>, Error> {
+        match sqlx::query_as("SELECT * FROM device_status WHERE fluid_meter_id = $1")
+            .bind(fluid_meter_id)
+            .fetch_one(&self.pool)
+            .await
+        {
+            Ok(s) => return Ok(Some(s)),
+            Err(e) => {
+                match e {
+                    sqlx::Error::RowNotFound => {
+                        return Ok(None);
+                    }
+                    _ => {}
+                }
+
+                error!("Error getting device status: {}", e);
+                return query_error(e, "device_status_by_fluid_meter", Some(fluid_meter_id));
+            }
+        };
+    }
+
+    async fn insert_device(&self, device: &Device) -> Result<Device, Error> {
+        match sqlx::query(
+            r#"
+            INSERT INTO device(serial, secret_hash, claim_code, fluid_meter_id, recorded_at, claimed_at)
+            VALUES($1, $2, $3, $4, $5, $6)
+            ON CONFLICT(serial) DO NOTHING
+            "#,
+        )
+        .bind(&device.serial)
+        .bind(&device.secret_hash)
+        .bind(&device.claim_code)
+        .bind(&device.fluid_meter_id)
+        .bind(device.recorded_at)
+        .bind(device.claimed_at)
+        .execute(&self.pool)
+        .await
+        {
+            Ok(r) => {
+                if r.rows_affected() == 0 {
+                    return duplicate();
+                }
+                return Ok(device.clone());
+            }
+            Err(e) => {
+                error!("Error inserting device: {}", e);
+                return query_error(e, "insert_device", Some(&device.serial));
+            }
+        };
+    }
+
+    async fn insert_device_command(&self, command: &DeviceCommand) -> Result<DeviceCommand, Error> {
+        match sqlx::query(
+            r#"
+            INSERT INTO device_command(id, fluid_meter_id, command_type, value, status, created_by, result, recorded_at, sent_at, acknowledged_at)
+            VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            "#,
+        )
+        .bind(&command.id)
+        .bind(&command.fluid_meter_id)
+        .bind(&command.command_type)
+        .bind(command.value)
+        .bind(&command.status)
+        .bind(&command.created_by)
+        .bind(&command.result)
+        .bind(command.recorded_at)
+        .bind(command.sent_at)
+        .bind(command.acknowledged_at)
+        .execute(&self.pool)
+        .await
+        {
+            Ok(_) => return Ok(command.clone()),
+            Err(e) => {
+                error!("Error inserting device command: {}", e);
+                return query_error(e, "insert_device_command", Some(&command.id));
+            }
+        };
+    }
+
+    async fn save_device_status(
+        &self,
+        fluid_meter_id: &str,
+        telemetry: &DeviceTelemetry,
+    ) -> Result<DeviceStatus, Error> {
+        match sqlx::query_as(
+            r#"
+            INSERT INTO device_status(fluid_meter_id, battery_voltage, rssi, firmware_version, uptime_secs, updated_at)
+            VALUES($1, $2, $3, $4, $5, $6)
+            ON CONFLICT(fluid_meter_id)
+            DO UPDATE SET
+              battery_voltage = COALESCE(EXCLUDED.battery_voltage, device_status.battery_voltage),
+              rssi = COALESCE(EXCLUDED.rssi, device_status.rssi),
+              firmware_version = COALESCE(EXCLUDED.firmware_version, device_status.firmware_version),
+              uptime_secs = COALESCE(EXCLUDED.uptime_secs, device_status.uptime_secs),
+              updated_at = EXCLUDED.updated_at
+            RETURNING *
+            "#,
+        )
+        .bind(fluid_meter_id)
+        .bind(telemetry.battery_voltage)
+        .bind(telemetry.rssi)
+        .bind(&telemetry.firmware_version)
+        .bind(telemetry.uptime_secs)
+        .bind(Utc::now().naive_utc())
+        .fetch_one(&self.pool)
+        .await
+        {
+            Ok(s) => return Ok(s),
+            Err(e) => {
+                error!("Error saving device status: {}", e);
+                return query_error(e, "save_device_status", Some(fluid_meter_id));
+            }
+        };
+    }
+
+    async fn take_device_commands(
+        &self,
+        fluid_meter_id: &str,
+    ) -> Result<Vec<DeviceCommand>, Error> {
+        match sqlx::query_as(
+            r#"
+            WITH sent AS (
+              UPDATE device_command
+              SET status = $1, sent_at = COALESCE(sent_at, $2)
+              WHERE fluid_meter_id = $3 AND status IN ($4, $1)
+              RETURNING *
+            )
+            SELECT * FROM sent ORDER BY recorded_at, id
+            "#,
+        )
+        .bind(&Sent)
+        .bind(Utc::now().naive_utc())
+        .bind(fluid_meter_id)
+        .bind(&Pending)
+        .fetch_all(&self.pool)
+        .await
+        {
+            Ok(c) => return Ok(c),
+            Err(e) => {
+                error!("Error taking device commands: {}", e);
+                return query_error(e, "take_device_commands", Some(fluid_meter_id));
+            }
+        };
+    }
+}
@@ -0,0 +1,293 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use tracing::error;
+
+use crate::{
+    api::{
+        common::{PaginatedRequest, PaginatedResponse},
+        firmware::{
+            FirmwareRelease, FirmwareUpdate, FIRMWARE_RELEASES_SORT, FIRMWARE_UPDATES_SORT,
+        },
+    },
+    storage::{
+        error::{duplicate, not_found, query_error, Error},
+        postgres::{Keyset, PostgresStorage},
+        FirmwareStorage,
+    },
+};
+
+#[async_trait]
+impl FirmwareStorage for PostgresStorage {
+    async fn firmware_release_by_id(&self, id: &str) -> Result<Option<FirmwareRelease>, Error> {
+        match sqlx::query_as("SELECT * FROM firmware_release WHERE id = $1")
+            .bind(id)
+            .fetch_one(&self.pool)
+            .await
+        {
+            Ok(r) => return Ok(Some(r)),
+            Err(e) => {
+                match e {
+                    sqlx::Error::RowNotFound => {
+                        return Ok(None);
+                    }
+                    _ => {}
+                }
+
+                error!("Error getting firmware release: {}", e);
+                return query_error(e, "firmware_release_by_id", Some(id));
+            }
+        };
+    }
+
+    async fn firmware_releases(
+        &self,
+        options: &PaginatedRequest,
+    ) -> Result<PaginatedResponse<FirmwareRelease>, Error> {
+        let keyset = Keyset {
+            sort: FIRMWARE_RELEASES_SORT.to_string(),
+            expr: "recorded_at",
+            key_type: "TIMESTAMP",
+            id: "id",
+            descending: true,
+            cursor: &options.page_cursor,
+            page_size: options.page_size,
+        };
+        let filters = "TRUE";
+        let query = format!(
+            r#"
+                SELECT *, {}
+                FROM firmware_release
+                WHERE {} AND {}
+                {}
+                LIMIT $3
+            "#,
+            keyset.columns("firmware_release", filters, 1, 2),
+            filters,
+            keyset.page_filter(1, 2),
+            keyset.order_by(),
+        );
+
+        let (key, id) = keyset.binds();
+        match sqlx::query(&query)
+            .bind(key)
+            .bind(id)
+            .bind(keyset.limit())
+            .fetch_all(&self.pool)
+            .await
+        {
+            Ok(rows) => return keyset.paginate(rows),
+            Err(e) => {
+                error!("Error getting firmware releases: {}", e);
+                return query_error(e, "firmware_releases", None);
+            }
+        };
+    }
+
+    async fn firmware_releases_for_device(
+        &self,
+        serial: &str,
+        hardware_model: &str,
+        firmware_version: &str,
+    ) -> Result<Vec<FirmwareRelease>, Error> {
+        match sqlx::query_as(
+            r#"
+            SELECT r.*
+            FROM firmware_release r
+            WHERE r.hardware_model = $1
+              AND r.version <> $2
+              AND r.uploaded_at IS NOT NULL
+              AND r.rollout_percentage > 0
+              AND (cardinality(r.target_versions) = 0 OR $2 = ANY(r.target_versions))
+              AND NOT EXISTS(
+                SELECT 1 FROM firmware_update u
+                WHERE u.release_id = r.id AND u.serial = $3 AND u.status = 'failed'
+              )
+            ORDER BY r.recorded_at DESC
+            "#,
+        )
+        .bind(hardware_model)
+        .bind(firmware_version)
+        .bind(serial)
+        .fetch_all(&self.pool)
+        .await
+        {
+            Ok(r) => return Ok(r),
+            Err(e) => {
+                error!("Error getting firmware releases for device: {}", e);
+                return query_error(e, "firmware_releases_for_device", Some(serial));
+            }
+        };
+    }
+
+    async fn firmware_updates(
+        &self,
+        release_id: &str,
+        options: &PaginatedRequest,
+    ) -> Result<PaginatedResponse<FirmwareUpdate>, Error> {
+        let keyset = Keyset {
+            sort: FIRMWARE_UPDATES_SORT.to_string(),
+            expr: "recorded_at",
+            key_type: "TIMESTAMP",
+            id: "serial",
+            descending: true,
+            cursor: &options.page_cursor,
+            page_size: options.page_size,
+        };
+        let filters = "release_id = $1";
+        let query = format!(
+            r#"
+                SELECT *, {}
+                FROM firmware_update
+                WHERE {} AND {}
+                {}
+                LIMIT $4
+            "#,
+            keyset.columns("firmware_update", filters, 2, 3),
+            filters,
+            keyset.page_filter(2, 3),
+            keyset.order_by(),
+        );
+
+        let (key, id) = keyset.binds();
+        match sqlx::query(&query)
+            .bind(release_id)
+            .bind(key)
+            .bind(id)
+            .bind(keyset.limit())
+            .fetch_all(&self.pool)
+            .await
+        {
+            Ok(rows) => return keyset.paginate(rows),
+            Err(e) => {
+                error!("Error getting firmware updates: {}", e);
+                return query_error(e, "firmware_updates", Some(release_id));
+            }
+        };
+    }
+
+    async fn insert_firmware_release(
+        &self,
+        release: &FirmwareRelease,
+    ) -> Result<FirmwareRelease, Error> {
+        match sqlx::query(
+            r#"
+            INSERT INTO firmware_release(id, hardware_model, version, target_versions, rollout_percentage, size_bytes, sha256, created_by, recorded_at, uploaded_at)
+            VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            ON CONFLICT(hardware_model, version) DO NOTHING
+            "#,
+        )
+        .bind(&release.id)
+        .bind(&release.hardware_model)
+        .bind(&release.version)
+        .bind(&release.target_versions)
+        .bind(release.rollout_percentage)
+        .bind(release.size_bytes)
+        .bind(&release.sha256)
+        .bind(&release.created_by)
+        .bind(release.recorded_at)
+        .bind(release.uploaded_at)
+        .execute(&self.pool)
+        .await
+        {
+            Ok(r) => {
+                if r.rows_affected() == 0 {
+                    return duplicate();
+                }
+                return Ok(release.clone());
+            }
+            Err(e) => {
+                error!("Error inserting firmware release: {}", e);
+                return query_error(e, "insert_firmware_release", Some(&release.id));
+            }
+        };
+    }
+
+    async fn set_firmware_binary(
+        &self,
+        id: &str,
+        size_bytes: i64,
+        sha256: &str,
+    ) -> Result<FirmwareRelease, Error> {
+        match sqlx::query_as(
+            r#"
+            UPDATE firmware_release SET size_bytes = $1, sha256 = $2, uploaded_at = $3
+            WHERE id = $4
+            RETURNING *
+            "#,
+        )
+        .bind(size_bytes)
+        .bind(sha256)
+        .bind(Utc::now().naive_utc())
+        .bind(id)
+        .fetch_one(&self.pool)
+        .await
+        {
+            Ok(r) => return Ok(r),
+            Err(e) => {
+                match e {
+                    sqlx::Error::RowNotFound => {
+                        return not_found();
+                    }
+                    _ => {}
+                }
+
+                error!("Error setting firmware binary: {}", e);
+                return query_error(e, "set_firmware_binary", Some(id));
+            }
+        };
+    }
+
+    async fn set_firmware_rollout(
+        &self,
+        id: &str,
+        rollout_percentage: i16,
+    ) -> Result<FirmwareRelease, Error> {
+        match sqlx::query_as(
+            "UPDATE firmware_release SET rollout_percentage = $1 WHERE id = $2 RETURNING *",
+        )
+        .bind(rollout_percentage)
+        .bind(id)
+        .fetch_one(&self.pool)
+        .await
+        {
+            Ok(r) => return Ok(r),
+            Err(e) => {
+                match e {
+                    sqlx::Error::RowNotFound => {
+                        return not_found();
+                    }
+                    _ => {}
+                }
+
+                error!("Error setting firmware rollout: {}", e);
+                return query_error(e, "set_firmware_rollout", Some(id));
+            }
+        };
+    }
+
+    async fn save_firmware_update(&self, update: &FirmwareUpdate) -> Result<FirmwareUpdate, Error> {
+        match sqlx::query(
+            r#"
+            INSERT INTO firmware_update(release_id, serial, from_version, status, result, recorded_at)
+            VALUES($1, $2, $3, $4, $5, $6)
+            ON CONFLICT(release_id, serial)
+            DO UPDATE SET from_version = EXCLUDED.from_version, status = EXCLUDED.status, result = EXCLUDED.result, recorded_at = EXCLUDED.recorded_at
+            "#,
+        )
+        .bind(&update.release_id)
+        .bind(&update.serial)
+        .bind(&update.from_version)
+        .bind(&update.status)
+        .bind(&update.result)
+        .bind(update.recorded_at)
+        .execute(&self.pool)
+        .await
+        {
+            Ok(_) => return Ok(update.clone()),
+            Err(e) => {
+                error!("Error saving firmware update: {}", e);
+                return query_error(e, "save_firmware_update", Some(&update.serial));
+            }
+        };
+    }
+}
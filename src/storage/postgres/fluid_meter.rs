@@ -1,229 +1,323 @@
 use async_trait::async_trait;
+use chrono::{NaiveDateTime, Utc};
 use tracing::{debug, error};
 
 use crate::{
     api::{
-        common::{
-            PaginatedRequest, PaginatedResponse, Pagination, SortDirection, DEFAULT_PAGE_SIZE,
-        },
+        alert::Alert,
+        common::{PaginatedRequest, PaginatedResponse, SortDirection, DEFAULT_PAGE_SIZE},
         fluid_meter::{
             FluidMeter,
             FluidMeterStatus::{Active, Deleted, Inactive},
-            FluidMetersInput, FluidMetersSort,
+            FluidMeterSummary, FluidMetersInput, FluidMetersSort, DELETED_FLUID_METERS_SORT,
         },
     },
     storage::{
-        error::{undefined, Error, ErrorCode},
-        postgres::PostgresStorage,
+        error::{not_found, query_error, Error},
+        postgres::{device::release_devices, Keyset, PostgresStorage},
         FluidMeterStorage,
     },
 };
 
+// Meters the user owns or is a member of, directly or through an organization.
+// The user id is expected to be bound to $1
+const ACCESSIBLE_BY_USER: &'static str = r#"
+    (owner_id = $1 OR id IN (
+        SELECT fluid_meter_id FROM fluid_meter_member WHERE account_id = $1
+    ) OR organization_id IN (
+        SELECT organization_id FROM organization_member WHERE account_id = $1
+    ))
+"#;
+
+// Meters the user owns, directly or as an owner of the organization. The user id
+// is expected to be bound to $1
+const OWNED_BY_USER: &'static str = r#"
+    (owner_id = $1 OR organization_id IN (
+        SELECT organization_id FROM organization_member WHERE account_id = $1 AND role = 'owner'
+    ))
+"#;
+
+// Columns of FluidMeterSummary that aren't part of the meter. Need the joins
+// below
+const SUMMARY_COLUMNS: &'static str = r#"
+    last_measurement.last_measurement_at,
+    last_measurement.last_value,
+    readings.readings_last_24h,
+    alerts.open_alerts
+"#;
+
+// Each join only reads the rows of the meter in scope, using the indexes on
+// measurement and fluid_meter_alert
+const SUMMARY_JOINS: &'static str = r#"
+    LEFT JOIN LATERAL (
+        SELECT m.recorded_at AS last_measurement_at, m.measurement AS last_value
+        FROM measurement m
+        WHERE m.device_id = fluid_meter.id
+        ORDER BY m.recorded_at DESC
+        LIMIT 1
+    ) last_measurement ON TRUE
+    CROSS JOIN LATERAL (
+        SELECT COUNT(*) AS readings_last_24h
+        FROM measurement m
+        WHERE m.device_id = fluid_meter.id
+        AND m.recorded_at >= (NOW() AT TIME ZONE 'UTC') - INTERVAL '24 hours'
+    ) readings
+    CROSS JOIN LATERAL (
+        SELECT COUNT(*) AS open_alerts
+        FROM fluid_meter_alert a
+        WHERE a.fluid_meter_id = fluid_meter.id AND fluid_meter.status = 'active'
+    ) alerts
+"#;
+
+// Time of the last measurement of the meter in scope. Meters that never
+// reported get -infinity so they can be compared and sorted
+const LAST_MEASUREMENT_AT: &'static str = r#"
+    COALESCE(
+        (SELECT MAX(m.recorded_at) FROM measurement m WHERE m.device_id = fluid_meter.id),
+        '-infinity'::TIMESTAMP
+    )
+"#;
+
 #[async_trait]
 impl FluidMeterStorage for PostgresStorage {
     async fn get_active_fluid_meters(
         &self,
         options: &PaginatedRequest,
     ) -> Result<PaginatedResponse<FluidMeter>, Error> {
-        let mut meters = match &options.page_cursor {
-            Some(pc) => {
-                let query = r#"
-                        SELECT *
-                        FROM fluid_meter
-                        WHERE status = $1 AND id > $2
-                        ORDER BY id
-                        LIMIT $3
-                    "#;
-
-                match sqlx::query_as(&query)
-                    .bind(Active)
-                    .bind(&pc)
-                    .bind((options.page_size + 1) as i32)
-                    .fetch_all(&self.pool)
-                    .await
-                {
-                    Ok(found) => found,
-                    Err(err) => {
-                        error!("Error getting fluid_meters: {}", err);
-                        return Err(Error {
-                            code: ErrorCode::UndefinedError,
-                        });
-                    }
-                }
-            }
-            None => {
-                let query = r#"
-                        SELECT *
-                        FROM fluid_meter
-                        WHERE status = $1
-                        ORDER BY id
-                        LIMIT $2
-                    "#;
-
-                match sqlx::query_as(&query)
-                    .bind(Active)
-                    .bind((options.page_size + 1) as i32)
-                    .fetch_all(&self.pool)
-                    .await
-                {
-                    Ok(found) => found,
-                    Err(err) => {
-                        error!("Error getting fluid_meters: {}", err);
-                        return Err(Error {
-                            code: ErrorCode::UndefinedError,
-                        });
-                    }
-                }
-            }
+        let keyset = Keyset {
+            sort: FluidMetersSort::Id.to_string(),
+            expr: "id",
+            key_type: "VARCHAR",
+            id: "id",
+            descending: false,
+            cursor: &options.page_cursor,
+            page_size: options.page_size,
         };
+        let filters = "status = $1";
+        let query = format!(
+            r#"
+                SELECT *, {}
+                FROM fluid_meter
+                WHERE {} AND {}
+                {}
+                LIMIT $4
+            "#,
+            keyset.columns("fluid_meter", filters, 2, 3),
+            filters,
+            keyset.page_filter(2, 3),
+            keyset.order_by(),
+        );
 
-        let mut has_more = false;
-        if (meters.len() as u8) > options.page_size {
-            has_more = true;
-            meters.pop();
+        let (key, id) = keyset.binds();
+        match sqlx::query(&query)
+            .bind(Active)
+            .bind(key)
+            .bind(id)
+            .bind(keyset.limit())
+            .fetch_all(&self.pool)
+            .await
+        {
+            Ok(rows) => return keyset.paginate(rows),
+            Err(err) => {
+                error!("Error getting fluid_meters: {}", err);
+                return query_error(err, "get_active_fluid_meters", None);
+            }
         }
-        return Ok(PaginatedResponse {
-            items: meters,
-            pagination: Pagination {
-                has_more,
-                has_less: options.page_cursor.is_some(),
-            },
-        });
     }
 
     async fn get_fluid_meters(
         &self,
         user: &str,
         options: &FluidMetersInput,
-    ) -> Result<Vec<FluidMeter>, Error> {
-        let page_size = options.page_size.unwrap_or(*DEFAULT_PAGE_SIZE) as i32;
-        let sort_field = match &options.sort {
-            Some(s) => match s {
-                FluidMetersSort::Id => "id",
-                FluidMetersSort::Name => "name",
-            },
-            None => "id",
+    ) -> Result<PaginatedResponse<FluidMeterSummary>, Error> {
+        let sort = options.sort.clone().unwrap_or(FluidMetersSort::Id);
+        let (expr, key_type) = match sort {
+            FluidMetersSort::Id => ("id", "VARCHAR"),
+            FluidMetersSort::Name => ("name", "VARCHAR"),
+            FluidMetersSort::RecordedAt => ("recorded_at", "TIMESTAMP"),
+            FluidMetersSort::LastMeasurement => (LAST_MEASUREMENT_AT, "TIMESTAMP"),
         };
-        let sort_direction = match &options.sort_direction {
-            Some(s) => match s {
-                SortDirection::Asc => "ASC",
-                SortDirection::Desc => "DESC",
-            },
-            None => "ASC",
+        let keyset = Keyset {
+            sort: options.cursor_sort(),
+            expr,
+            key_type,
+            id: "id",
+            descending: matches!(options.sort_direction, Some(SortDirection::Desc)),
+            cursor: &options.page_cursor,
+            page_size: options.page_size.unwrap_or(*DEFAULT_PAGE_SIZE),
         };
 
         let status_filter = match &options.status {
             Some(s) => format!("AND status = '{}'", s),
             None => format!("AND status != '{}'", Deleted),
         };
+        let filters = format!(
+            r#"
+            {0}
+            {1}
+            AND ($5::VARCHAR IS NULL OR organization_id = $5)
+            AND ($6::VARCHAR IS NULL OR site_id = $6)
+            AND ($7::VARCHAR IS NULL OR site_id IN (SELECT id FROM site WHERE $7 = ANY(tags)))
+            AND ($8::VARCHAR IS NULL OR name ILIKE $8)
+            AND ($9::TIMESTAMP IS NULL OR recorded_at >= $9)
+            AND ($10::TIMESTAMP IS NULL OR recorded_at < $10)
+            AND (($11::TIMESTAMP IS NULL AND $12::TIMESTAMP IS NULL) OR {2} > '-infinity'::TIMESTAMP)
+            AND ($11::TIMESTAMP IS NULL OR {2} >= $11)
+            AND ($12::TIMESTAMP IS NULL OR {2} < $12)
+            "#,
+            ACCESSIBLE_BY_USER, status_filter, LAST_MEASUREMENT_AT
+        );
+        let query = format!(
+            r#"
+                SELECT fluid_meter.*, {}, {}
+                FROM fluid_meter
+                {}
+                WHERE {} AND {}
+                {}
+                LIMIT $4
+            "#,
+            SUMMARY_COLUMNS,
+            keyset.columns("fluid_meter", &filters, 2, 3),
+            SUMMARY_JOINS,
+            filters,
+            keyset.page_filter(2, 3),
+            keyset.order_by(),
+        );
 
-        match options.page_cursor.clone() {
-            Some(cursor) => {
-                let where_stmt = if sort_direction == "ASC" {
-                    format!("WHERE owner_id = $1 AND {} > $2", sort_field)
-                } else {
-                    format!("WHERE owner_id = $1 AND {} < $2", sort_field)
-                };
-                let order_stmt = format!("ORDER BY {} {}", sort_field, sort_direction);
-                let query = format!(
-                    r#"
-                        SELECT *
-                        FROM fluid_meter
-                        {}
-                        {}
-                        {}
-                        LIMIT $3
-                    "#,
-                    where_stmt, status_filter, order_stmt,
-                );
-
-                match sqlx::query_as(&query)
-                    .bind(user)
-                    .bind(cursor)
-                    .bind(page_size)
-                    .fetch_all(&self.pool)
-                    .await
-                {
-                    Ok(found) => {
-                        return Ok(found);
-                    }
-                    Err(err) => {
-                        error!("Error getting fluid_meters: {}", err);
-                        return Err(Error {
-                            code: ErrorCode::UndefinedError,
-                        });
-                    }
-                };
-            }
-            None => {
-                let order_stmt = format!("ORDER BY {} {}", sort_field, sort_direction);
-                let query = format!(
-                    r#"
-                        SELECT *
-                        FROM fluid_meter
-                        WHERE owner_id = $1
-                        {}
-                        {}
-                        LIMIT $2
-                    "#,
-                    status_filter, order_stmt,
-                );
+        // The search text is matched literally, so wildcards need to be escaped
+        let name_pattern = options.q.as_ref().map(|q| {
+            format!(
+                "%{}%",
+                q.replace('\\', "\\\\")
+                    .replace('%', "\\%")
+                    .replace('_', "\\_")
+            )
+        });
 
-                match sqlx::query_as(&query)
-                    .bind(user)
-                    .bind(page_size)
-                    .fetch_all(&self.pool)
-                    .await
-                {
-                    Ok(found) => {
-                        return Ok(found);
-                    }
-                    Err(err) => {
-                        error!("Error getting fluid_meters: {}", err);
-                        return Err(Error {
-                            code: ErrorCode::UndefinedError,
-                        });
-                    }
-                };
+        let (key, id) = keyset.binds();
+        match sqlx::query(&query)
+            .bind(user)
+            .bind(key)
+            .bind(id)
+            .bind(keyset.limit())
+            .bind(&options.organization_id)
+            .bind(&options.site_id)
+            .bind(&options.tag)
+            .bind(name_pattern)
+            .bind(options.created_from)
+            .bind(options.created_to)
+            .bind(options.reported_from)
+            .bind(options.reported_to)
+            .fetch_all(&self.pool)
+            .await
+        {
+            Ok(rows) => return keyset.paginate(rows),
+            Err(err) => {
+                error!("Error getting fluid_meters: {}", err);
+                return query_error(err, "get_fluid_meters", Some(user));
             }
         };
     }
 
     async fn insert_fluid_meter(&self, fluid_meter: &FluidMeter) -> Result<FluidMeter, Error> {
         match sqlx::query(
-            "INSERT INTO fluid_meter(id, owner_id, name, status, recorded_at, updated_at) VALUES($1, $2, $3, $4, $5, $6)",
+            "INSERT INTO fluid_meter(id, owner_id, organization_id, site_id, parent_id, name, status, recorded_at, updated_at, deleted_at, reporting_interval_secs, activated_at) VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)",
         )
         .bind(&fluid_meter.id)
         .bind(&fluid_meter.owner_id)
+        .bind(&fluid_meter.organization_id)
+        .bind(&fluid_meter.site_id)
+        .bind(&fluid_meter.parent_id)
         .bind(&fluid_meter.name)
         .bind(&fluid_meter.status)
         .bind(fluid_meter.recorded_at)
         .bind(fluid_meter.updated_at)
+        .bind(fluid_meter.deleted_at)
+        .bind(fluid_meter.reporting_interval_secs)
+        .bind(fluid_meter.activated_at)
         .execute(&self.pool)
         .await
         {
             Ok(_) => return Ok(fluid_meter.clone()),
             Err(err) => {
                 error!("Error: {}", err);
-                return Err(Error {
-                    code: ErrorCode::UndefinedError,
-                });
+                return query_error(err, "insert_fluid_meter", Some(&fluid_meter.id));
             }
         };
     }
 
     async fn delete_fluid_meter(&self, id: &str) -> Result<(), Error> {
         debug!("delete_fluid_meter: {}", &id);
-        match sqlx::query("UPDATE fluid_meter SET status = $1 WHERE id = $2")
-            .bind(&Deleted)
-            .bind(&id)
-            .execute(&self.pool)
-            .await
+        let now = Utc::now().naive_utc();
+        // SET reads the old values of the row, so the current status is kept
+        match sqlx::query(
+            r#"
+            UPDATE fluid_meter
+            SET status = $1, status_before_delete = status, deleted_at = $2, updated_at = $2
+            WHERE id = $3 AND status <> $1
+            "#,
+        )
+        .bind(&Deleted)
+        .bind(now)
+        .bind(&id)
+        .execute(&self.pool)
+        .await
         {
             Ok(_) => return Ok(()),
             Err(e) => {
                 error!("Error deleting fluid meter: {}", e);
-                return undefined();
+                return query_error(e, "delete_fluid_meter", Some(id));
+            }
+        };
+    }
+
+    async fn deleted_fluid_meters(
+        &self,
+        user: &str,
+        since: NaiveDateTime,
+        options: &PaginatedRequest,
+    ) -> Result<PaginatedResponse<FluidMeter>, Error> {
+        let keyset = Keyset {
+            sort: DELETED_FLUID_METERS_SORT.to_string(),
+            expr: "deleted_at",
+            key_type: "TIMESTAMP",
+            id: "id",
+            descending: true,
+            cursor: &options.page_cursor,
+            page_size: options.page_size,
+        };
+        let filters = format!(
+            "{} AND status = '{}' AND deleted_at >= $5",
+            OWNED_BY_USER, Deleted
+        );
+        let query = format!(
+            r#"
+                SELECT *, {}
+                FROM fluid_meter
+                WHERE {} AND {}
+                {}
+                LIMIT $4
+            "#,
+            keyset.columns("fluid_meter", &filters, 2, 3),
+            filters,
+            keyset.page_filter(2, 3),
+            keyset.order_by(),
+        );
+
+        let (key, id) = keyset.binds();
+        match sqlx::query(&query)
+            .bind(user)
+            .bind(key)
+            .bind(id)
+            .bind(keyset.limit())
+            .bind(since)
+            .fetch_all(&self.pool)
+            .await
+        {
+            Ok(rows) => return keyset.paginate(rows),
+            Err(err) => {
+                error!("Error getting deleted fluid_meters: {}", err);
+                return query_error(err, "deleted_fluid_meters", Some(user));
             }
         };
     }
@@ -245,69 +339,353 @@ impl FluidMeterStorage for PostgresStorage {
                 }
 
                 error!("Error getting fluid_meter by id. {}", e);
-                return undefined();
+                return query_error(e, "get_fluid_meter_by_id", Some(id));
             }
         };
     }
 
-    async fn is_fluid_meter_owner(
-        &self,
-        fluid_meter_id: &str,
-        account_id: &str,
-    ) -> Result<bool, Error> {
-        match sqlx::query("SELECT * FROM fluid_meter WHERE id = $1 AND owner_id = $2")
-            .bind(&fluid_meter_id)
-            .bind(&account_id)
-            .fetch_one(&self.pool)
+    async fn activate_fluid_meter(&self, meter_id: &str) -> Result<(), Error> {
+        // Only restoring can bring back deleted meters. Meters that were
+        // already active keep their grace period
+        match sqlx::query(
+            r#"
+            UPDATE fluid_meter
+            SET status = $1, activated_at = CASE WHEN status = $1 THEN activated_at ELSE $4 END
+            WHERE id = $2 AND status <> $3
+            "#,
+        )
+        .bind(&Active)
+        .bind(&meter_id)
+        .bind(&Deleted)
+        .bind(Utc::now().naive_utc())
+        .execute(&self.pool)
+        .await
+        {
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                error!(
+                    "Error activating fluid_meter id. {}. Error: {}",
+                    meter_id, e
+                );
+                return query_error(e, "activate_fluid_meter", Some(meter_id));
+            }
+        };
+    }
+
+    async fn deactivate_fluid_meter(&self, meter_id: &str) -> Result<(), Error> {
+        match sqlx::query("UPDATE fluid_meter SET status = $1 WHERE id = $2 AND status <> $3")
+            .bind(&Inactive)
+            .bind(&meter_id)
+            .bind(&Deleted)
+            .execute(&self.pool)
             .await
         {
-            Ok(_) => return Ok(true),
+            Ok(_) => return Ok(()),
             Err(e) => {
-                match e {
-                    sqlx::Error::RowNotFound => {
-                        return Ok(false);
-                    }
-                    _ => {}
+                error!(
+                    "Error deactivating fluid_meter id. {}. Error: {}",
+                    meter_id, e
+                );
+                return query_error(e, "deactivate_fluid_meter", Some(meter_id));
+            }
+        };
+    }
+
+    async fn purge_deleted_fluid_meters(&self, date: NaiveDateTime) -> Result<(u64, u64), Error> {
+        let mut tx = match self.pool.begin().await {
+            Ok(t) => t,
+            Err(e) => {
+                error!("Error creating transaction. {}", e);
+                return query_error(e, "purge_deleted_fluid_meters", None);
+            }
+        };
+
+        let meter_ids: Vec<String> = match sqlx::query_scalar(
+            "SELECT id FROM fluid_meter WHERE status = $1 AND deleted_at < $2",
+        )
+        .bind(Deleted)
+        .bind(date)
+        .fetch_all(&mut *tx)
+        .await
+        {
+            Ok(ids) => ids,
+            Err(e) => {
+                let _ = tx.rollback().await;
+                error!("Error getting fluid meters to purge: {}", e);
+                return query_error(e, "purge_deleted_fluid_meters", None);
+            }
+        };
+        if let Err(e) = release_devices(&mut tx, &meter_ids).await {
+            let _ = tx.rollback().await;
+            error!("Error releasing devices: {}", e);
+            return query_error(e, "purge_deleted_fluid_meters", None);
+        }
+
+        // Measurements would be deleted by the foreign key anyway, but we want
+        // to know how many there were
+        let measurements = match sqlx::query(
+            r#"
+            DELETE FROM measurement WHERE device_id IN (
+                SELECT id FROM fluid_meter WHERE status = $1 AND deleted_at < $2
+            )
+            "#,
+        )
+        .bind(Deleted)
+        .bind(date)
+        .execute(&mut *tx)
+        .await
+        {
+            Ok(r) => r.rows_affected(),
+            Err(e) => {
+                let _ = tx.rollback().await;
+                error!("Error purging measurements: {}", e);
+                return query_error(e, "purge_deleted_fluid_meters", None);
+            }
+        };
+
+        let meters =
+            match sqlx::query("DELETE FROM fluid_meter WHERE status = $1 AND deleted_at < $2")
+                .bind(Deleted)
+                .bind(date)
+                .execute(&mut *tx)
+                .await
+            {
+                Ok(r) => r.rows_affected(),
+                Err(e) => {
+                    let _ = tx.rollback().await;
+                    error!("Error purging fluid meters: {}", e);
+                    return query_error(e, "purge_deleted_fluid_meters", None);
                 }
+            };
 
-                error!("Error getting fluid_meter for user by id. {}", e);
-                return undefined();
+        match tx.commit().await {
+            Ok(_) => return Ok((meters, measurements)),
+            Err(e) => {
+                error!("Error committing transaction: {}", e);
+                return query_error(e, "purge_deleted_fluid_meters", None);
+            }
+        }
+    }
+
+    async fn restore_fluid_meter(&self, id: &str) -> Result<FluidMeter, Error> {
+        match sqlx::query_as(
+            r#"
+            UPDATE fluid_meter
+            SET status = COALESCE(status_before_delete, $1), status_before_delete = NULL,
+                deleted_at = NULL, updated_at = $2, activated_at = $2
+            WHERE id = $3 AND status = $4
+            RETURNING *
+            "#,
+        )
+        .bind(Active)
+        .bind(Utc::now().naive_utc())
+        .bind(id)
+        .bind(Deleted)
+        .fetch_optional(&self.pool)
+        .await
+        {
+            Ok(Some(m)) => return Ok(m),
+            Ok(None) => return not_found(),
+            Err(e) => {
+                error!("Error restoring fluid_meter {}. Error: {}", id, e);
+                return query_error(e, "restore_fluid_meter", Some(id));
             }
         };
     }
 
-    async fn activate_fluid_meter(&self, meter_id: &str) -> Result<(), Error> {
-        match sqlx::query("UPDATE fluid_meter SET status = $1 WHERE id = $2")
-            .bind(&Active)
-            .bind(&meter_id)
+    async fn set_fluid_meter_alerts(
+        &self,
+        meter_id: &str,
+        alerts: &Vec<Alert>,
+    ) -> Result<(), Error> {
+        let alert_types: Vec<String> = alerts.iter().map(|a| a.alert_type.to_string()).collect();
+        let mut tx = match self.pool.begin().await {
+            Ok(t) => t,
+            Err(e) => {
+                error!("Error creating transaction. {}", e);
+                return query_error(e, "set_fluid_meter_alerts", Some(meter_id));
+            }
+        };
+
+        match sqlx::query(
+            "DELETE FROM fluid_meter_alert WHERE fluid_meter_id = $1 AND alert_type <> ALL($2)",
+        )
+        .bind(meter_id)
+        .bind(&alert_types)
+        .execute(&mut *tx)
+        .await
+        {
+            Ok(_) => {}
+            Err(e) => {
+                let _ = tx.rollback().await;
+                error!(
+                    "Error closing alerts of fluid_meter {}. Error: {}",
+                    meter_id, e
+                );
+                return query_error(e, "set_fluid_meter_alerts", Some(meter_id));
+            }
+        };
+
+        // Alerts that were already open keep the time they were first found
+        match sqlx::query(
+            r#"
+            INSERT INTO fluid_meter_alert(fluid_meter_id, alert_type, recorded_at)
+            SELECT $1, UNNEST($2::VARCHAR[]), $3
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(meter_id)
+        .bind(&alert_types)
+        .bind(Utc::now().naive_utc())
+        .execute(&mut *tx)
+        .await
+        {
+            Ok(_) => {}
+            Err(e) => {
+                let _ = tx.rollback().await;
+                error!(
+                    "Error opening alerts of fluid_meter {}. Error: {}",
+                    meter_id, e
+                );
+                return query_error(e, "set_fluid_meter_alerts", Some(meter_id));
+            }
+        };
+
+        match tx.commit().await {
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                error!("Error committing transaction: {}", e);
+                return query_error(e, "set_fluid_meter_alerts", Some(meter_id));
+            }
+        }
+    }
+
+    async fn set_fluid_meter_site(
+        &self,
+        meter_id: &str,
+        site_id: &Option<String>,
+    ) -> Result<(), Error> {
+        match sqlx::query("UPDATE fluid_meter SET site_id = $1, updated_at = $2 WHERE id = $3")
+            .bind(site_id)
+            .bind(Utc::now().naive_utc())
+            .bind(meter_id)
             .execute(&self.pool)
             .await
         {
             Ok(_) => return Ok(()),
             Err(e) => {
                 error!(
-                    "Error activating fluid_meter id. {}. Error: {}",
+                    "Error setting site of fluid_meter {}. Error: {}",
                     meter_id, e
                 );
-                return undefined();
+                return query_error(e, "set_fluid_meter_site", Some(meter_id));
             }
         };
     }
 
-    async fn deactivate_fluid_meter(&self, meter_id: &str) -> Result<(), Error> {
-        match sqlx::query("UPDATE fluid_meter SET status = $1 WHERE id = $2")
-            .bind(&Inactive)
-            .bind(&meter_id)
+    async fn set_fluid_meter_reporting_interval(
+        &self,
+        meter_id: &str,
+        reporting_interval_secs: Option<i64>,
+    ) -> Result<(), Error> {
+        match sqlx::query(
+            "UPDATE fluid_meter SET reporting_interval_secs = $1, updated_at = $2 WHERE id = $3",
+        )
+        .bind(reporting_interval_secs)
+        .bind(Utc::now().naive_utc())
+        .bind(meter_id)
+        .execute(&self.pool)
+        .await
+        {
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                error!(
+                    "Error setting reporting interval of fluid_meter {}. Error: {}",
+                    meter_id, e
+                );
+                return query_error(e, "set_fluid_meter_reporting_interval", Some(meter_id));
+            }
+        };
+    }
+
+    async fn get_fluid_meter_summary(&self, id: &str) -> Result<Option<FluidMeterSummary>, Error> {
+        let query = format!(
+            "SELECT fluid_meter.*, {} FROM fluid_meter {} WHERE fluid_meter.id = $1",
+            SUMMARY_COLUMNS, SUMMARY_JOINS
+        );
+        match sqlx::query_as(&query)
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+        {
+            Ok(s) => return Ok(s),
+            Err(e) => {
+                error!("Error getting summary of fluid_meter {}. Error: {}", id, e);
+                return query_error(e, "get_fluid_meter_summary", Some(id));
+            }
+        };
+    }
+
+    async fn get_fluid_meter_children(&self, parent_id: &str) -> Result<Vec<FluidMeter>, Error> {
+        match sqlx::query_as(
+            "SELECT * FROM fluid_meter WHERE parent_id = $1 AND status != $2 ORDER BY name, id",
+        )
+        .bind(parent_id)
+        .bind(Deleted)
+        .fetch_all(&self.pool)
+        .await
+        {
+            Ok(m) => return Ok(m),
+            Err(e) => {
+                error!(
+                    "Error getting children of fluid_meter {}. Error: {}",
+                    parent_id, e
+                );
+                return query_error(e, "get_fluid_meter_children", Some(parent_id));
+            }
+        };
+    }
+
+    async fn set_fluid_meter_parent(
+        &self,
+        meter_id: &str,
+        parent_id: &Option<String>,
+    ) -> Result<(), Error> {
+        match sqlx::query("UPDATE fluid_meter SET parent_id = $1, updated_at = $2 WHERE id = $3")
+            .bind(parent_id)
+            .bind(Utc::now().naive_utc())
+            .bind(meter_id)
             .execute(&self.pool)
             .await
         {
             Ok(_) => return Ok(()),
             Err(e) => {
                 error!(
-                    "Error deactivating fluid_meter id. {}. Error: {}",
+                    "Error setting parent of fluid_meter {}. Error: {}",
                     meter_id, e
                 );
-                return undefined();
+                return query_error(e, "set_fluid_meter_parent", Some(meter_id));
+            }
+        };
+    }
+
+    async fn update_fluid_meter(&self, fluid_meter: &FluidMeter) -> Result<FluidMeter, Error> {
+        let mut updated = fluid_meter.clone();
+        updated.updated_at = Utc::now().naive_utc();
+        match sqlx::query(
+            "UPDATE fluid_meter SET name = $1, site_id = $2, updated_at = $3 WHERE id = $4",
+        )
+        .bind(&updated.name)
+        .bind(&updated.site_id)
+        .bind(updated.updated_at)
+        .bind(&updated.id)
+        .execute(&self.pool)
+        .await
+        {
+            Ok(_) => return Ok(updated),
+            Err(e) => {
+                error!("Error updating fluid_meter {}. Error: {}", updated.id, e);
+                return query_error(e, "update_fluid_meter", Some(&fluid_meter.id));
             }
         };
     }
@@ -0,0 +1,407 @@
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use std::sync::Arc;
+use tracing::error;
+
+use crate::{
+    api::{
+        common::{PaginatedRequest, PaginatedResponse},
+        fluid_meter::{FluidMeter, FluidMeterStatus::Deleted},
+        fluid_meter_member::{
+            FluidMeterInvite, FluidMeterMember, FluidMeterRole, FLUID_METER_MEMBERS_SORT,
+        },
+        user::User,
+    },
+    helper::{mail::MailHelper, token::alphanumeric},
+    settings::settings::Settings,
+    storage::{
+        error::{query_error, undefined, Error},
+        postgres::{Keyset, PostgresStorage},
+        FluidMeterMemberStorage,
+    },
+};
+
+pub const INVITE_TTL_DAYS: &'static i64 = &7;
+
+#[async_trait]
+impl FluidMeterMemberStorage for PostgresStorage {
+    async fn accept_fluid_meter_invite(
+        &self,
+        invite: &FluidMeterInvite,
+        account_id: &str,
+    ) -> Result<FluidMeterMember, Error> {
+        let member = FluidMeterMember {
+            fluid_meter_id: invite.fluid_meter_id.clone(),
+            account_id: account_id.to_string(),
+            role: invite.role.clone(),
+            alerts: false,
+            recorded_at: Utc::now().naive_utc(),
+        };
+
+        let mut tx = match self.pool.begin().await {
+            Ok(t) => t,
+            Err(e) => {
+                error!("Error creating transaction. {}", e);
+                return query_error(e, "accept_fluid_meter_invite", Some(&invite.fluid_meter_id));
+            }
+        };
+
+        match sqlx::query(
+            r#"
+            INSERT INTO fluid_meter_member(fluid_meter_id, account_id, role, alerts, recorded_at)
+            VALUES($1, $2, $3, $4, $5)
+            ON CONFLICT(fluid_meter_id, account_id)
+            DO UPDATE SET role = $3
+            "#,
+        )
+        .bind(&member.fluid_meter_id)
+        .bind(&member.account_id)
+        .bind(&member.role)
+        .bind(member.alerts)
+        .bind(member.recorded_at)
+        .execute(&mut *tx)
+        .await
+        {
+            Ok(_) => {}
+            Err(e) => {
+                let _ = tx.rollback().await;
+                error!("Error saving fluid meter member: {}", e);
+                return query_error(e, "accept_fluid_meter_invite", Some(&invite.fluid_meter_id));
+            }
+        };
+
+        match sqlx::query("DELETE FROM fluid_meter_invite WHERE token = $1")
+            .bind(&invite.token)
+            .execute(&mut *tx)
+            .await
+        {
+            Ok(_) => {}
+            Err(e) => {
+                let _ = tx.rollback().await;
+                error!("Error deleting fluid meter invite: {}", e);
+                return query_error(e, "accept_fluid_meter_invite", Some(&invite.fluid_meter_id));
+            }
+        };
+
+        let _ = tx.commit().await;
+
+        return match self
+            .fluid_meter_member(&member.fluid_meter_id, &member.account_id)
+            .await?
+        {
+            Some(m) => Ok(m),
+            None => {
+                error!("Fluid meter member not found after accepting invite");
+                undefined()
+            }
+        };
+    }
+
+    async fn create_fluid_meter_invite(
+        &self,
+        meter: &FluidMeter,
+        email: &str,
+        role: &FluidMeterRole,
+        inviter: &User,
+        settings: Arc<Settings>,
+        mail_helper: Arc<dyn MailHelper>,
+    ) -> Result<FluidMeterInvite, Error> {
+        let now = Utc::now().naive_utc();
+        let invite = FluidMeterInvite {
+            token: alphanumeric(&100),
+            fluid_meter_id: meter.id.clone(),
+            email: email.to_string(),
+            role: role.clone(),
+            invited_by: inviter.id.clone(),
+            expires_at: now + Duration::days(*INVITE_TTL_DAYS),
+            recorded_at: now,
+        };
+
+        let mut tx = match self.pool.begin().await {
+            Ok(t) => t,
+            Err(e) => {
+                error!("Error creating transaction. {}", e);
+                return query_error(e, "create_fluid_meter_invite", Some(&meter.id));
+            }
+        };
+
+        // Only the latest invite for an e-mail is valid. Expired invites are
+        // also deleted, so we don't have unnecessary information in the DB
+        match sqlx::query(
+            "DELETE FROM fluid_meter_invite WHERE (fluid_meter_id = $1 AND email = $2) OR expires_at < $3",
+        )
+        .bind(&invite.fluid_meter_id)
+        .bind(&invite.email)
+        .bind(now)
+        .execute(&mut *tx)
+        .await
+        {
+            Ok(_) => {}
+            Err(e) => {
+                let _ = tx.rollback().await;
+                error!("Error deleting fluid meter invites: {}", e);
+                return query_error(e, "create_fluid_meter_invite", Some(&meter.id));
+            }
+        };
+
+        match sqlx::query(
+            r#"
+            INSERT INTO fluid_meter_invite(token, fluid_meter_id, email, role, invited_by, expires_at, recorded_at)
+            VALUES($1, $2, $3, $4, $5, $6, $7)
+            "#,
+        )
+        .bind(&invite.token)
+        .bind(&invite.fluid_meter_id)
+        .bind(&invite.email)
+        .bind(&invite.role)
+        .bind(&invite.invited_by)
+        .bind(invite.expires_at)
+        .bind(invite.recorded_at)
+        .execute(&mut *tx)
+        .await
+        {
+            Ok(_) => {
+                if !mail_helper
+                    .fluid_meter_invite(&settings, inviter, meter, &invite.email, &invite.token)
+                    .await
+                {
+                    let _ = tx.rollback().await;
+                    error!("Couldn't send fluid meter invite email");
+                    return undefined();
+                }
+            }
+            Err(e) => {
+                let _ = tx.rollback().await;
+                error!("Error saving fluid meter invite: {}", e);
+                return query_error(e, "create_fluid_meter_invite", Some(&meter.id));
+            }
+        };
+
+        let _ = tx.commit().await;
+        return Ok(invite);
+    }
+
+    async fn delete_fluid_meter_member(
+        &self,
+        meter_id: &str,
+        account_id: &str,
+    ) -> Result<(), Error> {
+        match sqlx::query(
+            "DELETE FROM fluid_meter_member WHERE fluid_meter_id = $1 AND account_id = $2",
+        )
+        .bind(meter_id)
+        .bind(account_id)
+        .execute(&self.pool)
+        .await
+        {
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                error!("Error deleting fluid meter member: {}", e);
+                return query_error(e, "delete_fluid_meter_member", Some(meter_id));
+            }
+        };
+    }
+
+    async fn fluid_meter_alert_recipients(&self, meter_id: &str) -> Result<Vec<String>, Error> {
+        match sqlx::query_scalar(
+            r#"
+            SELECT owner_id FROM fluid_meter WHERE id = $1 AND owner_id IS NOT NULL
+            UNION
+            SELECT om.account_id
+            FROM fluid_meter fm
+            JOIN organization_member om ON om.organization_id = fm.organization_id
+            WHERE fm.id = $1 AND om.role IN ('admin', 'owner')
+            UNION
+            SELECT account_id FROM fluid_meter_member WHERE fluid_meter_id = $1 AND alerts
+            "#,
+        )
+        .bind(meter_id)
+        .fetch_all(&self.pool)
+        .await
+        {
+            Ok(r) => return Ok(r),
+            Err(e) => {
+                error!("Error getting alert recipients: {}", e);
+                return query_error(e, "fluid_meter_alert_recipients", Some(meter_id));
+            }
+        };
+    }
+
+    async fn fluid_meter_invite_by_token(
+        &self,
+        token: &str,
+    ) -> Result<Option<FluidMeterInvite>, Error> {
+        match sqlx::query_as(
+            "SELECT * FROM fluid_meter_invite WHERE token = $1 AND expires_at > $2",
+        )
+        .bind(token)
+        .bind(Utc::now().naive_utc())
+        .fetch_one(&self.pool)
+        .await
+        {
+            Ok(i) => return Ok(Some(i)),
+            Err(e) => {
+                match e {
+                    sqlx::Error::RowNotFound => {
+                        return Ok(None);
+                    }
+                    _ => {}
+                }
+
+                error!("Error getting fluid meter invite: {}", e);
+                return query_error(e, "fluid_meter_invite_by_token", None);
+            }
+        };
+    }
+
+    async fn fluid_meter_invites(&self, meter_id: &str) -> Result<Vec<FluidMeterInvite>, Error> {
+        match sqlx::query_as(
+            "SELECT * FROM fluid_meter_invite WHERE fluid_meter_id = $1 AND expires_at > $2 ORDER BY recorded_at",
+        )
+        .bind(meter_id)
+        .bind(Utc::now().naive_utc())
+        .fetch_all(&self.pool)
+        .await
+        {
+            Ok(i) => return Ok(i),
+            Err(e) => {
+                error!("Error getting fluid meter invites: {}", e);
+                return query_error(e, "fluid_meter_invites", Some(meter_id));
+            }
+        };
+    }
+
+    async fn fluid_meter_member(
+        &self,
+        meter_id: &str,
+        account_id: &str,
+    ) -> Result<Option<FluidMeterMember>, Error> {
+        match sqlx::query_as(
+            "SELECT * FROM fluid_meter_member WHERE fluid_meter_id = $1 AND account_id = $2",
+        )
+        .bind(meter_id)
+        .bind(account_id)
+        .fetch_one(&self.pool)
+        .await
+        {
+            Ok(m) => return Ok(Some(m)),
+            Err(e) => {
+                match e {
+                    sqlx::Error::RowNotFound => {
+                        return Ok(None);
+                    }
+                    _ => {}
+                }
+
+                error!("Error getting fluid meter member: {}", e);
+                return query_error(e, "fluid_meter_member", Some(meter_id));
+            }
+        };
+    }
+
+    async fn fluid_meter_members(
+        &self,
+        meter_id: &str,
+        options: &PaginatedRequest,
+    ) -> Result<PaginatedResponse<FluidMeterMember>, Error> {
+        let keyset = Keyset {
+            sort: FLUID_METER_MEMBERS_SORT.to_string(),
+            expr: "recorded_at",
+            key_type: "TIMESTAMP",
+            id: "account_id",
+            descending: false,
+            cursor: &options.page_cursor,
+            page_size: options.page_size,
+        };
+        let filters = "fluid_meter_id = $1";
+        let query = format!(
+            r#"
+            SELECT *, {}
+            FROM fluid_meter_member
+            WHERE {} AND {}
+            {}
+            LIMIT $4
+            "#,
+            keyset.columns("fluid_meter_member", filters, 2, 3),
+            filters,
+            keyset.page_filter(2, 3),
+            keyset.order_by(),
+        );
+
+        let (key, id) = keyset.binds();
+        match sqlx::query(&query)
+            .bind(meter_id)
+            .bind(key)
+            .bind(id)
+            .bind(keyset.limit())
+            .fetch_all(&self.pool)
+            .await
+        {
+            Ok(rows) => return keyset.paginate(rows),
+            Err(e) => {
+                error!("Error getting fluid meter members: {}", e);
+                return query_error(e, "fluid_meter_members", Some(meter_id));
+            }
+        };
+    }
+
+    async fn fluid_meter_role(
+        &self,
+        meter_id: &str,
+        account_id: &str,
+    ) -> Result<Option<FluidMeterRole>, Error> {
+        // A user can have access through different paths (e.g. an organization
+        // member that was also invited to the meter), the highest role wins
+        let roles: Vec<FluidMeterRole> = match sqlx::query_scalar(
+            r#"
+            SELECT 'owner'::VARCHAR FROM fluid_meter WHERE id = $1 AND owner_id = $2 AND status <> $3
+            UNION ALL
+            SELECT CASE om.role WHEN 'owner' THEN 'owner' WHEN 'admin' THEN 'manager' ELSE 'viewer' END::VARCHAR
+            FROM fluid_meter fm
+            JOIN organization_member om ON om.organization_id = fm.organization_id
+            WHERE fm.id = $1 AND om.account_id = $2 AND fm.status <> $3
+            UNION ALL
+            SELECT fmm.role
+            FROM fluid_meter_member fmm
+            JOIN fluid_meter fm ON fm.id = fmm.fluid_meter_id
+            WHERE fmm.fluid_meter_id = $1 AND fmm.account_id = $2 AND fm.status <> $3
+            "#,
+        )
+        .bind(meter_id)
+        .bind(account_id)
+        .bind(Deleted)
+        .fetch_all(&self.pool)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => {
+                error!("Error getting fluid meter role: {}", e);
+                return query_error(e, "fluid_meter_role", Some(meter_id));
+            }
+        };
+
+        return Ok(roles.into_iter().reduce(|a, b| if b > a { b } else { a }));
+    }
+
+    async fn update_fluid_meter_member(
+        &self,
+        member: &FluidMeterMember,
+    ) -> Result<FluidMeterMember, Error> {
+        match sqlx::query(
+            "UPDATE fluid_meter_member SET role = $1, alerts = $2 WHERE fluid_meter_id = $3 AND account_id = $4",
+        )
+        .bind(&member.role)
+        .bind(member.alerts)
+        .bind(&member.fluid_meter_id)
+        .bind(&member.account_id)
+        .execute(&self.pool)
+        .await
+        {
+            Ok(_) => return Ok(member.clone()),
+            Err(e) => {
+                error!("Error updating fluid meter member: {}", e);
+                return query_error(e, "update_fluid_meter_member", Some(&member.fluid_meter_id));
+            }
+        };
+    }
+}
@@ -1,11 +1,12 @@
 use async_trait::async_trait;
-use chrono::{Duration, NaiveDateTime};
+use chrono::NaiveDateTime;
 use tracing::{debug, error};
 
 use crate::{
-    api::measurement::Measurement,
+    api::{fluid_meter::FluidMeterStatus::Deleted, measurement::Measurement},
+    helper::measurement::take_reporting_token,
     storage::{
-        error::{rate_limit, undefined, Error, ErrorCode},
+        error::{query_error, rate_limit, Error},
         postgres::PostgresStorage,
         MeasurementStorage,
     },
@@ -14,49 +15,78 @@ use crate::{
 #[async_trait]
 impl MeasurementStorage for PostgresStorage {
     // Measurements are rate-limitted by device to prevent devices from spamming us
-    async fn save_measurement(&self, measurement: &Measurement) -> Result<Measurement, Error> {
+    async fn save_measurement(
+        &self,
+        measurement: &Measurement,
+        reporting_interval_secs: i64,
+    ) -> Result<Measurement, Error> {
         debug!("Saving measurement {:?}", measurement);
         let mut tx = match self.pool.begin().await {
             Ok(t) => t,
             Err(e) => {
                 error!("Error creating transaction. {}", e);
-                return undefined();
+                return query_error(e, "save_measurement", Some(&measurement.device_id));
             }
         };
 
-        let last: Option<Measurement> = match sqlx::query_as(
-            r#"
-            SELECT *
-            FROM measurement
-            WHERE device_id = $1
-            ORDER BY recorded_at DESC
-            LIMIT 1
-            "#,
+        // Meters without a bucket haven't sent anything yet, so their bucket is
+        // full
+        let bucket: Option<(f64, NaiveDateTime)> = match sqlx::query_as(
+            "SELECT tokens, updated_at FROM reporting_bucket WHERE fluid_meter_id = $1 FOR UPDATE",
         )
         .bind(&measurement.device_id)
         .fetch_one(&mut *tx)
         .await
         {
-            Ok(m) => Some(m),
+            Ok(b) => Some(b),
             Err(e) => match e {
                 sqlx::Error::RowNotFound => None,
                 _ => {
-                    error!("Error getting measurements for device. {}", e);
-                    return undefined();
+                    let _ = tx.rollback().await;
+                    error!("Error getting reporting bucket for device. {}", e);
+                    return query_error(e, "save_measurement", Some(&measurement.device_id));
                 }
             },
         };
 
-        if last.is_some()
-            && last.clone().unwrap().recorded_at > measurement.recorded_at - Duration::minutes(10)
+        let (tokens, updated_at) = bucket.unwrap_or((1.0, measurement.recorded_at));
+        let tokens = match take_reporting_token(
+            tokens,
+            updated_at,
+            measurement.recorded_at,
+            reporting_interval_secs,
+        ) {
+            Some(t) => t,
+            None => {
+                let _ = tx.rollback().await;
+                error!(
+                    "Rate limiting device: {}. Bucket updated_at: {}. New recorded_at: {}",
+                    measurement.device_id, updated_at, measurement.recorded_at
+                );
+                return rate_limit();
+            }
+        };
+
+        match sqlx::query(
+            r#"
+            INSERT INTO reporting_bucket(fluid_meter_id, tokens, updated_at)
+            VALUES($1, $2, $3)
+            ON CONFLICT(fluid_meter_id)
+            DO UPDATE SET tokens = EXCLUDED.tokens, updated_at = EXCLUDED.updated_at
+            "#,
+        )
+        .bind(&measurement.device_id)
+        .bind(tokens)
+        .bind(measurement.recorded_at)
+        .execute(&mut *tx)
+        .await
         {
-            error!(
-                "Rate limiting device: {}. Last recorded_at: {}. New recorded_at: {}",
-                measurement.device_id,
-                last.unwrap().recorded_at,
-                measurement.recorded_at
-            );
-            return rate_limit();
+            Ok(_) => {}
+            Err(e) => {
+                let _ = tx.rollback().await;
+                error!("Error saving reporting bucket: {}", e);
+                return query_error(e, "save_measurement", Some(&measurement.device_id));
+            }
         }
 
         match sqlx::query("INSERT INTO measurement(id, device_id, measurement, recorded_at) VALUES($1, $2, $3, $4)")
@@ -70,7 +100,7 @@ impl MeasurementStorage for PostgresStorage {
             Ok(_) => {},
             Err(e) => {
                 error!("Error: {}", e);
-                return undefined()
+                return query_error(e, "save_measurement", Some(&measurement.device_id))
             }
         }
 
@@ -78,7 +108,7 @@ impl MeasurementStorage for PostgresStorage {
             Ok(_) => {}
             Err(e) => {
                 error!("Error committing transaction: {}", e);
-                return undefined();
+                return query_error(e, "save_measurement", Some(&measurement.device_id));
             }
         }
         return Ok(measurement.clone());
@@ -120,9 +150,49 @@ impl MeasurementStorage for PostgresStorage {
             }
             Err(err) => {
                 error!("Error: {}", err);
-                return Err(Error {
-                    code: ErrorCode::UndefinedError,
-                });
+                return query_error(err, "get_measurements", Some(&device_id));
+            }
+        };
+    }
+
+    async fn get_children_measurements(
+        &self,
+        parent_id: &str,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+        num_records: u32,
+    ) -> Result<Vec<Measurement>, Error> {
+        match sqlx::query_as(
+            r#"
+            SELECT
+                m.id,
+                m.device_id,
+                m.measurement,
+                m.recorded_at
+            FROM measurement m
+            JOIN fluid_meter fm ON fm.id = m.device_id
+            WHERE
+                fm.parent_id = $1 AND
+                fm.status != $2 AND
+                m.recorded_at >= $3 AND
+                m.recorded_at <= $4
+            ORDER BY
+                m.recorded_at DESC
+            LIMIT $5
+            "#,
+        )
+        .bind(parent_id)
+        .bind(Deleted)
+        .bind(from)
+        .bind(to)
+        .bind(num_records as i32)
+        .fetch_all(&self.pool)
+        .await
+        {
+            Ok(m) => return Ok(m),
+            Err(e) => {
+                error!("Error getting children measurements: {}", e);
+                return query_error(e, "get_children_measurements", Some(parent_id));
             }
         };
     }
@@ -4,7 +4,7 @@ use tracing::error;
 use crate::{
     api::metadata::Metadata,
     storage::{
-        error::{undefined, Error},
+        error::{query_error, Error},
         postgres::PostgresStorage,
         MetadataStorage,
     },
@@ -29,7 +29,7 @@ impl MetadataStorage for PostgresStorage {
                 sqlx::Error::RowNotFound => Ok(None),
                 _ => {
                     error!("Error getting measurements for device. {}", e);
-                    return undefined();
+                    return query_error(e, "get_metadata", Some(key));
                 }
             },
         }
@@ -56,7 +56,7 @@ impl MetadataStorage for PostgresStorage {
             }),
             Err(e) => {
                 error!("Error saving metadata. {}:{} . Error: {}", key, value, e);
-                return undefined();
+                return query_error(e, "save_metadata", Some(key));
             }
         }
     }
@@ -0,0 +1,300 @@
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use tracing::error;
+
+use crate::{
+    api::{
+        common::{PaginatedRequest, PaginatedResponse},
+        fluid_meter::FluidMeterStatus::Deleted,
+        measurement::Measurement,
+        organization::{
+            Organization, OrganizationMember, OrganizationRole, ORGANIZATIONS_SORT,
+            ORGANIZATION_MEMBERS_SORT,
+        },
+    },
+    storage::{
+        error::{query_error, Error},
+        postgres::{Keyset, PostgresStorage},
+        OrganizationStorage,
+    },
+};
+
+#[async_trait]
+impl OrganizationStorage for PostgresStorage {
+    async fn add_organization_member(
+        &self,
+        member: &OrganizationMember,
+    ) -> Result<OrganizationMember, Error> {
+        match sqlx::query(
+            r#"
+            INSERT INTO organization_member(organization_id, account_id, role, recorded_at)
+            VALUES($1, $2, $3, $4)
+            ON CONFLICT(organization_id, account_id)
+            DO UPDATE SET role = $3
+            "#,
+        )
+        .bind(&member.organization_id)
+        .bind(&member.account_id)
+        .bind(&member.role)
+        .bind(member.recorded_at)
+        .execute(&self.pool)
+        .await
+        {
+            Ok(_) => return Ok(member.clone()),
+            Err(e) => {
+                error!("Error adding organization member: {}", e);
+                return query_error(e, "add_organization_member", Some(&member.organization_id));
+            }
+        };
+    }
+
+    async fn count_organization_owners(&self, organization_id: &str) -> Result<i64, Error> {
+        match sqlx::query_scalar(
+            "SELECT COUNT(*) FROM organization_member WHERE organization_id = $1 AND role = $2",
+        )
+        .bind(organization_id)
+        .bind(OrganizationRole::Owner)
+        .fetch_one(&self.pool)
+        .await
+        {
+            Ok(c) => return Ok(c),
+            Err(e) => {
+                error!("Error counting organization owners: {}", e);
+                return query_error(e, "count_organization_owners", Some(organization_id));
+            }
+        };
+    }
+
+    async fn create_organization(
+        &self,
+        organization: &Organization,
+        owner_id: &str,
+    ) -> Result<Organization, Error> {
+        let mut tx = match self.pool.begin().await {
+            Ok(t) => t,
+            Err(e) => {
+                error!("Error creating transaction. {}", e);
+                return query_error(e, "create_organization", Some(&organization.id));
+            }
+        };
+
+        match sqlx::query("INSERT INTO organization(id, name, recorded_at) VALUES($1, $2, $3)")
+            .bind(&organization.id)
+            .bind(&organization.name)
+            .bind(organization.recorded_at)
+            .execute(&mut *tx)
+            .await
+        {
+            Ok(_) => {}
+            Err(e) => {
+                let _ = tx.rollback().await;
+                error!("Error creating organization: {}", e);
+                return query_error(e, "create_organization", Some(&organization.id));
+            }
+        };
+
+        match sqlx::query(
+            "INSERT INTO organization_member(organization_id, account_id, role, recorded_at) VALUES($1, $2, $3, $4)",
+        )
+        .bind(&organization.id)
+        .bind(owner_id)
+        .bind(OrganizationRole::Owner)
+        .bind(organization.recorded_at)
+        .execute(&mut *tx)
+        .await
+        {
+            Ok(_) => {}
+            Err(e) => {
+                let _ = tx.rollback().await;
+                error!("Error adding organization owner: {}", e);
+                return query_error(e, "create_organization", Some(&organization.id));
+            }
+        };
+
+        match tx.commit().await {
+            Ok(_) => return Ok(organization.clone()),
+            Err(e) => {
+                error!("Error committing transaction: {}", e);
+                return query_error(e, "create_organization", Some(&organization.id));
+            }
+        }
+    }
+
+    async fn delete_organization_member(
+        &self,
+        organization_id: &str,
+        account_id: &str,
+    ) -> Result<(), Error> {
+        match sqlx::query(
+            "DELETE FROM organization_member WHERE organization_id = $1 AND account_id = $2",
+        )
+        .bind(organization_id)
+        .bind(account_id)
+        .execute(&self.pool)
+        .await
+        {
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                error!("Error deleting organization member: {}", e);
+                return query_error(e, "delete_organization_member", Some(organization_id));
+            }
+        };
+    }
+
+    async fn get_organization_measurements(
+        &self,
+        organization_id: &str,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+        num_records: u32,
+    ) -> Result<Vec<Measurement>, Error> {
+        match sqlx::query_as(
+            r#"
+            SELECT
+                m.id,
+                m.device_id,
+                m.measurement,
+                m.recorded_at
+            FROM measurement m
+            JOIN fluid_meter fm ON fm.id = m.device_id
+            WHERE
+                fm.organization_id = $1 AND
+                fm.status != $2 AND
+                m.recorded_at >= $3 AND
+                m.recorded_at <= $4
+            ORDER BY
+                m.recorded_at DESC
+            LIMIT $5
+            "#,
+        )
+        .bind(organization_id)
+        .bind(Deleted)
+        .bind(from)
+        .bind(to)
+        .bind(num_records as i32)
+        .fetch_all(&self.pool)
+        .await
+        {
+            Ok(m) => return Ok(m),
+            Err(e) => {
+                error!("Error getting organization measurements: {}", e);
+                return query_error(e, "get_organization_measurements", Some(organization_id));
+            }
+        };
+    }
+
+    async fn organization_member(
+        &self,
+        organization_id: &str,
+        account_id: &str,
+    ) -> Result<Option<OrganizationMember>, Error> {
+        match sqlx::query_as(
+            "SELECT * FROM organization_member WHERE organization_id = $1 AND account_id = $2",
+        )
+        .bind(organization_id)
+        .bind(account_id)
+        .fetch_optional(&self.pool)
+        .await
+        {
+            Ok(m) => return Ok(m),
+            Err(e) => {
+                error!("Error getting organization member: {}", e);
+                return query_error(e, "organization_member", Some(organization_id));
+            }
+        };
+    }
+
+    async fn organization_members(
+        &self,
+        organization_id: &str,
+        options: &PaginatedRequest,
+    ) -> Result<PaginatedResponse<OrganizationMember>, Error> {
+        let keyset = Keyset {
+            sort: ORGANIZATION_MEMBERS_SORT.to_string(),
+            expr: "recorded_at",
+            key_type: "TIMESTAMP",
+            id: "account_id",
+            descending: false,
+            cursor: &options.page_cursor,
+            page_size: options.page_size,
+        };
+        let filters = "organization_id = $1";
+        let query = format!(
+            r#"
+            SELECT *, {}
+            FROM organization_member
+            WHERE {} AND {}
+            {}
+            LIMIT $4
+            "#,
+            keyset.columns("organization_member", filters, 2, 3),
+            filters,
+            keyset.page_filter(2, 3),
+            keyset.order_by(),
+        );
+
+        let (key, id) = keyset.binds();
+        match sqlx::query(&query)
+            .bind(organization_id)
+            .bind(key)
+            .bind(id)
+            .bind(keyset.limit())
+            .fetch_all(&self.pool)
+            .await
+        {
+            Ok(rows) => return keyset.paginate(rows),
+            Err(e) => {
+                error!("Error getting organization members: {}", e);
+                return query_error(e, "organization_members", Some(organization_id));
+            }
+        };
+    }
+
+    async fn organizations_by_user(
+        &self,
+        account_id: &str,
+        options: &PaginatedRequest,
+    ) -> Result<PaginatedResponse<Organization>, Error> {
+        let keyset = Keyset {
+            sort: ORGANIZATIONS_SORT.to_string(),
+            expr: "o.name",
+            key_type: "VARCHAR",
+            id: "o.id",
+            descending: false,
+            cursor: &options.page_cursor,
+            page_size: options.page_size,
+        };
+        let from = "organization o JOIN organization_member om ON om.organization_id = o.id";
+        let filters = "om.account_id = $1";
+        let query = format!(
+            r#"
+            SELECT o.*, {}
+            FROM {}
+            WHERE {} AND {}
+            {}
+            LIMIT $4
+            "#,
+            keyset.columns(from, filters, 2, 3),
+            from,
+            filters,
+            keyset.page_filter(2, 3),
+            keyset.order_by(),
+        );
+
+        let (key, id) = keyset.binds();
+        match sqlx::query(&query)
+            .bind(account_id)
+            .bind(key)
+            .bind(id)
+            .bind(keyset.limit())
+            .fetch_all(&self.pool)
+            .await
+        {
+            Ok(rows) => return keyset.paginate(rows),
+            Err(e) => {
+                error!("Error getting organizations: {}", e);
+                return query_error(e, "organizations_by_user", Some(account_id));
+            }
+        };
+    }
+}
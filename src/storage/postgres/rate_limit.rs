@@ -0,0 +1,125 @@
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use tracing::error;
+
+use crate::{
+    helper::rate_limit::take_token,
+    storage::{
+        error::{query_error, Error},
+        postgres::PostgresStorage,
+        RateLimitStorage,
+    },
+};
+
+#[async_trait]
+impl RateLimitStorage for PostgresStorage {
+    async fn delete_rate_limit_buckets(&self, before: NaiveDateTime) -> Result<u64, Error> {
+        match sqlx::query("DELETE FROM rate_limit_bucket WHERE updated_at < $1")
+            .bind(before)
+            .execute(&self.pool)
+            .await
+        {
+            Ok(r) => return Ok(r.rows_affected()),
+            Err(e) => {
+                error!("Error deleting rate limit buckets. {}", e);
+                return query_error(e, "delete_rate_limit_buckets", None);
+            }
+        }
+    }
+
+    async fn take_rate_limit_tokens(
+        &self,
+        buckets: &Vec<(String, u32)>,
+        now: NaiveDateTime,
+    ) -> Result<Option<(String, u64)>, Error> {
+        let mut tx = match self.pool.begin().await {
+            Ok(tx) => tx,
+            Err(e) => {
+                error!("Error starting transaction: {}", e);
+                return query_error(e, "take_rate_limit_tokens", None);
+            }
+        };
+
+        // Rows are always locked in the same order, so concurrent requests
+        // can't deadlock
+        let mut sorted = buckets.clone();
+        sorted.sort();
+        let mut taken = vec![];
+        for (key, requests_per_minute) in sorted {
+            // The bucket row may not exist yet, so concurrent requests could
+            // both insert it. Inserting first makes sure there is a row to lock
+            match sqlx::query(
+                r#"
+                INSERT INTO rate_limit_bucket(key, tokens, updated_at)
+                VALUES($1, $2, $3)
+                ON CONFLICT(key) DO NOTHING
+                "#,
+            )
+            .bind(&key)
+            .bind(requests_per_minute as f64)
+            .bind(now)
+            .execute(&mut *tx)
+            .await
+            {
+                Ok(_) => {}
+                Err(e) => {
+                    let _ = tx.rollback().await;
+                    error!("Error creating rate limit bucket. {}", e);
+                    return query_error(e, "take_rate_limit_tokens", Some(&key));
+                }
+            }
+
+            let (tokens, updated_at): (f64, NaiveDateTime) = match sqlx::query_as(
+                "SELECT tokens, updated_at FROM rate_limit_bucket WHERE key = $1 FOR UPDATE",
+            )
+            .bind(&key)
+            .fetch_one(&mut *tx)
+            .await
+            {
+                Ok(b) => b,
+                Err(e) => {
+                    let _ = tx.rollback().await;
+                    error!("Error getting rate limit bucket. {}", e);
+                    return query_error(e, "take_rate_limit_tokens", Some(&key));
+                }
+            };
+
+            match take_token(tokens, updated_at, now, requests_per_minute) {
+                Ok(t) => taken.push((key, t)),
+                Err(retry_after) => {
+                    let _ = tx.rollback().await;
+                    return Ok(Some((key, retry_after)));
+                }
+            };
+        }
+
+        // Only saved once every bucket had a token
+        for (key, tokens) in taken {
+            match sqlx::query(
+                "UPDATE rate_limit_bucket SET tokens = $2, updated_at = $3 WHERE key = $1",
+            )
+            .bind(&key)
+            .bind(tokens)
+            .bind(now)
+            .execute(&mut *tx)
+            .await
+            {
+                Ok(_) => {}
+                Err(e) => {
+                    let _ = tx.rollback().await;
+                    error!("Error saving rate limit bucket. {}", e);
+                    return query_error(e, "take_rate_limit_tokens", Some(&key));
+                }
+            }
+        }
+
+        match tx.commit().await {
+            Ok(_) => {}
+            Err(e) => {
+                error!("Error committing transaction: {}", e);
+                return query_error(e, "take_rate_limit_tokens", None);
+            }
+        }
+        return Ok(None);
+    }
+}
@@ -0,0 +1,167 @@
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use tracing::error;
+
+use crate::{
+    api::{
+        common::{PaginatedRequest, PaginatedResponse},
+        fluid_meter::FluidMeterStatus::Deleted,
+        measurement::Measurement,
+        site::{Site, SITES_SORT},
+    },
+    storage::{
+        error::{query_error, Error},
+        postgres::{Keyset, PostgresStorage},
+        SiteStorage,
+    },
+};
+
+#[async_trait]
+impl SiteStorage for PostgresStorage {
+    async fn delete_site(&self, id: &str) -> Result<(), Error> {
+        match sqlx::query("DELETE FROM site WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await
+        {
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                error!("Error deleting site: {}", e);
+                return query_error(e, "delete_site", Some(id));
+            }
+        };
+    }
+
+    async fn get_site_measurements(
+        &self,
+        site_id: &str,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+        num_records: u32,
+    ) -> Result<Vec<Measurement>, Error> {
+        match sqlx::query_as(
+            r#"
+            SELECT
+                m.id,
+                m.device_id,
+                m.measurement,
+                m.recorded_at
+            FROM measurement m
+            JOIN fluid_meter fm ON fm.id = m.device_id
+            WHERE
+                fm.site_id = $1 AND
+                fm.status != $2 AND
+                m.recorded_at >= $3 AND
+                m.recorded_at <= $4
+            ORDER BY
+                m.recorded_at DESC
+            LIMIT $5
+            "#,
+        )
+        .bind(site_id)
+        .bind(Deleted)
+        .bind(from)
+        .bind(to)
+        .bind(num_records as i32)
+        .fetch_all(&self.pool)
+        .await
+        {
+            Ok(m) => return Ok(m),
+            Err(e) => {
+                error!("Error getting site measurements: {}", e);
+                return query_error(e, "get_site_measurements", Some(site_id));
+            }
+        };
+    }
+
+    async fn insert_site(&self, site: &Site) -> Result<Site, Error> {
+        match sqlx::query(
+            r#"
+            INSERT INTO site(id, owner_id, organization_id, name, address, latitude, longitude, tags, recorded_at)
+            VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            "#,
+        )
+        .bind(&site.id)
+        .bind(&site.owner_id)
+        .bind(&site.organization_id)
+        .bind(&site.name)
+        .bind(&site.address)
+        .bind(site.latitude)
+        .bind(site.longitude)
+        .bind(&site.tags)
+        .bind(site.recorded_at)
+        .execute(&self.pool)
+        .await
+        {
+            Ok(_) => return Ok(site.clone()),
+            Err(e) => {
+                error!("Error inserting site: {}", e);
+                return query_error(e, "insert_site", Some(&site.id));
+            }
+        };
+    }
+
+    async fn site_by_id(&self, id: &str) -> Result<Option<Site>, Error> {
+        match sqlx::query_as("SELECT * FROM site WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+        {
+            Ok(s) => return Ok(s),
+            Err(e) => {
+                error!("Error getting site by id: {}", e);
+                return query_error(e, "site_by_id", Some(id));
+            }
+        };
+    }
+
+    async fn sites_by_user(
+        &self,
+        account_id: &str,
+        options: &PaginatedRequest,
+    ) -> Result<PaginatedResponse<Site>, Error> {
+        let keyset = Keyset {
+            sort: SITES_SORT.to_string(),
+            expr: "name",
+            key_type: "VARCHAR",
+            id: "id",
+            descending: false,
+            cursor: &options.page_cursor,
+            page_size: options.page_size,
+        };
+        let filters = r#"
+            (owner_id = $1 OR organization_id IN (
+                SELECT organization_id FROM organization_member WHERE account_id = $1
+            ))
+        "#;
+        let query = format!(
+            r#"
+            SELECT *, {}
+            FROM site
+            WHERE {} AND {}
+            {}
+            LIMIT $4
+            "#,
+            keyset.columns("site", filters, 2, 3),
+            filters,
+            keyset.page_filter(2, 3),
+            keyset.order_by(),
+        );
+
+        let (key, id) = keyset.binds();
+        match sqlx::query(&query)
+            .bind(account_id)
+            .bind(key)
+            .bind(id)
+            .bind(keyset.limit())
+            .fetch_all(&self.pool)
+            .await
+        {
+            Ok(rows) => return keyset.paginate(rows),
+            Err(e) => {
+                error!("Error getting sites: {}", e);
+                return query_error(e, "sites_by_user", Some(account_id));
+            }
+        };
+    }
+}
@@ -0,0 +1,399 @@
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use tracing::error;
+
+use crate::{
+    api::two_factor::{TwoFactor, TwoFactorChallenge},
+    helper::{
+        token::{alphanumeric, AUTH_TOKEN_LEN},
+        two_factor::{
+            ACCOUNT_MAX_FAILED_ATTEMPTS, CHALLENGE_MAX_ATTEMPTS, CHALLENGE_TTL_MINS, LOCKOUT_MINS,
+        },
+    },
+    storage::{
+        error::{query_error, Error},
+        postgres::PostgresStorage,
+        TwoFactorStorage,
+    },
+};
+
+#[async_trait]
+impl TwoFactorStorage for PostgresStorage {
+    async fn save_two_factor_secret(
+        &self,
+        account_id: &str,
+        secret: &str,
+    ) -> Result<TwoFactor, Error> {
+        let two_factor = TwoFactor {
+            account_id: account_id.to_string(),
+            secret: secret.to_string(),
+            last_used_step: None,
+            enabled_at: None,
+            recorded_at: Utc::now().naive_utc(),
+            failed_attempts: 0,
+            locked_until: None,
+        };
+
+        match sqlx::query(
+            r#"
+            INSERT INTO two_factor(account_id, secret, recorded_at)
+            VALUES($1, $2, $3)
+            ON CONFLICT(account_id)
+            DO UPDATE SET secret = $2, last_used_step = NULL, recorded_at = $3
+            WHERE two_factor.enabled_at IS NULL
+            "#,
+        )
+        .bind(&two_factor.account_id)
+        .bind(&two_factor.secret)
+        .bind(two_factor.recorded_at)
+        .execute(&self.pool)
+        .await
+        {
+            Ok(_) => return Ok(two_factor),
+            Err(e) => {
+                error!("Error saving two factor secret: {}", e);
+                return query_error(e, "save_two_factor_secret", Some(account_id));
+            }
+        };
+    }
+
+    async fn two_factor_by_user(&self, account_id: &str) -> Result<Option<TwoFactor>, Error> {
+        match sqlx::query_as("SELECT * FROM two_factor WHERE account_id = $1")
+            .bind(account_id)
+            .fetch_one(&self.pool)
+            .await
+        {
+            Ok(t) => return Ok(Some(t)),
+            Err(e) => {
+                match e {
+                    sqlx::Error::RowNotFound => {
+                        return Ok(None);
+                    }
+                    _ => {}
+                }
+
+                error!("Error getting two factor by user: {}", e);
+                return query_error(e, "two_factor_by_user", Some(account_id));
+            }
+        };
+    }
+
+    async fn enable_two_factor(
+        &self,
+        account_id: &str,
+        step: i64,
+        recovery_code_hashes: &Vec<String>,
+    ) -> Result<(), Error> {
+        let mut tx = match self.pool.begin().await {
+            Ok(t) => t,
+            Err(e) => {
+                error!("Error creating transaction. {}", e);
+                return query_error(e, "enable_two_factor", Some(account_id));
+            }
+        };
+
+        let now = Utc::now().naive_utc();
+        match sqlx::query(
+            "UPDATE two_factor SET enabled_at = $1, last_used_step = $2 WHERE account_id = $3",
+        )
+        .bind(now)
+        .bind(step)
+        .bind(account_id)
+        .execute(&mut *tx)
+        .await
+        {
+            Ok(_) => {}
+            Err(e) => {
+                let _ = tx.rollback().await;
+                error!("Error enabling two factor: {}", e);
+                return query_error(e, "enable_two_factor", Some(account_id));
+            }
+        };
+
+        match sqlx::query("DELETE FROM two_factor_recovery_code WHERE account_id = $1")
+            .bind(account_id)
+            .execute(&mut *tx)
+            .await
+        {
+            Ok(_) => {}
+            Err(e) => {
+                let _ = tx.rollback().await;
+                error!("Error deleting recovery codes: {}", e);
+                return query_error(e, "enable_two_factor", Some(account_id));
+            }
+        };
+
+        for hash in recovery_code_hashes {
+            match sqlx::query(
+                "INSERT INTO two_factor_recovery_code(code_hash, account_id, recorded_at) VALUES($1, $2, $3)",
+            )
+            .bind(hash)
+            .bind(account_id)
+            .bind(now)
+            .execute(&mut *tx)
+            .await
+            {
+                Ok(_) => {}
+                Err(e) => {
+                    let _ = tx.rollback().await;
+                    error!("Error saving recovery code: {}", e);
+                    return query_error(e, "enable_two_factor", Some(account_id));
+                }
+            };
+        }
+
+        match tx.commit().await {
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                error!("Error committing transaction: {}", e);
+                return query_error(e, "enable_two_factor", Some(account_id));
+            }
+        }
+    }
+
+    async fn disable_two_factor(&self, account_id: &str) -> Result<(), Error> {
+        let mut tx = match self.pool.begin().await {
+            Ok(t) => t,
+            Err(e) => {
+                error!("Error creating transaction. {}", e);
+                return query_error(e, "disable_two_factor", Some(account_id));
+            }
+        };
+
+        for table in [
+            "two_factor_challenge",
+            "two_factor_recovery_code",
+            "two_factor",
+        ] {
+            match sqlx::query(&format!("DELETE FROM {} WHERE account_id = $1", table))
+                .bind(account_id)
+                .execute(&mut *tx)
+                .await
+            {
+                Ok(_) => {}
+                Err(e) => {
+                    let _ = tx.rollback().await;
+                    error!("Error disabling two factor. Table: {}. {}", table, e);
+                    return query_error(e, "disable_two_factor", Some(account_id));
+                }
+            };
+        }
+
+        match tx.commit().await {
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                error!("Error committing transaction: {}", e);
+                return query_error(e, "disable_two_factor", Some(account_id));
+            }
+        }
+    }
+
+    async fn use_two_factor_step(&self, account_id: &str, step: i64) -> Result<bool, Error> {
+        match sqlx::query(
+            r#"
+            UPDATE two_factor
+            SET last_used_step = $1
+            WHERE account_id = $2 AND (last_used_step IS NULL OR last_used_step < $1)
+            "#,
+        )
+        .bind(step)
+        .bind(account_id)
+        .execute(&self.pool)
+        .await
+        {
+            Ok(r) => return Ok(r.rows_affected() == 1),
+            Err(e) => {
+                error!("Error using two factor step: {}", e);
+                return query_error(e, "use_two_factor_step", Some(account_id));
+            }
+        };
+    }
+
+    async fn use_recovery_code(&self, account_id: &str, code_hash: &str) -> Result<bool, Error> {
+        match sqlx::query(
+            r#"
+            UPDATE two_factor_recovery_code
+            SET used_at = $1
+            WHERE account_id = $2 AND code_hash = $3 AND used_at IS NULL
+            "#,
+        )
+        .bind(Utc::now().naive_utc())
+        .bind(account_id)
+        .bind(code_hash)
+        .execute(&self.pool)
+        .await
+        {
+            Ok(r) => return Ok(r.rows_affected() == 1),
+            Err(e) => {
+                error!("Error using recovery code: {}", e);
+                return query_error(e, "use_recovery_code", Some(account_id));
+            }
+        };
+    }
+
+    async fn create_two_factor_challenge(
+        &self,
+        account_id: &str,
+    ) -> Result<TwoFactorChallenge, Error> {
+        let challenge = TwoFactorChallenge {
+            token: alphanumeric(AUTH_TOKEN_LEN),
+            account_id: account_id.to_string(),
+            attempts: 0,
+            expires_at: Utc::now().naive_utc() + Duration::minutes(*CHALLENGE_TTL_MINS),
+        };
+
+        // Delete expired challenges just so we don't have unnecessary
+        // information in the DB
+        match sqlx::query("DELETE FROM two_factor_challenge WHERE expires_at < $1")
+            .bind(Utc::now().naive_utc())
+            .execute(&self.pool)
+            .await
+        {
+            Ok(_) => {}
+            Err(e) => {
+                error!("Error deleting two factor challenges: {}", e);
+                return query_error(e, "create_two_factor_challenge", Some(account_id));
+            }
+        };
+
+        match sqlx::query(
+            "INSERT INTO two_factor_challenge(token, account_id, attempts, expires_at) VALUES($1, $2, $3, $4)",
+        )
+        .bind(&challenge.token)
+        .bind(&challenge.account_id)
+        .bind(challenge.attempts)
+        .bind(challenge.expires_at)
+        .execute(&self.pool)
+        .await
+        {
+            Ok(_) => return Ok(challenge),
+            Err(e) => {
+                error!("Error creating two factor challenge: {}", e);
+                return query_error(e, "create_two_factor_challenge", Some(account_id));
+            }
+        };
+    }
+
+    async fn two_factor_challenge_by_token(
+        &self,
+        token: &str,
+    ) -> Result<Option<TwoFactorChallenge>, Error> {
+        match sqlx::query_as(
+            "SELECT * FROM two_factor_challenge WHERE token = $1 AND expires_at > $2",
+        )
+        .bind(token)
+        .bind(Utc::now().naive_utc())
+        .fetch_one(&self.pool)
+        .await
+        {
+            Ok(c) => return Ok(Some(c)),
+            Err(e) => {
+                match e {
+                    sqlx::Error::RowNotFound => {
+                        return Ok(None);
+                    }
+                    _ => {}
+                }
+
+                error!("Error getting two factor challenge: {}", e);
+                return query_error(e, "two_factor_challenge_by_token", None);
+            }
+        };
+    }
+
+    async fn fail_two_factor_challenge(&self, token: &str) -> Result<(), Error> {
+        // Counted for the account too. Reaching the limit locks it and ends
+        // its challenges
+        let locked_account: Option<String> = match sqlx::query_scalar(
+            r#"
+            UPDATE two_factor SET
+              failed_attempts = CASE WHEN failed_attempts + 1 >= $2 THEN 0 ELSE failed_attempts + 1 END,
+              locked_until = CASE WHEN failed_attempts + 1 >= $2 THEN $3 ELSE locked_until END
+            WHERE account_id = (SELECT account_id FROM two_factor_challenge WHERE token = $1)
+            RETURNING CASE WHEN locked_until = $3 THEN account_id END
+            "#,
+        )
+        .bind(token)
+        .bind(*ACCOUNT_MAX_FAILED_ATTEMPTS)
+        .bind(Utc::now().naive_utc() + Duration::minutes(*LOCKOUT_MINS))
+        .fetch_optional(&self.pool)
+        .await
+        {
+            Ok(a) => a.flatten(),
+            Err(e) => {
+                error!("Error counting failed two factor attempt: {}", e);
+                return query_error(e, "fail_two_factor_challenge", None);
+            }
+        };
+        if let Some(account_id) = locked_account {
+            match sqlx::query("DELETE FROM two_factor_challenge WHERE account_id = $1")
+                .bind(&account_id)
+                .execute(&self.pool)
+                .await
+            {
+                Ok(_) => return Ok(()),
+                Err(e) => {
+                    error!("Error deleting two factor challenges: {}", e);
+                    return query_error(e, "fail_two_factor_challenge", Some(&account_id));
+                }
+            };
+        }
+
+        match sqlx::query(
+            "UPDATE two_factor_challenge SET attempts = attempts + 1 WHERE token = $1",
+        )
+        .bind(token)
+        .execute(&self.pool)
+        .await
+        {
+            Ok(_) => {}
+            Err(e) => {
+                error!("Error updating two factor challenge: {}", e);
+                return query_error(e, "fail_two_factor_challenge", None);
+            }
+        };
+
+        match sqlx::query("DELETE FROM two_factor_challenge WHERE token = $1 AND attempts >= $2")
+            .bind(token)
+            .bind(*CHALLENGE_MAX_ATTEMPTS)
+            .execute(&self.pool)
+            .await
+        {
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                error!("Error deleting two factor challenge: {}", e);
+                return query_error(e, "fail_two_factor_challenge", None);
+            }
+        };
+    }
+
+    async fn clear_two_factor_failures(&self, account_id: &str) -> Result<(), Error> {
+        match sqlx::query(
+            "UPDATE two_factor SET failed_attempts = 0, locked_until = NULL WHERE account_id = $1",
+        )
+        .bind(account_id)
+        .execute(&self.pool)
+        .await
+        {
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                error!("Error clearing two factor failures: {}", e);
+                return query_error(e, "clear_two_factor_failures", Some(account_id));
+            }
+        };
+    }
+
+    async fn delete_two_factor_challenge(&self, token: &str) -> Result<(), Error> {
+        match sqlx::query("DELETE FROM two_factor_challenge WHERE token = $1")
+            .bind(token)
+            .execute(&self.pool)
+            .await
+        {
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                error!("Error deleting two factor challenge: {}", e);
+                return query_error(e, "delete_two_factor_challenge", None);
+            }
+        };
+    }
+}
@@ -1,25 +1,35 @@
 use async_trait::async_trait;
-use chrono::{Duration, Utc};
-use std::sync::Arc;
+use chrono::{Duration, NaiveDateTime, Utc};
+use std::{collections::HashMap, sync::Arc};
 use tracing::error;
 
 use crate::{
     api::{
         email_verification::EmailVerification,
-        user::{NewPasswordInput, PasswordRecovery, SessionToken, User},
+        fluid_meter::FluidMeter,
+        fluid_meter_member::FluidMeterMember,
+        measurement::Measurement,
+        organization::OrganizationMember,
+        user::{
+            EmailChange, FluidMeterExport, NewPasswordInput, PasswordRecovery, SessionToken, User,
+            UserDataExport,
+        },
     },
     helper::{
         mail::MailHelper,
         token::{alphanumeric, AUTH_TOKEN_LEN},
+        user::{EMAIL_VERIFICATION_RESEND_MINS, PASSWORD_RECOVERY_TTL_MINS},
     },
     settings::settings::Settings,
     storage::{
-        error::{not_found, rate_limit, undefined, Error},
-        postgres::PostgresStorage,
+        error::{duplicate, not_found, query_error, rate_limit, undefined, Error},
+        postgres::{device::release_devices, PostgresStorage},
         UserStorage,
     },
 };
 
+pub const EMAIL_VERIFICATION_TTL_HOURS: &'static i64 = &24;
+
 #[async_trait]
 impl UserStorage for PostgresStorage {
     /// Used in tests. Production users should use sign_up_user
@@ -38,7 +48,7 @@ impl UserStorage for PostgresStorage {
             Ok(_) => {},
             Err(err) => {
                 error!("Error inserting user: {}", err);
-                return undefined();
+                return query_error(err, "insert_user", Some(&user.id));
             }
         };
 
@@ -58,11 +68,11 @@ impl UserStorage for PostgresStorage {
             Ok(t) => t,
             Err(e) => {
                 error!("Error inserting user. {}", e);
-                return undefined();
+                return query_error(e, "sign_up_user", Some(&user.id));
             }
         };
 
-        match sqlx::query("INSERT INTO account(id, provider, email, password, name, recorded_at) VALUES($1, $2, $3, $4, $5, $6)")
+        match sqlx::query("INSERT INTO account(id, provider, email, password, name, recorded_at) VALUES($1, $2, $3, $4, $5, $6) ON CONFLICT DO NOTHING")
         .bind(&user.id)
         .bind(&user.provider)
         .bind(&user.email)
@@ -72,19 +82,25 @@ impl UserStorage for PostgresStorage {
         .execute(&mut *tx)
         .await
         {
-            Ok(_) => {},
+            Ok(r) => {
+                if r.rows_affected() == 0 {
+                    let _ = tx.rollback().await;
+                    return duplicate();
+                }
+            },
             Err(err) => {
                 error!("Error inserting user: {}", err);
-                return undefined();
+                return query_error(err, "sign_up_user", Some(&user.id));
             }
         };
 
         let verification_token = alphanumeric(&100);
         match sqlx::query(
-            "INSERT INTO email_verification(token, account_id, recorded_at) VALUES($1, $2, $3)",
+            "INSERT INTO email_verification(token, account_id, expires_at, recorded_at) VALUES($1, $2, $3, $4)",
         )
         .bind(&verification_token)
         .bind(&user.id)
+        .bind(user.recorded_at + Duration::hours(*EMAIL_VERIFICATION_TTL_HOURS))
         .bind(user.recorded_at)
         .execute(&mut *tx)
         .await
@@ -101,7 +117,7 @@ impl UserStorage for PostgresStorage {
             }
             Err(e) => {
                 error!("Error saving verification: {}", e);
-                return undefined();
+                return query_error(e, "sign_up_user", Some(&user.id));
             }
         };
 
@@ -125,7 +141,7 @@ impl UserStorage for PostgresStorage {
                 }
 
                 error!("Error getting user by id: {}", e);
-                return undefined();
+                return query_error(e, "user_by_id", Some(id));
             }
         };
     }
@@ -158,7 +174,7 @@ impl UserStorage for PostgresStorage {
                 }
 
                 error!("Failed to query for session token: {}", e);
-                return undefined();
+                return query_error(e, "user_by_token", None);
             }
         };
     }
@@ -168,7 +184,7 @@ impl UserStorage for PostgresStorage {
             Ok(t) => t,
             Err(e) => {
                 error!("Error creating transaction. {}", e);
-                return undefined();
+                return query_error(e, "verify_email", None);
             }
         };
 
@@ -181,10 +197,11 @@ impl UserStorage for PostgresStorage {
                 LEFT JOIN
                     account ON account.id = email_verification.account_id
                 WHERE
-                    token = $1
+                    token = $1 AND expires_at > $2
                 "#,
         )
         .bind(token)
+        .bind(Utc::now().naive_utc())
         .fetch_one(&mut *tx)
         .await
         {
@@ -199,7 +216,7 @@ impl UserStorage for PostgresStorage {
                 }
 
                 error!("Failed to query for email_verification {}", e);
-                return undefined();
+                return query_error(e, "verify_email", None);
             }
         };
 
@@ -215,7 +232,7 @@ impl UserStorage for PostgresStorage {
             Err(e) => {
                 let _ = tx.rollback().await;
                 error!("Failed to update user. {}", e);
-                return undefined();
+                return query_error(e, "verify_email", None);
             }
         };
 
@@ -239,7 +256,7 @@ impl UserStorage for PostgresStorage {
                 }
 
                 error!("Error in email_verification_by_id: {}", e);
-                return undefined();
+                return query_error(e, "email_verification_by_id", Some(id));
             }
         };
     }
@@ -260,10 +277,22 @@ impl UserStorage for PostgresStorage {
         .execute(&self.pool)
         .await
         {
-            Ok(_) => return Ok(token),
+            Ok(_) => {}
             Err(e) => {
                 error!("Error logging in: {}", e);
-                return undefined();
+                return query_error(e, "log_in", Some(id));
+            }
+        };
+
+        match sqlx::query("UPDATE account SET deletion_scheduled_at = NULL WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await
+        {
+            Ok(_) => return Ok(token),
+            Err(e) => {
+                error!("Error cancelling account deletion: {}", e);
+                return query_error(e, "log_in", Some(id));
             }
         };
     }
@@ -277,7 +306,7 @@ impl UserStorage for PostgresStorage {
             Ok(_) => return Ok(()),
             Err(e) => {
                 error!("Error deleting session: {}", e);
-                return undefined();
+                return query_error(e, "log_out", None);
             }
         };
     }
@@ -298,11 +327,11 @@ impl UserStorage for PostgresStorage {
             Ok(_) => {}
             Err(e) => {
                 error!("Error deleting password recovery tokens: {}", e);
-                return undefined();
+                return query_error(e, "password_recovery", Some(&user.id));
             }
         };
 
-        // Don't allow the user to recover password more than every 30 minutes
+        // Don't allow the user to recover password more than once per token TTL
         match sqlx::query("SELECT * FROM password_recovery WHERE account_id = $1")
             .bind(&user.id)
             .fetch_one(&self.pool)
@@ -316,7 +345,7 @@ impl UserStorage for PostgresStorage {
                 sqlx::Error::RowNotFound => {}
                 _ => {
                     error!("Error: {}", e);
-                    return undefined();
+                    return query_error(e, "password_recovery", Some(&user.id));
                 }
             },
         };
@@ -325,7 +354,7 @@ impl UserStorage for PostgresStorage {
             Ok(t) => t,
             Err(e) => {
                 error!("Error creating transaction. {}", e);
-                return undefined();
+                return query_error(e, "password_recovery", Some(&user.id));
             }
         };
 
@@ -335,7 +364,7 @@ impl UserStorage for PostgresStorage {
         )
         .bind(&token)
         .bind(&user.id)
-        .bind(Utc::now().naive_utc() + Duration::minutes(30))
+        .bind(Utc::now().naive_utc() + Duration::minutes(*PASSWORD_RECOVERY_TTL_MINS))
         .bind(Utc::now().naive_utc())
         .execute(&mut *tx)
         .await
@@ -352,7 +381,7 @@ impl UserStorage for PostgresStorage {
             }
             Err(e) => {
                 error!("Error saving password_recovery: {}", e);
-                return undefined();
+                return query_error(e, "password_recovery", Some(&user.id));
             }
         };
 
@@ -380,7 +409,7 @@ impl UserStorage for PostgresStorage {
                 }
 
                 error!("Failed to query for password_recovery {}", e);
-                return undefined();
+                return query_error(e, "password_recovery_by_user", Some(user_id));
             }
         }
     }
@@ -390,7 +419,7 @@ impl UserStorage for PostgresStorage {
             Ok(t) => t,
             Err(e) => {
                 error!("Error creating transaction. {}", e);
-                return undefined();
+                return query_error(e, "new_password", None);
             }
         };
 
@@ -412,7 +441,7 @@ impl UserStorage for PostgresStorage {
                 }
 
                 error!("Failed to query for password_recovery {}", e);
-                return undefined();
+                return query_error(e, "new_password", None);
             }
         };
 
@@ -426,7 +455,7 @@ impl UserStorage for PostgresStorage {
             Err(e) => {
                 let _ = tx.rollback().await;
                 error!("Failed to update user. {}", e);
-                return undefined();
+                return query_error(e, "new_password", None);
             }
         };
 
@@ -439,7 +468,531 @@ impl UserStorage for PostgresStorage {
             Err(e) => {
                 let _ = tx.rollback().await;
                 error!("Failed to update user. {}", e);
-                return undefined();
+                return query_error(e, "new_password", None);
+            }
+        };
+
+        let _ = tx.commit().await;
+        return Ok(());
+    }
+
+    async fn update_user(&self, user: &User) -> Result<User, Error> {
+        match sqlx::query("UPDATE account SET name = $1 WHERE id = $2")
+            .bind(&user.name)
+            .bind(&user.id)
+            .execute(&self.pool)
+            .await
+        {
+            Ok(_) => return Ok(user.clone()),
+            Err(e) => {
+                error!("Failed to update user. {}", e);
+                return query_error(e, "update_user", Some(&user.id));
+            }
+        };
+    }
+
+    async fn change_password(
+        &self,
+        user_id: &str,
+        password: &str,
+        keep_token: &str,
+    ) -> Result<(), Error> {
+        let mut tx = match self.pool.begin().await {
+            Ok(t) => t,
+            Err(e) => {
+                error!("Error creating transaction. {}", e);
+                return query_error(e, "change_password", Some(user_id));
+            }
+        };
+
+        match sqlx::query("UPDATE account SET password = $1 WHERE id = $2")
+            .bind(password)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+        {
+            Ok(_) => {}
+            Err(e) => {
+                let _ = tx.rollback().await;
+                error!("Failed to update user. {}", e);
+                return query_error(e, "change_password", Some(user_id));
+            }
+        };
+
+        match sqlx::query("DELETE FROM session_token WHERE account_id = $1 AND token != $2")
+            .bind(user_id)
+            .bind(keep_token)
+            .execute(&mut *tx)
+            .await
+        {
+            Ok(_) => {}
+            Err(e) => {
+                let _ = tx.rollback().await;
+                error!("Error deleting sessions: {}", e);
+                return query_error(e, "change_password", Some(user_id));
+            }
+        };
+
+        let _ = tx.commit().await;
+        return Ok(());
+    }
+
+    async fn email_change(
+        &self,
+        user: &User,
+        email: &str,
+        settings: Arc<Settings>,
+        mail_helper: Arc<dyn MailHelper>,
+    ) -> Result<(), Error> {
+        let mut tx = match self.pool.begin().await {
+            Ok(t) => t,
+            Err(e) => {
+                error!("Error creating transaction. {}", e);
+                return query_error(e, "email_change", Some(&user.id));
+            }
+        };
+
+        // Only the latest request is valid
+        match sqlx::query("DELETE FROM email_change WHERE account_id = $1 OR expires_at < $2")
+            .bind(&user.id)
+            .bind(Utc::now().naive_utc())
+            .execute(&mut *tx)
+            .await
+        {
+            Ok(_) => {}
+            Err(e) => {
+                let _ = tx.rollback().await;
+                error!("Error deleting email change requests: {}", e);
+                return query_error(e, "email_change", Some(&user.id));
+            }
+        };
+
+        let token = alphanumeric(&100);
+        match sqlx::query(
+            "INSERT INTO email_change(token, account_id, email, expires_at, recorded_at) VALUES($1, $2, $3, $4, $5)",
+        )
+        .bind(&token)
+        .bind(&user.id)
+        .bind(email)
+        .bind(Utc::now().naive_utc() + Duration::hours(24))
+        .bind(Utc::now().naive_utc())
+        .execute(&mut *tx)
+        .await
+        {
+            Ok(_) => {
+                if !mail_helper
+                    .email_change_verification(&settings, user, email, &token)
+                    .await
+                {
+                    let _ = tx.rollback().await;
+                    error!("Couldn't send email change verification email");
+                    return undefined();
+                }
+            }
+            Err(e) => {
+                let _ = tx.rollback().await;
+                error!("Error saving email_change: {}", e);
+                return query_error(e, "email_change", Some(&user.id));
+            }
+        };
+
+        let _ = tx.commit().await;
+        return Ok(());
+    }
+
+    async fn email_change_by_user(&self, user_id: &str) -> Result<Option<EmailChange>, Error> {
+        match sqlx::query_as("SELECT * FROM email_change WHERE account_id = $1")
+            .bind(user_id)
+            .fetch_one(&self.pool)
+            .await
+        {
+            Ok(c) => return Ok(Some(c)),
+            Err(e) => {
+                match e {
+                    sqlx::Error::RowNotFound => {
+                        return Ok(None);
+                    }
+                    _ => {}
+                }
+
+                error!("Failed to query for email_change {}", e);
+                return query_error(e, "email_change_by_user", Some(user_id));
+            }
+        };
+    }
+
+    async fn confirm_email_change(&self, token: &str) -> Result<User, Error> {
+        let mut tx = match self.pool.begin().await {
+            Ok(t) => t,
+            Err(e) => {
+                error!("Error creating transaction. {}", e);
+                return query_error(e, "confirm_email_change", None);
+            }
+        };
+
+        let change: EmailChange =
+            match sqlx::query_as("SELECT * FROM email_change WHERE token = $1 AND expires_at > $2")
+                .bind(token)
+                .bind(Utc::now().naive_utc())
+                .fetch_one(&mut *tx)
+                .await
+            {
+                Ok(c) => c,
+                Err(e) => {
+                    match e {
+                        sqlx::Error::RowNotFound => {
+                            error!("No email change matches token {}", &token);
+                            return not_found();
+                        }
+                        _ => {}
+                    }
+
+                    error!("Failed to query for email_change {}", e);
+                    return query_error(e, "confirm_email_change", None);
+                }
+            };
+
+        let mut user: User = match sqlx::query_as("SELECT * FROM account WHERE id = $1")
+            .bind(&change.account_id)
+            .fetch_one(&mut *tx)
+            .await
+        {
+            Ok(u) => u,
+            Err(e) => {
+                error!("Failed to query for user {}", e);
+                return query_error(e, "confirm_email_change", None);
+            }
+        };
+
+        let new_id = format!("{}+{}", change.email, user.provider);
+        match sqlx::query("SELECT id FROM account WHERE id = $1")
+            .bind(&new_id)
+            .fetch_one(&mut *tx)
+            .await
+        {
+            Ok(_) => {
+                error!("Account already exists for {}", &new_id);
+                return duplicate();
+            }
+            Err(e) => match e {
+                sqlx::Error::RowNotFound => {}
+                _ => {
+                    error!("Failed to query for user {}", e);
+                    return query_error(e, "confirm_email_change", None);
+                }
+            },
+        };
+
+        user.id = new_id;
+        user.email = change.email.clone();
+        user.email_verified_at = Some(Utc::now().naive_utc());
+
+        // Foreign keys cascade the new id to all the tables referencing the account
+        match sqlx::query(
+            "UPDATE account SET id = $1, email = $2, email_verified_at = $3 WHERE id = $4",
+        )
+        .bind(&user.id)
+        .bind(&user.email)
+        .bind(user.email_verified_at)
+        .bind(&change.account_id)
+        .execute(&mut *tx)
+        .await
+        {
+            Ok(_) => {}
+            Err(e) => {
+                let _ = tx.rollback().await;
+                error!("Failed to update user. {}", e);
+                return query_error(e, "confirm_email_change", None);
+            }
+        };
+
+        match sqlx::query("DELETE FROM email_change WHERE account_id = $1")
+            .bind(&user.id)
+            .execute(&mut *tx)
+            .await
+        {
+            Ok(_) => {}
+            Err(e) => {
+                let _ = tx.rollback().await;
+                error!("Error deleting email change requests: {}", e);
+                return query_error(e, "confirm_email_change", None);
+            }
+        };
+
+        let _ = tx.commit().await;
+        return Ok(user);
+    }
+
+    async fn schedule_account_deletion(
+        &self,
+        user_id: &str,
+        delete_at: NaiveDateTime,
+    ) -> Result<(), Error> {
+        let mut tx = match self.pool.begin().await {
+            Ok(t) => t,
+            Err(e) => {
+                error!("Error creating transaction. {}", e);
+                return query_error(e, "schedule_account_deletion", Some(user_id));
+            }
+        };
+
+        match sqlx::query("UPDATE account SET deletion_scheduled_at = $1 WHERE id = $2")
+            .bind(delete_at)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+        {
+            Ok(_) => {}
+            Err(e) => {
+                let _ = tx.rollback().await;
+                error!("Failed to schedule account deletion. {}", e);
+                return query_error(e, "schedule_account_deletion", Some(user_id));
+            }
+        };
+
+        match sqlx::query("DELETE FROM session_token WHERE account_id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+        {
+            Ok(_) => {}
+            Err(e) => {
+                let _ = tx.rollback().await;
+                error!("Error deleting sessions: {}", e);
+                return query_error(e, "schedule_account_deletion", Some(user_id));
+            }
+        };
+
+        let _ = tx.commit().await;
+        return Ok(());
+    }
+
+    async fn delete_accounts_scheduled_before(&self, date: NaiveDateTime) -> Result<u64, Error> {
+        let mut tx = match self.pool.begin().await {
+            Ok(t) => t,
+            Err(e) => {
+                error!("Error creating transaction. {}", e);
+                return query_error(e, "delete_accounts_scheduled_before", None);
+            }
+        };
+
+        // The devices of the meters survive them, so they can be claimed again
+        let meter_ids: Vec<String> = match sqlx::query_scalar(
+            r#"
+            SELECT fm.id FROM fluid_meter fm
+            JOIN account a ON a.id = fm.owner_id
+            WHERE a.deletion_scheduled_at < $1
+            "#,
+        )
+        .bind(date)
+        .fetch_all(&mut *tx)
+        .await
+        {
+            Ok(ids) => ids,
+            Err(e) => {
+                let _ = tx.rollback().await;
+                error!("Error getting fluid meters of deleted accounts: {}", e);
+                return query_error(e, "delete_accounts_scheduled_before", None);
+            }
+        };
+        if let Err(e) = release_devices(&mut tx, &meter_ids).await {
+            let _ = tx.rollback().await;
+            error!("Error releasing devices: {}", e);
+            return query_error(e, "delete_accounts_scheduled_before", None);
+        }
+
+        // Everything else that belongs to the account is deleted by the
+        // foreign keys
+        let accounts = match sqlx::query("DELETE FROM account WHERE deletion_scheduled_at < $1")
+            .bind(date)
+            .execute(&mut *tx)
+            .await
+        {
+            Ok(r) => r.rows_affected(),
+            Err(e) => {
+                let _ = tx.rollback().await;
+                error!("Error deleting accounts: {}", e);
+                return query_error(e, "delete_accounts_scheduled_before", None);
+            }
+        };
+
+        match tx.commit().await {
+            Ok(_) => return Ok(accounts),
+            Err(e) => {
+                error!("Error committing transaction: {}", e);
+                return query_error(e, "delete_accounts_scheduled_before", None);
+            }
+        }
+    }
+
+    async fn user_data_export(&self, user_id: &str) -> Result<Option<UserDataExport>, Error> {
+        let mut user = match self.user_by_id(user_id).await? {
+            Some(u) => u,
+            None => return Ok(None),
+        };
+        user.password = None;
+
+        let meters: Vec<FluidMeter> =
+            match sqlx::query_as("SELECT * FROM fluid_meter WHERE owner_id = $1 ORDER BY id")
+                .bind(user_id)
+                .fetch_all(&self.pool)
+                .await
+            {
+                Ok(m) => m,
+                Err(e) => {
+                    error!("Error getting fluid meters for export: {}", e);
+                    return query_error(e, "user_data_export", Some(user_id));
+                }
+            };
+
+        // Measurements of all the meters are read at once and grouped by meter
+        let meter_ids: Vec<String> = meters.iter().map(|m| m.id.clone()).collect();
+        let measurements: Vec<Measurement> = match sqlx::query_as(
+            "SELECT * FROM measurement WHERE device_id = ANY($1) ORDER BY recorded_at",
+        )
+        .bind(&meter_ids)
+        .fetch_all(&self.pool)
+        .await
+        {
+            Ok(m) => m,
+            Err(e) => {
+                error!("Error getting measurements for export: {}", e);
+                return query_error(e, "user_data_export", Some(user_id));
+            }
+        };
+        let mut measurements_by_meter: HashMap<String, Vec<Measurement>> = HashMap::new();
+        for measurement in measurements {
+            measurements_by_meter
+                .entry(measurement.device_id.clone())
+                .or_default()
+                .push(measurement);
+        }
+        let fluid_meters = meters
+            .into_iter()
+            .map(|fluid_meter| FluidMeterExport {
+                measurements: measurements_by_meter
+                    .remove(&fluid_meter.id)
+                    .unwrap_or_default(),
+                fluid_meter,
+            })
+            .collect();
+
+        let fluid_meter_memberships: Vec<FluidMeterMember> = match sqlx::query_as(
+            "SELECT * FROM fluid_meter_member WHERE account_id = $1 ORDER BY fluid_meter_id",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        {
+            Ok(m) => m,
+            Err(e) => {
+                error!("Error getting fluid meter memberships for export: {}", e);
+                return query_error(e, "user_data_export", Some(user_id));
+            }
+        };
+
+        let organization_memberships: Vec<OrganizationMember> = match sqlx::query_as(
+            "SELECT * FROM organization_member WHERE account_id = $1 ORDER BY organization_id",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        {
+            Ok(m) => m,
+            Err(e) => {
+                error!("Error getting organization memberships for export: {}", e);
+                return query_error(e, "user_data_export", Some(user_id));
+            }
+        };
+
+        return Ok(Some(UserDataExport {
+            user,
+            fluid_meters,
+            fluid_meter_memberships,
+            organization_memberships,
+            exported_at: Utc::now().naive_utc(),
+        }));
+    }
+
+    async fn resend_email_verification(
+        &self,
+        user: &User,
+        settings: Arc<Settings>,
+        mail_helper: Arc<dyn MailHelper>,
+    ) -> Result<(), Error> {
+        // Don't allow the user to request a new e-mail too often
+        let since = Utc::now().naive_utc() - Duration::minutes(*EMAIL_VERIFICATION_RESEND_MINS);
+        match sqlx::query(
+            "SELECT * FROM email_verification WHERE account_id = $1 AND recorded_at > $2",
+        )
+        .bind(&user.id)
+        .bind(since)
+        .fetch_one(&self.pool)
+        .await
+        {
+            Ok(_) => {
+                error!(
+                    "Throttling resend_email_verification for user: {}",
+                    &user.id
+                );
+                return rate_limit();
+            }
+            Err(e) => match e {
+                sqlx::Error::RowNotFound => {}
+                _ => {
+                    error!("Error: {}", e);
+                    return query_error(e, "resend_email_verification", Some(&user.id));
+                }
+            },
+        };
+
+        let mut tx = match self.pool.begin().await {
+            Ok(t) => t,
+            Err(e) => {
+                error!("Error creating transaction. {}", e);
+                return query_error(e, "resend_email_verification", Some(&user.id));
+            }
+        };
+
+        match sqlx::query("DELETE FROM email_verification WHERE account_id = $1")
+            .bind(&user.id)
+            .execute(&mut *tx)
+            .await
+        {
+            Ok(_) => {}
+            Err(e) => {
+                let _ = tx.rollback().await;
+                error!("Error deleting email verification tokens: {}", e);
+                return query_error(e, "resend_email_verification", Some(&user.id));
+            }
+        };
+
+        let now = Utc::now().naive_utc();
+        let token = alphanumeric(&100);
+        match sqlx::query(
+            "INSERT INTO email_verification(token, account_id, expires_at, recorded_at) VALUES($1, $2, $3, $4)",
+        )
+        .bind(&token)
+        .bind(&user.id)
+        .bind(now + Duration::hours(*EMAIL_VERIFICATION_TTL_HOURS))
+        .bind(now)
+        .execute(&mut *tx)
+        .await
+        {
+            Ok(_) => {
+                if !mail_helper
+                    .sign_up_verification(&settings, user, &token)
+                    .await
+                {
+                    let _ = tx.rollback().await;
+                    error!("Couldn't send verification email");
+                    return undefined();
+                }
+            }
+            Err(e) => {
+                let _ = tx.rollback().await;
+                error!("Error saving verification: {}", e);
+                return query_error(e, "resend_email_verification", Some(&user.id));
             }
         };
 
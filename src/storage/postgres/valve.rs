@@ -0,0 +1,233 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use tracing::error;
+
+use crate::{
+    api::{
+        common::{PaginatedRequest, PaginatedResponse},
+        device::DeviceCommand,
+        valve::{Valve, ValveAction, ValveState, VALVE_ACTIONS_SORT},
+    },
+    storage::{
+        error::{query_error, Error},
+        postgres::{Keyset, PostgresStorage},
+        ValveStorage,
+    },
+};
+
+#[async_trait]
+impl ValveStorage for PostgresStorage {
+    async fn acknowledge_valve_command(
+        &self,
+        fluid_meter_id: &str,
+        target_state: &ValveState,
+        success: bool,
+    ) -> Result<(), Error> {
+        // A failed command doesn't move the valve. The requested state goes
+        // back to the real one, unless another command was sent meanwhile
+        let query = if success {
+            "UPDATE valve SET state = $1, updated_at = $2 WHERE fluid_meter_id = $3"
+        } else {
+            "UPDATE valve SET requested_state = state, updated_at = $2 WHERE fluid_meter_id = $3 AND requested_state = $1"
+        };
+        match sqlx::query(query)
+            .bind(target_state)
+            .bind(Utc::now().naive_utc())
+            .bind(fluid_meter_id)
+            .execute(&self.pool)
+            .await
+        {
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                error!("Error acknowledging valve command: {}", e);
+                return query_error(e, "acknowledge_valve_command", Some(fluid_meter_id));
+            }
+        };
+    }
+
+    async fn insert_valve_action(
+        &self,
+        action: &ValveAction,
+        command: &DeviceCommand,
+    ) -> Result<ValveAction, Error> {
+        let mut tx = match self.pool.begin().await {
+            Ok(t) => t,
+            Err(e) => {
+                error!("Error creating transaction. {}", e);
+                return query_error(e, "insert_valve_action", Some(&action.fluid_meter_id));
+            }
+        };
+
+        match sqlx::query(
+            r#"
+            INSERT INTO device_command(id, fluid_meter_id, command_type, value, status, created_by, result, recorded_at, sent_at, acknowledged_at)
+            VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            "#,
+        )
+        .bind(&command.id)
+        .bind(&command.fluid_meter_id)
+        .bind(&command.command_type)
+        .bind(command.value)
+        .bind(&command.status)
+        .bind(&command.created_by)
+        .bind(&command.result)
+        .bind(command.recorded_at)
+        .bind(command.sent_at)
+        .bind(command.acknowledged_at)
+        .execute(&mut *tx)
+        .await
+        {
+            Ok(_) => {}
+            Err(e) => {
+                let _ = tx.rollback().await;
+                error!("Error inserting valve command: {}", e);
+                return query_error(e, "insert_valve_action", Some(&action.fluid_meter_id));
+            }
+        };
+
+        match sqlx::query(
+            r#"
+            INSERT INTO valve_action(id, fluid_meter_id, target_state, origin, account_id, command_id, recorded_at)
+            VALUES($1, $2, $3, $4, $5, $6, $7)
+            "#,
+        )
+        .bind(&action.id)
+        .bind(&action.fluid_meter_id)
+        .bind(&action.target_state)
+        .bind(&action.origin)
+        .bind(&action.account_id)
+        .bind(&action.command_id)
+        .bind(action.recorded_at)
+        .execute(&mut *tx)
+        .await
+        {
+            Ok(_) => {}
+            Err(e) => {
+                let _ = tx.rollback().await;
+                error!("Error inserting valve action: {}", e);
+                return query_error(e, "insert_valve_action", Some(&action.fluid_meter_id));
+            }
+        };
+
+        match sqlx::query(
+            "UPDATE valve SET requested_state = $1, updated_at = $2 WHERE fluid_meter_id = $3",
+        )
+        .bind(&action.target_state)
+        .bind(action.recorded_at)
+        .bind(&action.fluid_meter_id)
+        .execute(&mut *tx)
+        .await
+        {
+            Ok(_) => {}
+            Err(e) => {
+                let _ = tx.rollback().await;
+                error!("Error updating valve: {}", e);
+                return query_error(e, "insert_valve_action", Some(&action.fluid_meter_id));
+            }
+        };
+
+        match tx.commit().await {
+            Ok(_) => return Ok(action.clone()),
+            Err(e) => {
+                error!("Error committing transaction. {}", e);
+                return query_error(e, "insert_valve_action", Some(&action.fluid_meter_id));
+            }
+        };
+    }
+
+    async fn save_valve(&self, valve: &Valve) -> Result<Valve, Error> {
+        match sqlx::query_as(
+            r#"
+            INSERT INTO valve(fluid_meter_id, state, requested_state, auto_shut_off, recorded_at, updated_at)
+            VALUES($1, $2, $3, $4, $5, $6)
+            ON CONFLICT(fluid_meter_id)
+            DO UPDATE SET auto_shut_off = EXCLUDED.auto_shut_off, updated_at = EXCLUDED.updated_at
+            RETURNING *
+            "#,
+        )
+        .bind(&valve.fluid_meter_id)
+        .bind(&valve.state)
+        .bind(&valve.requested_state)
+        .bind(valve.auto_shut_off)
+        .bind(valve.recorded_at)
+        .bind(valve.updated_at)
+        .fetch_one(&self.pool)
+        .await
+        {
+            Ok(v) => return Ok(v),
+            Err(e) => {
+                error!("Error saving valve: {}", e);
+                return query_error(e, "save_valve", Some(&valve.fluid_meter_id));
+            }
+        };
+    }
+
+    async fn valve_actions(
+        &self,
+        fluid_meter_id: &str,
+        options: &PaginatedRequest,
+    ) -> Result<PaginatedResponse<ValveAction>, Error> {
+        let keyset = Keyset {
+            sort: VALVE_ACTIONS_SORT.to_string(),
+            expr: "recorded_at",
+            key_type: "TIMESTAMP",
+            id: "id",
+            descending: true,
+            cursor: &options.page_cursor,
+            page_size: options.page_size,
+        };
+        let filters = "fluid_meter_id = $1";
+        let query = format!(
+            r#"
+                SELECT *,
+                  (SELECT status FROM device_command WHERE id = command_id) AS command_status,
+                  {}
+                FROM valve_action
+                WHERE {} AND {}
+                {}
+                LIMIT $4
+            "#,
+            keyset.columns("valve_action", filters, 2, 3),
+            filters,
+            keyset.page_filter(2, 3),
+            keyset.order_by(),
+        );
+
+        let (key, id) = keyset.binds();
+        match sqlx::query(&query)
+            .bind(fluid_meter_id)
+            .bind(key)
+            .bind(id)
+            .bind(keyset.limit())
+            .fetch_all(&self.pool)
+            .await
+        {
+            Ok(rows) => return keyset.paginate(rows),
+            Err(e) => {
+                error!("Error getting valve actions: {}", e);
+                return query_error(e, "valve_actions", Some(fluid_meter_id));
+            }
+        };
+    }
+
+    async fn valve_by_fluid_meter(&self, fluid_meter_id: &str) -> Result<Option<Valve>, Error> {
+        match sqlx::query_as("SELECT * FROM valve WHERE fluid_meter_id = $1")
+            .bind(fluid_meter_id)
+            .fetch_one(&self.pool)
+            .await
+        {
+            Ok(v) => return Ok(Some(v)),
+            Err(e) => {
+                match e {
+                    sqlx::Error::RowNotFound => {
+                        return Ok(None);
+                    }
+                    _ => {}
+                }
+
+                error!("Error getting valve: {}", e);
+                return query_error(e, "valve_by_fluid_meter", Some(fluid_meter_id));
+            }
+        };
+    }
+}
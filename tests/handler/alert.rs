@@ -14,11 +14,17 @@ use uuid::Uuid;
 use mekadomus_api::{
     api::{
         alert::{Alert, AlertType},
+        common::PaginatedRequest,
         fluid_meter::{FluidMeter, FluidMeterAlerts, FluidMeterStatus::Active},
         measurement::Measurement,
+        valve::{
+            Valve,
+            ValveActionOrigin::AutoShutOff,
+            ValveState::{Closed, Open},
+        },
     },
     helper::{mail::MockMailHelper, user::MockUserHelper},
-    storage::{postgres::PostgresStorage, FluidMeterStorage, MeasurementStorage},
+    storage::{postgres::PostgresStorage, FluidMeterStorage, MeasurementStorage, ValveStorage},
 };
 
 use crate::helper::{
@@ -38,43 +44,59 @@ async fn alert_success() {
         measurement: "1".to_string(),
         recorded_at: Utc::now().naive_utc() - Duration::minutes(80),
     };
-    let _ = storage.save_measurement(&m).await;
+    let _ = storage.save_measurement(&m, 600).await;
     m.id = Uuid::new_v4().to_string();
     m.recorded_at = m.recorded_at + Duration::minutes(20);
-    let _ = storage.save_measurement(&m).await;
+    let _ = storage.save_measurement(&m, 600).await;
     m.id = Uuid::new_v4().to_string();
     m.recorded_at = m.recorded_at + Duration::minutes(20);
-    let _ = storage.save_measurement(&m).await;
+    let _ = storage.save_measurement(&m, 600).await;
     m.id = Uuid::new_v4().to_string();
     m.recorded_at = m.recorded_at + Duration::minutes(20);
-    let _ = storage.save_measurement(&m).await;
+    let _ = storage.save_measurement(&m, 600).await;
     m.id = Uuid::new_v4().to_string();
     m.recorded_at = m.recorded_at + Duration::minutes(20);
-    let _ = storage.save_measurement(&m).await;
+    let _ = storage.save_measurement(&m, 600).await;
 
     // Fluid meter without alert
     let fm2 = create_fluid_meter().await;
     let mut m = Measurement {
         id: Uuid::new_v4().to_string(),
-        device_id: fm2.id,
+        device_id: fm2.id.clone(),
         measurement: "1".to_string(),
         recorded_at: Utc::now().naive_utc() + Duration::minutes(80),
     };
-    let _ = storage.save_measurement(&m).await;
+    let _ = storage.save_measurement(&m, 600).await;
     m.id = Uuid::new_v4().to_string();
     m.recorded_at = m.recorded_at + Duration::minutes(20);
     m.measurement = "0".to_string();
-    let _ = storage.save_measurement(&m).await;
+    let _ = storage.save_measurement(&m, 600).await;
     m.id = Uuid::new_v4().to_string();
     m.recorded_at = m.recorded_at + Duration::minutes(20);
     m.measurement = "1".to_string();
-    let _ = storage.save_measurement(&m).await;
+    let _ = storage.save_measurement(&m, 600).await;
     m.id = Uuid::new_v4().to_string();
     m.recorded_at = m.recorded_at + Duration::minutes(20);
-    let _ = storage.save_measurement(&m).await;
+    let _ = storage.save_measurement(&m, 600).await;
     m.id = Uuid::new_v4().to_string();
     m.recorded_at = m.recorded_at + Duration::minutes(20);
-    let _ = storage.save_measurement(&m).await;
+    let _ = storage.save_measurement(&m, 600).await;
+
+    // Both meters have a valve that closes automatically, but only the one
+    // with constant flow should close
+    for meter in [&fm, &fm2] {
+        let _ = storage
+            .save_valve(&Valve {
+                fluid_meter_id: meter.id.clone(),
+                state: Open,
+                requested_state: Open,
+                auto_shut_off: true,
+                recorded_at: Utc::now().naive_utc(),
+                updated_at: Utc::now().naive_utc(),
+            })
+            .await
+            .unwrap();
+    }
 
     // Fluid meter with not reporting alert and no measurements at all
     let fm3 = create_fluid_meter_at(Utc::now().naive_utc() - Duration::hours(25)).await;
@@ -87,7 +109,7 @@ async fn alert_success() {
         measurement: "1".to_string(),
         recorded_at: Utc::now().naive_utc() - Duration::hours(25),
     };
-    let _ = storage.save_measurement(&m).await;
+    let _ = storage.save_measurement(&m, 600).await;
 
     // Expect alert email submission
     let mut mail_helper_mock = MockMailHelper::new();
@@ -121,6 +143,11 @@ async fn alert_success() {
         .expect_alerts()
         .with(always(), always(), eq(alerts3))
         .return_const(true);
+    mail_helper_mock
+        .expect_valve_closed()
+        .with(always(), always(), eq(fm.clone()))
+        .times(1)
+        .return_const(true);
 
     let app = create_app_mail_helper(Arc::new(mail_helper_mock)).await;
 
@@ -139,6 +166,35 @@ async fn alert_success() {
 
     assert_eq!(response.status(), StatusCode::OK);
 
+    // Alerts found by the job stay open
+    for (meter, open_alerts) in [(&fm, 1), (&fm2, 0), (&fm3, 1)] {
+        let summary = storage
+            .get_fluid_meter_summary(&meter.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(summary.open_alerts, open_alerts);
+    }
+
+    // The valve is closing, but the device hasn't confirmed it yet
+    let valve = storage.valve_by_fluid_meter(&fm.id).await.unwrap().unwrap();
+    assert_eq!(valve.state, Open);
+    assert_eq!(valve.requested_state, Closed);
+    let options = PaginatedRequest {
+        page_size: 10,
+        page_cursor: None,
+    };
+    let actions = storage.valve_actions(&fm.id, &options).await.unwrap();
+    assert_eq!(actions.items.len(), 1);
+    assert_eq!(actions.items[0].origin, AutoShutOff);
+    assert_eq!(actions.items[0].account_id, None);
+    let valve = storage
+        .valve_by_fluid_meter(&fm2.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(valve.requested_state, Open);
+
     // Expect rate limit
     let response = app
         .clone()
@@ -153,7 +209,7 @@ async fn alert_success() {
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
 }
 
 #[test(tokio::test)]
@@ -164,9 +220,9 @@ async fn get_alerts() {
     // Mock UserHelper
     let mut user_helper_mock = MockUserHelper::new();
     user_helper_mock
-        .expect_owns_fluid_meter()
-        .with(always(), always(), always())
-        .returning(|_, _, _| Ok(true));
+        .expect_has_fluid_meter_role()
+        .with(always(), always(), always(), always())
+        .returning(|_, _, _, _| Ok(true));
 
     let app = create_app_with_user_user_helper(user_id, Arc::new(user_helper_mock)).await;
 
@@ -175,10 +231,16 @@ async fn get_alerts() {
     let fluid_meter = FluidMeter {
         id: meter_id.to_string(),
         name: "kitchen".to_string(),
-        owner_id: user_id.to_string(),
+        owner_id: Some(user_id.to_string()),
+        organization_id: None,
+        site_id: None,
+        parent_id: None,
         status: Active,
         recorded_at: Utc::now().naive_utc(),
         updated_at: Utc::now().naive_utc(),
+        deleted_at: None,
+        reporting_interval_secs: None,
+        activated_at: Utc::now().naive_utc(),
     };
     assert!(storage.insert_fluid_meter(&fluid_meter).await.is_ok());
 
@@ -1,6 +1,7 @@
 use mekadomus_api::{
     api::{
         common::{Series, SeriesGranularity::Day},
+        device::DeviceTelemetry,
         fluid_meter::{
             FluidMeter,
             FluidMeterStatus::{Active, Inactive},
@@ -20,6 +21,8 @@ use mekadomus_api::{
     },
 };
 
+use crate::helper::response::without_request_id;
+
 use axum::{
     body::Body,
     http,
@@ -63,27 +66,45 @@ async fn create_app_user_helper(
     };
     let fm = FluidMeter {
         id: DEVICE_ID.to_string(),
-        owner_id: user_id.to_string(),
+        owner_id: Some(user_id.to_string()),
+        organization_id: None,
+        site_id: None,
+        parent_id: None,
         name: "kitchen".to_string(),
         status: Active,
         recorded_at: Utc::now().naive_utc(),
         updated_at: Utc::now().naive_utc(),
+        deleted_at: None,
+        reporting_interval_secs: None,
+        activated_at: Utc::now().naive_utc(),
     };
     let fm2 = FluidMeter {
         id: DEVICE_ID2.to_string(),
-        owner_id: user_id.to_string(),
+        owner_id: Some(user_id.to_string()),
+        organization_id: None,
+        site_id: None,
+        parent_id: None,
         name: "kitchen".to_string(),
         status: Active,
         recorded_at: Utc::now().naive_utc(),
         updated_at: Utc::now().naive_utc(),
+        deleted_at: None,
+        reporting_interval_secs: None,
+        activated_at: Utc::now().naive_utc(),
     };
     let fm3 = FluidMeter {
         id: INACTIVE_DEVICE_ID.to_string(),
-        owner_id: user_id.to_string(),
+        owner_id: Some(user_id.to_string()),
+        organization_id: None,
+        site_id: None,
+        parent_id: None,
         name: "kitchen".to_string(),
         status: Inactive,
         recorded_at: Utc::now().naive_utc(),
         updated_at: Utc::now().naive_utc(),
+        deleted_at: None,
+        reporting_interval_secs: None,
+        activated_at: Utc::now().naive_utc(),
     };
     let measurement = Measurement {
         id: MEASUREMENT_ID.to_string(),
@@ -95,7 +116,7 @@ async fn create_app_user_helper(
     let _ = storage.insert_fluid_meter(&fm).await;
     let _ = storage.insert_fluid_meter(&fm2).await;
     let _ = storage.insert_fluid_meter(&fm3).await;
-    let _ = storage.save_measurement(&measurement).await;
+    let _ = storage.save_measurement(&measurement, 600).await;
 
     let mut authorizer = MockAuthorizer::new();
     authorizer
@@ -143,7 +164,7 @@ async fn save_measurement_invalid_json() {
     let body = response.into_body().collect().await.unwrap().to_bytes();
     let body: Value = serde_json::from_slice(&body).unwrap();
     assert_eq!(
-        body,
+        without_request_id(body),
         json!({ "code": "InvalidInput", "data": "", "message": "Invalid JSON for this endpoint" })
     );
 }
@@ -155,6 +176,8 @@ async fn save_measurement_not_owner() {
     let input = SaveMeasurementInput {
         device_id: "some-dev-id".to_string(),
         measurement: "134".to_string(),
+        telemetry: None,
+        secret: None,
     };
     let response = app
         .oneshot(
@@ -171,6 +194,36 @@ async fn save_measurement_not_owner() {
     assert_eq!(response.status(), StatusCode::BAD_REQUEST);
 }
 
+#[test(tokio::test)]
+async fn save_measurement_telemetry_unauthenticated() {
+    let (app, _) = create_app(false).await;
+
+    // Meters without a claimed device can't authenticate, so they can't send
+    // telemetry
+    let input = SaveMeasurementInput {
+        device_id: DEVICE_ID.to_string(),
+        measurement: "134".to_string(),
+        telemetry: Some(DeviceTelemetry {
+            battery_voltage: Some(2.1),
+            ..Default::default()
+        }),
+        secret: None,
+    };
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method(http::Method::POST)
+                .uri("/v1/measurement")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(Body::from(serde_json::to_string(&input).unwrap()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
 #[test(tokio::test)]
 async fn save_measurement_inactive() {
     let (app, _) = create_app(false).await;
@@ -178,6 +231,8 @@ async fn save_measurement_inactive() {
     let input = SaveMeasurementInput {
         device_id: INACTIVE_DEVICE_ID.to_string(),
         measurement: "134".to_string(),
+        telemetry: None,
+        secret: None,
     };
     let response = app
         .oneshot(
@@ -201,6 +256,8 @@ async fn save_measurement_success() {
     let input = SaveMeasurementInput {
         device_id: DEVICE_ID.to_string(),
         measurement: "134".to_string(),
+        telemetry: None,
+        secret: None,
     };
     let response = app
         .oneshot(
@@ -241,6 +298,8 @@ async fn save_measurement_ignores_duplicate() {
     let input = SaveMeasurementInput {
         device_id: DEVICE_ID2.to_string(),
         measurement: "3.781159".to_string(),
+        telemetry: None,
+        secret: None,
     };
     let response = app
         .clone()
@@ -270,7 +329,7 @@ async fn save_measurement_ignores_duplicate() {
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
 
     match storage
         .get_measurements(
@@ -295,9 +354,9 @@ async fn get_measurements_for_meter_not_owned() {
     // Mock UserHelper
     let mut user_helper_mock = MockUserHelper::new();
     user_helper_mock
-        .expect_owns_fluid_meter()
-        .with(always(), always(), always())
-        .returning(|_, _, _| Ok(false));
+        .expect_has_fluid_meter_role()
+        .with(always(), always(), always(), always())
+        .returning(|_, _, _, _| Ok(false));
 
     let (app, _) = create_app_user_helper(true, Arc::new(user_helper_mock)).await;
 
@@ -314,7 +373,7 @@ async fn get_measurements_for_meter_not_owned() {
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
 }
 
 #[tokio::test]
@@ -322,9 +381,9 @@ async fn get_measurements_for_meter_success() {
     // Mock UserHelper
     let mut user_helper_mock = MockUserHelper::new();
     user_helper_mock
-        .expect_owns_fluid_meter()
-        .with(always(), always(), always())
-        .returning(|_, _, _| Ok(true));
+        .expect_has_fluid_meter_role()
+        .with(always(), always(), always(), always())
+        .returning(|_, _, _, _| Ok(true));
 
     let (app, _) = create_app_user_helper(true, Arc::new(user_helper_mock)).await;
 
@@ -1,4 +1,19 @@
 mod alert;
+mod device;
+mod firmware;
 mod fluid_meter;
+mod fluid_meter_member;
 mod measurement;
+mod metrics;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod organization;
+mod profile;
+mod purge;
+mod rate_limit;
+mod reconciliation;
+mod request_id;
+mod site;
+mod two_factor;
 mod user;
+mod valve;
@@ -0,0 +1,67 @@
+use axum::{
+    body::Body,
+    http::{Method, Request, StatusCode},
+    Router,
+};
+use http_body_util::BodyExt;
+use test_log::test;
+use tower::util::ServiceExt;
+
+use mekadomus_api::{
+    error::app_error::{AppErrorCode, ErrorResponse},
+    middleware::request_id::REQUEST_ID_HEADER,
+};
+
+use crate::helper::app::create_app_basic;
+
+async fn send(app: &Router, uri: &str, request_id: Option<&str>) -> (StatusCode, String, Vec<u8>) {
+    let mut request = Request::builder().method(Method::GET).uri(uri);
+    if let Some(id) = request_id {
+        request = request.header(&REQUEST_ID_HEADER, id);
+    }
+    let response = app
+        .clone()
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+
+    let status = response.status();
+    let id = response
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    return (status, id, body.to_vec());
+}
+
+#[test(tokio::test)]
+async fn request_id_generated() {
+    let app = create_app_basic().await;
+
+    let (status, first, _) = send(&app, "/health", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(first.len(), 36);
+
+    let (_, second, _) = send(&app, "/health", None).await;
+    assert_ne!(first, second);
+
+    // Invalid ids are replaced
+    let (_, id, _) = send(&app, "/health", Some("not a valid id")).await;
+    assert_ne!(id, "not a valid id");
+    assert_eq!(id.len(), 36);
+}
+
+#[test(tokio::test)]
+async fn request_id_in_error_response() {
+    let app = create_app_basic().await;
+
+    let (status, id, body) = send(&app, "/v1/me", Some("support-1234")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(id, "support-1234");
+    let resp: ErrorResponse = serde_json::from_slice(&body).unwrap();
+    assert_eq!(resp.code, AppErrorCode::Unauthorized);
+    assert_eq!(resp.request_id, Some("support-1234".to_string()));
+}
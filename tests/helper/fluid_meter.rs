@@ -33,11 +33,17 @@ pub async fn create_fluid_meter_at(creation_time: NaiveDateTime) -> FluidMeter {
         .expect("Couldn't format date for fluid meter");
     let fm = FluidMeter {
         id: fm_id.to_string(),
-        owner_id: user_id.to_string(),
+        owner_id: Some(user_id.to_string()),
+        organization_id: None,
+        site_id: None,
+        parent_id: None,
         name: fm_id.to_string(),
         status: Active,
         recorded_at: at,
         updated_at: at,
+        deleted_at: None,
+        reporting_interval_secs: None,
+        activated_at: at,
     };
     let _ = storage.insert_user(&user).await;
     let _ = storage.insert_fluid_meter(&fm).await;
@@ -1,2 +1,3 @@
 pub mod app;
 pub mod fluid_meter;
+pub mod response;
@@ -0,0 +1,9 @@
+use serde_json::Value;
+
+/// Error responses include the id of the request, which changes on every call.
+/// Checks it is there and removes it so the rest can be compared
+pub fn without_request_id(mut body: Value) -> Value {
+    assert!(body["request_id"].is_string());
+    body.as_object_mut().unwrap().remove("request_id");
+    return body;
+}